@@ -50,7 +50,7 @@ fn main() {
     // Put.
     for i in 0..rounds {
         let istr = format!("{}", i);
-        store.insert(vec![istr.clone(), istr])
+        store.insert(vec![istr.clone(), istr]);
     }
 
     let t1 = PreciseTime::now();
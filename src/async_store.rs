@@ -0,0 +1,335 @@
+use std::future::Future;
+use std::ops;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::vec;
+
+use futures_core::Stream;
+
+use cmp;
+use Candidates;
+use Row;
+use Store;
+
+/// The number of rows an `AsyncStore` looks at before it yields to the executor, unless given
+/// otherwise with `AsyncStore::with_batch_size`.
+const BATCH_SIZE: usize = 1024;
+
+/// An `AsyncStore` wraps a `Store` for use from asynchronous code. Its `find` returns a `Stream`
+/// of rows, and its `delete` and `update` return `Future`s.
+///
+/// None of its operations ever block for long: after looking at a batch of rows, they wake their
+/// task up again and return `Poll::Pending`, so that the executor gets to run other tasks in
+/// between batches even during a long scan. All other (non-blocking) methods of the `Store`,
+/// such as `get`, can be called directly on the `AsyncStore`, which dereferences to it.
+pub struct AsyncStore<T, R = Vec<T>> {
+    store: Store<T, R>,
+    batch: usize,
+}
+
+impl<T, R> AsyncStore<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Wrap the given `Store`.
+    pub fn new(store: Store<T, R>) -> AsyncStore<T, R> {
+        AsyncStore::with_batch_size(store, BATCH_SIZE)
+    }
+
+    /// Wrap the given `Store`, and yield to the executor after looking at every `batch` rows.
+    pub fn with_batch_size(store: Store<T, R>, batch: usize) -> AsyncStore<T, R> {
+        assert!(batch > 0);
+        AsyncStore { store, batch }
+    }
+
+    /// Returns the wrapped `Store`.
+    pub fn into_inner(self) -> Store<T, R> {
+        self.store
+    }
+
+    /// Returns a mutable reference to the wrapped `Store`, for changes that are quick to make,
+    /// such as adding an index to an empty `Store`.
+    pub fn get_mut(&mut self) -> &mut Store<T, R> {
+        &mut self.store
+    }
+
+    /// Returns a `Stream` of all rows matching all the given `Condition`s. See `Store::find`.
+    pub fn find<'c, 's: 'c>(&'s self,
+                            conds: &'c [cmp::Condition<'c, T>])
+                            -> FindStream<'s, 'c, T, R> {
+        FindStream {
+            store: &self.store,
+            conds,
+            rowids: self.store.using_index(conds),
+            batch: self.batch,
+        }
+    }
+
+    /// Insert a new data row, and return the rowid assigned to it. See `Store::insert`. Since
+    /// inserting a row does not take long, the returned future is always ready right away.
+    pub fn insert(&mut self, row: R) -> Insert {
+        Insert(Some(self.store.insert(row)))
+    }
+
+    /// Delete all rows that match the given conditions. The returned future resolves to the
+    /// number of rows deleted. See `Store::delete`.
+    ///
+    /// The candidate rows are collected when `delete` is called, and are then checked against the
+    /// conditions and deleted a batch at a time. Since the future holds on to the `Store` until it
+    /// is done, no rows can be inserted or changed in the meantime.
+    pub fn delete<'a, 'c>(&'a mut self,
+                          conds: &'c [cmp::Condition<'c, T>])
+                          -> Delete<'a, 'c, T, R> {
+        Delete(Batched::new(&mut self.store, conds, self.batch))
+    }
+
+    /// Update all rows that match the given conditions using the given function. The returned
+    /// future resolves to the number of rows updated. See `Store::update`.
+    ///
+    /// # Panics
+    ///
+    /// Polling the future panics if an updated row has the same value in a uniquely indexed
+    /// column as another row. Since the rows are updated a batch at a time, the rows updated
+    /// before it stay updated.
+    pub fn update<'a, 'c, F>(&'a mut self,
+                             conds: &'c [cmp::Condition<'c, T>],
+                             f: F)
+                             -> Update<'a, 'c, T, R, F>
+        where F: FnMut(&mut R) + Unpin,
+              R: Clone
+    {
+        Update {
+            batched: Batched::new(&mut self.store, conds, self.batch),
+            f,
+        }
+    }
+}
+
+impl<T, R> ops::Deref for AsyncStore<T, R> {
+    type Target = Store<T, R>;
+    fn deref(&self) -> &Store<T, R> {
+        &self.store
+    }
+}
+
+/// Wake the task up again right away, so that the executor can run other tasks in the meantime.
+fn yield_now<O>(cx: &mut Context) -> Poll<O> {
+    cx.waker().wake_by_ref();
+    Poll::Pending
+}
+
+/// A `Stream` of the rows that match a query. See `AsyncStore::find`.
+pub struct FindStream<'s, 'c, T: Clone + 'c, R: 's> {
+    store: &'s Store<T, R>,
+    conds: &'c [cmp::Condition<'c, T>],
+    rowids: Candidates<'s, R>,
+    batch: usize,
+}
+
+impl<'s, 'c, T, R> Stream for FindStream<'s, 'c, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    type Item = &'s R;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        for _ in 0..this.batch {
+            let row = match this.rowids.next() {
+                Some(rowid) => &this.store.rows[&rowid],
+                None => return Poll::Ready(None),
+            };
+            if this.conds.iter().all(|c| c.matches(row)) {
+                return Poll::Ready(Some(row));
+            }
+        }
+        yield_now(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.rowids.size_hint().1)
+    }
+}
+
+/// A future that is ready right away. See `AsyncStore::insert`.
+pub struct Insert(Option<usize>);
+
+impl Future for Insert {
+    type Output = usize;
+    fn poll(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<usize> {
+        Poll::Ready(self.0.take().expect("polled after completion"))
+    }
+}
+
+/// The rows matching a query, found and then changed a batch at a time.
+struct Batched<'a, 'c, T: Clone + 'c, R: 'a> {
+    store: &'a mut Store<T, R>,
+    conds: &'c [cmp::Condition<'c, T>],
+    /// The candidate rows that have yet to be checked against the conditions.
+    candidates: vec::IntoIter<usize>,
+    /// The matching rows that have yet to be changed.
+    matched: Vec<usize>,
+    changed: usize,
+    batch: usize,
+}
+
+impl<'a, 'c, T, R> Batched<'a, 'c, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    fn new(store: &'a mut Store<T, R>,
+           conds: &'c [cmp::Condition<'c, T>],
+           batch: usize)
+           -> Batched<'a, 'c, T, R> {
+        let candidates = store.using_index(conds).collect::<Vec<_>>().into_iter();
+        Batched {
+            store,
+            conds,
+            candidates,
+            matched: Vec::new(),
+            changed: 0,
+            batch,
+        }
+    }
+
+    /// Check the next batch of candidate rows against the conditions, and then change the
+    /// matching ones with the given function. Returns the total number of rows changed once all
+    /// the candidates have been checked.
+    fn poll_with<F>(&mut self, cx: &mut Context, mut change: F) -> Poll<usize>
+        where F: FnMut(&mut Store<T, R>, usize)
+    {
+        let store = &*self.store;
+        let conds = self.conds;
+        let matched = self.candidates
+            .by_ref()
+            .take(self.batch)
+            .filter(|rowid| match store.rows.get(rowid) {
+                Some(row) => conds.iter().all(|c| c.matches(row)),
+                // deleted by an earlier batch of an update or delete on the same rows
+                None => false,
+            });
+        self.matched.extend(matched);
+        for rowid in self.matched.drain(..) {
+            change(self.store, rowid);
+            self.changed += 1;
+        }
+        if self.candidates.len() == 0 {
+            return Poll::Ready(self.changed);
+        }
+        yield_now(cx)
+    }
+}
+
+/// A future that deletes the rows matching a query. See `AsyncStore::delete`.
+pub struct Delete<'a, 'c, T: Clone + 'c, R: 'a>(Batched<'a, 'c, T, R>);
+
+impl<'a, 'c, T, R> Future for Delete<'a, 'c, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    type Output = usize;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<usize> {
+        self.get_mut().0.poll_with(cx, |store, rowid| {
+            store.remove(rowid);
+        })
+    }
+}
+
+/// A future that updates the rows matching a query. See `AsyncStore::update`.
+pub struct Update<'a, 'c, T: Clone + 'c, R: 'a, F> {
+    batched: Batched<'a, 'c, T, R>,
+    f: F,
+}
+
+impl<'a, 'c, T, R, F> Future for Update<'a, 'c, T, R, F>
+    where T: Ord + Clone,
+          R: Row<T> + Clone,
+          F: FnMut(&mut R) + Unpin
+{
+    type Output = usize;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<usize> {
+        let this = self.get_mut();
+        let f = &mut this.f;
+        this.batched.poll_with(cx, |store, rowid| {
+            let mut row = store.rows[&rowid].clone();
+            f(&mut row);
+            store.replace(rowid, row);
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp::{Comparison, Condition, Value};
+    use idx::HashIndex;
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+
+    /// A waker that does nothing, since the tests poll in a loop until the future is done anyway.
+    struct Noop;
+
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    /// Poll the given future to completion, and return its output along with the number of times
+    /// it yielded.
+    fn block_on<F: Future + Unpin>(mut f: F) -> (F::Output, usize) {
+        let waker = Waker::from(Arc::new(Noop));
+        let mut cx = Context::from_waker(&waker);
+        let mut yields = 0;
+        loop {
+            match Pin::new(&mut f).poll(&mut cx) {
+                Poll::Ready(out) => return (out, yields),
+                Poll::Pending => yields += 1,
+            }
+        }
+    }
+
+    #[test]
+    fn async_store() {
+        let mut store = Store::new(2);
+        store.index(1, HashIndex::new());
+        for i in 0..100 {
+            store.insert(vec![i, i % 10]);
+        }
+        let mut store = AsyncStore::with_batch_size(store, 8);
+        let cmp = [Condition {
+                       column: 0,
+                       cmp: Comparison::Equal(Value::new(95)),
+                   }];
+
+        let waker = Waker::from(Arc::new(Noop));
+        let mut cx = Context::from_waker(&waker);
+        let mut rows = Vec::new();
+        let mut yields = 0;
+        {
+            let mut stream = store.find(&cmp);
+            loop {
+                match Pin::new(&mut stream).poll_next(&mut cx) {
+                    Poll::Ready(Some(row)) => rows.push(row.clone()),
+                    Poll::Ready(None) => break,
+                    Poll::Pending => yields += 1,
+                }
+            }
+        }
+        assert_eq!(rows, vec![vec![95, 5]]);
+        assert_eq!(yields, 11);
+
+        assert_eq!(block_on(store.insert(vec![100, 0])), (100, 0));
+        let cmp = [Condition {
+                       column: 1,
+                       cmp: Comparison::Equal(Value::new(0)),
+                   }];
+        assert_eq!(block_on(store.update(&cmp, |row| row[1] = 10)).0, 11);
+        assert_eq!(store.find(&cmp).size_hint(), (0, Some(0)));
+        let cmp = [Condition {
+                       column: 0,
+                       cmp: Comparison::GreaterEqual(Value::new(50)),
+                   }];
+        assert_eq!(block_on(store.delete(&cmp)), (51, 12));
+        assert_eq!(store.len(), 50);
+        assert_eq!(store.into_inner().len(), 50);
+    }
+}
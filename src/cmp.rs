@@ -1,7 +1,14 @@
+use idx;
+use MaybeSync;
 use Row;
+use Store;
 use std::fmt;
 use std::borrow::Cow;
 use std::borrow::Borrow;
+use std::iter;
+use std::ops::Bound;
+use std::option;
+use std::sync::Arc;
 
 /// A value represents something to compare against.
 #[derive(Clone, Debug)]
@@ -12,16 +19,26 @@ pub enum Value<'a, T: Clone + 'a> {
     /// A different column for the same row. Note that comparisons of this kind *cannot use an
     /// index*, at least not in the current implementation.
     Column(usize),
+
+    /// A placeholder for the parameter at the given position, which is only given a value when a
+    /// prepared query is executed. See `Store::prepare`. Conditions that compare against a
+    /// `Param` can only be run through a prepared query; `Store` rejects them everywhere else.
+    Param(usize),
 }
 
 impl<'a, T: Clone + 'a> Value<'a, T> {
     /// Extract the value literal for this `Value` when evaluated for the given row.
     /// For `Const` values, this evaluates to the `Const` value itself. For `Column`, it evaluates
     /// to the value of that column in the given row.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is a `Param`, since it has no value until it is bound (see `bind`).
     pub fn value<'b: 'a, R: Row<T> + ?Sized>(&'b self, row: &'b R) -> &'b T {
         match *self {
             Value::Column(i) => &row.index(i),
             Value::Const(ref val) => val,
+            Value::Param(i) => panic!("parameter {} was not bound to a value", i),
         }
     }
 
@@ -39,6 +56,293 @@ impl<'a, T: Clone + 'a> Value<'a, T> {
     pub fn column(c: usize) -> Self {
         Value::Column(c)
     }
+
+    /// Construct a new `Value` that is a placeholder for the parameter at the given position.
+    pub fn param(i: usize) -> Self {
+        Value::Param(i)
+    }
+
+    /// Returns true if this is a `Const` value.
+    pub fn is_const(&self) -> bool {
+        match *self {
+            Value::Const(..) => true,
+            Value::Column(..) | Value::Param(..) => false,
+        }
+    }
+
+    /// Returns a copy of this `Value` where a `Param` is replaced by the corresponding value in
+    /// `params`. `Const` values are borrowed rather than cloned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is a `Param` whose position is out of bounds for `params`.
+    pub fn bind<'b>(&'b self, params: &'b [T]) -> Value<'b, T> {
+        match *self {
+            Value::Const(ref val) => Value::Const(Cow::Borrowed(&**val)),
+            Value::Column(c) => Value::Column(c),
+            Value::Param(i) => Value::Const(Cow::Borrowed(&params[i])),
+        }
+    }
+}
+
+/// The `Value`s a `Comparison` compares against. See `Comparison::values`.
+pub(crate) type Values<'b, 'a, T> = iter::Chain<option::IntoIter<&'b Value<'a, T>>,
+                                                option::IntoIter<&'b Value<'a, T>>>;
+
+/// An arbitrary, user-provided test to perform on a value. See `Comparison::Predicate`.
+pub struct Predicate<'a, T: 'a>(Test<'a, T>);
+
+// without the `sync` feature, tests need not be thread-safe, so they can capture `Rc`s.
+#[cfg(feature = "sync")]
+type Test<'a, T> = Arc<dyn Fn(&T) -> bool + Send + Sync + 'a>;
+#[cfg(not(feature = "sync"))]
+type Test<'a, T> = Arc<dyn Fn(&T) -> bool + 'a>;
+
+impl<'a, T: 'a> Predicate<'a, T> {
+    /// Construct a new `Predicate` that holds for values for which the given function returns
+    /// true.
+    pub fn new<F>(f: F) -> Self
+        where F: Fn(&T) -> bool + MaybeSync + 'a
+    {
+        Predicate(Arc::new(f))
+    }
+
+    /// Returns true if this predicate holds for the given value.
+    pub fn test(&self, value: &T) -> bool {
+        (self.0)(value)
+    }
+}
+
+impl<'a, T: 'a> Clone for Predicate<'a, T> {
+    fn clone(&self) -> Self {
+        Predicate(self.0.clone())
+    }
+}
+
+impl<'a, T: 'a> fmt::Debug for Predicate<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Predicate")
+    }
+}
+
+/// A membership test against a column of another `Store`. See `Comparison::InStore`.
+///
+/// This lets one `Store` be filtered by whether each row's value appears in another `Store` (a
+/// semi-join). Each membership check is an equality lookup in the other `Store`, and so uses its
+/// index on the given column if it has one.
+pub struct InStore<'a, T: 'a> {
+    column: usize,
+    contains: Test<'a, T>,
+}
+
+impl<'a, T: 'a> InStore<'a, T> {
+    /// Construct a new `InStore` that holds for values that appear in the given column of any row
+    /// of the given `Store`.
+    pub fn new<R>(store: &'a Store<T, R>, column: usize) -> Self
+        where T: Ord + Clone + MaybeSync,
+              R: Row<T> + MaybeSync
+    {
+        InStore {
+            column,
+            contains: Arc::new(move |v| {
+                store.exists(&[Condition {
+                                   column,
+                                   cmp: Comparison::Equal(Value::using(v)),
+                               }])
+            }),
+        }
+    }
+
+    /// Returns the column of the other `Store` that values are looked up in.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns true if the given value appears in the other `Store`.
+    pub fn contains(&self, value: &T) -> bool {
+        (self.contains)(value)
+    }
+}
+
+impl<'a, T: 'a> Clone for InStore<'a, T> {
+    fn clone(&self) -> Self {
+        InStore {
+            column: self.column,
+            contains: self.contains.clone(),
+        }
+    }
+}
+
+impl<'a, T: 'a> fmt::Debug for InStore<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InStore").field("column", &self.column).finish()
+    }
+}
+
+/// A string affix (prefix or suffix) to compare values against. See `Comparison::StartsWith` and
+/// `Comparison::EndsWith`. It is also used for whole-string comparisons that need to view values
+/// as text, such as `Comparison::EqualIgnoreCase`.
+///
+/// Since the values in a `Store` are not necessarily strings, an `Affix` also remembers how to
+/// view values of type `T` as a `str`. This is why it can only be constructed for `T: AsRef<str>`.
+#[derive(Clone, Debug)]
+pub struct Affix<'a, T: Clone + 'a> {
+    value: Value<'a, T>,
+    pub(crate) as_text: fn(&T) -> &str,
+}
+
+fn as_text<T: AsRef<str>>(t: &T) -> &str {
+    t.as_ref()
+}
+
+impl<'a, T: Clone + 'a> Affix<'a, T> {
+    /// Construct a new `Affix` from the given `Value`.
+    ///
+    /// Note that `Comparison::StartsWith` uses a `RangeIndex` to find values with a given prefix.
+    /// This assumes that `T` is ordered the same way as its `str` representation.
+    pub fn new(value: Value<'a, T>) -> Self
+        where T: AsRef<str>
+    {
+        Affix {
+            value,
+            as_text: as_text::<T>,
+        }
+    }
+
+    /// The `Value` holding this affix.
+    pub fn value(&self) -> &Value<'a, T> {
+        &self.value
+    }
+
+    /// View the given value as a `str`.
+    pub fn text<'b>(&self, t: &'b T) -> &'b str {
+        (self.as_text)(t)
+    }
+}
+
+/// A single word to search for in text values. See `Comparison::ContainsTerm`.
+///
+/// Values are split into terms using `idx::tokenize`, and a value contains the term if any of its
+/// terms is equal to it. Like `Affix`, a `Term` remembers how to view values of type `T` as a
+/// `str`, and so can only be constructed for `T: AsRef<str>`.
+#[derive(Clone, Debug)]
+pub struct Term<T> {
+    term: String,
+    as_text: fn(&T) -> &str,
+}
+
+impl<T> Term<T> {
+    /// Construct a new `Term` for the given word. Terms are case-insensitive.
+    pub fn new(term: &str) -> Self
+        where T: AsRef<str>
+    {
+        Term {
+            term: term.to_lowercase(),
+            as_text: as_text::<T>,
+        }
+    }
+
+    /// The (lowercased) word to search for.
+    pub fn term(&self) -> &str {
+        &self.term
+    }
+
+    /// Returns true if the given value contains this term.
+    pub fn is_contained_in(&self, t: &T) -> bool {
+        idx::tokenize((self.as_text)(t)).any(|term| term == self.term)
+    }
+}
+
+/// A value type that has a distinguished null value, such as `Option<U>`. See `Comparison::IsNull`
+/// and `idx::SkipNull`.
+pub trait Nullable {
+    /// Returns the null value.
+    fn null() -> Self;
+
+    /// Returns true if this is the null value.
+    fn is_null(&self) -> bool;
+}
+
+impl<U> Nullable for Option<U> {
+    fn null() -> Self {
+        None
+    }
+
+    fn is_null(&self) -> bool {
+        self.is_none()
+    }
+}
+
+/// A test for null values. See `Comparison::IsNull` and `Comparison::IsNotNull`.
+///
+/// Like `Affix`, a `Null` remembers how to test values of type `T` for null, and so can only be
+/// constructed for `T: Nullable`.
+#[derive(Clone, Debug)]
+pub struct Null<T> {
+    null: fn() -> T,
+    is_null: fn(&T) -> bool,
+}
+
+impl<T> Null<T> {
+    /// Construct a new `Null` test.
+    pub fn new() -> Self
+        where T: Nullable
+    {
+        Null {
+            null: T::null,
+            is_null: T::is_null,
+        }
+    }
+
+    /// Returns the null value.
+    pub fn null(&self) -> T {
+        (self.null)()
+    }
+
+    /// Returns true if the given value is null.
+    pub fn test(&self, t: &T) -> bool {
+        (self.is_null)(t)
+    }
+}
+
+impl<T: Nullable> Default for Null<T> {
+    fn default() -> Self {
+        Null::new()
+    }
+}
+
+/// A regular expression to match values against. See `Comparison::Matches`.
+///
+/// Like `Affix`, a `Pattern` remembers how to view values of type `T` as a `str`, and so can only
+/// be constructed for `T: AsRef<str>`.
+#[cfg(feature = "regex")]
+#[derive(Clone, Debug)]
+pub struct Pattern<T> {
+    regex: ::regex::Regex,
+    as_text: fn(&T) -> &str,
+}
+
+#[cfg(feature = "regex")]
+impl<T> Pattern<T> {
+    /// Construct a new `Pattern` from the given regular expression.
+    pub fn new(regex: ::regex::Regex) -> Self
+        where T: AsRef<str>
+    {
+        Pattern {
+            regex,
+            as_text: as_text::<T>,
+        }
+    }
+
+    /// The regular expression this pattern matches against.
+    pub fn regex(&self) -> &::regex::Regex {
+        &self.regex
+    }
+
+    /// Returns true if the given value matches this pattern.
+    pub fn is_match(&self, t: &T) -> bool {
+        self.regex.is_match((self.as_text)(t))
+    }
 }
 
 /// A comparison to perform for a literal value against a `Value`.
@@ -46,14 +350,174 @@ impl<'a, T: Clone + 'a> Value<'a, T> {
 pub enum Comparison<'a, T: Clone + 'a> {
     /// Is the value equal to the given `Value`?
     Equal(Value<'a, T>),
+
+    /// Is the value *not* equal to the given `Value`? Note that comparisons of this kind *cannot
+    /// use an index*, since they generally match most of the rows in the dataset.
+    NotEqual(Value<'a, T>),
+
+    /// Is the value strictly less than the given `Value`?
+    Less(Value<'a, T>),
+
+    /// Is the value strictly greater than the given `Value`?
+    Greater(Value<'a, T>),
+
+    /// Is the value less than or equal to the given `Value`?
+    LessEqual(Value<'a, T>),
+
+    /// Is the value greater than or equal to the given `Value`?
+    GreaterEqual(Value<'a, T>),
+
+    /// Does the value lie between the two given bounds? The first bound is the lower bound, and
+    /// the second the upper bound.
+    Between(Bound<Value<'a, T>>, Bound<Value<'a, T>>),
+
+    /// Does the value satisfy the given `Predicate`? This allows filtering on conditions that the
+    /// other comparisons cannot express. Note that comparisons of this kind *cannot use an index*,
+    /// though other conditions in the same query still can.
+    Predicate(Predicate<'a, T>),
+
+    /// Does the value appear in another `Store`? Note that comparisons of this kind *cannot use an
+    /// index* on this `Store`, though each membership check can use an index on the other one.
+    InStore(InStore<'a, T>),
+
+    /// Is the value equal to the given `Affix`, ignoring case? Comparisons of this kind can use an
+    /// index that is itself case-insensitive, such as `idx::CaseInsensitive`.
+    EqualIgnoreCase(Affix<'a, T>),
+
+    /// Does the value start with the given `Affix`?
+    StartsWith(Affix<'a, T>),
+
+    /// Does the value end with the given `Affix`? Note that comparisons of this kind *cannot use
+    /// an index*.
+    EndsWith(Affix<'a, T>),
+
+    /// Does the value contain the given `Term`? Comparisons of this kind can use an index that
+    /// supports term lookups, such as `idx::TextIndex`.
+    ContainsTerm(Term<T>),
+
+    /// Is the value null? Comparisons of this kind can use an index that keeps track of null
+    /// values, such as `idx::SkipNull`, or look up the null value in any other index.
+    IsNull(Null<T>),
+
+    /// Is the value *not* null? Comparisons of this kind can use a `RangeIndex`, which is most
+    /// useful if it keeps track of null values, such as `idx::SkipNull`.
+    IsNotNull(Null<T>),
+
+    /// Does the value match the given regular expression `Pattern`? Note that comparisons of this
+    /// kind *cannot use an index*.
+    #[cfg(feature = "regex")]
+    Matches(Pattern<T>),
 }
 
 impl<'a, T: Ord + Clone + 'a> Comparison<'a, T> {
+    /// Returns a copy of this comparison where every `Value::Param` is replaced by the
+    /// corresponding value in `params`. See `Value::bind`.
+    pub fn bind<'b>(&'b self, params: &'b [T]) -> Comparison<'b, T> {
+        let bound = |b: &'b Bound<Value<'a, T>>| match *b {
+            Bound::Included(ref v) => Bound::Included(v.bind(params)),
+            Bound::Excluded(ref v) => Bound::Excluded(v.bind(params)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let affix = |a: &'b Affix<'a, T>| {
+            Affix {
+                value: a.value.bind(params),
+                as_text: a.as_text,
+            }
+        };
+        match *self {
+            Comparison::Equal(ref v) => Comparison::Equal(v.bind(params)),
+            Comparison::NotEqual(ref v) => Comparison::NotEqual(v.bind(params)),
+            Comparison::Less(ref v) => Comparison::Less(v.bind(params)),
+            Comparison::Greater(ref v) => Comparison::Greater(v.bind(params)),
+            Comparison::LessEqual(ref v) => Comparison::LessEqual(v.bind(params)),
+            Comparison::GreaterEqual(ref v) => Comparison::GreaterEqual(v.bind(params)),
+            Comparison::Between(ref min, ref max) => Comparison::Between(bound(min), bound(max)),
+            Comparison::Predicate(ref p) => Comparison::Predicate(p.clone()),
+            Comparison::InStore(ref s) => Comparison::InStore(s.clone()),
+            Comparison::EqualIgnoreCase(ref a) => Comparison::EqualIgnoreCase(affix(a)),
+            Comparison::StartsWith(ref a) => Comparison::StartsWith(affix(a)),
+            Comparison::EndsWith(ref a) => Comparison::EndsWith(affix(a)),
+            Comparison::ContainsTerm(ref t) => Comparison::ContainsTerm(t.clone()),
+            Comparison::IsNull(ref n) => Comparison::IsNull(n.clone()),
+            Comparison::IsNotNull(ref n) => Comparison::IsNotNull(n.clone()),
+            #[cfg(feature = "regex")]
+            Comparison::Matches(ref p) => Comparison::Matches(p.clone()),
+        }
+    }
+
+    /// Returns the `Value`s this comparison compares against. There are at most two.
+    pub(crate) fn values<'b>(&'b self) -> Values<'b, 'a, T> {
+        let bound = |b: &'b Bound<Value<'a, T>>| match *b {
+            Bound::Included(ref v) | Bound::Excluded(ref v) => Some(v),
+            Bound::Unbounded => None,
+        };
+        let (first, second) = match *self {
+            Comparison::Equal(ref v) |
+            Comparison::NotEqual(ref v) |
+            Comparison::Less(ref v) |
+            Comparison::Greater(ref v) |
+            Comparison::LessEqual(ref v) |
+            Comparison::GreaterEqual(ref v) => (Some(v), None),
+            Comparison::Between(ref min, ref max) => (bound(min), bound(max)),
+            Comparison::StartsWith(ref a) |
+            Comparison::EndsWith(ref a) |
+            Comparison::EqualIgnoreCase(ref a) => (Some(a.value()), None),
+            _ => (None, None),
+        };
+        first.into_iter().chain(second)
+    }
+
+    /// Returns true if this comparison only compares against constants, and so does not depend on
+    /// the values in any other columns of the row.
+    pub fn is_const(&self) -> bool {
+        self.values().all(|v| v.is_const())
+    }
+
+    /// Returns true if this comparison compares against a `Value::Param` placeholder.
+    pub(crate) fn has_params(&self) -> bool {
+        self.values().any(|v| match *v {
+            Value::Param(..) => true,
+            Value::Const(..) | Value::Column(..) => false,
+        })
+    }
+
     /// Returns true if the given value compares successfully against this `Value` when evaluated
     /// against the given row.
     pub fn matches<R: Row<T> + ?Sized>(&self, value: &T, row: &R) -> bool {
         match *self {
             Comparison::Equal(ref v) => value == v.value(row),
+            Comparison::NotEqual(ref v) => value != v.value(row),
+            Comparison::Less(ref v) => value < v.value(row),
+            Comparison::Greater(ref v) => value > v.value(row),
+            Comparison::LessEqual(ref v) => value <= v.value(row),
+            Comparison::GreaterEqual(ref v) => value >= v.value(row),
+            Comparison::Between(ref min, ref max) => {
+                let above = match *min {
+                    Bound::Included(ref v) => value >= v.value(row),
+                    Bound::Excluded(ref v) => value > v.value(row),
+                    Bound::Unbounded => true,
+                };
+                let below = match *max {
+                    Bound::Included(ref v) => value <= v.value(row),
+                    Bound::Excluded(ref v) => value < v.value(row),
+                    Bound::Unbounded => true,
+                };
+                above && below
+            }
+            Comparison::Predicate(ref p) => p.test(value),
+            Comparison::InStore(ref s) => s.contains(value),
+            Comparison::StartsWith(ref a) => {
+                a.text(value).starts_with(a.text(a.value().value(row)))
+            }
+            Comparison::EndsWith(ref a) => a.text(value).ends_with(a.text(a.value().value(row))),
+            Comparison::ContainsTerm(ref t) => t.is_contained_in(value),
+            Comparison::IsNull(ref n) => n.test(value),
+            Comparison::IsNotNull(ref n) => !n.test(value),
+            Comparison::EqualIgnoreCase(ref a) => {
+                a.text(value).to_lowercase() == a.text(a.value().value(row)).to_lowercase()
+            }
+            #[cfg(feature = "regex")]
+            Comparison::Matches(ref p) => p.is_match(value),
         }
     }
 }
@@ -74,6 +538,105 @@ impl<'a, T: Ord + Clone + 'a> Condition<'a, T> {
     pub fn matches<R: Row<T> + ?Sized>(&self, row: &R) -> bool {
         self.cmp.matches(&row.index(self.column), row)
     }
+
+    /// Returns a copy of this condition where every `Value::Param` is replaced by the
+    /// corresponding value in `params`. See `Value::bind`.
+    pub fn bind<'b>(&'b self, params: &'b [T]) -> Condition<'b, T> {
+        Condition {
+            column: self.column,
+            cmp: self.cmp.bind(params),
+        }
+    }
+}
+
+/// A comparison to perform for a tuple of values against a tuple of `Value`s.
+#[derive(Clone, Debug)]
+pub enum TupleComparison<'a, T: Clone + 'a> {
+    /// Is each value equal to the corresponding `Value`?
+    Equal(Vec<Value<'a, T>>),
+}
+
+impl<'a, T: Ord + Clone + 'a> TupleComparison<'a, T> {
+    /// Returns true if the given values compare successfully against this comparison's `Value`s
+    /// when evaluated against the given row. If there are more or fewer values than `Value`s, the
+    /// comparison does not hold.
+    pub fn matches<'b, I, R>(&self, values: I, row: &R) -> bool
+        where I: IntoIterator<Item = &'b T>,
+              R: Row<T> + ?Sized,
+              T: 'b
+    {
+        match *self {
+            TupleComparison::Equal(ref vs) => {
+                let mut values = values.into_iter();
+                let mut vs = vs.iter();
+                loop {
+                    match (values.next(), vs.next()) {
+                        (Some(a), Some(b)) if a == b.value(row) => {}
+                        (None, None) => return true,
+                        _ => return false,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A single condition over several columns of a row in the dataset, such as `(col0, col1) = (a,
+/// b)`.
+#[derive(Clone, Debug)]
+pub struct TupleCondition<'a, T: Clone + 'a> {
+    /// The columns of the row to use as the comparison values, in order.
+    pub columns: Vec<usize>,
+
+    /// The comparison to perform on the selected values. It must have as many `Value`s as there
+    /// are `columns`.
+    pub cmp: TupleComparison<'a, T>,
+}
+
+impl<'a, T: Ord + Clone + 'a> TupleCondition<'a, T> {
+    /// Returns true if this condition holds true for the given row.
+    pub fn matches<R: Row<T> + ?Sized>(&self, row: &R) -> bool {
+        self.cmp.matches(self.columns.iter().map(|&c| row.index(c)), row)
+    }
+}
+
+/// A boolean combination of `Condition`s to evaluate for a row in the dataset.
+#[derive(Clone, Debug)]
+pub enum ConditionExpr<'a, T: Clone + 'a> {
+    /// Holds if the given `Condition` holds.
+    Leaf(Condition<'a, T>),
+
+    /// Holds if the given `TupleCondition` holds.
+    Tuple(TupleCondition<'a, T>),
+
+    /// Holds if *all* the given expressions hold. An empty `And` always holds.
+    And(Vec<ConditionExpr<'a, T>>),
+
+    /// Holds if *any* of the given expressions hold. An empty `Or` never holds.
+    Or(Vec<ConditionExpr<'a, T>>),
+
+    /// Holds if the given expression does *not* hold. Note that expressions of this kind *cannot
+    /// use an index*.
+    Not(Box<ConditionExpr<'a, T>>),
+}
+
+impl<'a, T: Ord + Clone + 'a> ConditionExpr<'a, T> {
+    /// Returns true if this expression holds true for the given row.
+    pub fn matches<R: Row<T> + ?Sized>(&self, row: &R) -> bool {
+        match *self {
+            ConditionExpr::Leaf(ref c) => c.matches(row),
+            ConditionExpr::Tuple(ref c) => c.matches(row),
+            ConditionExpr::And(ref es) => es.iter().all(|e| e.matches(row)),
+            ConditionExpr::Or(ref es) => es.iter().any(|e| e.matches(row)),
+            ConditionExpr::Not(ref e) => !e.matches(row),
+        }
+    }
+}
+
+impl<'a, T: Clone + 'a> From<Condition<'a, T>> for ConditionExpr<'a, T> {
+    fn from(c: Condition<'a, T>) -> Self {
+        ConditionExpr::Leaf(c)
+    }
 }
 
 impl<'a, T: fmt::Display + Clone + 'a> fmt::Display for Value<'a, T> {
@@ -81,6 +644,7 @@ impl<'a, T: fmt::Display + Clone + 'a> fmt::Display for Value<'a, T> {
         match *self {
             Value::Column(i) => write!(f, "[{}]", i),
             Value::Const(ref val) => write!(f, "{}", val),
+            Value::Param(i) => write!(f, "${}", i),
         }
     }
 }
@@ -89,6 +653,33 @@ impl<'a, T: fmt::Display + Clone + 'a> fmt::Display for Comparison<'a, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Comparison::Equal(ref v) => write!(f, "= {}", v),
+            Comparison::NotEqual(ref v) => write!(f, "!= {}", v),
+            Comparison::Less(ref v) => write!(f, "< {}", v),
+            Comparison::Greater(ref v) => write!(f, "> {}", v),
+            Comparison::LessEqual(ref v) => write!(f, "<= {}", v),
+            Comparison::GreaterEqual(ref v) => write!(f, ">= {}", v),
+            Comparison::Between(ref min, ref max) => {
+                match *min {
+                    Bound::Included(ref v) => write!(f, "in [{}, ", v)?,
+                    Bound::Excluded(ref v) => write!(f, "in ({}, ", v)?,
+                    Bound::Unbounded => write!(f, "in (.., ")?,
+                }
+                match *max {
+                    Bound::Included(ref v) => write!(f, "{}]", v),
+                    Bound::Excluded(ref v) => write!(f, "{})", v),
+                    Bound::Unbounded => write!(f, "..)"),
+                }
+            }
+            Comparison::Predicate(..) => write!(f, "matches <predicate>"),
+            Comparison::InStore(ref s) => write!(f, "in <store>[{}]", s.column()),
+            Comparison::StartsWith(ref a) => write!(f, "starts with {}", a.value()),
+            Comparison::EndsWith(ref a) => write!(f, "ends with {}", a.value()),
+            Comparison::ContainsTerm(ref t) => write!(f, "contains term {}", t.term()),
+            Comparison::IsNull(..) => write!(f, "is null"),
+            Comparison::IsNotNull(..) => write!(f, "is not null"),
+            Comparison::EqualIgnoreCase(ref a) => write!(f, "= {} (ignoring case)", a.value()),
+            #[cfg(feature = "regex")]
+            Comparison::Matches(ref p) => write!(f, "matches /{}/", p.regex()),
         }
     }
 }
@@ -99,6 +690,49 @@ impl<'a, T: fmt::Display + Clone + 'a> fmt::Display for Condition<'a, T> {
     }
 }
 
+impl<'a, T: fmt::Display + Clone + 'a> fmt::Display for TupleCondition<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (op, vs) = match self.cmp {
+            TupleComparison::Equal(ref vs) => ("=", vs),
+        };
+        write!(f, "(")?;
+        for (i, c) in self.columns.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "[{}]", c)?;
+        }
+        write!(f, ") {} (", op)?;
+        for (i, v) in vs.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", v)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl<'a, T: fmt::Display + Clone + 'a> fmt::Display for ConditionExpr<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (es, op) = match *self {
+            ConditionExpr::Leaf(ref c) => return write!(f, "{}", c),
+            ConditionExpr::Tuple(ref c) => return write!(f, "{}", c),
+            ConditionExpr::Not(ref e) => return write!(f, "NOT {}", e),
+            ConditionExpr::And(ref es) => (es, " AND "),
+            ConditionExpr::Or(ref es) => (es, " OR "),
+        };
+        write!(f, "(")?;
+        for (i, e) in es.iter().enumerate() {
+            if i != 0 {
+                write!(f, "{}", op)?;
+            }
+            write!(f, "{}", e)?;
+        }
+        write!(f, ")")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,6 +755,120 @@ mod tests {
         assert!(!Comparison::Equal(Value::new("b")).matches(&"a", &a[..]));
     }
 
+    #[test]
+    fn cmp_neq() {
+        let a = &["a"];
+        let b = &["b"];
+        assert!(!Comparison::NotEqual(Value::column(0)).matches(&"a", &a[..]));
+        assert!(Comparison::NotEqual(Value::column(0)).matches(&"a", &b[..]));
+        assert!(!Comparison::NotEqual(Value::new("a")).matches(&"a", &b[..]));
+        assert!(Comparison::NotEqual(Value::new("b")).matches(&"a", &a[..]));
+    }
+
+    #[test]
+    fn cmp_ord() {
+        let a = &["a"];
+        let b = &["b"];
+        assert!(Comparison::Less(Value::column(0)).matches(&"a", &b[..]));
+        assert!(!Comparison::Less(Value::column(0)).matches(&"a", &a[..]));
+        assert!(Comparison::LessEqual(Value::column(0)).matches(&"a", &a[..]));
+        assert!(!Comparison::LessEqual(Value::new("a")).matches(&"b", &a[..]));
+        assert!(Comparison::Greater(Value::new("a")).matches(&"b", &a[..]));
+        assert!(!Comparison::Greater(Value::new("b")).matches(&"b", &a[..]));
+        assert!(Comparison::GreaterEqual(Value::new("b")).matches(&"b", &a[..]));
+        assert!(!Comparison::GreaterEqual(Value::column(0)).matches(&"a", &b[..]));
+    }
+
+    #[test]
+    fn cmp_between() {
+        use std::ops::Bound::*;
+        let a = &["a"];
+        let c = &["c"];
+        let incl = Comparison::Between(Included(Value::new("a")), Included(Value::column(0)));
+        assert!(incl.matches(&"a", &c[..]));
+        assert!(incl.matches(&"b", &c[..]));
+        assert!(incl.matches(&"c", &c[..]));
+        assert!(!incl.matches(&"b", &a[..]));
+
+        let excl = Comparison::Between(Excluded(Value::new("a")), Excluded(Value::new("c")));
+        assert!(!excl.matches(&"a", &a[..]));
+        assert!(excl.matches(&"b", &a[..]));
+        assert!(!excl.matches(&"c", &a[..]));
+
+        let open = Comparison::Between(Unbounded, Excluded(Value::new("c")));
+        assert!(open.matches(&"a", &a[..]));
+        assert!(!open.matches(&"c", &a[..]));
+    }
+
+    #[test]
+    fn cmp_predicate() {
+        let a = vec!["a".to_string()];
+        let len = 2;
+        let p = Comparison::Predicate(Predicate::new(|v: &String| v.len() == len));
+        assert!(p.matches(&"ab".to_string(), &a));
+        assert!(!p.matches(&"abc".to_string(), &a));
+        assert!(p.clone().matches(&"xy".to_string(), &a));
+    }
+
+    #[test]
+    fn cmp_affix() {
+        let a = &["ab"];
+        let sw = Comparison::StartsWith(Affix::new(Value::new("a")));
+        assert!(sw.matches(&"abc", &a[..]));
+        assert!(!sw.matches(&"bc", &a[..]));
+        assert!(Comparison::StartsWith(Affix::new(Value::column(0))).matches(&"abc", &a[..]));
+        assert!(!Comparison::StartsWith(Affix::new(Value::column(0))).matches(&"a", &a[..]));
+
+        let ew = Comparison::EndsWith(Affix::new(Value::new("bc")));
+        assert!(ew.matches(&"abc", &a[..]));
+        assert!(!ew.matches(&"ab", &a[..]));
+
+        let s = vec!["x".to_string()];
+        let sw = Comparison::StartsWith(Affix::new(Value::new("x")));
+        assert!(sw.matches(&"xy".to_string(), &s));
+        assert_eq!(format!("{}", sw), "starts with x");
+
+        let eq = Comparison::EqualIgnoreCase(Affix::new(Value::new("Ab")));
+        assert!(eq.matches(&"aB", &a[..]));
+        assert!(!eq.matches(&"abc", &a[..]));
+        assert!(Comparison::EqualIgnoreCase(Affix::new(Value::column(0))).matches(&"AB", &a[..]));
+        assert_eq!(format!("{}", eq), "= Ab (ignoring case)");
+    }
+
+    #[test]
+    fn cmp_term() {
+        let a = &["a"];
+        let ct = Comparison::ContainsTerm(Term::new("Quick"));
+        assert!(ct.matches(&"The quick brown fox", &a[..]));
+        assert!(ct.matches(&"QUICK!", &a[..]));
+        assert!(!ct.matches(&"quickly", &a[..]));
+        assert!(!ct.matches(&"", &a[..]));
+        assert_eq!(format!("{}", ct), "contains term quick");
+    }
+
+    #[test]
+    fn cmp_null() {
+        let a = &[Some(1)];
+        let null = Comparison::IsNull(Null::new());
+        let not_null = Comparison::IsNotNull(Null::new());
+        assert!(null.matches(&None, &a[..]));
+        assert!(!null.matches(&Some(0), &a[..]));
+        assert!(!not_null.matches(&None, &a[..]));
+        assert!(not_null.matches(&Some(0), &a[..]));
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn cmp_regex() {
+        use regex::Regex;
+        let a = &["a"];
+        let re = Comparison::Matches(Pattern::new(Regex::new("^a+b?$").unwrap()));
+        assert!(re.matches(&"aab", &a[..]));
+        assert!(re.matches(&"a", &a[..]));
+        assert!(!re.matches(&"ba", &a[..]));
+        assert_eq!(format!("{}", re), "matches /^a+b?$/");
+    }
+
     #[test]
     fn borrowed_values() {
         let a = vec!["a".to_string()];
@@ -172,6 +920,72 @@ mod tests {
         assert!(!ccb.matches(&a[..]));
     }
 
+    #[test]
+    fn expr() {
+        let ca = Condition {
+            column: 0,
+            cmp: Comparison::Equal(Value::new("a")),
+        };
+        let cb = Condition {
+            column: 0,
+            cmp: Comparison::Equal(Value::new("b")),
+        };
+        let cx = Condition {
+            column: 1,
+            cmp: Comparison::Equal(Value::new("x")),
+        };
+
+        let aor = ConditionExpr::Or(vec![ca.into(), cb.into()]);
+        let and = ConditionExpr::And(vec![aor.clone(), cx.into()]);
+
+        let ax = &["a", "x"];
+        let bx = &["b", "x"];
+        let by = &["b", "y"];
+        let cx = &["c", "x"];
+        assert!(aor.matches(&ax[..]));
+        assert!(aor.matches(&by[..]));
+        assert!(!aor.matches(&cx[..]));
+        assert!(and.matches(&ax[..]));
+        assert!(and.matches(&bx[..]));
+        assert!(!and.matches(&by[..]));
+        assert!(!and.matches(&cx[..]));
+
+        assert!(ConditionExpr::And::<&str>(vec![]).matches(&ax[..]));
+        assert!(!ConditionExpr::Or::<&str>(vec![]).matches(&ax[..]));
+
+        let not = ConditionExpr::Not(Box::new(and.clone()));
+        assert!(!not.matches(&ax[..]));
+        assert!(not.matches(&by[..]));
+        assert!(not.matches(&cx[..]));
+
+        assert_eq!(format!("{}", and), "(([0] = a OR [0] = b) AND [1] = x)");
+        assert_eq!(format!("{}", not), "NOT (([0] = a OR [0] = b) AND [1] = x)");
+    }
+
+    #[test]
+    fn tuple() {
+        let c = TupleCondition {
+            columns: vec![0, 1],
+            cmp: TupleComparison::Equal(vec![Value::new("a"), Value::column(2)]),
+        };
+        assert!(c.matches(&["a", "x", "x"][..]));
+        assert!(!c.matches(&["a", "x", "y"][..]));
+        assert!(!c.matches(&["b", "x", "x"][..]));
+        assert_eq!(format!("{}", c), "([0], [1]) = (a, [2])");
+
+        // tuples of different lengths are never equal
+        let short = TupleCondition {
+            columns: vec![0],
+            cmp: TupleComparison::Equal(vec![Value::new("a"), Value::new("x")]),
+        };
+        assert!(!short.matches(&["a", "x"][..]));
+        let long = TupleCondition {
+            columns: vec![0, 1],
+            cmp: TupleComparison::Equal(vec![Value::new("a")]),
+        };
+        assert!(!long.matches(&["a", "x"][..]));
+    }
+
     #[test]
     fn display() {
         let cf01: Condition<String> = Condition {
@@ -184,7 +998,25 @@ mod tests {
             cmp: Comparison::Equal::<&str>(Value::new("a")),
         };
 
+        let cnb = Condition {
+            column: 0,
+            cmp: Comparison::NotEqual::<&str>(Value::new("b")),
+        };
+
+        let cle = Condition {
+            column: 0,
+            cmp: Comparison::LessEqual::<&str>(Value::new("b")),
+        };
+
+        let cbt = Condition {
+            column: 0,
+            cmp: Comparison::Between::<&str>(Bound::Included(Value::new("a")), Bound::Unbounded),
+        };
+
         assert_eq!(format!("{}", cf01), "[0] = [1]");
-        assert_eq!(format!("{}", cca), "[0] = a")
+        assert_eq!(format!("{}", cca), "[0] = a");
+        assert_eq!(format!("{}", cnb), "[0] != b");
+        assert_eq!(format!("{}", cle), "[0] <= b");
+        assert_eq!(format!("{}", cbt), "[0] in [a, ..)")
     }
 }
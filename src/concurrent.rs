@@ -0,0 +1,404 @@
+use std::cell::{Cell, UnsafeCell};
+use std::ops;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use cmp;
+use Row;
+use Store;
+use UniqueViolation;
+
+/// A change made through a `WriteHandle` that has yet to be applied to the other copy of the
+/// `Store`.
+enum Op<R> {
+    Insert(R),
+    Remove(usize),
+    Replace(usize, R),
+}
+
+/// The two copies of the `Store`, and which one readers should currently use.
+///
+/// Readers only ever look at the published copy, and the writer only ever changes the other one.
+/// Before the writer starts changing a copy that was published, it waits for every reader that
+/// may still be looking at it to finish, as told by the readers' epoch counters.
+struct Shared<T, R> {
+    stores: [UnsafeCell<Store<T, R>>; 2],
+    published: AtomicUsize,
+    /// The epoch counter of each `ReadHandle`. A counter is odd while its reader holds a
+    /// `ReadGuard`, and is incremented every time the reader starts and stops reading.
+    epochs: Mutex<Vec<Arc<AtomicUsize>>>,
+}
+
+// the copies are only ever shared between threads as described for `Shared`, so that no copy is
+// changed while it is being read.
+unsafe impl<T, R> Sync for Shared<T, R> where Store<T, R>: Send + Sync {}
+
+impl<T, R> Shared<T, R> {
+    /// Register a new reader, and return its epoch counter.
+    fn register(&self) -> Arc<AtomicUsize> {
+        let epoch = Arc::new(AtomicUsize::new(0));
+        self.epochs.lock().unwrap().push(epoch.clone());
+        epoch
+    }
+}
+
+/// Create a `WriteHandle` and a `ReadHandle` to a `Store` that one writer can change while any
+/// number of readers query it from other threads.
+///
+/// This works like a left-right map: two copies of the `Store` are kept, and readers only ever see
+/// the *published* copy, while the writer changes the other one. Changes made through the
+/// `WriteHandle` are therefore not visible to readers until `WriteHandle::refresh` is called,
+/// which publishes the writer's copy and then brings the copy readers were using up to date for
+/// the writer to use next. Readers never take a lock or wait for the writer; only `refresh`
+/// waits, for the readers still using the previously published copy to finish.
+///
+/// Since indices cannot be copied, the two copies of the `Store` are made by calling `make`
+/// twice. It **must** return two identical `Store`s each time, with the same rows (inserted in
+/// the same order) and the same indices.
+pub fn new<T, R, F>(mut make: F) -> (WriteHandle<T, R>, ReadHandle<T, R>)
+    where T: Ord + Clone,
+          R: Row<T> + Clone,
+          F: FnMut() -> Store<T, R>
+{
+    let a = make();
+    let b = make();
+    assert_eq!(a.len(), b.len());
+    assert_eq!(a.cols(), b.cols());
+    let shared = Arc::new(Shared {
+        stores: [UnsafeCell::new(a), UnsafeCell::new(b)],
+        published: AtomicUsize::new(0),
+        epochs: Mutex::new(Vec::new()),
+    });
+    let r = ReadHandle::new(shared.clone());
+    let w = WriteHandle {
+        shared,
+        oplog: Vec::new(),
+    };
+    (w, r)
+}
+
+/// The writing half of a `Store` shared with readers. See `concurrent::new`.
+pub struct WriteHandle<T, R = Vec<T>> {
+    shared: Arc<Shared<T, R>>,
+    oplog: Vec<Op<R>>,
+}
+
+impl<T, R> WriteHandle<T, R>
+    where T: Ord + Clone,
+          R: Row<T> + Clone
+{
+    /// Apply the given function to the writer's copy of the `Store`.
+    fn write<F, O>(&mut self, f: F) -> O
+        where F: FnOnce(&mut Store<T, R>) -> O
+    {
+        // only the writer changes which copy is published
+        let w = 1 - self.shared.published.load(Ordering::Relaxed);
+        // readers never look at the unpublished copy, and there is only one writer
+        let store = unsafe { &mut *self.shared.stores[w].get() };
+        f(store)
+    }
+
+    /// Insert a new data row, and return the rowid assigned to it. Readers will not see the row
+    /// until the next call to `refresh`.
+    ///
+    /// Like `Store::try_insert`, returns an error if the row has the same value in a uniquely
+    /// indexed column as an existing row, in which case nothing is changed.
+    pub fn insert(&mut self, row: R) -> Result<usize, UniqueViolation> {
+        let rowid = self.write(|store| store.try_insert(row.clone()))?;
+        self.oplog.push(Op::Insert(row));
+        Ok(rowid)
+    }
+
+    /// Delete all rows that match the given conditions, and return how many were deleted.
+    /// Readers will keep seeing the rows until the next call to `refresh`.
+    pub fn delete(&mut self, conds: &[cmp::Condition<T>]) -> usize {
+        let rowids = self.write(|store| {
+            let rowids = store.find_with_ids(conds).map(|(rowid, _)| rowid).collect::<Vec<_>>();
+            for &rowid in &rowids {
+                store.remove(rowid);
+            }
+            rowids
+        });
+        let deleted = rowids.len();
+        self.oplog.extend(rowids.into_iter().map(Op::Remove));
+        deleted
+    }
+
+    /// Update all rows that match the given conditions using the given function, and return how
+    /// many were updated. See `Store::update`. Readers will keep seeing the old rows until the
+    /// next call to `refresh`.
+    ///
+    /// If an updated row would have the same value in a uniquely indexed column as another row
+    /// (including another updated row), none of the rows are updated, and an error is returned.
+    pub fn update<F>(&mut self,
+                     conds: &[cmp::Condition<T>],
+                     mut f: F)
+                     -> Result<usize, UniqueViolation>
+        where F: FnMut(&mut R)
+    {
+        let mut updated = Vec::new();
+        let result = self.write(|store| {
+            let mut rows = store.find_with_ids(conds)
+                .map(|(rowid, row)| (rowid, row.clone()))
+                .collect::<Vec<_>>();
+            for &mut (_, ref mut row) in &mut rows {
+                f(row);
+            }
+            store.check_updates(&rows)?;
+            for &(rowid, ref row) in &rows {
+                store.replace_unchecked(rowid, row.clone());
+            }
+            updated = rows;
+            Ok(())
+        });
+        let n = updated.len();
+        self.oplog.extend(updated.into_iter().map(|(rowid, row)| Op::Replace(rowid, row)));
+        result.map(|()| n)
+    }
+
+    /// Returns the number of changes that readers have not yet seen.
+    pub fn pending(&self) -> usize {
+        self.oplog.len()
+    }
+
+    /// Make all changes so far visible to readers.
+    ///
+    /// This waits for any reader still holding a `ReadGuard` to the previously published copy of
+    /// the `Store` to drop it, so readers should not hold on to their guards for long.
+    pub fn refresh(&mut self) {
+        if self.oplog.is_empty() {
+            return;
+        }
+
+        let w = 1 - self.shared.published.load(Ordering::Relaxed);
+        self.shared.published.store(w, Ordering::SeqCst);
+
+        // readers that start reading from now on see the copy we just published, so we only have
+        // to wait for the ones that were already reading when we published it.
+        let epochs = self.shared
+            .epochs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|epoch| (epoch.clone(), epoch.load(Ordering::SeqCst)))
+            .collect::<Vec<_>>();
+        for (epoch, started) in epochs {
+            if started % 2 == 1 {
+                while epoch.load(Ordering::Acquire) == started {
+                    thread::yield_now();
+                }
+            }
+        }
+
+        // no reader is using the other copy any more, so bring it up to date for us to write to
+        // next.
+        let store = unsafe { &mut *self.shared.stores[1 - w].get() };
+        for op in self.oplog.drain(..) {
+            match op {
+                Op::Insert(row) => {
+                    store.insert(row);
+                }
+                Op::Remove(rowid) => {
+                    store.remove(rowid);
+                }
+                Op::Replace(rowid, row) => {
+                    // the rows were checked against the unique indices when they were updated,
+                    // and may only be unique once all of them have been replaced
+                    store.replace_unchecked(rowid, row);
+                }
+            }
+        }
+    }
+
+    /// Returns a new `ReadHandle` to the published copy of the `Store`.
+    pub fn reader(&self) -> ReadHandle<T, R> {
+        ReadHandle::new(self.shared.clone())
+    }
+}
+
+/// The reading half of a `Store` shared with a writer. See `concurrent::new`.
+///
+/// A `ReadHandle` can be cloned to give each reader thread a handle of its own.
+pub struct ReadHandle<T, R = Vec<T>> {
+    shared: Arc<Shared<T, R>>,
+    epoch: Arc<AtomicUsize>,
+    /// The number of `ReadGuard`s from this handle that are alive, and the copy they use.
+    guards: Cell<(usize, usize)>,
+}
+
+impl<T, R> ReadHandle<T, R> {
+    fn new(shared: Arc<Shared<T, R>>) -> ReadHandle<T, R> {
+        ReadHandle {
+            epoch: shared.register(),
+            shared,
+            guards: Cell::new((0, 0)),
+        }
+    }
+
+    /// Returns a guard that gives access to the currently published copy of the `Store`. The copy
+    /// does not change for as long as the guard is held, but the writer cannot `refresh` twice
+    /// until it is dropped.
+    ///
+    /// This never takes a lock, nor waits for the writer.
+    pub fn read<'a>(&'a self) -> ReadGuard<'a, T, R> {
+        let (guards, r) = self.guards.get();
+        let r = if guards == 0 {
+            // the writer will not change the copy we pick up now until we are done with it
+            self.epoch.fetch_add(1, Ordering::SeqCst);
+            self.shared.published.load(Ordering::SeqCst)
+        } else {
+            r
+        };
+        self.guards.set((guards + 1, r));
+        ReadGuard {
+            handle: self,
+            store: unsafe { &*self.shared.stores[r].get() },
+        }
+    }
+}
+
+impl<T, R> Clone for ReadHandle<T, R> {
+    fn clone(&self) -> Self {
+        ReadHandle::new(self.shared.clone())
+    }
+}
+
+impl<T, R> Drop for ReadHandle<T, R> {
+    fn drop(&mut self) {
+        let epoch = &self.epoch;
+        self.shared.epochs.lock().unwrap().retain(|e| !Arc::ptr_eq(e, epoch));
+    }
+}
+
+/// A read-only view of the published copy of a `Store`. See `ReadHandle::read`.
+pub struct ReadGuard<'a, T: 'a, R: 'a = Vec<T>> {
+    handle: &'a ReadHandle<T, R>,
+    store: &'a Store<T, R>,
+}
+
+impl<'a, T: 'a, R: 'a> ops::Deref for ReadGuard<'a, T, R> {
+    type Target = Store<T, R>;
+    fn deref(&self) -> &Store<T, R> {
+        self.store
+    }
+}
+
+impl<'a, T: 'a, R: 'a> Drop for ReadGuard<'a, T, R> {
+    fn drop(&mut self) {
+        let (guards, r) = self.handle.guards.get();
+        self.handle.guards.set((guards - 1, r));
+        if guards == 1 {
+            self.handle.epoch.fetch_add(1, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp::{Comparison, Condition, Value};
+    use idx::HashIndex;
+    use std::thread;
+
+    #[test]
+    fn read_write_handles() {
+        let (mut w, r) = new(|| {
+            let mut store = Store::new(2);
+            store.index(1, HashIndex::new());
+            store.insert(vec![0, 0]);
+            store
+        });
+        let cmp = [Condition {
+                       column: 1,
+                       cmp: Comparison::Equal(Value::new(1)),
+                   }];
+
+        for i in 1..10 {
+            w.insert(vec![i, i % 2]).unwrap();
+        }
+        assert_eq!(w.pending(), 9);
+        assert_eq!(r.read().len(), 1);
+        w.refresh();
+        assert_eq!(w.pending(), 0);
+        assert_eq!(r.read().len(), 10);
+        assert_eq!(r.read().find(&cmp).count(), 5);
+
+        assert_eq!(w.update(&cmp, |row| row[1] = 2), Ok(5));
+        assert_eq!(w.delete(&[Condition {
+                                  column: 0,
+                                  cmp: Comparison::Equal(Value::new(0)),
+                              }]),
+                   1);
+        assert_eq!(r.read().find(&cmp).count(), 5);
+        w.refresh();
+        assert_eq!(r.read().find(&cmp).count(), 0);
+        assert_eq!(r.read().len(), 9);
+
+        // both copies must have ended up the same
+        w.insert(vec![10, 1]).unwrap();
+        w.refresh();
+        let reader = w.reader();
+        assert_eq!(reader.read().find(&cmp).map(|row| row[0]).collect::<Vec<_>>(), vec![10]);
+        assert_eq!(reader.read().get(10), Some(&vec![10, 1]));
+
+        let readers = (0..4)
+            .map(|_| {
+                let r = r.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let store = r.read();
+                        let n = store.len();
+                        assert_eq!(store.iter().count(), n);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for i in 11..111 {
+            w.insert(vec![i, 1]).unwrap();
+            w.refresh();
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(r.read().find(&cmp).count(), 101);
+    }
+
+    #[test]
+    fn unique_violations_are_returned() {
+        let (mut w, r) = new(|| {
+            let mut store = Store::new(2);
+            store.index_unique(0, HashIndex::new()).unwrap();
+            store
+        });
+        assert_eq!(w.insert(vec![0, 0]), Ok(0));
+        assert_eq!(w.insert(vec![1, 0]), Ok(1));
+        assert_eq!(w.insert(vec![0, 1]),
+                   Err(UniqueViolation { column: 0, rowid: 0 }));
+        assert_eq!(w.update(&[], |row| row[0] = 2),
+                   Err(UniqueViolation { column: 0, rowid: 0 }));
+        w.refresh();
+
+        // nested guards see the same copy, and the writer can keep going once they are dropped
+        let outer = r.read();
+        {
+            let inner = r.read();
+            assert_eq!(inner.get(0), Some(&vec![0, 0]));
+        }
+        assert_eq!(outer.get(1), Some(&vec![1, 0]));
+        drop(outer);
+        w.insert(vec![3, 0]).unwrap();
+        w.refresh();
+        w.insert(vec![4, 0]).unwrap();
+        w.refresh();
+        assert_eq!(r.read().len(), 4);
+
+        // both copies take updates that swap unique values
+        assert_eq!(w.update(&[], |row| row[0] = 1 - row[0]), Ok(4));
+        w.refresh();
+        assert_eq!(r.read().get(0), Some(&vec![1, 0]));
+        w.insert(vec![0, 1]).unwrap_err();
+        w.refresh();
+        assert_eq!(r.read().get(1), Some(&vec![0, 0]));
+    }
+}
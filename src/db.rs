@@ -0,0 +1,391 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::error;
+use std::fmt;
+
+use cmp;
+use Find;
+use Row;
+use Store;
+use UniqueViolation;
+
+/// The reason an operation on a named table in a `Database` failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableError {
+    /// A table with the given name already exists.
+    AlreadyExists,
+    /// There is no table with the given name.
+    NotFound,
+    /// A row does not have the same number of columns as the table it was added to.
+    WrongColumns,
+    /// A row has the same value in a uniquely indexed column as another row in its table.
+    Unique(UniqueViolation),
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TableError::AlreadyExists => write!(f, "a table with the given name already exists"),
+            TableError::NotFound => write!(f, "no table with the given name exists"),
+            TableError::WrongColumns => write!(f, "row has the wrong number of columns"),
+            TableError::Unique(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for TableError {}
+
+/// A `Database` is a collection of `Store`s (tables), each identified by a unique name.
+///
+/// All the tables in a `Database` share the same value type `T` and row type `R`, but may have
+/// different numbers of columns and different indices. Individual tables can be accessed directly
+/// using `table` and `table_mut`, or queried by name using `find`.
+pub struct Database<T, R = Vec<T>> {
+    tables: BTreeMap<String, Store<T, R>>,
+}
+
+impl<T, R> Default for Database<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    fn default() -> Self {
+        Database::new()
+    }
+}
+
+impl<T, R> Database<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Allocate a new, empty `Database`.
+    pub fn new() -> Database<T, R> {
+        Database { tables: BTreeMap::new() }
+    }
+
+    /// Create a new, empty table with the given name and number of columns, and return a
+    /// reference to it so that indices can be added.
+    pub fn create_table<S: Into<String>>(&mut self,
+                                         name: S,
+                                         cols: usize)
+                                         -> Result<&mut Store<T, R>, TableError> {
+        self.add_table(name, Store::new(cols))
+    }
+
+    /// Add an existing `Store` as a table with the given name, and return a reference to it.
+    pub fn add_table<S: Into<String>>(&mut self,
+                                      name: S,
+                                      store: Store<T, R>)
+                                      -> Result<&mut Store<T, R>, TableError> {
+        use std::collections::btree_map::Entry;
+        match self.tables.entry(name.into()) {
+            Entry::Occupied(..) => Err(TableError::AlreadyExists),
+            Entry::Vacant(e) => Ok(e.insert(store)),
+        }
+    }
+
+    /// Remove the table with the given name from the `Database`, and return it.
+    pub fn drop_table(&mut self, name: &str) -> Result<Store<T, R>, TableError> {
+        self.tables.remove(name).ok_or(TableError::NotFound)
+    }
+
+    /// Returns the table with the given name, if it exists.
+    pub fn table(&self, name: &str) -> Option<&Store<T, R>> {
+        self.tables.get(name)
+    }
+
+    /// Returns a mutable reference to the table with the given name, if it exists.
+    pub fn table_mut(&mut self, name: &str) -> Option<&mut Store<T, R>> {
+        self.tables.get_mut(name)
+    }
+
+    /// Returns an iterator over the names of all tables in the `Database`, in sorted order.
+    pub fn tables<'a>(&'a self) -> Box<dyn Iterator<Item = &'a str> + 'a> {
+        Box::new(self.tables.keys().map(|name| &**name))
+    }
+
+    /// Insert a row into the table with the given name, and return its rowid in that table. See
+    /// `Store::insert`.
+    pub fn insert(&mut self, name: &str, row: R) -> Result<usize, TableError> {
+        self.table_mut(name).map(|t| t.insert(row)).ok_or(TableError::NotFound)
+    }
+
+    /// Returns an iterator that yields all rows in the table with the given name that match all
+    /// the given `Condition`s. See `Store::find`.
+    pub fn find<'c, 's: 'c>(&'s self,
+                            name: &str,
+                            conds: &'c [cmp::Condition<'c, T>])
+                            -> Result<Find<'s, 'c, T, R>, TableError> {
+        self.table(name).map(|t| t.find(conds)).ok_or(TableError::NotFound)
+    }
+
+    /// Start a new `Transaction` against the tables in this `Database`.
+    pub fn transaction<'a>(&'a mut self) -> Transaction<'a, T, R> {
+        Transaction {
+            db: self,
+            ops: Vec::new(),
+        }
+    }
+}
+
+enum Op<R> {
+    Insert(R),
+    Delete(usize),
+}
+
+/// The state of a table part-way through checking the changes a `Transaction` makes to it.
+struct Pending<'a, T: 'a, R: 'a> {
+    /// The rowid the next inserted row will get.
+    next: usize,
+    /// The rowids of the existing rows that have been deleted.
+    deleted: HashSet<usize>,
+    /// The rows that have been inserted, by the rowids they will get.
+    inserted: HashMap<usize, &'a R>,
+    /// The rowid of the inserted row with each value in each uniquely indexed column.
+    keys: BTreeMap<(usize, &'a T), usize>,
+}
+
+/// A `Transaction` buffers changes to any number of tables in a `Database`, and applies them all
+/// at once when it is committed. See `Database::transaction`.
+///
+/// None of the buffered changes are visible until `commit` is called. If the `Transaction` is
+/// rolled back or dropped without being committed, the changes are discarded.
+pub struct Transaction<'a, T: 'a, R: 'a> {
+    db: &'a mut Database<T, R>,
+    ops: Vec<(String, Op<R>)>,
+}
+
+impl<'a, T, R> Transaction<'a, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Buffer the insertion of a row into the table with the given name.
+    pub fn insert<S: Into<String>>(&mut self, name: S, row: R) {
+        self.ops.push((name.into(), Op::Insert(row)));
+    }
+
+    /// Buffer the deletion of the row with the given rowid from the table with the given name. If
+    /// no such row exists when the `Transaction` is committed, the deletion is ignored.
+    pub fn delete<S: Into<String>>(&mut self, name: S, rowid: usize) {
+        self.ops.push((name.into(), Op::Delete(rowid)));
+    }
+
+    /// Apply all the buffered changes, in the order they were made, and return the rowids of the
+    /// inserted rows.
+    ///
+    /// If any of the changes refers to a table that does not exist, inserts a row with the wrong
+    /// number of columns, or inserts a row with the same value in a uniquely indexed column as a
+    /// row that would still be in the table at that point (including rows inserted earlier in the
+    /// `Transaction`), an error is returned, and *none* of the changes are applied.
+    pub fn commit(self) -> Result<Vec<usize>, TableError> {
+        self.check()?;
+
+        let mut rowids = Vec::new();
+        for (name, op) in self.ops {
+            let table = self.db.tables.get_mut(&name).unwrap();
+            match op {
+                Op::Insert(row) => rowids.push(table.insert(row)),
+                Op::Delete(rowid) => {
+                    if table.rows.contains_key(&rowid) {
+                        table.remove(rowid);
+                    }
+                }
+            }
+        }
+        Ok(rowids)
+    }
+
+    /// Check that all the buffered changes can be applied, without applying any of them.
+    fn check(&self) -> Result<(), TableError> {
+        if self.ops.iter().any(|(name, _)| !self.db.tables.contains_key(name)) {
+            return Err(TableError::NotFound);
+        }
+
+        let mut pending: HashMap<&str, Pending<T, R>> = HashMap::new();
+        for (name, op) in &self.ops {
+            let table = &self.db.tables[name];
+            let p = pending.entry(&**name).or_insert_with(|| {
+                Pending {
+                    next: table.rowid,
+                    deleted: HashSet::new(),
+                    inserted: HashMap::new(),
+                    keys: BTreeMap::new(),
+                }
+            });
+            match *op {
+                Op::Insert(ref row) => {
+                    if row.columns() != table.cols {
+                        return Err(TableError::WrongColumns);
+                    }
+                    let deleted = &p.deleted;
+                    if let Some(e) = table.unique_violation_except(row, |r| deleted.contains(&r)) {
+                        return Err(TableError::Unique(e));
+                    }
+                    for &column in &table.unique {
+                        if let Some(&rowid) = p.keys.get(&(column, row.index(column))) {
+                            return Err(TableError::Unique(UniqueViolation { column, rowid }));
+                        }
+                    }
+                    for &column in &table.unique {
+                        p.keys.insert((column, row.index(column)), p.next);
+                    }
+                    p.inserted.insert(p.next, row);
+                    p.next += 1;
+                }
+                Op::Delete(rowid) => {
+                    if let Some(row) = p.inserted.remove(&rowid) {
+                        for &column in &table.unique {
+                            p.keys.remove(&(column, row.index(column)));
+                        }
+                    } else {
+                        p.deleted.insert(rowid);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Discard all the buffered changes. This is equivalent to dropping the `Transaction`.
+    pub fn rollback(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use idx;
+
+    #[test]
+    fn it_manages_tables() {
+        let mut db = Database::new();
+        db.create_table("users", 2).unwrap().index(0, idx::HashIndex::new());
+        db.create_table("orders", 3).unwrap();
+        assert_eq!(db.create_table("users", 1).err(), Some(TableError::AlreadyExists));
+        assert_eq!(db.tables().collect::<Vec<_>>(), vec!["orders", "users"]);
+
+        assert_eq!(db.insert("users", vec!["alice", "a@example.com"]), Ok(0));
+        assert_eq!(db.insert("users", vec!["bob", "b@example.com"]), Ok(1));
+        assert_eq!(db.insert("nobody", vec!["x"]), Err(TableError::NotFound));
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("bob")),
+                   }];
+        assert_eq!(db.find("users", &cmp).unwrap().collect::<Vec<_>>(),
+                   vec![&vec!["bob", "b@example.com"]]);
+        assert_eq!(db.find("orders", &cmp).unwrap().count(), 0);
+        assert!(db.find("nobody", &cmp).is_err());
+
+        let users = db.drop_table("users").unwrap();
+        assert_eq!(users.len(), 2);
+        assert!(db.table("users").is_none());
+        assert_eq!(db.drop_table("users").err(), Some(TableError::NotFound));
+        assert!(db.add_table("users", users).is_ok());
+        assert_eq!(db.table("users").map(|t| t.len()), Some(2));
+    }
+
+    #[test]
+    fn it_commits_transactions() {
+        let mut db = Database::new();
+        db.create_table("a", 1).unwrap().index(0, idx::HashIndex::new());
+        db.create_table("b", 1).unwrap();
+        db.insert("a", vec![1]).unwrap();
+
+        let mut tx = db.transaction();
+        tx.insert("a", vec![2]);
+        tx.delete("a", 0);
+        tx.insert("b", vec![3]);
+        tx.delete("b", 42);
+        assert_eq!(tx.commit(), Ok(vec![1, 0]));
+        assert_eq!(db.table("a").unwrap().values().collect::<Vec<_>>(), vec![&vec![2]]);
+        assert_eq!(db.table("b").unwrap().values().collect::<Vec<_>>(), vec![&vec![3]]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1)),
+                   }];
+        assert_eq!(db.find("a", &cmp).unwrap().count(), 0);
+
+        // a bad table name means nothing is applied
+        let mut tx = db.transaction();
+        tx.insert("a", vec![4]);
+        tx.insert("c", vec![5]);
+        assert_eq!(tx.commit(), Err(TableError::NotFound));
+        assert_eq!(db.table("a").unwrap().len(), 1);
+
+        // as does a row with the wrong number of columns, or a duplicate in a unique column
+        db.create_table("u", 1).unwrap().index_unique(0, idx::HashIndex::new()).unwrap();
+        db.insert("u", vec![7]).unwrap();
+        let mut tx = db.transaction();
+        tx.insert("a", vec![4]);
+        tx.insert("b", vec![4, 4]);
+        assert_eq!(tx.commit(), Err(TableError::WrongColumns));
+        let mut tx = db.transaction();
+        tx.insert("a", vec![4]);
+        tx.insert("u", vec![7]);
+        assert_eq!(tx.commit(), Err(TableError::Unique(UniqueViolation { column: 0, rowid: 0 })));
+        let mut tx = db.transaction();
+        tx.insert("u", vec![8]);
+        tx.insert("u", vec![8]);
+        assert_eq!(tx.commit(), Err(TableError::Unique(UniqueViolation { column: 0, rowid: 1 })));
+        assert_eq!(db.table("a").unwrap().len(), 1);
+        assert_eq!(db.table("u").unwrap().len(), 1);
+
+        // but not once the other row has been deleted
+        let mut tx = db.transaction();
+        tx.delete("u", 0);
+        tx.insert("u", vec![7]);
+        tx.insert("u", vec![8]);
+        tx.delete("u", 2);
+        tx.insert("u", vec![8]);
+        assert_eq!(tx.commit(), Ok(vec![1, 2, 3]));
+        assert_eq!(db.table("u").unwrap().len(), 2);
+
+        let mut tx = db.transaction();
+        tx.insert("a", vec![4]);
+        tx.rollback();
+        {
+            let mut tx = db.transaction();
+            tx.delete("a", 1);
+        }
+        assert_eq!(db.table("a").unwrap().values().collect::<Vec<_>>(), vec![&vec![2]]);
+    }
+
+    #[test]
+    fn it_leaves_every_table_untouched_on_conflict() {
+        let mut db = Database::new();
+        db.create_table("a", 2).unwrap().index(1, idx::HashIndex::new());
+        db.create_table("u", 1).unwrap().index_unique(0, idx::BTreeIndex::new()).unwrap();
+        db.insert("a", vec![1, 0]).unwrap();
+        db.insert("a", vec![2, 0]).unwrap();
+        db.insert("u", vec![1]).unwrap();
+        db.insert("u", vec![2]).unwrap();
+
+        // changes to every table, followed by one that conflicts with a row that is not deleted
+        let mut tx = db.transaction();
+        tx.delete("a", 0);
+        tx.insert("a", vec![3, 0]);
+        tx.delete("u", 0);
+        tx.insert("u", vec![1]);
+        tx.insert("u", vec![3]);
+        tx.insert("a", vec![4, 1]);
+        tx.insert("u", vec![2]);
+        assert_eq!(tx.commit(), Err(TableError::Unique(UniqueViolation { column: 0, rowid: 1 })));
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                   }];
+        assert_eq!(db.find("a", &cmp).unwrap().map(|r| r[0]).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(db.table("u").unwrap().values().collect::<Vec<_>>(),
+                   vec![&vec![1], &vec![2]]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3)),
+                   }];
+        assert_eq!(db.find("u", &cmp).unwrap().count(), 0);
+
+        // and no rowids were handed out
+        assert_eq!(db.insert("a", vec![5, 0]), Ok(2));
+        assert_eq!(db.insert("u", vec![3]), Ok(2));
+    }
+}
@@ -0,0 +1,85 @@
+use std::ops;
+
+use Row;
+use Store;
+
+/// A `FrozenStore` is a `Store` that can no longer be changed, and whose rows and indices have been
+/// laid out for fast reads. See `Store::freeze`.
+///
+/// A `FrozenStore` dereferences to the `Store` it was frozen from, so all of the `Store`'s
+/// methods for reading rows, such as `find`, `find_range`, `get`, and `explain`, can be called on
+/// it directly. None of its methods for changing rows or indices can.
+pub struct FrozenStore<T, R = Vec<T>> {
+    pub(crate) store: Store<T, R>,
+}
+
+impl<T, R> FrozenStore<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Turn this `FrozenStore` back into a regular `Store` that can be changed. The rows and
+    /// indices keep the layout they were given when the `Store` was frozen.
+    pub fn thaw(self) -> Store<T, R> {
+        self.store
+    }
+}
+
+impl<T, R> ops::Deref for FrozenStore<T, R> {
+    type Target = Store<T, R>;
+    fn deref(&self) -> &Store<T, R> {
+        &self.store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp::{Comparison, Condition, Value};
+    use idx::{BTreeIndex, HashIndex};
+    use std::ops::Bound;
+
+    #[test]
+    fn frozen_store() {
+        let mut store = Store::new(2);
+        for i in 0..20 {
+            store.insert(vec![i, i % 4]);
+        }
+        store.index(0, BTreeIndex::new());
+        store.index(1, HashIndex::new());
+        store.index_multi(&[0, 1], HashIndex::new());
+        store.delete(&[Condition {
+                           column: 1,
+                           cmp: Comparison::Equal(Value::new(3)),
+                       }]);
+
+        let frozen = store.freeze();
+        assert_eq!(frozen.len(), 15);
+        assert_eq!(frozen.get(4), Some(&vec![4, 0]));
+        assert!(frozen.get(3).is_none());
+        let cmp = [Condition {
+                       column: 1,
+                       cmp: Comparison::Equal(Value::new(1)),
+                   }];
+        assert_eq!(frozen.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(),
+                   vec![1, 5, 9, 13, 17]);
+        assert_eq!(frozen.explain(&cmp).estimate, 5);
+        assert_eq!(frozen.find_range(0, Bound::Excluded(&15), Bound::Unbounded).count(), 3);
+        let cmp = [Condition {
+                       column: 0,
+                       cmp: Comparison::Equal(Value::new(8)),
+                   },
+                   Condition {
+                       column: 1,
+                       cmp: Comparison::Equal(Value::new(0)),
+                   }];
+        assert_eq!(frozen.find(&cmp).count(), 1);
+
+        let mut store = frozen.thaw();
+        store.insert(vec![20, 0]);
+        let cmp = [Condition {
+                       column: 1,
+                       cmp: Comparison::Equal(Value::new(0)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 6);
+    }
+}
@@ -0,0 +1,134 @@
+use rows::RowMap;
+
+// without the `sync` feature, hooks need not be thread-safe, so they can capture `Rc`s.
+#[cfg(feature = "sync")]
+pub(crate) type Hook<R> = Box<dyn FnMut(usize, &R) + Send + Sync>;
+#[cfg(not(feature = "sync"))]
+pub(crate) type Hook<R> = Box<dyn FnMut(usize, &R)>;
+#[cfg(feature = "sync")]
+pub(crate) type Filter<R> = Box<dyn Fn(&R) -> bool + Send + Sync>;
+#[cfg(not(feature = "sync"))]
+pub(crate) type Filter<R> = Box<dyn Fn(&R) -> bool>;
+
+/// The kind of change to a row that a trigger fires on. See `Store::add_trigger`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerEvent {
+    /// A row that matches the trigger's conditions was inserted.
+    Insert,
+    /// A row that matches the trigger's conditions was deleted.
+    Delete,
+    /// A row was updated so that it now matches the trigger's conditions, where before the update
+    /// it did not.
+    Update,
+}
+
+/// A function registered with `Store::add_trigger`, along with when to call it.
+struct Trigger<R> {
+    event: TriggerEvent,
+    filter: Filter<R>,
+    f: Hook<R>,
+}
+
+/// The functions a `Store` calls whenever a row is inserted, deleted, or updated. See
+/// `Store::on_insert`, `Store::on_delete`, and `Store::add_trigger`.
+pub(crate) struct Hooks<R> {
+    insert: Vec<Hook<R>>,
+    delete: Vec<Hook<R>>,
+    triggers: Vec<Trigger<R>>,
+    /// Whether the row being updated matched each `Update` trigger before the update.
+    matched: Vec<bool>,
+}
+
+impl<R> Default for Hooks<R> {
+    fn default() -> Self {
+        Hooks {
+            insert: Vec::new(),
+            delete: Vec::new(),
+            triggers: Vec::new(),
+            matched: Vec::new(),
+        }
+    }
+}
+
+impl<R> Hooks<R> {
+    pub(crate) fn on_insert(&mut self, f: Hook<R>) {
+        self.insert.push(f);
+    }
+
+    pub(crate) fn on_delete(&mut self, f: Hook<R>) {
+        self.delete.push(f);
+    }
+
+    pub(crate) fn add_trigger(&mut self, event: TriggerEvent, filter: Filter<R>, f: Hook<R>) {
+        self.triggers.push(Trigger { event, filter, f });
+    }
+
+    /// Call the triggers for the given event whose conditions the given row matches.
+    fn fire(&mut self, event: TriggerEvent, rowid: usize, row: &R) {
+        for t in &mut self.triggers {
+            if t.event == event && (t.filter)(row) {
+                (t.f)(rowid, row);
+            }
+        }
+    }
+
+    /// Report that the given row was inserted under the given rowid.
+    pub(crate) fn inserted(&mut self, rowid: usize, row: &R) {
+        for f in &mut self.insert {
+            f(rowid, row);
+        }
+        self.fire(TriggerEvent::Insert, rowid, row);
+    }
+
+    /// Report that the given row, which had the given rowid, was deleted.
+    pub(crate) fn deleted(&mut self, rowid: usize, row: &R) {
+        for f in &mut self.delete {
+            f(rowid, row);
+        }
+        self.fire(TriggerEvent::Delete, rowid, row);
+    }
+
+    /// Report that the row with the given rowid, which is currently the given row, is about to be
+    /// updated. `updated` must be called once the update is done.
+    pub(crate) fn updating(&mut self, rowid: usize, old: &R) {
+        for f in &mut self.delete {
+            f(rowid, old);
+        }
+        self.matched.clear();
+        self.matched.extend(self.triggers
+            .iter()
+            .map(|t| t.event == TriggerEvent::Update && (t.filter)(old)));
+    }
+
+    /// Report that the row with the given rowid was updated to the given row. See `updating`.
+    pub(crate) fn updated(&mut self, rowid: usize, new: &R) {
+        for f in &mut self.insert {
+            f(rowid, new);
+        }
+        for (t, &matched) in self.triggers.iter_mut().zip(&self.matched) {
+            if t.event == TriggerEvent::Update && !matched && (t.filter)(new) {
+                (t.f)(rowid, new);
+            }
+        }
+    }
+
+    /// Report that all the given rows were inserted, in rowid order.
+    pub(crate) fn inserted_all(&mut self, rows: &RowMap<R>) {
+        if self.insert.is_empty() && self.triggers.is_empty() {
+            return;
+        }
+        for (rowid, row) in rows.iter() {
+            self.inserted(rowid, row);
+        }
+    }
+
+    /// Report that all the given rows were deleted, in rowid order.
+    pub(crate) fn deleted_all(&mut self, rows: &RowMap<R>) {
+        if self.delete.is_empty() && self.triggers.is_empty() {
+            return;
+        }
+        for (rowid, row) in rows.iter() {
+            self.deleted(rowid, row);
+        }
+    }
+}
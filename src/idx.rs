@@ -1,8 +1,30 @@
+use std::any::Any;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::hash::BuildHasher;
 use std::hash::Hash;
+use std::hash::Hasher;
+use std::collections::hash_map::RandomState;
+use std::collections::hash_map::DefaultHasher;
+#[cfg(feature = "serde")]
+use std::collections::hash_map;
+#[cfg(feature = "serde")]
+use std::collections::btree_map;
+use std::iter;
+use std::marker::PhantomData;
+use std::slice;
 
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::cmp;
+use std::ops;
 use std::ops::Bound;
+use std::sync::Arc;
+
+use cmp::Nullable;
+use MaybeSync;
+use Row;
+use rows::Shared;
 
 /// An `EqualityIndex` is an index that can perform *efficient* equality lookups.
 pub trait EqualityIndex<T> {
@@ -15,59 +37,489 @@ pub trait EqualityIndex<T> {
     /// Remove the given row index under the given value from the index.
     fn undex(&mut self, &T, usize);
 
+    /// Add the given row index to the index under the given value, without taking ownership of
+    /// the value. `Store` calls this rather than `index` when it indexes a newly inserted or
+    /// updated row, so that indices that do not keep the values they index around (such as
+    /// `HashedIndex`) do not have to be given a clone of each value.
+    ///
+    /// The default implementation clones the value, and calls `index`.
+    fn index_ref(&mut self, key: &T, row: usize)
+        where T: Clone
+    {
+        self.index(key.clone(), row)
+    }
+
+    /// Remove all row indices from the index, and return true, or return false if the index
+    /// cannot be emptied in one go. `Store` calls this from `Store::clear`, `Store::truncate`,
+    /// `Store::compact`, and `Store::reindex`, and removes the rows one at a time with `undex`
+    /// instead if it returns false.
+    ///
+    /// The default implementation returns false, since an index can only be emptied by code that
+    /// knows how it is laid out.
+    fn clear(&mut self) -> bool {
+        false
+    }
+
+    /// Give the total number of (value, row index) entries in the index, if the index keeps track
+    /// of it. This is reported by `Store::stats`, and indices that implement
+    /// `ExtendedIndex::nulls` should give it too, since the query planner uses both to estimate
+    /// how many rows are not null.
+    ///
+    /// The default implementation returns `None`.
+    fn entries(&self) -> Option<usize> {
+        None
+    }
+
     /// Give the expected number of rows returned for a key.
     /// This method may be called often, and in rapid succession, and so should return quickly.
     fn estimate(&self) -> usize;
+
+    /// Give the expected number of rows returned for the given key. Indices that know how many
+    /// rows they hold for each key should return that number, so that skewed key distributions do
+    /// not mislead the query planner. Like `estimate`, this should return quickly.
+    ///
+    /// The default implementation returns `estimate()`.
+    fn estimate_for(&self, _key: &T) -> usize {
+        self.estimate()
+    }
+
+    /// Reorganize the index after many entries have been added at once. `Store` calls this after
+    /// populating a new index and after bulk insertions. See `FlatIndex`.
+    ///
+    /// The default implementation does nothing.
+    fn rebuild(&mut self) {}
+
+    /// Reserve capacity for at least `additional` more entries, so that they can be added without
+    /// reallocating. `Store` calls this before populating an index with all its rows, or with the
+    /// number of distinct keys it was told to expect (see `Store::index_with_capacity`). Indices
+    /// that keep a single entry for all rows with the same value should size themselves for that
+    /// many distinct values.
+    ///
+    /// The default implementation does nothing.
+    fn reserve(&mut self, _additional: usize) {}
+
+    /// Release any memory the index holds on to beyond what its current entries need, such as
+    /// spare capacity in its postings lists, or lists left empty by removed rows. `Store` calls
+    /// this from `Store::shrink_to_fit`.
+    ///
+    /// The default implementation does nothing.
+    fn shrink_to_fit(&mut self) {}
+
+    /// Add all the given (value, row index) entries to the index at once. `Store` calls this when
+    /// populating an index with all its rows, so indices that can be built faster from many
+    /// entries than by adding them one at a time should override it. See `BTreeIndex`. `Store`
+    /// calls `reserve` first as appropriate.
+    ///
+    /// The default implementation calls `index` for each entry.
+    fn index_all(&mut self, entries: Vec<(T, usize)>) {
+        for (key, row) in entries {
+            self.index(key, row);
+        }
+    }
+
+    /// Returns this index as an `ExtendedIndex`, if it supports any of the lookups of that trait.
+    /// Since `Store` keeps most indices as trait objects, this is how it finds them.
+    ///
+    /// The default implementation returns `None`.
+    fn extended(&self) -> Option<&dyn ExtendedIndex<T>> {
+        None
+    }
+
+    /// Returns this index as a `RangeIndex`, if it supports range queries. An index given to the
+    /// `Store` as an `Index::Equality` trait object, such as a `BloomIndex` wrapping a
+    /// `BTreeIndex`, can only be used for range queries if it returns `Some` here.
+    ///
+    /// The default implementation returns `None`.
+    fn as_range(&self) -> Option<&dyn RangeIndex<T>> {
+        None
+    }
+}
+
+/// Lookups that only a few special-purpose indices support, on top of those of `EqualityIndex`.
+/// `Store` uses them for the comparisons that need them, when the index on a column returns itself
+/// from `EqualityIndex::extended`.
+pub trait ExtendedIndex<T> {
+    /// Return an iterator that yields the indices of all rows whose value contains the given
+    /// (lowercased) term, or `None` if this index does not support term lookups. See
+    /// `TextIndex`.
+    ///
+    /// The default implementation returns `None`.
+    fn lookup_term<'a>(&'a self, _term: &str) -> Option<Box<dyn Iterator<Item = usize> + 'a>> {
+        None
+    }
+
+    /// Returns true if this index ignores case when looking up keys, and so can be used for
+    /// case-insensitive comparisons. See `CaseInsensitive`.
+    ///
+    /// The default implementation returns `false`.
+    fn is_case_insensitive(&self) -> bool {
+        false
+    }
+
+    /// Returns the rows whose value is null, if this index keeps track of them separately from
+    /// the other values. See `SkipNull`.
+    ///
+    /// The default implementation returns `None`.
+    fn nulls(&self) -> Option<&BTreeSet<usize>> {
+        None
+    }
+}
+
+/// The set of row indices that an index keeps for a single value, also known as a postings list.
+///
+/// `HashIndex` and `BTreeIndex` store their postings as a `Vec<usize>` by default, but can use any
+/// other implementation of this trait instead. This includes `Vec<u32>`, which halves the size of
+/// every entry on 64-bit platforms. With the `roaring` feature, it includes
+/// `roaring::RoaringBitmap`, which uses far less memory for values that many rows share. With the
+/// `smallvec` feature, it includes `smallvec::SmallVec<[u32; N]>`, which avoids a heap allocation
+/// for values that at most `N` rows share.
+///
+/// Only the postings get smaller this way. Apart from in `FlatIndex`, rowids are `usize`s
+/// everywhere else, including in the keys of the rows map of a `Store` with `Storage::BTree`
+/// (a `Store` with `Storage::Slab` does not store rowids alongside its rows at all). Postings that
+/// hold `u32`s therefore narrow each row index as it is added, and panic if it does not fit. They
+/// can only be used in `Store`s that will never hand out a rowid past `u32::MAX`. Since rowids are
+/// not reused until `Store::truncate` or `Store::compact`, this counts every row ever inserted,
+/// not just those currently in the `Store`.
+pub trait Postings: Default {
+    /// The iterator returned by `rows`.
+    type Rows<'a>: Iterator<Item = usize>
+        where Self: 'a;
+
+    /// Add the given row index.
+    fn insert_row(&mut self, row: usize);
+
+    /// Remove the given row index. Returns true if it was present.
+    fn remove_row(&mut self, row: usize) -> bool;
+
+    /// Give the number of row indices.
+    fn row_count(&self) -> usize;
+
+    /// Return an iterator over the row indices.
+    fn rows<'a>(&'a self) -> Self::Rows<'a>;
+
+    /// Release any spare capacity.
+    ///
+    /// The default implementation does nothing.
+    fn shrink_to_fit(&mut self) {}
+}
+
+/// Row indices are kept in no particular order, since removing one moves the last one into its
+/// place.
+impl Postings for Vec<usize> {
+    type Rows<'a> = iter::Cloned<slice::Iter<'a, usize>>;
+
+    fn insert_row(&mut self, row: usize) {
+        self.push(row);
+    }
+
+    fn remove_row(&mut self, row: usize) -> bool {
+        match self.iter().position(|&r| r == row) {
+            Some(i) => {
+                self.swap_remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn row_count(&self) -> usize {
+        self.len()
+    }
+
+    fn rows<'a>(&'a self) -> Self::Rows<'a> {
+        self.iter().cloned()
+    }
+
+    fn shrink_to_fit(&mut self) {
+        Vec::shrink_to_fit(self)
+    }
+}
+
+/// Row indices are kept in no particular order, like in a `Vec<usize>`, but take up half the space
+/// on 64-bit platforms. Since they are stored as `u32`s, rows whose index does not fit in a `u32`
+/// cannot be added.
+impl Postings for Vec<u32> {
+    type Rows<'a> = iter::Map<slice::Iter<'a, u32>, fn(&u32) -> usize>;
+
+    fn insert_row(&mut self, row: usize) {
+        assert!(row <= u32::MAX as usize,
+                "row index {} is too large for Vec<u32> postings",
+                row);
+        self.push(row as u32);
+    }
+
+    fn remove_row(&mut self, row: usize) -> bool {
+        match self.iter().position(|&r| r as usize == row) {
+            Some(i) => {
+                self.swap_remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn row_count(&self) -> usize {
+        self.len()
+    }
+
+    fn rows<'a>(&'a self) -> Self::Rows<'a> {
+        fn widen(row: &u32) -> usize {
+            *row as usize
+        }
+        self.iter().map(widen as fn(&u32) -> usize)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        Vec::shrink_to_fit(self)
+    }
+}
+
+/// Row indices are kept in ascending order. Since a `RoaringBitmap` holds `u32`s, rows whose index
+/// does not fit in a `u32` cannot be added.
+#[cfg(feature = "roaring")]
+impl Postings for ::roaring::RoaringBitmap {
+    type Rows<'a> = iter::Map<::roaring::bitmap::Iter<'a>, fn(u32) -> usize>;
+
+    fn insert_row(&mut self, row: usize) {
+        assert!(row <= u32::MAX as usize,
+                "row index {} is too large for a RoaringBitmap",
+                row);
+        self.insert(row as u32);
+    }
+
+    fn remove_row(&mut self, row: usize) -> bool {
+        row <= u32::MAX as usize && self.remove(row as u32)
+    }
+
+    fn row_count(&self) -> usize {
+        self.len() as usize
+    }
+
+    fn rows<'a>(&'a self) -> Self::Rows<'a> {
+        fn widen(row: u32) -> usize {
+            row as usize
+        }
+        self.iter().map(widen as fn(u32) -> usize)
+    }
+}
+
+/// Row indices are kept in no particular order, like in a `Vec<usize>`, and are stored inline as
+/// long as there are at most `N` of them. Since they are stored as `u32`s, rows whose index does
+/// not fit in a `u32` cannot be added.
+#[cfg(feature = "smallvec")]
+impl<const N: usize> Postings for ::smallvec::SmallVec<[u32; N]> {
+    type Rows<'a> = iter::Map<slice::Iter<'a, u32>, fn(&u32) -> usize>;
+
+    fn insert_row(&mut self, row: usize) {
+        assert!(row <= u32::MAX as usize,
+                "row index {} is too large for SmallVec postings",
+                row);
+        self.push(row as u32);
+    }
+
+    fn remove_row(&mut self, row: usize) -> bool {
+        match self.iter().position(|&r| r as usize == row) {
+            Some(i) => {
+                self.swap_remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn row_count(&self) -> usize {
+        self.len()
+    }
+
+    fn rows<'a>(&'a self) -> Self::Rows<'a> {
+        fn widen(row: &u32) -> usize {
+            *row as usize
+        }
+        self.iter().map(widen as fn(&u32) -> usize)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        ::smallvec::SmallVec::shrink_to_fit(self)
+    }
 }
 
 /// An implementation of `EqualityIndex` that uses a `HashMap`.
+///
+/// Like `HashMap`, the index uses `RandomState` to hash keys by default. A faster (but not
+/// DoS-resistant) hasher can be given using `with_hasher`. The row indices for each key are kept
+/// in a `Vec<usize>` by default; see `Postings` for alternatives. A `HashIndex` with another
+/// hasher or other postings is added to a `Store` as an `Index::Equality`.
+///
+/// Cloning a `HashIndex` is cheap: the clone shares its contents until either index is changed,
+/// which then copies them.
 #[derive(Clone)]
-pub struct HashIndex<K: Eq + Hash> {
+pub struct HashIndex<K, S = RandomState, P = Vec<usize>> {
     num: usize,
-    map: HashMap<K, Vec<usize>>,
+    map: Shared<HashMap<K, P, S>>,
+    ops: HashOps<K, S, P>,
+}
+
+/// A pointer to an implementation of `EqualityIndex::lookup`.
+type LookupFn<I, K> = for<'a> fn(&'a I, &K) -> Box<dyn Iterator<Item = usize> + 'a>;
+/// A pointer to `HashIndex::get`.
+type GetFn<I, K, P> = for<'a> fn(&'a I, &K) -> Option<&'a P>;
+
+/// The `EqualityIndex` methods of a `HashIndex` that hash its keys, and so can only be named where
+/// the keys are known to be hashable. Every `HashIndex` keeps pointers to them, so that
+/// `Index::Hash` can call them without requiring `T: Hash` of every `Index<T>` (and so of every
+/// `Store`).
+struct HashOps<K, S, P> {
+    lookup: LookupFn<HashIndex<K, S, P>, K>,
+    get: GetFn<HashIndex<K, S, P>, K, P>,
+    index: fn(&mut HashIndex<K, S, P>, K, usize),
+    undex: fn(&mut HashIndex<K, S, P>, &K, usize),
+    estimate_for: fn(&HashIndex<K, S, P>, &K) -> usize,
+    reserve: fn(&mut HashIndex<K, S, P>, usize),
+    shrink_to_fit: fn(&mut HashIndex<K, S, P>),
+}
+
+impl<K: Eq + Hash, S: BuildHasher, P: Postings> HashOps<K, S, P> {
+    fn new() -> Self {
+        HashOps {
+            lookup: <HashIndex<K, S, P> as EqualityIndex<K>>::lookup,
+            get: HashIndex::get,
+            index: <HashIndex<K, S, P> as EqualityIndex<K>>::index,
+            undex: <HashIndex<K, S, P> as EqualityIndex<K>>::undex,
+            estimate_for: <HashIndex<K, S, P> as EqualityIndex<K>>::estimate_for,
+            reserve: <HashIndex<K, S, P> as EqualityIndex<K>>::reserve,
+            shrink_to_fit: <HashIndex<K, S, P> as EqualityIndex<K>>::shrink_to_fit,
+        }
+    }
+}
+
+impl<K, S, P> Clone for HashOps<K, S, P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, S, P> Copy for HashOps<K, S, P> {}
+
+impl<K: Eq + Hash, S: BuildHasher + Default, P: Postings> Default for HashIndex<K, S, P> {
+    fn default() -> Self {
+        HashIndex {
+            map: Shared::new(HashMap::default()),
+            num: 0,
+            ops: HashOps::new(),
+        }
+    }
 }
 
 impl<K: Eq + Hash> HashIndex<K> {
     /// Allocate a new `HashIndex`.
     pub fn new() -> HashIndex<K> {
         HashIndex {
-            map: HashMap::new(),
+            map: Shared::new(HashMap::new()),
+            num: 0,
+            ops: HashOps::new(),
+        }
+    }
+
+    /// Allocate a new `HashIndex` with room for at least `keys` distinct keys.
+    pub fn with_capacity(keys: usize) -> HashIndex<K> {
+        HashIndex {
+            map: Shared::new(HashMap::with_capacity(keys)),
+            num: 0,
+            ops: HashOps::new(),
+        }
+    }
+
+    /// Construct a `HashIndex` that holds the given keys, each with the given rows indexed under
+    /// it, or `None` if a key is given more than once.
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_postings(postings: Vec<(K, Vec<usize>)>) -> Option<HashIndex<K>> {
+        let mut num = 0;
+        let mut map = HashMap::with_capacity(postings.len());
+        for (key, rows) in postings {
+            num += rows.len();
+            if map.insert(key, rows).is_some() {
+                return None;
+            }
+        }
+        Some(HashIndex {
+            num,
+            map: Shared::new(map),
+            ops: HashOps::new(),
+        })
+    }
+}
+
+impl<K> HashIndex<K> {
+    /// Returns each key in the index, along with the rows indexed under it.
+    #[cfg(feature = "serde")]
+    pub(crate) fn postings<'a>(&'a self) -> hash_map::Iter<'a, K, Vec<usize>> {
+        self.map.iter()
+    }
+}
+
+impl<K: Eq + Hash, S: BuildHasher, P> HashIndex<K, S, P> {
+    /// Returns the rows indexed under the given key, if any.
+    fn get(&self, key: &K) -> Option<&P> {
+        self.map.get(key)
+    }
+}
+
+impl<K: Eq + Hash, S: BuildHasher> HashIndex<K, S> {
+    /// Allocate a new `HashIndex` that uses the given hash builder to hash keys.
+    pub fn with_hasher(hasher: S) -> HashIndex<K, S> {
+        HashIndex {
+            map: Shared::new(HashMap::with_hasher(hasher)),
             num: 0,
+            ops: HashOps::new(),
         }
     }
 }
 
-impl<T: Eq + Hash> EqualityIndex<T> for HashIndex<T> {
+impl<T, S, P> EqualityIndex<T> for HashIndex<T, S, P>
+    where T: Eq + Hash,
+          S: BuildHasher,
+          P: Postings
+{
     fn lookup<'a>(&'a self, key: &T) -> Box<Iterator<Item = usize> + 'a> {
         match self.map.get(key) {
-            Some(ref v) => Box::new(v.iter().map(|row| *row)),
+            Some(ref v) => Box::new(v.rows()),
             None => Box::new(None.into_iter()),
         }
     }
 
     fn index(&mut self, key: T, row: usize) {
-        self.map.entry(key).or_insert_with(Vec::new).push(row);
+        self.map.make_mut().entry(key).or_default().insert_row(row);
         self.num += 1;
     }
 
     fn undex(&mut self, key: &T, row: usize) {
         let mut empty = false;
-        if let Some(mut l) = self.map.get_mut(key) {
+        if let Some(l) = self.map.make_mut().get_mut(key) {
             empty = {
-                match l.iter().position(|&r| r == row) {
-                    Some(i) => {
-                        l.swap_remove(i);
-                    }
-                    None => unreachable!(),
+                if l.remove_row(row) {
+                    self.num -= 1;
+                } else {
+                    unreachable!();
                 }
-                l.is_empty()
+                l.row_count() == 0
             };
         }
         if empty {
-            self.map.remove(key);
+            self.map.make_mut().remove(key);
         }
     }
 
+    fn clear(&mut self) -> bool {
+        self.map.make_mut().clear();
+        self.num = 0;
+        true
+    }
+
+    fn entries(&self) -> Option<usize> {
+        Some(self.num)
+    }
+
     fn estimate(&self) -> usize {
         let len = self.map.len();
         if len > 0 {
@@ -76,116 +528,1937 @@ impl<T: Eq + Hash> EqualityIndex<T> for HashIndex<T> {
             0
         }
     }
+
+    fn estimate_for(&self, key: &T) -> usize {
+        self.map.get(key).map(|rows| rows.row_count()).unwrap_or(0)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.map.make_mut().reserve(additional)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        let map = self.map.make_mut();
+        for rows in map.values_mut() {
+            rows.shrink_to_fit();
+        }
+        map.shrink_to_fit()
+    }
+}
+
+/// An implementation of `EqualityIndex` that keeps only a hash of each value, rather than the value
+/// itself. Indexing a row only requires hashing the borrowed value, so a `Store` never has to
+/// clone values (such as `String`s) for it when inserting or updating rows, and the index takes up
+/// the same small amount of memory per key no matter how large the values are.
+///
+/// In exchange, rows whose values happen to have the same hash as the value being looked up are
+/// also yielded by lookups. `Store` checks every row it finds through an index against the query's
+/// conditions anyway, so queries still only return rows with the right value, but a
+/// `HashedIndex` cannot tell two values apart when estimating, or when it is used on its own.
+/// It can still be used as a unique index, since `Store` compares the value of every row a unique
+/// index yields before deciding that a new value is taken.
+#[derive(Clone)]
+pub struct HashedIndex<K, S = RandomState> {
+    num: usize,
+    map: HashMap<u64, Vec<usize>>,
+    hasher: S,
+    key: PhantomData<fn(&K)>,
+}
+
+impl<K: Hash> HashedIndex<K> {
+    /// Allocate a new `HashedIndex`.
+    pub fn new() -> HashedIndex<K> {
+        HashedIndex::with_hasher(RandomState::new())
+    }
+}
+
+impl<K: Hash, S: BuildHasher> HashedIndex<K, S> {
+    /// Allocate a new `HashedIndex` that uses the given hash builder to hash keys.
+    pub fn with_hasher(hasher: S) -> HashedIndex<K, S> {
+        HashedIndex {
+            num: 0,
+            map: HashMap::new(),
+            hasher,
+            key: PhantomData,
+        }
+    }
+
+    fn hash(&self, key: &K) -> u64 {
+        let mut hasher = self.hasher.build_hasher();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn insert(&mut self, key: &K, row: usize) {
+        let hash = self.hash(key);
+        self.map.entry(hash).or_default().push(row);
+        self.num += 1;
+    }
+}
+
+impl<K: Hash> Default for HashedIndex<K> {
+    fn default() -> Self {
+        HashedIndex::new()
+    }
+}
+
+impl<T: Hash, S: BuildHasher> EqualityIndex<T> for HashedIndex<T, S> {
+    fn lookup<'a>(&'a self, key: &T) -> Box<dyn Iterator<Item = usize> + 'a> {
+        match self.map.get(&self.hash(key)) {
+            Some(rows) => Box::new(rows.iter().cloned()),
+            None => Box::new(None.into_iter()),
+        }
+    }
+
+    fn index(&mut self, key: T, row: usize) {
+        self.insert(&key, row)
+    }
+
+    fn index_ref(&mut self, key: &T, row: usize) {
+        self.insert(key, row)
+    }
+
+    fn undex(&mut self, key: &T, row: usize) {
+        let hash = self.hash(key);
+        let empty = match self.map.get_mut(&hash) {
+            Some(rows) => {
+                if let Some(i) = rows.iter().position(|&r| r == row) {
+                    rows.swap_remove(i);
+                    self.num -= 1;
+                }
+                rows.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.map.remove(&hash);
+        }
+    }
+
+    fn clear(&mut self) -> bool {
+        self.map.clear();
+        self.num = 0;
+        true
+    }
+
+    fn entries(&self) -> Option<usize> {
+        Some(self.num)
+    }
+
+    fn estimate(&self) -> usize {
+        self.num.checked_div(self.map.len()).unwrap_or(0)
+    }
+
+    fn estimate_for(&self, key: &T) -> usize {
+        self.map.get(&self.hash(key)).map(|rows| rows.len()).unwrap_or(0)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.map.reserve(additional)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        for rows in self.map.values_mut() {
+            rows.shrink_to_fit();
+        }
+        self.map.shrink_to_fit()
+    }
 }
 
 /// A `RangeIndex` is an index that, in addition to performing efficient equality lookups, can
 /// *also* perform efficient range queries.
 pub trait RangeIndex<T>: EqualityIndex<T> {
     /// Return an iterator that yields the indices of all rows whose value (in the column this
-    /// index is assigned to) lies within the given `Bound`s.
+    /// index is assigned to) lies within the given `Bound`s. The rows must be yielded in
+    /// ascending order of their values.
     fn between<'a>(&'a self, Bound<&T>, Bound<&T>) -> Box<Iterator<Item = usize> + 'a>;
+
+    /// Like `between`, but yields the rows in *descending* order of their values.
+    ///
+    /// The default implementation collects the results of `between` and reverses them.
+    /// Implementors should override this if they can iterate in reverse more efficiently.
+    fn between_rev<'a>(&'a self,
+                       min: Bound<&T>,
+                       max: Bound<&T>)
+                       -> Box<dyn Iterator<Item = usize> + 'a> {
+        let rows = self.between(min, max).collect::<Vec<_>>();
+        Box::new(rows.into_iter().rev())
+    }
+
+    /// Like `between`, but also yields the value each row is indexed under, so that queries that
+    /// only need the indexed values can be answered without looking up the rows themselves.
+    ///
+    /// The default implementation returns `None`, which indicates that the index does not keep
+    /// the values it indexes around.
+    fn keys_between<'a>(&'a self,
+                        _min: Bound<&T>,
+                        _max: Bound<&T>)
+                        -> Option<Box<dyn Iterator<Item = (&'a T, usize)> + 'a>> {
+        None
+    }
 }
 
 /// An implementation of `RangeIndex` using a `BTreeMap`.
+///
+/// Like a `HashIndex`, a `BTreeIndex` shares its contents with its clones until either is changed.
 #[derive(Clone)]
-pub struct BTreeIndex<K: Ord + Eq> {
+pub struct BTreeIndex<K, P = Vec<usize>> {
     num: usize,
-    map: BTreeMap<K, Vec<usize>>,
+    map: Shared<BTreeMap<K, P>>,
+}
+
+impl<K: Ord + Eq, P> Default for BTreeIndex<K, P> {
+    fn default() -> Self {
+        BTreeIndex {
+            map: Shared::new(BTreeMap::new()),
+            num: 0,
+        }
+    }
 }
 
 impl<K: Ord + Eq> BTreeIndex<K> {
     /// Allocate a new `BTreeIndex`.
     pub fn new() -> BTreeIndex<K> {
         BTreeIndex {
-            map: BTreeMap::new(),
+            map: Shared::new(BTreeMap::new()),
             num: 0,
         }
     }
+
+    /// Returns each key in the index, in order, along with the rows indexed under it.
+    #[cfg(feature = "serde")]
+    pub(crate) fn postings<'a>(&'a self) -> btree_map::Iter<'a, K, Vec<usize>> {
+        self.map.iter()
+    }
+
+    /// Construct a `BTreeIndex` that holds the given keys, each with the given rows indexed under
+    /// it, or `None` if a key is given more than once.
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_postings(postings: Vec<(K, Vec<usize>)>) -> Option<BTreeIndex<K>> {
+        let mut num = 0;
+        let mut map = BTreeMap::new();
+        for (key, rows) in postings {
+            num += rows.len();
+            if map.insert(key, rows).is_some() {
+                return None;
+            }
+        }
+        Some(BTreeIndex {
+            num,
+            map: Shared::new(map),
+        })
+    }
 }
 
-impl<T: Ord + Eq> EqualityIndex<T> for BTreeIndex<T> {
+impl<T: Ord + Eq, P: Postings> EqualityIndex<T> for BTreeIndex<T, P> {
     fn lookup<'a>(&'a self, key: &T) -> Box<Iterator<Item = usize> + 'a> {
         match self.map.get(key) {
-            Some(ref v) => Box::new(v.iter().map(|row| *row)),
+            Some(ref v) => Box::new(v.rows()),
             None => Box::new(None.into_iter()),
         }
     }
 
     fn index(&mut self, key: T, row: usize) {
-        self.map.entry(key).or_insert_with(Vec::new).push(row);
+        self.map.make_mut().entry(key).or_default().insert_row(row);
         self.num += 1;
     }
 
     fn undex(&mut self, key: &T, row: usize) {
-        if let Some(ref mut l) = self.map.get_mut(key) {
-            self.num -= l.len();
-            l.retain(|&i| i != row);
-            self.num += l.len();
+        if let Some(ref mut l) = self.map.make_mut().get_mut(key) {
+            if l.remove_row(row) {
+                self.num -= 1;
+            }
         }
     }
 
+    fn clear(&mut self) -> bool {
+        self.map.make_mut().clear();
+        self.num = 0;
+        true
+    }
+
+    fn entries(&self) -> Option<usize> {
+        Some(self.num)
+    }
+
     fn estimate(&self) -> usize {
-        self.num / self.map.len()
+        self.num.checked_div(self.map.len()).unwrap_or(0)
     }
-}
-impl<T: Ord + Eq> RangeIndex<T> for BTreeIndex<T> {
-    fn between<'a>(&'a self, min: Bound<&T>, max: Bound<&T>) -> Box<Iterator<Item = usize> + 'a> {
-        Box::new(self.map.range((min, max)).flat_map(|rows| rows.1.iter().map(|row| *row)))
+
+    fn estimate_for(&self, key: &T) -> usize {
+        self.map.get(key).map(|rows| rows.row_count()).unwrap_or(0)
     }
-}
 
-/// A sum type expressing all different types of indices so they can easily be stored. Since all
-/// indices must at least implement `EqualityIndex`, this enum also forwards all calls of
-/// that trait to the underlying index for convenience.
-pub enum Index<T> {
-    /// A `RangeIndex` trait object.
-    Range(Box<RangeIndex<T> + Send + Sync>),
-    /// An `EqualityIndex` trait object.
-    Equality(Box<EqualityIndex<T> + Send + Sync>),
-}
+    fn shrink_to_fit(&mut self) {
+        // `undex` leaves the postings of keys whose rows have all been removed behind
+        let map = self.map.make_mut();
+        map.retain(|_, rows| rows.row_count() > 0);
+        for rows in map.values_mut() {
+            rows.shrink_to_fit();
+        }
+    }
 
-impl<T> EqualityIndex<T> for Index<T> {
-    fn lookup<'a>(&'a self, key: &T) -> Box<Iterator<Item = usize> + 'a> {
-        match *self {
-            Index::Range(ref ri) => ri.lookup(key),
-            Index::Equality(ref ei) => ei.lookup(key),
+    fn index_all(&mut self, mut entries: Vec<(T, usize)>) {
+        if !self.map.is_empty() {
+            for (key, row) in entries {
+                self.index(key, row);
+            }
+            return;
+        }
+
+        // group the entries by key (the sort is stable, so each key's rows keep their order), and
+        // then build the map from the sorted groups in one go, which takes linear time.
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        self.num += entries.len();
+        let mut groups: Vec<(T, P)> = Vec::new();
+        for (key, row) in entries {
+            if let Some(&mut (ref k, ref mut rows)) = groups.last_mut() {
+                if *k == key {
+                    rows.insert_row(row);
+                    continue;
+                }
+            }
+            let mut rows = P::default();
+            rows.insert_row(row);
+            groups.push((key, rows));
         }
+        self.map = Shared::new(groups.into_iter().collect());
     }
-    fn index(&mut self, key: T, row: usize) {
-        match *self {
-            Index::Range(ref mut ri) => ri.index(key, row),
-            Index::Equality(ref mut ei) => ei.index(key, row),
+
+    fn as_range(&self) -> Option<&dyn RangeIndex<T>> {
+        Some(self)
+    }
+}
+impl<T: Ord + Eq, P> BTreeIndex<T, P> {
+    /// Returns true if `BTreeMap::range` would consider the given bounds invalid.
+    fn is_empty_range(min: Bound<&T>, max: Bound<&T>) -> bool {
+        match (min, max) {
+            (Bound::Included(a), Bound::Included(b)) => a > b,
+            (Bound::Included(a), Bound::Excluded(b)) |
+            (Bound::Excluded(a), Bound::Included(b)) |
+            (Bound::Excluded(a), Bound::Excluded(b)) => a >= b,
+            _ => false,
         }
     }
-    fn undex(&mut self, key: &T, row: usize) {
-        match *self {
-            Index::Range(ref mut ri) => ri.undex(key, row),
-            Index::Equality(ref mut ei) => ei.undex(key, row),
+}
+
+impl<T: Ord + Eq, P: Postings> RangeIndex<T> for BTreeIndex<T, P> {
+    fn between<'a>(&'a self, min: Bound<&T>, max: Bound<&T>) -> Box<dyn Iterator<Item = usize> + 'a> {
+        // BTreeMap::range panics on empty or inverted ranges, so catch those first
+        if Self::is_empty_range(min, max) {
+            return Box::new(None.into_iter());
         }
+
+        Box::new(self.map.range((min, max)).flat_map(|rows| rows.1.rows()))
     }
-    fn estimate(&self) -> usize {
-        match *self {
-            Index::Range(ref ri) => ri.estimate(),
-            Index::Equality(ref ei) => ei.estimate(),
+
+    fn between_rev<'a>(&'a self,
+                       min: Bound<&T>,
+                       max: Bound<&T>)
+                       -> Box<dyn Iterator<Item = usize> + 'a> {
+        if Self::is_empty_range(min, max) {
+            return Box::new(None.into_iter());
         }
+
+        Box::new(self.map.range((min, max)).rev().flat_map(|rows| rows.1.rows()))
     }
-}
 
-impl<T: Eq + Hash + 'static + Send + Sync> From<HashIndex<T>> for Index<T> {
-    fn from(x: HashIndex<T>) -> Index<T> {
-        Index::Equality(Box::new(x))
+    fn keys_between<'a>(&'a self,
+                        min: Bound<&T>,
+                        max: Bound<&T>)
+                        -> Option<Box<dyn Iterator<Item = (&'a T, usize)> + 'a>> {
+        if Self::is_empty_range(min, max) {
+            return Some(Box::new(None.into_iter()));
+        }
+
+        Some(Box::new(self.map
+            .range((min, max))
+            .flat_map(|(key, rows)| rows.rows().map(move |row| (key, row)))))
     }
 }
 
-impl<T: Ord + Eq + 'static + Send + Sync> From<BTreeIndex<T>> for Index<T> {
-    fn from(x: BTreeIndex<T>) -> Index<T> {
-        Index::Range(Box::new(x))
-    }
+/// An implementation of `RangeIndex` using a sorted `Vec` of (value, row index) pairs.
+///
+/// Lookups are binary searches over a single contiguous allocation, which makes a `FlatIndex`
+/// both smaller and faster to query than a `BTreeIndex`, especially for mostly-static data. In
+/// exchange, keeping the `Vec` sorted on every change would make modifications expensive, so
+/// newly indexed entries are instead appended to an unsorted tail that lookups have to scan.
+/// The tail is sorted into place once it grows too long (see `tail_too_long`), or when `rebuild`
+/// is called; `Store` does the latter after populating a new index and after `extend` and
+/// `absorb`.
+///
+/// Row indices are stored as `u32`s to save space, so rows whose index does not fit in a `u32`
+/// cannot be added to a `FlatIndex`.
+#[derive(Clone)]
+pub struct FlatIndex<K> {
+    /// The number of leading entries in `pairs` that are sorted.
+    sorted: usize,
+    /// The number of distinct keys as of the last rebuild.
+    keys: usize,
+    pairs: Vec<(K, u32)>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl<K: Ord> FlatIndex<K> {
+    /// Allocate a new `FlatIndex`.
+    pub fn new() -> FlatIndex<K> {
+        FlatIndex {
+            sorted: 0,
+            keys: 0,
+            pairs: Vec::new(),
+        }
+    }
+
+    /// Returns the range of positions in the sorted part of the index whose keys lie within the
+    /// given bounds.
+    fn span(&self, min: Bound<&K>, max: Bound<&K>) -> ops::Range<usize> {
+        let sorted = &self.pairs[..self.sorted];
+        let start = match min {
+            Bound::Included(k) => sorted.partition_point(|p| p.0 < *k),
+            Bound::Excluded(k) => sorted.partition_point(|p| p.0 <= *k),
+            Bound::Unbounded => 0,
+        };
+        let end = match max {
+            Bound::Included(k) => sorted.partition_point(|p| p.0 <= *k),
+            Bound::Excluded(k) => sorted.partition_point(|p| p.0 < *k),
+            Bound::Unbounded => sorted.len(),
+        };
+        start..cmp::max(start, end)
+    }
+
+    /// Returns all entries within the given bounds, in key order.
+    fn range(&self, min: Bound<&K>, max: Bound<&K>) -> Vec<&(K, u32)> {
+        let mut pairs = self.pairs[self.span(min, max)].iter().collect::<Vec<_>>();
+        let tail = self.pairs[self.sorted..].iter().filter(|p| in_range(&p.0, min, max));
+        let before = pairs.len();
+        pairs.extend(tail);
+        if pairs.len() != before {
+            pairs.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        pairs
+    }
+}
+
+impl<K: Ord> Default for FlatIndex<K> {
+    fn default() -> Self {
+        FlatIndex::new()
+    }
+}
+
+impl<T: Ord> EqualityIndex<T> for FlatIndex<T> {
+    fn lookup<'a>(&'a self, key: &T) -> Box<dyn Iterator<Item = usize> + 'a> {
+        let span = self.span(Bound::Included(key), Bound::Included(key));
+        let tail = self.pairs[self.sorted..]
+            .iter()
+            .filter(|p| p.0 == *key)
+            .map(|p| p.1 as usize)
+            .collect::<Vec<_>>();
+        Box::new(self.pairs[span].iter().map(|p| p.1 as usize).chain(tail))
+    }
+
+    fn index(&mut self, key: T, row: usize) {
+        assert!(row <= u32::MAX as usize,
+                "row index {} is too large for a FlatIndex",
+                row);
+        self.pairs.push((key, row as u32));
+        if tail_too_long(self.sorted, self.pairs.len() - self.sorted) {
+            self.rebuild();
+        }
+    }
+
+    fn undex(&mut self, key: &T, row: usize) {
+        let row = row as u32;
+        let sorted = &self.pairs[..self.sorted];
+        if let Ok(i) = sorted.binary_search_by(|p| p.0.cmp(key).then(p.1.cmp(&row))) {
+            self.pairs.remove(i);
+            self.sorted -= 1;
+        } else if let Some(i) = self.pairs[self.sorted..]
+            .iter()
+            .position(|p| p.0 == *key && p.1 == row) {
+            self.pairs.swap_remove(self.sorted + i);
+        }
+    }
+
+    fn clear(&mut self) -> bool {
+        self.pairs.clear();
+        self.sorted = 0;
+        self.keys = 0;
+        true
+    }
+
+    fn entries(&self) -> Option<usize> {
+        Some(self.pairs.len())
+    }
+
+    fn estimate(&self) -> usize {
+        self.pairs.len() / cmp::max(self.keys, 1)
+    }
+
+    fn estimate_for(&self, key: &T) -> usize {
+        if self.sorted < self.pairs.len() {
+            // counting the matches in the unsorted tail would take too long
+            return self.estimate();
+        }
+        self.span(Bound::Included(key), Bound::Included(key)).len()
+    }
+
+    fn rebuild(&mut self) {
+        // the sorted prefix and the newly sorted tail form two runs, which a stable sort merges
+        // in linear time
+        self.pairs.sort();
+        self.sorted = self.pairs.len();
+        self.keys = self.pairs.windows(2).filter(|w| w[0].0 != w[1].0).count();
+        if !self.pairs.is_empty() {
+            self.keys += 1;
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.pairs.reserve(additional)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.pairs.shrink_to_fit()
+    }
+
+    fn as_range(&self) -> Option<&dyn RangeIndex<T>> {
+        Some(self)
+    }
+}
+
+impl<T: Ord> RangeIndex<T> for FlatIndex<T> {
+    fn between<'a>(&'a self, min: Bound<&T>, max: Bound<&T>) -> Box<dyn Iterator<Item = usize> + 'a> {
+        if self.sorted == self.pairs.len() {
+            let span = self.span(min, max);
+            return Box::new(self.pairs[span].iter().map(|p| p.1 as usize));
+        }
+        Box::new(self.range(min, max).into_iter().map(|p| p.1 as usize))
+    }
+
+    fn between_rev<'a>(&'a self,
+                       min: Bound<&T>,
+                       max: Bound<&T>)
+                       -> Box<dyn Iterator<Item = usize> + 'a> {
+        if self.sorted == self.pairs.len() {
+            let span = self.span(min, max);
+            return Box::new(self.pairs[span].iter().rev().map(|p| p.1 as usize));
+        }
+        Box::new(self.range(min, max).into_iter().rev().map(|p| p.1 as usize))
+    }
+
+    fn keys_between<'a>(&'a self,
+                        min: Bound<&T>,
+                        max: Bound<&T>)
+                        -> Option<Box<dyn Iterator<Item = (&'a T, usize)> + 'a>> {
+        if self.sorted == self.pairs.len() {
+            let span = self.span(min, max);
+            return Some(Box::new(self.pairs[span].iter().map(|p| (&p.0, p.1 as usize))));
+        }
+        Some(Box::new(self.range(min, max).into_iter().map(|p| (&p.0, p.1 as usize))))
+    }
+}
+
+/// The shortest unsorted tail that a `FlatIndex` or `IntervalTreeIndex` sorts into place without
+/// waiting for a `rebuild`.
+const MIN_TAIL: usize = 64;
+
+/// How many times longer than its unsorted tail the sorted part of a `FlatIndex` or
+/// `IntervalTreeIndex` may be before the tail is sorted into place. Merging the tail costs time
+/// linear in the size of the whole index, so letting the tail grow along with the index keeps the
+/// amortized cost of an insertion constant, while bounding how much of the index lookups scan.
+const TAIL_RATIO: usize = 8;
+
+/// Returns true if an index with the given number of sorted entries and the given number of
+/// entries in its unsorted tail should sort the tail into place.
+pub(crate) fn tail_too_long(sorted: usize, tail: usize) -> bool {
+    tail > cmp::max(MIN_TAIL, sorted / TAIL_RATIO)
+}
+
+/// Returns true if the given value lies within the given bounds.
+fn in_range<T: Ord>(v: &T, min: Bound<&T>, max: Bound<&T>) -> bool {
+    let above = match min {
+        Bound::Included(m) => v >= m,
+        Bound::Excluded(m) => v > m,
+        Bound::Unbounded => true,
+    };
+    let below = match max {
+        Bound::Included(m) => v <= m,
+        Bound::Excluded(m) => v < m,
+        Bound::Unbounded => true,
+    };
+    above && below
+}
+
+/// A `BloomIndex` wraps another index with a bloom filter over the indexed keys, so that lookups
+/// for keys that are not in the index can usually return without consulting the inner index at
+/// all. This is beneficial when most lookups are for absent keys, and the inner index is large.
+///
+/// Since bloom filters do not support removal, keys that have been removed from the index still
+/// pass the filter until the index is cleared. Lookups for them are still correct, but are
+/// forwarded to the inner index.
+#[derive(Clone)]
+pub struct BloomIndex<T, I> {
+    bits: Vec<u64>,
+    inner: I,
+    key: PhantomData<fn(&T)>,
+}
+
+/// The number of bits in the filter of a `BloomIndex` for each expected key.
+const BLOOM_BITS_PER_KEY: usize = 10;
+
+/// The number of bits set in the filter of a `BloomIndex` for each key. With 10 bits per key, this
+/// gives a false positive rate of about 1%.
+const BLOOM_HASHES: u64 = 7;
+
+impl<T: Hash, I: EqualityIndex<T>> BloomIndex<T, I> {
+    /// Wrap the given (empty) index with a bloom filter sized for the given number of distinct
+    /// keys. If more keys than this are indexed, the filter becomes less effective.
+    pub fn new(inner: I, expected_keys: usize) -> BloomIndex<T, I> {
+        let words = (expected_keys.max(1) * BLOOM_BITS_PER_KEY + 63) / 64;
+        BloomIndex {
+            bits: vec![0; words],
+            inner,
+            key: PhantomData,
+        }
+    }
+
+    /// Returns the positions of the bits in the filter for the given key.
+    fn positions(&self, key: &T) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        // derive all the hash functions from a single hash using double hashing
+        let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        let m = self.bits.len() as u64 * 64;
+        (0..BLOOM_HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % m) as usize)
+    }
+
+    /// Returns false if the given key is definitely not in the index.
+    pub fn may_contain(&self, key: &T) -> bool {
+        self.positions(key).all(|b| self.bits[b / 64] & (1 << (b % 64)) != 0)
+    }
+
+    /// Returns the wrapped index.
+    pub fn inner(&self) -> &I {
+        &self.inner
+    }
+}
+
+impl<T: Hash, I: EqualityIndex<T>> EqualityIndex<T> for BloomIndex<T, I> {
+    fn lookup<'a>(&'a self, key: &T) -> Box<dyn Iterator<Item = usize> + 'a> {
+        if self.may_contain(key) {
+            self.inner.lookup(key)
+        } else {
+            Box::new(None.into_iter())
+        }
+    }
+
+    fn index(&mut self, key: T, row: usize) {
+        for b in self.positions(&key).collect::<Vec<_>>() {
+            self.bits[b / 64] |= 1 << (b % 64);
+        }
+        self.inner.index(key, row);
+    }
+
+    fn index_ref(&mut self, key: &T, row: usize)
+        where T: Clone
+    {
+        for b in self.positions(key).collect::<Vec<_>>() {
+            self.bits[b / 64] |= 1 << (b % 64);
+        }
+        self.inner.index_ref(key, row);
+    }
+
+    fn undex(&mut self, key: &T, row: usize) {
+        self.inner.undex(key, row);
+    }
+
+    fn clear(&mut self) -> bool {
+        if !self.inner.clear() {
+            return false;
+        }
+        for w in &mut self.bits {
+            *w = 0;
+        }
+        true
+    }
+
+    fn entries(&self) -> Option<usize> {
+        self.inner.entries()
+    }
+
+    fn estimate(&self) -> usize {
+        self.inner.estimate()
+    }
+
+    fn estimate_for(&self, key: &T) -> usize {
+        if self.may_contain(key) {
+            self.inner.estimate_for(key)
+        } else {
+            0
+        }
+    }
+
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.inner.reserve(additional)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.inner.shrink_to_fit()
+    }
+
+    fn index_all(&mut self, entries: Vec<(T, usize)>) {
+        for (key, _) in &entries {
+            for b in self.positions(key).collect::<Vec<_>>() {
+                self.bits[b / 64] |= 1 << (b % 64);
+            }
+        }
+        self.inner.index_all(entries)
+    }
+
+    fn extended(&self) -> Option<&dyn ExtendedIndex<T>> {
+        self.inner.extended()
+    }
+
+    fn as_range(&self) -> Option<&dyn RangeIndex<T>> {
+        // the filter only helps with lookups of a single key, so range queries skip it
+        self.inner.as_range()
+    }
+}
+
+impl<T: Hash, I: RangeIndex<T>> RangeIndex<T> for BloomIndex<T, I> {
+    fn between<'a>(&'a self, min: Bound<&T>, max: Bound<&T>) -> Box<dyn Iterator<Item = usize> + 'a> {
+        self.inner.between(min, max)
+    }
+
+    fn between_rev<'a>(&'a self,
+                       min: Bound<&T>,
+                       max: Bound<&T>)
+                       -> Box<dyn Iterator<Item = usize> + 'a> {
+        self.inner.between_rev(min, max)
+    }
+
+    fn keys_between<'a>(&'a self,
+                        min: Bound<&T>,
+                        max: Bound<&T>)
+                        -> Option<Box<dyn Iterator<Item = (&'a T, usize)> + 'a>> {
+        self.inner.keys_between(min, max)
+    }
+}
+
+/// Split the given text into lowercased terms, as used by `TextIndex` and
+/// `cmp::Comparison::ContainsTerm`. Terms are maximal runs of alphanumeric characters.
+pub fn tokenize<'a>(text: &'a str) -> impl Iterator<Item = String> + 'a {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+}
+
+/// An inverted index that maps each term in a text column to the rows whose value contains it,
+/// allowing `Comparison::ContainsTerm` to be answered without a full scan. See `tokenize` for how
+/// values are split into terms.
+///
+/// When used for equality lookups, a `TextIndex` yields all rows that contain *every* term of the
+/// given value. This is a superset of the rows with that exact value, so it is still correct, but
+/// a `HashIndex` is a better choice if equality lookups are common.
+#[derive(Clone)]
+pub struct TextIndex<T> {
+    num: usize,
+    map: HashMap<String, Vec<usize>>,
+    as_text: fn(&T) -> &str,
+}
+
+fn as_text<T: AsRef<str>>(t: &T) -> &str {
+    t.as_ref()
+}
+
+impl<T> TextIndex<T> {
+    /// Allocate a new `TextIndex`.
+    pub fn new() -> TextIndex<T>
+        where T: AsRef<str>
+    {
+        TextIndex {
+            num: 0,
+            map: HashMap::new(),
+            as_text: as_text::<T>,
+        }
+    }
+
+    /// Returns the distinct terms of the given value. Values without any terms are indexed under
+    /// the empty term, so that equality lookups for them still find them.
+    fn terms(&self, value: &T) -> Vec<String> {
+        let mut terms = tokenize((self.as_text)(value)).collect::<Vec<_>>();
+        terms.sort();
+        terms.dedup();
+        if terms.is_empty() {
+            terms.push(String::new());
+        }
+        terms
+    }
+}
+
+impl<T: AsRef<str>> Default for TextIndex<T> {
+    fn default() -> Self {
+        TextIndex::new()
+    }
+}
+
+impl<T> EqualityIndex<T> for TextIndex<T> {
+    fn lookup<'a>(&'a self, key: &T) -> Box<dyn Iterator<Item = usize> + 'a> {
+        let mut postings = Vec::new();
+        for term in self.terms(key) {
+            match self.map.get(&term) {
+                Some(rows) => postings.push(rows),
+                None => return Box::new(None.into_iter()),
+            }
+        }
+
+        // walk the shortest posting list, and keep the rows that contain all the other terms
+        postings.sort_by_key(|rows| rows.len());
+        let (first, rest) = postings.split_first().unwrap();
+        let rest = rest.iter()
+            .map(|rows| rows.iter().cloned().collect::<HashSet<_>>())
+            .collect::<Vec<_>>();
+        Box::new(first.iter()
+            .cloned()
+            .filter(move |row| rest.iter().all(|rows| rows.contains(row))))
+    }
+
+    fn index(&mut self, key: T, row: usize) {
+        for term in self.terms(&key) {
+            self.map.entry(term).or_default().push(row);
+            self.num += 1;
+        }
+    }
+
+    fn undex(&mut self, key: &T, row: usize) {
+        for term in self.terms(key) {
+            let empty = match self.map.get_mut(&term) {
+                Some(rows) => {
+                    if let Some(i) = rows.iter().position(|&r| r == row) {
+                        rows.swap_remove(i);
+                        self.num -= 1;
+                    }
+                    rows.is_empty()
+                }
+                None => false,
+            };
+            if empty {
+                self.map.remove(&term);
+            }
+        }
+    }
+
+    fn clear(&mut self) -> bool {
+        self.map.clear();
+        self.num = 0;
+        true
+    }
+
+    fn entries(&self) -> Option<usize> {
+        Some(self.num)
+    }
+
+    fn estimate(&self) -> usize {
+        self.num.checked_div(self.map.len()).unwrap_or(0)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        for rows in self.map.values_mut() {
+            rows.shrink_to_fit();
+        }
+        self.map.shrink_to_fit()
+    }
+
+    fn extended(&self) -> Option<&dyn ExtendedIndex<T>> {
+        Some(self)
+    }
+}
+
+impl<T> ExtendedIndex<T> for TextIndex<T> {
+    fn lookup_term<'a>(&'a self, term: &str) -> Option<Box<dyn Iterator<Item = usize> + 'a>> {
+        Some(match self.map.get(term) {
+            Some(rows) => Box::new(rows.iter().cloned()),
+            None => Box::new(None.into_iter()),
+        })
+    }
+}
+
+/// An adapter that makes another index over strings case-insensitive, by lowercasing every key
+/// before passing it on to the wrapped index. For example, `CaseInsensitive<HashIndex<String>>`
+/// finds "Foo", "FOO", and "foo" when looking up any one of them.
+///
+/// A case-insensitive index can be used for `Comparison::EqualIgnoreCase`. It can still be used
+/// for `Comparison::Equal`, since the rows it yields are a superset of the rows with the exact
+/// value, but that requires the `Store` to filter out rows that differ in case.
+#[derive(Clone)]
+pub struct CaseInsensitive<I>(I);
+
+impl<I> CaseInsensitive<I> {
+    /// Wrap the given (empty) index.
+    pub fn new(inner: I) -> CaseInsensitive<I> {
+        CaseInsensitive(inner)
+    }
+
+    /// Returns the wrapped index.
+    pub fn inner(&self) -> &I {
+        &self.0
+    }
+}
+
+/// Lowercase the given key.
+fn fold<T: AsRef<str> + From<String>>(key: &T) -> T {
+    T::from(key.as_ref().to_lowercase())
+}
+
+impl<T, I> EqualityIndex<T> for CaseInsensitive<I>
+    where T: AsRef<str> + From<String>,
+          I: EqualityIndex<T>
+{
+    fn lookup<'a>(&'a self, key: &T) -> Box<dyn Iterator<Item = usize> + 'a> {
+        self.0.lookup(&fold(key))
+    }
+
+    fn index(&mut self, key: T, row: usize) {
+        self.0.index(fold(&key), row)
+    }
+
+    fn undex(&mut self, key: &T, row: usize) {
+        self.0.undex(&fold(key), row)
+    }
+
+    fn clear(&mut self) -> bool {
+        self.0.clear()
+    }
+
+    fn entries(&self) -> Option<usize> {
+        self.0.entries()
+    }
+
+    fn estimate(&self) -> usize {
+        self.0.estimate()
+    }
+
+    fn estimate_for(&self, key: &T) -> usize {
+        self.0.estimate_for(&fold(key))
+    }
+
+    fn extended(&self) -> Option<&dyn ExtendedIndex<T>> {
+        Some(self)
+    }
+
+    fn rebuild(&mut self) {
+        self.0.rebuild()
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.0.reserve(additional)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.0.shrink_to_fit()
+    }
+
+    fn index_all(&mut self, entries: Vec<(T, usize)>) {
+        self.0.index_all(entries.into_iter().map(|(key, row)| (fold(&key), row)).collect())
+    }
+}
+
+impl<T, I> ExtendedIndex<T> for CaseInsensitive<I>
+    where T: AsRef<str> + From<String>,
+          I: EqualityIndex<T>
+{
+    fn is_case_insensitive(&self) -> bool {
+        true
+    }
+}
+
+/// A `SkipNull` wraps another index over a column of `Nullable` values, such as `Option<U>`, and
+/// keeps null values out of it. The rows with a null value are instead kept in a separate set,
+/// which also lets `Comparison::IsNull` and `Comparison::IsNotNull` use the index. This keeps the
+/// wrapped index small when most values in the column are null, and avoids one giant bucket of
+/// rows with the null value.
+///
+/// Converting a `SkipNull` into an `Index` always makes it an `Index::Equality`. If the wrapped
+/// index is a `RangeIndex`, construct an `Index::Range` directly to also use it for ranges.
+#[derive(Clone)]
+pub struct SkipNull<I> {
+    inner: I,
+    nulls: BTreeSet<usize>,
+}
+
+impl<I> SkipNull<I> {
+    /// Wrap the given (empty) index.
+    pub fn new(inner: I) -> SkipNull<I> {
+        SkipNull {
+            inner,
+            nulls: BTreeSet::new(),
+        }
+    }
+
+    /// Returns the wrapped index.
+    pub fn inner(&self) -> &I {
+        &self.inner
+    }
+}
+
+impl<T: Nullable, I: EqualityIndex<T>> EqualityIndex<T> for SkipNull<I> {
+    fn lookup<'a>(&'a self, key: &T) -> Box<dyn Iterator<Item = usize> + 'a> {
+        if key.is_null() {
+            Box::new(self.nulls.iter().cloned())
+        } else {
+            self.inner.lookup(key)
+        }
+    }
+
+    fn index(&mut self, key: T, row: usize) {
+        if key.is_null() {
+            self.nulls.insert(row);
+        } else {
+            self.inner.index(key, row);
+        }
+    }
+
+    fn index_ref(&mut self, key: &T, row: usize)
+        where T: Clone
+    {
+        if key.is_null() {
+            self.nulls.insert(row);
+        } else {
+            self.inner.index_ref(key, row);
+        }
+    }
+
+    fn undex(&mut self, key: &T, row: usize) {
+        if key.is_null() {
+            self.nulls.remove(&row);
+        } else {
+            self.inner.undex(key, row);
+        }
+    }
+
+    fn clear(&mut self) -> bool {
+        if !self.inner.clear() {
+            return false;
+        }
+        self.nulls.clear();
+        true
+    }
+
+    fn entries(&self) -> Option<usize> {
+        self.inner.entries().map(|n| n + self.nulls.len())
+    }
+
+    fn estimate(&self) -> usize {
+        self.inner.estimate()
+    }
+
+    fn estimate_for(&self, key: &T) -> usize {
+        if key.is_null() {
+            self.nulls.len()
+        } else {
+            self.inner.estimate_for(key)
+        }
+    }
+
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.inner.reserve(additional)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.inner.shrink_to_fit()
+    }
+
+    fn index_all(&mut self, mut entries: Vec<(T, usize)>) {
+        let nulls = &mut self.nulls;
+        entries.retain(|&(ref key, row)| if key.is_null() {
+            nulls.insert(row);
+            false
+        } else {
+            true
+        });
+        self.inner.index_all(entries)
+    }
+
+    fn extended(&self) -> Option<&dyn ExtendedIndex<T>> {
+        Some(self)
+    }
+}
+
+impl<T: Nullable, I: EqualityIndex<T>> ExtendedIndex<T> for SkipNull<I> {
+    fn nulls(&self) -> Option<&BTreeSet<usize>> {
+        Some(&self.nulls)
+    }
+}
+
+impl<T: Nullable + Ord, I: RangeIndex<T>> RangeIndex<T> for SkipNull<I> {
+    fn between<'a>(&'a self, min: Bound<&T>, max: Bound<&T>) -> Box<dyn Iterator<Item = usize> + 'a> {
+        let null = T::null();
+        if !in_range(&null, min, max) {
+            return self.inner.between(min, max);
+        }
+
+        // the null rows must be yielded where the null value falls in the order
+        let below = self.inner.between(min, Bound::Excluded(&null));
+        let above = self.inner.between(Bound::Excluded(&null), max);
+        Box::new(below.chain(self.nulls.iter().cloned()).chain(above))
+    }
+
+    fn between_rev<'a>(&'a self,
+                       min: Bound<&T>,
+                       max: Bound<&T>)
+                       -> Box<dyn Iterator<Item = usize> + 'a> {
+        let null = T::null();
+        if !in_range(&null, min, max) {
+            return self.inner.between_rev(min, max);
+        }
+
+        let below = self.inner.between_rev(min, Bound::Excluded(&null));
+        let above = self.inner.between_rev(Bound::Excluded(&null), max);
+        Box::new(above.chain(self.nulls.iter().rev().cloned()).chain(below))
+    }
+}
+
+/// A sum type expressing all different types of indices so they can easily be stored. Since all
+/// indices must at least implement `EqualityIndex`, this enum also forwards all calls of
+/// that trait to the underlying index for convenience.
+///
+/// The crate's own `HashIndex` and `BTreeIndex` (with their default hasher and postings) have
+/// their own variants, so that they need not be boxed. Calls to a `BTreeIndex` are dispatched
+/// statically. Since an `Index<T>` does not require `T: Hash`, the methods of a `HashIndex` that
+/// hash keys (such as `lookup`, `index`, and `undex`) are instead called through function pointers
+/// that the `HashIndex` keeps to them, which is an indirect call much like that through a trait
+/// object. Converting one of them into an `Index` picks the right variant automatically. All other
+/// indices, including a `HashIndex` or `BTreeIndex` with another hasher or other postings, are
+/// kept as trait objects.
+pub enum Index<T> {
+    /// A `HashIndex`.
+    Hash(HashIndex<T>),
+    /// A `BTreeIndex`.
+    BTree(BTreeIndex<T>),
+    /// A `RangeIndex` trait object.
+    Range(BoxedRange<T>),
+    /// An `EqualityIndex` trait object.
+    Equality(BoxedEquality<T>),
+}
+
+// without the `sync` feature, indices need not be thread-safe, so they can hold `Rc`s.
+#[cfg(feature = "sync")]
+type BoxedRange<T> = Box<dyn RangeIndex<T> + Send + Sync>;
+#[cfg(not(feature = "sync"))]
+type BoxedRange<T> = Box<dyn RangeIndex<T>>;
+#[cfg(feature = "sync")]
+type BoxedEquality<T> = Box<dyn EqualityIndex<T> + Send + Sync>;
+#[cfg(not(feature = "sync"))]
+type BoxedEquality<T> = Box<dyn EqualityIndex<T>>;
+#[cfg(feature = "sync")]
+type KeyFn<T, K> = Arc<dyn Fn(&T) -> K + Send + Sync>;
+#[cfg(not(feature = "sync"))]
+type KeyFn<T, K> = Arc<dyn Fn(&T) -> K>;
+#[cfg(feature = "sync")]
+pub(crate) type BoxedFunctional<T, R> = Box<dyn ErasedFunctional<T, R> + Send + Sync>;
+#[cfg(not(feature = "sync"))]
+pub(crate) type BoxedFunctional<T, R> = Box<dyn ErasedFunctional<T, R>>;
+
+impl<T: Clone> Index<T> {
+    /// Returns a copy of this index, which shares its contents with it until either is changed, or
+    /// `None` if it is kept as a trait object, and so cannot be copied.
+    pub(crate) fn try_clone(&self) -> Option<Index<T>> {
+        match *self {
+            Index::Hash(ref hi) => Some(Index::Hash(hi.clone())),
+            Index::BTree(ref bi) => Some(Index::BTree(bi.clone())),
+            Index::Range(..) | Index::Equality(..) => None,
+        }
+    }
+}
+
+/// An iterator over the rows indexed under a single key in an `Index::Hash` or `Index::BTree`.
+/// See `Index::lookup_postings`.
+pub(crate) type PostingsIter<'a> = iter::Cloned<slice::Iter<'a, usize>>;
+
+impl<T: Ord> Index<T> {
+    /// Like `lookup`, but returns a concrete iterator rather than a boxed one. This is only
+    /// possible for an `Index::Hash` or `Index::BTree`, whose postings are known to be
+    /// `Vec<usize>`s, so `None` is returned for indices kept as trait objects.
+    pub(crate) fn lookup_postings(&self, key: &T) -> Option<PostingsIter<'_>> {
+        let rows = match *self {
+            Index::Hash(ref hi) => (hi.ops.get)(hi, key),
+            Index::BTree(ref bi) => bi.map.get(key),
+            Index::Range(..) | Index::Equality(..) => return None,
+        };
+        Some(rows.map_or(&[][..], |rows| &rows[..]).iter().cloned())
+    }
+
+    /// Returns true if this index also supports range queries.
+    pub fn is_range(&self) -> bool {
+        self.as_range().is_some()
+    }
+
+    /// Returns this index as a `RangeIndex`, if it supports range queries.
+    pub fn as_range(&self) -> Option<&dyn RangeIndex<T>> {
+        match *self {
+            Index::BTree(ref bi) => Some(bi),
+            Index::Range(ref ri) => Some(&**ri),
+            Index::Equality(ref ei) => ei.as_range(),
+            Index::Hash(..) => None,
+        }
+    }
+}
+
+// a `HashIndex`'s keys are not known to be hashable here, so the methods that need to hash them
+// are called through the pointers it keeps to them. those calls cannot be inlined, unlike the
+// calls to a `BTreeIndex`.
+impl<T: Ord> EqualityIndex<T> for Index<T> {
+    fn lookup<'a>(&'a self, key: &T) -> Box<Iterator<Item = usize> + 'a> {
+        match *self {
+            Index::Hash(ref hi) => (hi.ops.lookup)(hi, key),
+            Index::BTree(ref bi) => bi.lookup(key),
+            Index::Range(ref ri) => ri.lookup(key),
+            Index::Equality(ref ei) => ei.lookup(key),
+        }
+    }
+    fn index(&mut self, key: T, row: usize) {
+        match *self {
+            Index::Hash(ref mut hi) => (hi.ops.index)(hi, key, row),
+            Index::BTree(ref mut bi) => bi.index(key, row),
+            Index::Range(ref mut ri) => ri.index(key, row),
+            Index::Equality(ref mut ei) => ei.index(key, row),
+        }
+    }
+    fn index_ref(&mut self, key: &T, row: usize)
+        where T: Clone
+    {
+        match *self {
+            Index::Hash(ref mut hi) => (hi.ops.index)(hi, key.clone(), row),
+            Index::BTree(ref mut bi) => bi.index(key.clone(), row),
+            Index::Range(ref mut ri) => ri.index_ref(key, row),
+            Index::Equality(ref mut ei) => ei.index_ref(key, row),
+        }
+    }
+    fn undex(&mut self, key: &T, row: usize) {
+        match *self {
+            Index::Hash(ref mut hi) => (hi.ops.undex)(hi, key, row),
+            Index::BTree(ref mut bi) => bi.undex(key, row),
+            Index::Range(ref mut ri) => ri.undex(key, row),
+            Index::Equality(ref mut ei) => ei.undex(key, row),
+        }
+    }
+    fn clear(&mut self) -> bool {
+        match *self {
+            Index::Hash(ref mut hi) => {
+                hi.map.make_mut().clear();
+                hi.num = 0;
+                true
+            }
+            Index::BTree(ref mut bi) => bi.clear(),
+            Index::Range(ref mut ri) => ri.clear(),
+            Index::Equality(ref mut ei) => ei.clear(),
+        }
+    }
+    fn entries(&self) -> Option<usize> {
+        match *self {
+            Index::Hash(ref hi) => Some(hi.num),
+            Index::BTree(ref bi) => bi.entries(),
+            Index::Range(ref ri) => ri.entries(),
+            Index::Equality(ref ei) => ei.entries(),
+        }
+    }
+    fn estimate(&self) -> usize {
+        match *self {
+            Index::Hash(ref hi) => hi.num.checked_div(hi.map.len()).unwrap_or(0),
+            Index::BTree(ref bi) => bi.estimate(),
+            Index::Range(ref ri) => ri.estimate(),
+            Index::Equality(ref ei) => ei.estimate(),
+        }
+    }
+    fn estimate_for(&self, key: &T) -> usize {
+        match *self {
+            Index::Hash(ref hi) => (hi.ops.estimate_for)(hi, key),
+            Index::BTree(ref bi) => bi.estimate_for(key),
+            Index::Range(ref ri) => ri.estimate_for(key),
+            Index::Equality(ref ei) => ei.estimate_for(key),
+        }
+    }
+    fn rebuild(&mut self) {
+        match *self {
+            Index::Hash(..) => (),
+            Index::BTree(ref mut bi) => bi.rebuild(),
+            Index::Range(ref mut ri) => ri.rebuild(),
+            Index::Equality(ref mut ei) => ei.rebuild(),
+        }
+    }
+    fn reserve(&mut self, additional: usize) {
+        match *self {
+            Index::Hash(ref mut hi) => (hi.ops.reserve)(hi, additional),
+            Index::BTree(ref mut bi) => bi.reserve(additional),
+            Index::Range(ref mut ri) => ri.reserve(additional),
+            Index::Equality(ref mut ei) => ei.reserve(additional),
+        }
+    }
+    fn shrink_to_fit(&mut self) {
+        match *self {
+            Index::Hash(ref mut hi) => (hi.ops.shrink_to_fit)(hi),
+            Index::BTree(ref mut bi) => bi.shrink_to_fit(),
+            Index::Range(ref mut ri) => ri.shrink_to_fit(),
+            Index::Equality(ref mut ei) => ei.shrink_to_fit(),
+        }
+    }
+    fn index_all(&mut self, entries: Vec<(T, usize)>) {
+        match *self {
+            Index::Hash(ref mut hi) => {
+                for (key, row) in entries {
+                    (hi.ops.index)(hi, key, row);
+                }
+            }
+            Index::BTree(ref mut bi) => bi.index_all(entries),
+            Index::Range(ref mut ri) => ri.index_all(entries),
+            Index::Equality(ref mut ei) => ei.index_all(entries),
+        }
+    }
+    fn extended(&self) -> Option<&dyn ExtendedIndex<T>> {
+        match *self {
+            Index::Hash(..) => None,
+            Index::BTree(ref bi) => bi.extended(),
+            Index::Range(ref ri) => ri.extended(),
+            Index::Equality(ref ei) => ei.extended(),
+        }
+    }
+    fn as_range(&self) -> Option<&dyn RangeIndex<T>> {
+        Index::as_range(self)
+    }
+}
+
+/// An index that is maintained from whole rows, rather than from the value of a single column like
+/// an `EqualityIndex`. The `Store` maintains all its indices other than its single-column ones
+/// through this trait, so that each of them is kept up to date in the same way.
+pub(crate) trait RowIndex<R: ?Sized> {
+    /// Add the given row to the index.
+    fn index_row(&mut self, row: &R, rowid: usize);
+
+    /// Remove the given row from the index.
+    fn undex_row(&mut self, row: &R, rowid: usize);
+
+    /// Remove all rows from the index, and return true, or return false if the index cannot be
+    /// emptied in one go, and each row has to be removed with `undex_row` instead.
+    fn clear(&mut self) -> bool;
+
+    /// Reorganize the index after many rows have been added at once. See
+    /// `EqualityIndex::rebuild`.
+    ///
+    /// The default implementation does nothing.
+    fn rebuild(&mut self) {}
+
+    /// Release any memory the index holds on to beyond what its current rows need. See
+    /// `EqualityIndex::shrink_to_fit`.
+    ///
+    /// The default implementation does nothing.
+    fn shrink_to_fit(&mut self) {}
+}
+
+/// A `CompositeIndex` indexes the values of several columns together, so that rows can be looked
+/// up by the values of all of those columns at once.
+///
+/// The key of each row in the index is the `Vec` of the row's values in the indexed columns, in
+/// the order the columns were given. The underlying index can be any `Index<Vec<T>>`, such as a
+/// `HashIndex<Vec<T>>` or a `BTreeIndex<Vec<T>>`.
+pub struct CompositeIndex<T> {
+    columns: Vec<usize>,
+    index: Index<Vec<T>>,
+}
+
+impl<T: Ord + Clone> CompositeIndex<T> {
+    /// Construct a new `CompositeIndex` over the given columns using the given index.
+    pub fn new<I: Into<Index<Vec<T>>>>(columns: &[usize], indexer: I) -> CompositeIndex<T> {
+        CompositeIndex {
+            columns: columns.to_vec(),
+            index: indexer.into(),
+        }
+    }
+
+    /// Returns the columns indexed by this index, in key order.
+    pub fn columns(&self) -> &[usize] {
+        &self.columns
+    }
+
+    /// Returns the underlying index as a `RangeIndex`, if it supports range queries. Only such
+    /// composite indices can be used for queries that only constrain a leading prefix of the
+    /// indexed columns.
+    pub fn as_range(&self) -> Option<&dyn RangeIndex<Vec<T>>> {
+        self.index.as_range()
+    }
+
+    /// Returns the key the given row is indexed under.
+    pub fn key<R: Row<T> + ?Sized>(&self, row: &R) -> Vec<T> {
+        self.columns.iter().map(|&col| row.index(col).clone()).collect()
+    }
+
+    /// Returns a copy of this index, or `None` if its underlying index cannot be copied. See
+    /// `Index::try_clone`.
+    pub(crate) fn try_clone(&self) -> Option<CompositeIndex<T>> {
+        self.index.try_clone().map(|index| {
+            CompositeIndex {
+                columns: self.columns.clone(),
+                index,
+            }
+        })
+    }
+}
+
+impl<T: Ord + Clone, R: Row<T> + ?Sized> RowIndex<R> for CompositeIndex<T> {
+    fn index_row(&mut self, row: &R, rowid: usize) {
+        let key = self.key(row);
+        self.index.index(key, rowid);
+    }
+
+    fn undex_row(&mut self, row: &R, rowid: usize) {
+        let key = self.key(row);
+        self.index.undex(&key, rowid);
+    }
+
+    fn clear(&mut self) -> bool {
+        self.index.clear()
+    }
+
+    fn rebuild(&mut self) {
+        self.index.rebuild()
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.index.shrink_to_fit()
+    }
+}
+
+impl<T: Ord> EqualityIndex<Vec<T>> for CompositeIndex<T> {
+    fn lookup<'a>(&'a self, key: &Vec<T>) -> Box<dyn Iterator<Item = usize> + 'a> {
+        self.index.lookup(key)
+    }
+    fn index(&mut self, key: Vec<T>, row: usize) {
+        self.index.index(key, row)
+    }
+    fn undex(&mut self, key: &Vec<T>, row: usize) {
+        self.index.undex(key, row)
+    }
+    fn clear(&mut self) -> bool {
+        self.index.clear()
+    }
+    fn entries(&self) -> Option<usize> {
+        self.index.entries()
+    }
+    fn estimate(&self) -> usize {
+        self.index.estimate()
+    }
+    fn estimate_for(&self, key: &Vec<T>) -> usize {
+        self.index.estimate_for(key)
+    }
+    fn reserve(&mut self, additional: usize) {
+        self.index.reserve(additional)
+    }
+    fn shrink_to_fit(&mut self) {
+        self.index.shrink_to_fit()
+    }
+    fn rebuild(&mut self) {
+        self.index.rebuild()
+    }
+}
+
+/// A `PartialIndex` indexes a column of only those rows whose value in a (possibly different)
+/// filter column lies within a given range. For example, it can index the email column of only
+/// the rows whose status column is equal to "active".
+///
+/// A partial index is smaller and cheaper to maintain than a full index over the same column, but
+/// can only be used for queries that are guaranteed to only match rows it covers.
+pub struct PartialIndex<T> {
+    column: usize,
+    filter_column: usize,
+    min: Bound<T>,
+    max: Bound<T>,
+    index: Index<T>,
+}
+
+impl<T: Ord + Clone> PartialIndex<T> {
+    /// Construct a new `PartialIndex` over the given column using the given index, covering only
+    /// rows whose value in `filter_column` lies within the given bounds.
+    pub fn new<I: Into<Index<T>>>(column: usize,
+                                  filter_column: usize,
+                                  min: Bound<T>,
+                                  max: Bound<T>,
+                                  indexer: I)
+                                  -> PartialIndex<T> {
+        PartialIndex {
+            column,
+            filter_column,
+            min,
+            max,
+            index: indexer.into(),
+        }
+    }
+
+    /// Returns the column indexed by this index.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns the column used to decide which rows this index covers, along with the bounds its
+    /// value must lie within.
+    pub fn filter(&self) -> (usize, Bound<&T>, Bound<&T>) {
+        (self.filter_column, self.min.as_ref(), self.max.as_ref())
+    }
+
+    /// Returns true if the given row is covered by this index.
+    pub fn covers<R: Row<T> + ?Sized>(&self, row: &R) -> bool {
+        ::in_bounds(row.index(self.filter_column), &self.min, &self.max)
+    }
+
+    /// Returns the key the given row is indexed under, or `None` if it is not covered.
+    pub fn key<'a, R: Row<T> + ?Sized>(&self, row: &'a R) -> Option<&'a T> {
+        if self.covers(row) {
+            Some(row.index(self.column))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the underlying index.
+    pub fn inner(&self) -> &Index<T> {
+        &self.index
+    }
+
+    /// Returns a copy of this index, or `None` if its underlying index cannot be copied. See
+    /// `Index::try_clone`.
+    pub(crate) fn try_clone(&self) -> Option<PartialIndex<T>> {
+        self.index.try_clone().map(|index| {
+            PartialIndex {
+                column: self.column,
+                filter_column: self.filter_column,
+                min: self.min.clone(),
+                max: self.max.clone(),
+                index,
+            }
+        })
+    }
+}
+
+impl<T: Ord + Clone, R: Row<T> + ?Sized> RowIndex<R> for PartialIndex<T> {
+    fn index_row(&mut self, row: &R, rowid: usize) {
+        if let Some(key) = self.key(row) {
+            self.index.index_ref(key, rowid);
+        }
+    }
+
+    fn undex_row(&mut self, row: &R, rowid: usize) {
+        if let Some(key) = self.key(row) {
+            self.index.undex(key, rowid);
+        }
+    }
+
+    fn clear(&mut self) -> bool {
+        self.index.clear()
+    }
+
+    fn rebuild(&mut self) {
+        self.index.rebuild()
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.index.shrink_to_fit()
+    }
+}
+
+impl<T: Ord> EqualityIndex<T> for PartialIndex<T> {
+    fn lookup<'a>(&'a self, key: &T) -> Box<dyn Iterator<Item = usize> + 'a> {
+        self.index.lookup(key)
+    }
+    fn index(&mut self, key: T, row: usize) {
+        self.index.index(key, row)
+    }
+    fn index_ref(&mut self, key: &T, row: usize)
+        where T: Clone
+    {
+        self.index.index_ref(key, row)
+    }
+    fn undex(&mut self, key: &T, row: usize) {
+        self.index.undex(key, row)
+    }
+    fn clear(&mut self) -> bool {
+        self.index.clear()
+    }
+    fn entries(&self) -> Option<usize> {
+        self.index.entries()
+    }
+    fn estimate(&self) -> usize {
+        self.index.estimate()
+    }
+    fn estimate_for(&self, key: &T) -> usize {
+        self.index.estimate_for(key)
+    }
+    fn rebuild(&mut self) {
+        self.index.rebuild()
+    }
+    fn shrink_to_fit(&mut self) {
+        self.index.shrink_to_fit()
+    }
+    fn extended(&self) -> Option<&dyn ExtendedIndex<T>> {
+        self.index.extended()
+    }
+}
+
+/// A `FunctionalIndex` indexes a column by a key computed from each row's value in that column,
+/// rather than by the value itself. For example, it can index a string column by the lowercased
+/// string, allowing case-insensitive lookups, or a date column by the year.
+///
+/// The computed keys can be of any type `K` (the type of the values in the column by default), and
+/// are kept in an `Index<K>`.
+pub struct FunctionalIndex<T, K = T> {
+    column: usize,
+    key: KeyFn<T, K>,
+    index: Index<K>,
+}
+
+impl<T, K: Ord> FunctionalIndex<T, K> {
+    /// Construct a new `FunctionalIndex` over the given column using the given index, where
+    /// values are indexed by the key computed by `f`.
+    pub fn new<F, I>(column: usize, f: F, indexer: I) -> FunctionalIndex<T, K>
+        where F: Fn(&T) -> K + MaybeSync + 'static,
+              I: Into<Index<K>>
+    {
+        FunctionalIndex {
+            column,
+            key: Arc::new(f),
+            index: indexer.into(),
+        }
+    }
+
+    /// Returns the column indexed by this index.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns the key the given value is indexed under.
+    pub fn key(&self, value: &T) -> K {
+        (self.key)(value)
+    }
+
+    /// Returns a copy of this index, or `None` if its underlying index cannot be copied. See
+    /// `Index::try_clone`.
+    pub(crate) fn try_clone(&self) -> Option<FunctionalIndex<T, K>>
+        where K: Clone
+    {
+        self.index.try_clone().map(|index| {
+            FunctionalIndex {
+                column: self.column,
+                key: self.key.clone(),
+                index,
+            }
+        })
+    }
+}
+
+impl<T, K: Ord, R: Row<T> + ?Sized> RowIndex<R> for FunctionalIndex<T, K> {
+    fn index_row(&mut self, row: &R, rowid: usize) {
+        let key = self.key(row.index(self.column));
+        self.index.index(key, rowid);
+    }
+
+    fn undex_row(&mut self, row: &R, rowid: usize) {
+        let key = self.key(row.index(self.column));
+        self.index.undex(&key, rowid);
+    }
+
+    fn clear(&mut self) -> bool {
+        self.index.clear()
+    }
+
+    fn rebuild(&mut self) {
+        self.index.rebuild()
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.index.shrink_to_fit()
+    }
+}
+
+impl<T, K: Ord> EqualityIndex<K> for FunctionalIndex<T, K> {
+    fn lookup<'a>(&'a self, key: &K) -> Box<dyn Iterator<Item = usize> + 'a> {
+        self.index.lookup(key)
+    }
+    fn index(&mut self, key: K, row: usize) {
+        self.index.index(key, row)
+    }
+    fn index_ref(&mut self, key: &K, row: usize)
+        where K: Clone
+    {
+        self.index.index_ref(key, row)
+    }
+    fn undex(&mut self, key: &K, row: usize) {
+        self.index.undex(key, row)
+    }
+    fn clear(&mut self) -> bool {
+        self.index.clear()
+    }
+    fn entries(&self) -> Option<usize> {
+        self.index.entries()
+    }
+    fn estimate(&self) -> usize {
+        self.index.estimate()
+    }
+    fn estimate_for(&self, key: &K) -> usize {
+        self.index.estimate_for(key)
+    }
+    fn rebuild(&mut self) {
+        self.index.rebuild()
+    }
+    fn shrink_to_fit(&mut self) {
+        self.index.shrink_to_fit()
+    }
+}
+
+/// A `FunctionalIndex` whose key type is hidden, so that a `Store` can keep functional indices
+/// with different key types side by side. Lookups pass the key as an `Any`, and find nothing if it
+/// is not of the index's key type.
+pub(crate) trait ErasedFunctional<T, R: ?Sized>: RowIndex<R> {
+    /// Returns the rows indexed under the given key, or `None` if the key is not of the type this
+    /// index computes.
+    fn lookup_any<'a>(&'a self, key: &dyn Any) -> Option<Box<dyn Iterator<Item = usize> + 'a>>;
+
+    /// Move the given row from the key computed from its old value in the indexed column to the
+    /// key computed from its new value, if the two keys differ.
+    fn update(&mut self, old: &T, new: &T, rowid: usize);
+
+    /// Returns this index as a `RowIndex`.
+    fn as_row_index(&mut self) -> &mut dyn RowIndex<R>;
+
+    /// Returns a copy of this index, or `None` if its underlying index cannot be copied.
+    fn try_clone(&self) -> Option<BoxedFunctional<T, R>>;
+}
+
+impl<T, K, R> ErasedFunctional<T, R> for FunctionalIndex<T, K>
+    where T: 'static,
+          K: Ord + Clone + MaybeSync + 'static,
+          R: Row<T> + ?Sized
+{
+    fn lookup_any<'a>(&'a self, key: &dyn Any) -> Option<Box<dyn Iterator<Item = usize> + 'a>> {
+        key.downcast_ref::<K>().map(|key| self.index.lookup(key))
+    }
+
+    fn update(&mut self, old: &T, new: &T, rowid: usize) {
+        let (old, new) = (self.key(old), self.key(new));
+        if old != new {
+            self.index.undex(&old, rowid);
+            self.index.index(new, rowid);
+        }
+    }
+
+    fn as_row_index(&mut self) -> &mut dyn RowIndex<R> {
+        self
+    }
+
+    fn try_clone(&self) -> Option<BoxedFunctional<T, R>> {
+        FunctionalIndex::try_clone(self).map(|fi| Box::new(fi) as BoxedFunctional<T, R>)
+    }
+}
+
+/// A `CoveringIndex` indexes a column like a `BTreeIndex`, but also keeps a copy of each indexed
+/// row alongside its rowid. Rows found through a covering index can therefore be yielded directly
+/// from the index, without looking up each rowid in the `Store`, which makes point lookups that
+/// match many rows considerably cheaper.
+///
+/// In exchange, every row is stored twice, and every change to a row (even to columns that are not
+/// indexed) must also be applied to its copy.
+pub struct CoveringIndex<T, R> {
+    column: usize,
+    num: usize,
+    map: Shared<BTreeMap<T, Vec<(usize, R)>>>,
+    copy: fn(&R) -> R,
+}
+
+impl<T: Clone, R: Clone> Clone for CoveringIndex<T, R> {
+    fn clone(&self) -> Self {
+        CoveringIndex {
+            column: self.column,
+            num: self.num,
+            map: self.map.clone(),
+            copy: self.copy,
+        }
+    }
+}
+
+impl<T: Ord + Clone, R: Row<T>> CoveringIndex<T, R> {
+    /// Construct a new `CoveringIndex` over the given column.
+    pub fn new(column: usize) -> CoveringIndex<T, R>
+        where R: Clone
+    {
+        CoveringIndex {
+            column,
+            num: 0,
+            map: Shared::new(BTreeMap::new()),
+            copy: R::clone,
+        }
+    }
+
+    /// Returns the column indexed by this index.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Return an iterator that yields the rowids and copies of all rows with the given value.
+    pub fn lookup<'a>(&'a self, key: &T) -> Box<dyn Iterator<Item = (usize, &'a R)> + 'a> {
+        match self.map.get(key) {
+            Some(rows) => Box::new(rows.iter().map(|&(rowid, ref row)| (rowid, row))),
+            None => Box::new(None.into_iter()),
+        }
+    }
+
+    /// Remove all rows from the index.
+    pub fn clear(&mut self) {
+        self.map.make_mut().clear();
+        self.num = 0;
+    }
+
+    /// Give the total number of rows in the index.
+    pub fn entries(&self) -> usize {
+        self.num
+    }
+
+    /// Release any spare capacity in the lists of rows kept for each value. See
+    /// `EqualityIndex::shrink_to_fit`.
+    pub fn shrink_to_fit(&mut self) {
+        for rows in self.map.make_mut().values_mut() {
+            rows.shrink_to_fit();
+        }
+    }
+
+    /// Give the expected number of rows returned for a key. See `EqualityIndex::estimate`.
+    pub fn estimate(&self) -> usize {
+        self.num.checked_div(self.map.len()).unwrap_or(0)
+    }
+
+    /// Give the number of rows returned for the given key. See `EqualityIndex::estimate_for`.
+    pub fn estimate_for(&self, key: &T) -> usize {
+        self.map.get(key).map(|rows| rows.len()).unwrap_or(0)
+    }
+}
+
+impl<T: Ord + Clone, R: Row<T>> RowIndex<R> for CoveringIndex<T, R> {
+    fn index_row(&mut self, row: &R, rowid: usize) {
+        let key = row.index(self.column).clone();
+        self.map.make_mut().entry(key).or_default().push((rowid, (self.copy)(row)));
+        self.num += 1;
+    }
+
+    fn undex_row(&mut self, row: &R, rowid: usize) {
+        let key = row.index(self.column);
+        let empty = match self.map.make_mut().get_mut(key) {
+            Some(rows) => {
+                let before = rows.len();
+                rows.retain(|&(i, _)| i != rowid);
+                self.num -= before - rows.len();
+                rows.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.map.make_mut().remove(key);
+        }
+    }
+
+    fn clear(&mut self) -> bool {
+        CoveringIndex::clear(self);
+        true
+    }
+
+    fn shrink_to_fit(&mut self) {
+        CoveringIndex::shrink_to_fit(self)
+    }
+}
+
+// an index with the default hasher and postings gets its own variant, and any other is boxed.
+// `Any` can tell the two apart without boxing the index first.
+impl<T, S, P> From<HashIndex<T, S, P>> for Index<T>
+    where T: Eq + Hash + 'static + MaybeSync,
+          S: BuildHasher + 'static + MaybeSync,
+          P: Postings + 'static + MaybeSync
+{
+    fn from(x: HashIndex<T, S, P>) -> Index<T> {
+        let mut x = Some(x);
+        if let Some(hi) = (&mut x as &mut dyn Any).downcast_mut::<Option<HashIndex<T>>>() {
+            return Index::Hash(hi.take().unwrap());
+        }
+        Index::Equality(Box::new(x.unwrap()))
+    }
+}
+
+impl<T, P> From<BTreeIndex<T, P>> for Index<T>
+    where T: Ord + 'static + MaybeSync,
+          P: Postings + 'static + MaybeSync
+{
+    fn from(x: BTreeIndex<T, P>) -> Index<T> {
+        let mut x = Some(x);
+        if let Some(bi) = (&mut x as &mut dyn Any).downcast_mut::<Option<BTreeIndex<T>>>() {
+            return Index::BTree(bi.take().unwrap());
+        }
+        Index::Range(Box::new(x.unwrap()))
+    }
+}
+
+impl<T, I> From<CaseInsensitive<I>> for Index<T>
+    where T: AsRef<str> + From<String> + 'static,
+          I: EqualityIndex<T> + 'static + MaybeSync
+{
+    fn from(x: CaseInsensitive<I>) -> Index<T> {
+        Index::Equality(Box::new(x))
+    }
+}
+
+impl<T: Ord + 'static + MaybeSync> From<FlatIndex<T>> for Index<T> {
+    fn from(x: FlatIndex<T>) -> Index<T> {
+        Index::Range(Box::new(x))
+    }
+}
+
+impl<T, I> From<SkipNull<I>> for Index<T>
+    where T: Nullable + 'static,
+          I: EqualityIndex<T> + 'static + MaybeSync
+{
+    fn from(x: SkipNull<I>) -> Index<T> {
+        Index::Equality(Box::new(x))
+    }
+}
+
+impl<T, S> From<HashedIndex<T, S>> for Index<T>
+    where T: Hash + 'static,
+          S: BuildHasher + 'static + MaybeSync
+{
+    fn from(x: HashedIndex<T, S>) -> Index<T> {
+        Index::Equality(Box::new(x))
+    }
+}
+
+impl<T: 'static> From<TextIndex<T>> for Index<T> {
+    fn from(x: TextIndex<T>) -> Index<T> {
+        Index::Equality(Box::new(x))
+    }
+}
+
+impl<T, I> From<BloomIndex<T, I>> for Index<T>
+    where T: Hash + 'static,
+          I: EqualityIndex<T> + 'static + MaybeSync
+{
+    fn from(x: BloomIndex<T, I>) -> Index<T> {
+        Index::Equality(Box::new(x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
     #[test]
     fn hashmap_eq_index() {
@@ -198,6 +2471,285 @@ mod tests {
         assert_eq!(eqidx.lookup(&"a").count(), 2);
         eqidx.undex(&"a", 0);
         assert_eq!(eqidx.lookup(&"a").count(), 1);
+        assert_eq!(eqidx.entries(), Some(1));
+        eqidx.clear();
+        assert_eq!(eqidx.lookup(&"a").count(), 0);
+    }
+
+    #[test]
+    fn hashmap_custom_hasher() {
+        use std::hash::BuildHasherDefault;
+
+        /// A hasher that uses integer keys as their own hash.
+        #[derive(Default)]
+        struct Identity(u64);
+        impl Hasher for Identity {
+            fn finish(&self) -> u64 {
+                self.0
+            }
+            fn write(&mut self, bytes: &[u8]) {
+                for b in bytes {
+                    self.0 = (self.0 << 8) | u64::from(*b);
+                }
+            }
+            fn write_u32(&mut self, i: u32) {
+                self.0 = u64::from(i);
+            }
+        }
+
+        let mut idx = HashIndex::with_hasher(BuildHasherDefault::<Identity>::default());
+        for i in 0..100u32 {
+            idx.index(i % 10, i as usize);
+        }
+        assert_eq!(idx.estimate(), 10);
+        assert_eq!(idx.lookup(&3).count(), 10);
+        idx.undex(&3, 3);
+        assert_eq!(idx.lookup(&3).count(), 9);
+
+        let mut store = ::Store::new(1);
+        let idx = HashIndex::with_hasher(BuildHasherDefault::<Identity>::default());
+        store.index(0, idx);
+        store.insert(vec![1u32]);
+        assert_eq!(store.stats().indices[&0].entries, 1);
+    }
+
+    #[test]
+    fn hashed_index() {
+        use std::hash::BuildHasherDefault;
+
+        let mut idx = HashedIndex::new();
+        idx.index_ref(&"a".to_owned(), 0);
+        idx.index("b".to_owned(), 1);
+        idx.index_ref(&"a".to_owned(), 2);
+        assert_eq!(idx.lookup(&"a".to_owned()).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(idx.estimate_for(&"b".to_owned()), 1);
+        idx.undex(&"a".to_owned(), 0);
+        assert_eq!(idx.lookup(&"a".to_owned()).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(idx.entries(), Some(2));
+
+        // a hasher that sends every value to the same bucket
+        #[derive(Default)]
+        struct Collide;
+        impl Hasher for Collide {
+            fn finish(&self) -> u64 {
+                0
+            }
+            fn write(&mut self, _: &[u8]) {}
+        }
+
+        let mut store = ::Store::new(2);
+        store.index(0, HashedIndex::with_hasher(BuildHasherDefault::<Collide>::default()));
+        store.insert(vec!["a".to_owned(), "x".to_owned()]);
+        store.insert(vec!["b".to_owned(), "y".to_owned()]);
+        let cmp = [::Condition {
+                       column: 0,
+                       cmp: ::Comparison::Equal(::Value::new("b".to_owned())),
+                   }];
+        // the index yields both rows, but only the matching one is returned
+        assert_eq!(store.find(&cmp).map(|r| &r[1][..]).collect::<Vec<_>>(), vec!["y"]);
+        store.update(&cmp, |r| r[0] = "c".to_owned());
+        assert_eq!(store.find(&cmp).count(), 0);
+        assert_eq!(store.stats().indices[&0].entries, 2);
+    }
+
+    /// An index that implements only the required methods of `EqualityIndex`.
+    struct Minimal(HashMap<i32, Vec<usize>>);
+
+    impl EqualityIndex<i32> for Minimal {
+        fn lookup<'a>(&'a self, key: &i32) -> Box<dyn Iterator<Item = usize> + 'a> {
+            Box::new(self.0.get(key).into_iter().flat_map(|rows| rows.iter().cloned()))
+        }
+        fn index(&mut self, key: i32, row: usize) {
+            self.0.entry(key).or_default().push(row);
+        }
+        fn undex(&mut self, key: &i32, row: usize) {
+            if let Some(rows) = self.0.get_mut(key) {
+                rows.retain(|&r| r != row);
+            }
+        }
+        fn estimate(&self) -> usize {
+            1
+        }
+    }
+
+    #[test]
+    fn minimal_index() {
+        let mut store = ::Store::new(1);
+        store.index(0, Index::Equality(Box::new(Minimal(HashMap::new()))));
+        store.insert(vec![1]);
+        store.insert(vec![2]);
+        let cmp = [::Condition {
+                       column: 0,
+                       cmp: ::Comparison::Equal(::Value::new(2)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(!store.stats().indices.contains_key(&0));
+
+        // an index that cannot be cleared has its rows removed one at a time instead
+        assert!(store.reindex(0));
+        assert_eq!(store.find(&cmp).count(), 1);
+        store.clear();
+        assert_eq!(store.find(&cmp).count(), 0);
+        store.insert(vec![2]);
+        store.insert(vec![3]);
+        store.delete(&[::Condition {
+                           column: 0,
+                           cmp: ::Comparison::Equal(::Value::new(2)),
+                       }]);
+        assert_eq!(store.compact(), vec![3]);
+        assert_eq!(store.find(&cmp).count(), 0);
+        store.truncate();
+        store.insert(vec![2]);
+        assert_eq!(store.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn index_variants() {
+        let mut idx = Index::from(HashIndex::new());
+        assert!(matches!(idx, Index::Hash(..)));
+        for i in 0..100 {
+            idx.index(i % 10, i as usize);
+        }
+        assert_eq!(idx.entries(), Some(100));
+        assert_eq!(idx.estimate(), 10);
+        assert_eq!(idx.estimate_for(&3), 10);
+        idx.undex(&3, 3);
+        assert_eq!(idx.lookup(&3).count(), 9);
+        assert!(!idx.is_range());
+        idx.clear();
+        assert_eq!(idx.entries(), Some(0));
+        assert_eq!(idx.estimate(), 0);
+
+        let idx = Index::<i32>::from(BTreeIndex::new());
+        assert!(matches!(idx, Index::BTree(..)));
+        assert!(idx.is_range());
+
+        // indices with other postings are kept as trait objects
+        let idx = Index::<i32>::from(HashIndex::<_, RandomState, Vec<u32>>::default());
+        assert!(matches!(idx, Index::Equality(..)));
+        let idx = Index::<i32>::from(BTreeIndex::<_, Vec<u32>>::default());
+        assert!(matches!(idx, Index::Range(..)));
+        assert!(idx.is_range());
+
+        // values need not be hashable unless they are put in a `HashIndex`
+        #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+        struct Unhashable(i32);
+        let mut store = ::Store::new(1);
+        store.index(0, BTreeIndex::new());
+        store.insert(vec![Unhashable(1)]);
+        assert_eq!(store.find(&[::cmp::Condition {
+                                     column: 0,
+                                     cmp: ::cmp::Comparison::Equal(::cmp::Value::new(Unhashable(1))),
+                                 }])
+                       .count(),
+                   1);
+    }
+
+    #[test]
+    fn shared_clones() {
+        let mut idx = HashIndex::new();
+        idx.index(1, 0);
+        let mut copy = idx.clone();
+        copy.index(1, 1);
+        idx.undex(&1, 0);
+        assert_eq!(idx.lookup(&1).count(), 0);
+        assert_eq!(copy.lookup(&1).collect::<Vec<_>>(), vec![0, 1]);
+
+        let mut idx = BTreeIndex::new();
+        idx.index_all(vec![(1, 0), (2, 1)]);
+        let copy = idx.clone();
+        idx.clear();
+        assert_eq!(idx.entries(), Some(0));
+        assert_eq!(copy.between(Bound::Unbounded, Bound::Unbounded).collect::<Vec<_>>(),
+                   vec![0, 1]);
+    }
+
+    #[test]
+    fn u32_postings() {
+        use std::ops::Bound::{Included, Unbounded};
+
+        let mut idx = HashIndex::<_, RandomState, Vec<u32>>::default();
+        idx.index("a", 2);
+        idx.index("a", 1);
+        assert_eq!(idx.lookup(&"a").collect::<Vec<_>>(), vec![2, 1]);
+        idx.undex(&"a", 2);
+        assert_eq!(idx.lookup(&"a").collect::<Vec<_>>(), vec![1]);
+        assert_eq!(idx.entries(), Some(1));
+
+        let mut store = ::Store::new(1);
+        store.index(0, BTreeIndex::<_, Vec<u32>>::default());
+        for i in 0..10 {
+            store.insert(vec![i % 2]);
+        }
+        assert_eq!(store.stats().indices[&0].entries, 10);
+        let cmp = [::Condition {
+                       column: 0,
+                       cmp: ::Comparison::Equal(::Value::new(1)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 5);
+        assert_eq!(store.find_range(0, Included(&1), Unbounded).count(), 5);
+    }
+
+    #[test]
+    #[cfg(feature = "roaring")]
+    fn roaring_postings() {
+        use roaring::RoaringBitmap;
+        use std::ops::Bound::{Included, Unbounded};
+
+        let mut idx = BTreeIndex::<_, RoaringBitmap>::default();
+        for i in 0..1000 {
+            idx.index(i % 3, 999 - i);
+        }
+        assert_eq!(idx.entries(), Some(1000));
+        assert_eq!(idx.estimate(), 333);
+        // rows come out in ascending order, not insertion order
+        assert_eq!(idx.lookup(&0).take(3).collect::<Vec<_>>(), vec![0, 3, 6]);
+        assert_eq!(idx.between(Included(&1), Unbounded).count(), 666);
+        idx.undex(&0, 0);
+        idx.undex(&0, 1);
+        assert_eq!(idx.entries(), Some(999));
+        assert_eq!(idx.lookup(&0).next(), Some(3));
+
+        let mut idx = HashIndex::<_, RandomState, RoaringBitmap>::default();
+        idx.index("a", 2);
+        idx.index("a", 1);
+        assert_eq!(idx.lookup(&"a").collect::<Vec<_>>(), vec![1, 2]);
+        idx.undex(&"a", 1);
+        idx.undex(&"a", 2);
+        assert_eq!(idx.lookup(&"a").count(), 0);
+        assert_eq!(idx.entries(), Some(0));
+
+        let mut store = ::Store::new(1);
+        store.index(0, BTreeIndex::<_, RoaringBitmap>::default());
+        store.insert(vec![1]);
+        store.insert(vec![1]);
+        assert_eq!(store.stats().indices[&0].entries, 2);
+    }
+
+    #[test]
+    #[cfg(feature = "smallvec")]
+    fn smallvec_postings() {
+        use smallvec::SmallVec;
+        use std::ops::Bound::{Excluded, Unbounded};
+
+        let mut idx = HashIndex::<_, RandomState, SmallVec<[u32; 1]>>::default();
+        idx.index("a", 0);
+        idx.index("b", 1);
+        idx.index("a", 2);
+        assert_eq!(idx.lookup(&"a").collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(idx.estimate(), 1);
+        idx.undex(&"a", 0);
+        assert_eq!(idx.lookup(&"a").collect::<Vec<_>>(), vec![2]);
+        assert_eq!(idx.entries(), Some(2));
+
+        let mut idx = BTreeIndex::<_, SmallVec<[u32; 2]>>::default();
+        for i in 0..6 {
+            idx.index(i / 2, i);
+        }
+        assert_eq!(idx.between(Excluded(&0), Unbounded).collect::<Vec<_>>(),
+                   vec![2, 3, 4, 5]);
+        idx.undex(&1, 2);
+        assert_eq!(idx.lookup(&1).collect::<Vec<_>>(), vec![3]);
     }
 
     #[test]
@@ -211,6 +2763,200 @@ mod tests {
         assert_eq!(idx.lookup(&"a").count(), 2);
         idx.undex(&"a", 0);
         assert_eq!(idx.lookup(&"a").count(), 1);
+        assert_eq!(idx.entries(), Some(1));
+        idx.clear();
+        assert_eq!(idx.lookup(&"a").count(), 0);
+    }
+
+    #[test]
+    fn btree_index_all() {
+        use super::RangeIndex;
+        use std::ops::Bound::Unbounded;
+
+        let mut idx = BTreeIndex::new();
+        idx.index_all(vec![("b", 0), ("a", 1), ("b", 2), ("c", 3), ("a", 4)]);
+        assert_eq!(idx.entries(), Some(5));
+        assert_eq!(idx.lookup(&"a").collect::<Vec<_>>(), vec![1, 4]);
+        assert_eq!(idx.lookup(&"b").collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(idx.between(Unbounded, Unbounded).collect::<Vec<_>>(), vec![1, 4, 0, 2, 3]);
+
+        // entries are merged into an index that already holds some
+        idx.index_all(vec![("a", 5), ("d", 6)]);
+        assert_eq!(idx.entries(), Some(7));
+        assert_eq!(idx.lookup(&"a").collect::<Vec<_>>(), vec![1, 4, 5]);
+        assert_eq!(idx.lookup(&"d").collect::<Vec<_>>(), vec![6]);
+
+        let mut idx = SkipNull::new(BloomIndex::new(BTreeIndex::new(), 10));
+        idx.index_all(vec![(Some(1), 0), (None, 1), (Some(1), 2)]);
+        assert_eq!(idx.entries(), Some(3));
+        assert_eq!(idx.lookup(&Some(1)).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(idx.lookup(&None).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(idx.lookup(&Some(2)).count(), 0);
+    }
+
+    #[test]
+    fn btree_shrink_to_fit() {
+        let mut idx = BTreeIndex::new();
+        idx.index("a", 0);
+        idx.index("b", 1);
+        idx.index("b", 2);
+        idx.undex(&"a", 0);
+        assert_eq!(idx.estimate(), 1);
+
+        // the emptied postings for "a" are dropped
+        idx.shrink_to_fit();
+        assert_eq!(idx.estimate(), 2);
+        assert_eq!(idx.entries(), Some(2));
+        assert_eq!(idx.lookup(&"b").collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn composite_index() {
+        let mut idx = CompositeIndex::new(&[2, 0], HashIndex::new());
+        assert_eq!(idx.columns(), &[2, 0]);
+        assert_eq!(idx.key(&["a", "b", "c"][..]), vec!["c", "a"]);
+
+        idx.index_row(&["a", "b", "c"][..], 0);
+        idx.index_row(&["a", "x", "c"][..], 1);
+        idx.index_row(&["c", "b", "a"][..], 2);
+        assert_eq!(idx.entries(), Some(3));
+        assert_eq!(idx.lookup(&vec!["c", "a"]).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(idx.lookup(&vec!["a", "c"]).collect::<Vec<_>>(), vec![2]);
+        idx.undex_row(&["a", "b", "c"][..], 0);
+        assert_eq!(idx.lookup(&vec!["c", "a"]).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(idx.entries(), Some(2));
+    }
+
+    #[test]
+    fn partial_index() {
+        let mut idx =
+            PartialIndex::new(0, 1, Bound::Included("b"), Bound::Unbounded, HashIndex::new());
+        assert_eq!(idx.column(), 0);
+        assert_eq!(idx.filter(), (1, Bound::Included(&"b"), Bound::Unbounded));
+        assert!(!idx.covers(&["x", "a"][..]));
+        assert!(idx.covers(&["x", "b"][..]));
+
+        idx.index_row(&["x", "a"][..], 0);
+        idx.index_row(&["x", "c"][..], 1);
+        assert_eq!(idx.entries(), Some(1));
+        assert_eq!(idx.lookup(&"x").collect::<Vec<_>>(), vec![1]);
+        idx.undex_row(&["x", "a"][..], 0);
+        idx.undex_row(&["x", "c"][..], 1);
+        assert_eq!(idx.entries(), Some(0));
+    }
+
+    #[test]
+    fn functional_index() {
+        let mut idx = FunctionalIndex::new(1, |v: &String| v.to_lowercase(), HashIndex::new());
+        assert_eq!(idx.column(), 1);
+        assert_eq!(idx.key(&"AbC".to_owned()), "abc");
+
+        let a = vec!["x".to_owned(), "Foo".to_owned()];
+        let b = vec!["y".to_owned(), "FOO".to_owned()];
+        idx.index_row(&a, 0);
+        idx.index_row(&b, 1);
+        assert_eq!(idx.lookup(&"foo".to_owned()).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(idx.lookup(&"Foo".to_owned()).count(), 0);
+        idx.undex_row(&a, 0);
+        assert_eq!(idx.lookup(&"foo".to_owned()).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn bloom_index() {
+        let mut idx = BloomIndex::new(HashIndex::new(), 100);
+        for i in 0..100 {
+            idx.index(i, i as usize);
+        }
+        for i in 0..100 {
+            assert!(idx.may_contain(&i));
+            assert_eq!(idx.lookup(&i).collect::<Vec<_>>(), vec![i as usize]);
+        }
+        let false_positives = (100..10100).filter(|i| idx.may_contain(i)).count();
+        assert!(false_positives < 500, "{} false positives", false_positives);
+        assert_eq!(idx.lookup(&100).count(), 0);
+
+        idx.undex(&0, 0);
+        assert_eq!(idx.lookup(&0).count(), 0);
+        assert_eq!(idx.entries(), Some(99));
+        idx.clear();
+        assert!(!idx.may_contain(&1));
+        assert_eq!(idx.inner().entries(), Some(0));
+
+        // wrapping a range index keeps it usable for range queries
+        assert!(Index::from(BloomIndex::new(HashIndex::<u32>::new(), 10)).as_range().is_none());
+        let mut idx = Index::from(BloomIndex::new(BTreeIndex::new(), 10));
+        for i in 0..10 {
+            idx.index(i, i as usize);
+        }
+        let range = idx.as_range().unwrap();
+        assert_eq!(range.between(Bound::Included(&3), Bound::Excluded(&6)).collect::<Vec<_>>(),
+                   vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn text_index() {
+        assert_eq!(tokenize("Hello, wide world!").collect::<Vec<_>>(),
+                   vec!["hello", "wide", "world"]);
+
+        let mut idx = TextIndex::new();
+        idx.index("hello world", 0);
+        idx.index("Hello hello there", 1);
+        idx.index("...", 2);
+        assert_eq!(idx.entries(), Some(5));
+        assert_eq!(idx.lookup_term("hello").unwrap().collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(idx.lookup_term("there").unwrap().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(idx.lookup_term("nope").unwrap().count(), 0);
+        assert_eq!(idx.lookup(&"World, hello").collect::<Vec<_>>(), vec![0]);
+        assert_eq!(idx.lookup(&"").collect::<Vec<_>>(), vec![2]);
+
+        idx.undex(&"hello world", 0);
+        assert_eq!(idx.lookup_term("hello").unwrap().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(idx.lookup_term("world").unwrap().count(), 0);
+        assert_eq!(idx.entries(), Some(3));
+        assert!(HashIndex::<&str>::new().extended().is_none());
+    }
+
+    #[test]
+    fn case_insensitive_index() {
+        let mut idx = CaseInsensitive::new(HashIndex::new());
+        assert!(idx.is_case_insensitive());
+        assert!(idx.inner().extended().is_none());
+        idx.index("Foo".to_owned(), 0);
+        idx.index("FOO".to_owned(), 1);
+        idx.index("bar".to_owned(), 2);
+        assert_eq!(idx.lookup(&"foo".to_owned()).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(idx.lookup(&"BAR".to_owned()).collect::<Vec<_>>(), vec![2]);
+        idx.undex(&"Foo".to_owned(), 0);
+        assert_eq!(idx.lookup(&"fOO".to_owned()).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(idx.entries(), Some(2));
+    }
+
+    #[test]
+    fn skip_null_index() {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+
+        let mut idx = SkipNull::new(BTreeIndex::new());
+        idx.index(None, 0);
+        idx.index(Some(2), 1);
+        idx.index(None, 2);
+        idx.index(Some(1), 3);
+        assert_eq!(idx.entries(), Some(4));
+        assert_eq!(idx.inner().entries(), Some(2));
+        assert_eq!(idx.lookup(&None).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(idx.lookup(&Some(2)).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(idx.nulls().map(|n| n.len()), Some(2));
+
+        // None sorts before all other values
+        assert_eq!(idx.between(Unbounded, Unbounded).collect::<Vec<_>>(), vec![0, 2, 3, 1]);
+        assert_eq!(idx.between_rev(Unbounded, Included(&Some(1))).collect::<Vec<_>>(),
+                   vec![3, 2, 0]);
+        assert_eq!(idx.between(Excluded(&None), Unbounded).collect::<Vec<_>>(), vec![3, 1]);
+
+        idx.undex(&None, 0);
+        idx.undex(&Some(1), 3);
+        assert_eq!(idx.between(Unbounded, Unbounded).collect::<Vec<_>>(), vec![2, 1]);
+        idx.clear();
+        assert_eq!(idx.entries(), Some(0));
     }
 
     #[test]
@@ -227,4 +2973,73 @@ mod tests {
         idx.undex(&"b", 1);
         assert_eq!(idx.between(Included(&"a"), Included(&"b")).count(), 1);
     }
+
+    #[test]
+    fn btree_range_index_order() {
+        use super::RangeIndex;
+        use std::ops::Bound::{Included, Unbounded};
+
+        let mut idx = BTreeIndex::new();
+        idx.index("b", 0);
+        idx.index("c", 1);
+        idx.index("a", 2);
+        assert_eq!(idx.between(Unbounded, Unbounded).collect::<Vec<_>>(), vec![2, 0, 1]);
+        assert_eq!(idx.between_rev(Included(&"b"), Unbounded).collect::<Vec<_>>(),
+                   vec![1, 0]);
+    }
+
+    #[test]
+    fn flat_index() {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+
+        let mut idx = FlatIndex::new();
+        for (i, k) in [3, 1, 4, 1, 5, 9, 2, 6].iter().enumerate() {
+            idx.index(*k, i);
+        }
+        assert_eq!(idx.entries(), Some(8));
+        // lookups work before the index is rebuilt, just more slowly
+        assert_eq!(idx.lookup(&1).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(idx.between(Included(&2), Excluded(&6)).collect::<Vec<_>>(),
+                   vec![6, 0, 2, 4]);
+
+        idx.rebuild();
+        assert_eq!(idx.estimate(), 1);
+        assert_eq!(idx.lookup(&1).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(idx.lookup(&7).count(), 0);
+        assert_eq!(idx.between(Included(&2), Excluded(&6)).collect::<Vec<_>>(),
+                   vec![6, 0, 2, 4]);
+        assert_eq!(idx.between_rev(Excluded(&4), Unbounded).collect::<Vec<_>>(),
+                   vec![5, 7, 4]);
+        assert_eq!(idx.between(Included(&6), Included(&2)).count(), 0);
+
+        // new entries go to the tail until the next rebuild
+        idx.index(0, 8);
+        idx.index(5, 9);
+        assert_eq!(idx.lookup(&5).collect::<Vec<_>>(), vec![4, 9]);
+        assert_eq!(idx.between(Unbounded, Included(&1)).collect::<Vec<_>>(), vec![8, 1, 3]);
+        idx.undex(&1, 1);
+        idx.undex(&0, 8);
+        idx.undex(&0, 3);
+        assert_eq!(idx.entries(), Some(8));
+        assert_eq!(idx.between_rev(Unbounded, Included(&2)).collect::<Vec<_>>(), vec![6, 3]);
+
+        idx.rebuild();
+        assert_eq!(idx.between(Unbounded, Unbounded).collect::<Vec<_>>(),
+                   vec![3, 6, 0, 2, 4, 9, 7, 5]);
+        idx.clear();
+        assert_eq!(idx.entries(), Some(0));
+        assert_eq!(idx.lookup(&1).count(), 0);
+    }
+
+    #[test]
+    fn flat_index_bounds_tail() {
+        let mut idx = FlatIndex::new();
+        for i in 0..1000 {
+            idx.index(1000 - i, i);
+            assert!(!tail_too_long(idx.sorted, idx.pairs.len() - idx.sorted));
+        }
+        assert!(idx.sorted > 0);
+        assert_eq!(idx.lookup(&1).collect::<Vec<_>>(), vec![999]);
+        assert_eq!(idx.between(Bound::Unbounded, Bound::Unbounded).count(), 1000);
+    }
 }
@@ -0,0 +1,201 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::ops;
+use std::sync::Arc;
+
+/// A value that has been interned by an `Interner`, which is stored only once no matter how many
+/// rows hold it. Each `Interned` is just a pointer to the shared value, so a column of heavily
+/// repeated values (such as enum-like strings) takes up a pointer per row, plus the distinct
+/// values once.
+///
+/// Two `Interned` values from the same `Interner` are equal exactly when they point to the same
+/// value, so equality checks on equal values are a pointer comparison. Ordering and hashing still
+/// go by the values themselves, so interned columns support range indices and range queries just
+/// like any other column.
+pub struct Interned<T>(Arc<T>);
+
+impl<T> Interned<T> {
+    /// Wrap a value without interning it. The value is stored separately from any equal value,
+    /// but still compares equal to it.
+    pub fn new(value: T) -> Interned<T> {
+        Interned(Arc::new(value))
+    }
+
+    /// Returns true if both values are the very same interned value.
+    pub fn ptr_eq(a: &Interned<T>, b: &Interned<T>) -> bool {
+        Arc::ptr_eq(&a.0, &b.0)
+    }
+}
+
+impl<T> Clone for Interned<T> {
+    fn clone(&self) -> Self {
+        Interned(self.0.clone())
+    }
+}
+
+impl<T> ops::Deref for Interned<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> AsRef<T> for Interned<T> {
+    fn as_ref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> Borrow<T> for Interned<T> {
+    fn borrow(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: PartialEq> PartialEq for Interned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Interned::ptr_eq(self, other) || *self.0 == *other.0
+    }
+}
+
+impl<T: Eq> Eq for Interned<T> {}
+
+impl<T: PartialOrd> PartialOrd for Interned<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if Interned::ptr_eq(self, other) {
+            return Some(Ordering::Equal);
+        }
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+impl<T: Ord> Ord for Interned<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if Interned::ptr_eq(self, other) {
+            return Ordering::Equal;
+        }
+        self.0.cmp(&other.0)
+    }
+}
+
+impl<T: Hash> Hash for Interned<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Interned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Interned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// An `Interner` hands out a single shared `Interned` value for all equal values given to it.
+/// This is a dictionary encoding of the values: keep an `Interner` around for each column (or
+/// each set of columns) with heavily repeated values, and intern values before inserting rows
+/// that hold them.
+#[derive(Clone)]
+pub struct Interner<T> {
+    values: HashSet<Arc<T>>,
+}
+
+impl<T: Eq + Hash> Default for Interner<T> {
+    fn default() -> Self {
+        Interner::new()
+    }
+}
+
+impl<T: Eq + Hash> Interner<T> {
+    /// Allocate a new, empty `Interner`.
+    pub fn new() -> Interner<T> {
+        Interner { values: HashSet::new() }
+    }
+
+    /// Returns the interned value equal to `value`, interning it first if no such value has been
+    /// interned yet.
+    pub fn intern(&mut self, value: T) -> Interned<T> {
+        if let Some(v) = self.values.get(&value) {
+            return Interned(v.clone());
+        }
+        let v = Arc::new(value);
+        self.values.insert(v.clone());
+        Interned(v)
+    }
+
+    /// Returns the interned value equal to `value`, if there is one. This is useful for looking up
+    /// values in a `Store` without interning values that no row holds.
+    pub fn get(&self, value: &T) -> Option<Interned<T>> {
+        self.values.get(value).map(|v| Interned(v.clone()))
+    }
+
+    /// Returns the number of distinct interned values.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns true if no values have been interned.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Forget all interned values that are no longer held anywhere but in the `Interner`, such as
+    /// values whose rows have all been deleted. Returns how many values were forgotten.
+    pub fn remove_unused(&mut self) -> usize {
+        let before = self.values.len();
+        self.values.retain(|v| Arc::strong_count(v) > 1);
+        before - self.values.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use idx::BTreeIndex;
+    use {Comparison, Condition, Store, Value};
+
+    #[test]
+    fn interner() {
+        let mut interner = Interner::new();
+        let a = interner.intern("a".to_owned());
+        let b = interner.intern("b".to_owned());
+        let a2 = interner.intern("a".to_owned());
+        assert!(Interned::ptr_eq(&a, &a2));
+        assert_eq!(a, a2);
+        assert_ne!(a, b);
+        assert!(a < b);
+        assert_eq!(a, Interned::new("a".to_owned()));
+        assert_eq!(*a, "a");
+        assert_eq!(interner.len(), 2);
+        assert!(interner.get(&"b".to_owned()).is_some());
+        assert!(interner.get(&"c".to_owned()).is_none());
+
+        drop(b);
+        assert_eq!(interner.remove_unused(), 1);
+        assert_eq!(interner.len(), 1);
+        assert!(interner.get(&"b".to_owned()).is_none());
+
+        let mut store = Store::new(2);
+        for i in 0..100 {
+            let status = if i % 10 == 0 { "done" } else { "pending" };
+            store.insert(vec![Interned::new(i.to_string()), interner.intern(status.to_owned())]);
+        }
+        store.index(1, BTreeIndex::new());
+        assert_eq!(interner.len(), 3);
+        let done = interner.get(&"done".to_owned()).unwrap();
+        let cmp = [Condition {
+                       column: 1,
+                       cmp: Comparison::Equal(Value::new(done)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 10);
+    }
+}
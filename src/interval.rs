@@ -0,0 +1,249 @@
+use idx::tail_too_long;
+
+/// An `IntervalIndex` indexes rows by an interval given by a pair of columns holding its start and
+/// end, and can efficiently find all rows whose interval overlaps a given range, or contains a
+/// given point. Both ends of an interval are inclusive.
+pub trait IntervalIndex<T> {
+    /// Add the given row index to the index under the given interval.
+    fn index(&mut self, start: T, end: T, row: usize);
+
+    /// Remove the given row index under the given interval from the index.
+    fn undex(&mut self, start: &T, end: &T, row: usize);
+
+    /// Return an iterator that yields the indices of all rows whose interval overlaps the range
+    /// from `min` to `max` (inclusive). To find the intervals that contain a single point, give
+    /// that point as both `min` and `max`.
+    fn overlapping<'a>(&'a self, min: &T, max: &T) -> Box<dyn Iterator<Item = usize> + 'a>;
+
+    /// Remove all row indices from the index.
+    fn clear(&mut self);
+
+    /// Give the total number of (interval, row index) entries in the index.
+    fn entries(&self) -> usize;
+
+    /// Reorganize the index after many entries have been added at once. See
+    /// `EqualityIndex::rebuild`.
+    ///
+    /// The default implementation does nothing.
+    fn rebuild(&mut self) {}
+
+    /// Release any memory the index holds on to beyond what its current entries need. See
+    /// `EqualityIndex::shrink_to_fit`.
+    ///
+    /// The default implementation does nothing.
+    fn shrink_to_fit(&mut self) {}
+}
+
+#[derive(Clone)]
+struct Entry<T> {
+    start: T,
+    end: T,
+    row: usize,
+    live: bool,
+}
+
+/// An implementation of `IntervalIndex` using an augmented interval tree.
+///
+/// The intervals are kept in a `Vec` sorted by their start, which is viewed as an implicit binary
+/// search tree where each position also records the largest end of any interval in its subtree.
+/// This lets queries skip all subtrees that end before the queried range begins, and all
+/// intervals that start after it ends.
+///
+/// Like `FlatIndex`, newly indexed intervals are appended to an unsorted tail that queries have to
+/// scan, and removed intervals are only marked as such, until `rebuild` is called or the tail
+/// grows too long. `Store` calls `rebuild` after populating a new index and after bulk insertions.
+#[derive(Clone)]
+pub struct IntervalTreeIndex<T> {
+    num: usize,
+    /// The number of leading entries in `entries` that are sorted and covered by `max_end`.
+    sorted: usize,
+    entries: Vec<Entry<T>>,
+    max_end: Vec<T>,
+}
+
+impl<T: Ord + Clone> IntervalTreeIndex<T> {
+    /// Allocate a new `IntervalTreeIndex`.
+    pub fn new() -> IntervalTreeIndex<T> {
+        IntervalTreeIndex {
+            num: 0,
+            sorted: 0,
+            entries: Vec::new(),
+            max_end: Vec::new(),
+        }
+    }
+
+    /// Compute `max_end` for the subtree spanning the given positions, and return it.
+    fn augment(&mut self, lo: usize, hi: usize) -> Option<T> {
+        if lo >= hi {
+            return None;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let left = self.augment(lo, mid);
+        let right = self.augment(mid + 1, hi);
+        let mut max = self.entries[mid].end.clone();
+        for m in left.into_iter().chain(right) {
+            if m > max {
+                max = m;
+            }
+        }
+        self.max_end[mid] = max.clone();
+        Some(max)
+    }
+
+    /// Find all live intervals in the subtree spanning the given positions that overlap the
+    /// range from `min` to `max`.
+    fn search(&self, lo: usize, hi: usize, min: &T, max: &T, rows: &mut Vec<usize>) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        if self.max_end[mid] < *min {
+            // every interval in this subtree ends before the range begins
+            return;
+        }
+
+        self.search(lo, mid, min, max, rows);
+        let e = &self.entries[mid];
+        if e.start <= *max {
+            if e.live && e.end >= *min {
+                rows.push(e.row);
+            }
+            self.search(mid + 1, hi, min, max, rows);
+        }
+    }
+}
+
+impl<T: Ord + Clone> Default for IntervalTreeIndex<T> {
+    fn default() -> Self {
+        IntervalTreeIndex::new()
+    }
+}
+
+impl<T: Ord + Clone> IntervalIndex<T> for IntervalTreeIndex<T> {
+    fn index(&mut self, start: T, end: T, row: usize) {
+        self.entries.push(Entry {
+            start,
+            end,
+            row,
+            live: true,
+        });
+        self.num += 1;
+        if tail_too_long(self.sorted, self.entries.len() - self.sorted) {
+            self.rebuild();
+        }
+    }
+
+    fn undex(&mut self, start: &T, end: &T, row: usize) {
+        let sorted = &self.entries[..self.sorted];
+        let first = sorted.partition_point(|e| e.start < *start);
+        let found = sorted[first..]
+            .iter()
+            .take_while(|e| e.start == *start)
+            .position(|e| e.live && e.end == *end && e.row == row);
+        if let Some(i) = found {
+            self.entries[first + i].live = false;
+            self.num -= 1;
+        } else if let Some(i) = self.entries[self.sorted..]
+            .iter()
+            .position(|e| e.start == *start && e.end == *end && e.row == row) {
+            self.entries.swap_remove(self.sorted + i);
+            self.num -= 1;
+        }
+    }
+
+    fn overlapping<'a>(&'a self, min: &T, max: &T) -> Box<dyn Iterator<Item = usize> + 'a> {
+        let mut rows = Vec::new();
+        if min <= max {
+            self.search(0, self.sorted, min, max, &mut rows);
+            rows.extend(self.entries[self.sorted..]
+                .iter()
+                .filter(|e| e.start <= *max && e.end >= *min)
+                .map(|e| e.row));
+        }
+        Box::new(rows.into_iter())
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.max_end.clear();
+        self.sorted = 0;
+        self.num = 0;
+    }
+
+    fn entries(&self) -> usize {
+        self.num
+    }
+
+    fn rebuild(&mut self) {
+        self.entries.retain(|e| e.live);
+        self.entries.sort_by(|a, b| a.start.cmp(&b.start));
+        self.sorted = self.entries.len();
+        self.max_end = self.entries.iter().map(|e| e.end.clone()).collect();
+        let n = self.sorted;
+        self.augment(0, n);
+    }
+
+    fn shrink_to_fit(&mut self) {
+        // removed intervals are only dropped by a rebuild
+        self.rebuild();
+        self.entries.shrink_to_fit();
+        self.max_end.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval_tree_index() {
+        let mut idx = IntervalTreeIndex::new();
+        let intervals = [(1, 4), (2, 3), (5, 9), (6, 6), (0, 10), (8, 12), (11, 11)];
+        for (row, &(start, end)) in intervals.iter().enumerate() {
+            idx.index(start, end, row);
+        }
+        assert_eq!(idx.entries(), 7);
+
+        let overlapping = |idx: &IntervalTreeIndex<_>, min, max| {
+            let mut rows = idx.overlapping(&min, &max).collect::<Vec<_>>();
+            rows.sort();
+            rows
+        };
+        // queries work before the index is rebuilt, just more slowly
+        assert_eq!(overlapping(&idx, 6, 6), vec![2, 3, 4]);
+        idx.rebuild();
+        assert_eq!(overlapping(&idx, 6, 6), vec![2, 3, 4]);
+        assert_eq!(overlapping(&idx, 3, 5), vec![0, 1, 2, 4]);
+        assert_eq!(overlapping(&idx, 11, 20), vec![5, 6]);
+        assert_eq!(overlapping(&idx, 13, 20), vec![]);
+        assert_eq!(overlapping(&idx, 5, 3), vec![]);
+
+        idx.undex(&0, &10, 4);
+        idx.undex(&0, &10, 4);
+        idx.index(4, 5, 7);
+        assert_eq!(idx.entries(), 7);
+        assert_eq!(overlapping(&idx, 5, 5), vec![2, 7]);
+        idx.undex(&4, &5, 7);
+        assert_eq!(overlapping(&idx, 5, 5), vec![2]);
+        assert_eq!(idx.entries(), 6);
+
+        idx.rebuild();
+        assert_eq!(overlapping(&idx, 0, 20), vec![0, 1, 2, 3, 5, 6]);
+        idx.clear();
+        assert_eq!(overlapping(&idx, 0, 20), vec![]);
+        assert_eq!(idx.entries(), 0);
+    }
+
+    #[test]
+    fn interval_tree_index_bounds_tail() {
+        let mut idx = IntervalTreeIndex::new();
+        for i in 0..1000 {
+            idx.index(1000 - i, 1000, i);
+            assert!(!tail_too_long(idx.sorted, idx.entries.len() - idx.sorted));
+        }
+        assert!(idx.sorted > 0);
+        assert_eq!(idx.overlapping(&0, &0).count(), 0);
+        assert_eq!(idx.overlapping(&1, &1).collect::<Vec<_>>(), vec![999]);
+        assert_eq!(idx.overlapping(&1000, &1000).count(), 1000);
+    }
+}
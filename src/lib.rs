@@ -15,25 +15,71 @@
 //! speeding up exact lookups, whereas the latter can also perform efficient range queries.
 //!
 //! Queries are performed over the dataset by calling `find` with a set of `Condition`s that will
-//! be `AND`ed together. `OR` is currently not supported --- issue multiple quieries instead. Each
-//! `Condition` represents a value comparison against the value in a single column. The system
-//! automatically picks what index to use to satisfy the query, using a heuristic based on the
-//! expected number of rows returned for that column for each index.
+//! be `AND`ed together. Each `Condition` represents a value comparison against the value in a
+//! single column. More complex queries that also involve `OR` can be expressed as a
+//! `ConditionExpr` tree and issued using `find_expr`. The system automatically picks what index to
+//! use to satisfy the query, using a heuristic based on the expected number of rows returned for
+//! that column for each index.
+//!
+//! # Threads
+//!
+//! By default, a `Store` is `Send` and `Sync` as long as its values and rows are, which requires
+//! every index on it to be `Send + Sync` too. Single-threaded users who want to store (and index)
+//! `Rc`-backed values can turn off the default `sync` feature to drop that requirement (see
+//! `MaybeSync`), at the cost of the `Store` no longer being shareable between threads.
 //!
 //! # Known limitations
 //!
 //!  - The set of match operations is currently fairly limited.
-//!  - The system currently provides an add/remove-only abstraction (i.e., no edit).
 
 #![deny(missing_docs)]
 
+#[cfg(feature = "parquet")]
+extern crate arrow_array;
+#[cfg(feature = "parquet")]
+extern crate arrow_schema;
+#[cfg(feature = "futures")]
+extern crate futures_core;
+#[cfg(feature = "parquet")]
+extern crate parquet;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "regex")]
+extern crate regex;
+#[cfg(feature = "roaring")]
+extern crate roaring;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate bincode;
+#[cfg(all(test, feature = "parquet"))]
+extern crate bytes;
+#[cfg(feature = "smallvec")]
+extern crate smallvec;
+
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::io;
+use std::collections::BTreeSet;
+use std::borrow::Cow;
+use std::iter::FromIterator;
+use std::mem;
+use std::slice;
+use std::vec;
+use std::ops;
+use std::ops::Bound;
+use std::convert::TryFrom;
+use std::rc;
 
 /// The `cmp` module holds the mechanisms needed to compare values and express conditionals.
 pub mod cmp;
 pub use cmp::Comparison;
 pub use cmp::Condition;
+pub use cmp::ConditionExpr;
+pub use cmp::TupleCondition;
 pub use cmp::Value;
 
 /// The `idx` module described the traits indexers must adhere to, and implements sensible default
@@ -41,24 +87,599 @@ pub use cmp::Value;
 pub mod idx;
 pub use idx::EqualityIndex;
 pub use idx::RangeIndex;
+pub use idx::ExtendedIndex;
 pub use idx::Index;
+pub use idx::CompositeIndex;
+pub use idx::PartialIndex;
+pub use idx::FunctionalIndex;
+pub use idx::CoveringIndex;
+use idx::RowIndex;
+use idx::BoxedFunctional;
+
+/// The `spatial` module describes the trait spatial indexers must adhere to, and implements an
+/// R-tree based spatial indexer.
+#[cfg(feature = "spatial")]
+pub mod spatial;
+
+/// The `interval` module describes the trait interval indexers must adhere to, and implements an
+/// interval tree based interval indexer.
+pub mod interval;
+
+/// The `intern` module provides an `Interner`, which lets many rows share a single copy of each
+/// distinct value in a column.
+pub mod intern;
+pub use intern::Interned;
+pub use intern::Interner;
+
+/// The `rows` module keeps the rows of a `Store` in the data structure chosen by its `Storage`.
+mod rows;
+pub use rows::Storage;
+use rows::RowMap;
+
+/// The `db` module provides a `Database`, which holds multiple named `Store`s, and `Transaction`s
+/// that change several of them at once.
+pub mod db;
+pub use db::Database;
+pub use db::Transaction;
+
+/// The `frozen` module provides a `FrozenStore`, a read-only `Store` laid out for fast reads.
+pub mod frozen;
+pub use frozen::FrozenStore;
+
+/// The `concurrent` module lets one writer change a `Store` while many readers query it from other
+/// threads without waiting for the writer.
+#[cfg(feature = "sync")]
+pub mod concurrent;
+#[cfg(feature = "sync")]
+pub use concurrent::ReadHandle;
+#[cfg(feature = "sync")]
+pub use concurrent::WriteHandle;
+
+/// The `partitioned` module provides a `PartitionedStore`, which splits its rows across several
+/// `Store`s by the value in one of their columns.
+pub mod partitioned;
+pub use partitioned::PartitionedStore;
+
+/// The `snapshot` module provides `Snapshot`s, which give a consistent view of a `Store` as of
+/// the moment they were taken.
+pub mod snapshot;
+pub use snapshot::Snapshot;
+pub use snapshot::SnapshotView;
+use snapshot::Versions;
+
+/// The `transaction` module provides `StoreTransaction`s, which group changes to a `Store` so that
+/// they can all be undone together.
+pub mod transaction;
+pub use transaction::Savepoint;
+pub use transaction::StoreTransaction;
+
+/// The `async_store` module provides an `AsyncStore`, which wraps a `Store` for use from
+/// asynchronous code without blocking the executor during long scans.
+#[cfg(feature = "futures")]
+pub mod async_store;
+#[cfg(feature = "futures")]
+pub use async_store::AsyncStore;
+
+/// The `wal` module provides a `LoggedStore`, which writes every change to a `Store` to an
+/// append-only log that `Store::replay` can recover the `Store` from.
+pub mod wal;
+pub use wal::LoggedStore;
+
+/// The `serialize` module implements `Serialize` and `Deserialize` for `Store`.
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "serde")]
+pub use serialize::WithIndexState;
+#[cfg(feature = "serde")]
+pub use serialize::Trusted;
+
+/// The `hooks` module keeps the functions registered with `Store::on_insert`,
+/// `Store::on_delete`, and `Store::add_trigger`.
+mod hooks;
+pub use hooks::TriggerEvent;
+use hooks::Hooks;
+
+/// When looking up rows with one index, rows found through another index are intersected with
+/// them as long as that other index is expected to yield at most this many times as many rows.
+const INTERSECT_FACTOR: usize = 4;
+
+/// When finding rows in sorted order, walking the index on the sort column is preferred over
+/// sorting the rows found through another index as long as the walk is expected to visit at most
+/// this many times as many rows.
+const SORT_FACTOR: usize = 4;
+
+/// The number of rows `Store::write_parquet` converts to Arrow arrays and writes at a time.
+#[cfg(feature = "parquet")]
+pub const PARQUET_BATCH_SIZE: usize = 8192;
+
+/// The number of rows `Store::ingest` decodes before adding them to the `Store` and its indices.
+pub const INGEST_BATCH_SIZE: usize = 8192;
+
+/// The number of rows `Store::export` writes between each time it flushes its writer.
+pub const EXPORT_FLUSH_ROWS: usize = 8192;
+
+/// The number of buckets in the histograms built by `Store::analyze`.
+const HISTOGRAM_BUCKETS: usize = 100;
+
+/// Returns true if rows found through an index expected to yield `other` rows should be
+/// intersected with the rows found through one expected to yield `best` rows.
+fn worth_intersecting(best: usize, other: usize) -> bool {
+    best > 1 && other <= best.saturating_mul(INTERSECT_FACTOR)
+}
 
 /// A `Store` is the main storage unit in shortcut. It keeps track of all the rows of data, as well
 /// as what indices are available. You will generally be accessing the `Store` either through the
 /// `find` method (which lets you find rows that match a certain condition), or through the
 /// `insert` method, which lets you add another row.
 ///
-/// Note that the type used for the rows needs to be `Clone`. This is because the value is also
-/// given to the index, which (currently) take a full value, not just a borrow. This *might* change
-/// down the line, but it's tricky to get the lifetimes to work out, because the indices would then
-/// be scoped by the lifetime of the `Store`.
+/// Note that the type used for the rows needs to be `Clone`. This is because most indices keep a
+/// copy of each value they index. Indices that do not, such as `idx::HashedIndex`, are only ever
+/// given a borrow of each value when rows are inserted or updated (see
+/// `EqualityIndex::index_ref`), so that values need not be cloned for them. Alternatively, values
+/// that are expensive to clone can be wrapped in an `Arc` or interned (see `Interner`), which
+/// makes every clone a reference count increment.
 pub struct Store<T, C = Vec<T>> {
     cols: usize,
     rowid: usize,
-    rows: BTreeMap<usize, C>,
+    rows: RowMap<C>,
     indices: HashMap<usize, Index<T>>,
+    composites: Vec<CompositeIndex<T>>,
+    partials: Vec<PartialIndex<T>>,
+    functionals: HashMap<usize, BoxedFunctional<T, C>>,
+    coverings: HashMap<usize, CoveringIndex<T, C>>,
+    #[cfg(feature = "spatial")]
+    spatials: Vec<SpatialEntry<T>>,
+    intervals: Vec<IntervalEntry<T>>,
+    unique: BTreeSet<usize>,
+    column_stats: HashMap<usize, ColumnStats<T>>,
+    /// The number of rows the `Store` is expected to hold. Indices are sized for this many rows
+    /// when they are added.
+    capacity: usize,
+    /// Rows that have been deleted with `delete_lazy`, but not yet removed from the indices.
+    tombstones: RowMap<C>,
+    /// Incremented whenever rowids may be handed out again, so that stale `RowId`s are detected.
+    generation: u64,
+    /// Old versions of rows that live `Snapshot`s can still see.
+    versions: Versions<C>,
+    /// The number of times each row has been changed since it was inserted, for rows that have
+    /// been changed at all. See `Store::version`.
+    row_versions: HashMap<usize, u64>,
+    /// The functions to call whenever a row is inserted, deleted, or updated.
+    hooks: Hooks<C>,
+}
+
+/// A spatial index, along with the columns holding the x and y coordinates it indexes.
+#[cfg(feature = "spatial")]
+struct SpatialEntry<T> {
+    x: usize,
+    y: usize,
+    #[cfg(feature = "sync")]
+    index: Box<dyn spatial::SpatialIndex<T> + Send + Sync>,
+    #[cfg(not(feature = "sync"))]
+    index: Box<dyn spatial::SpatialIndex<T>>,
+}
+
+/// An interval index, along with the columns holding the start and end of the intervals it
+/// indexes.
+struct IntervalEntry<T> {
+    start: usize,
+    end: usize,
+    #[cfg(feature = "sync")]
+    index: Box<dyn interval::IntervalIndex<T> + Send + Sync>,
+    #[cfg(not(feature = "sync"))]
+    index: Box<dyn interval::IntervalIndex<T>>,
+}
+
+#[cfg(feature = "spatial")]
+impl<T: Clone, R: Row<T>> RowIndex<R> for SpatialEntry<T> {
+    fn index_row(&mut self, row: &R, rowid: usize) {
+        self.index.index(row.index(self.x).clone(), row.index(self.y).clone(), rowid);
+    }
+
+    fn undex_row(&mut self, row: &R, rowid: usize) {
+        self.index.undex(row.index(self.x), row.index(self.y), rowid);
+    }
+
+    fn clear(&mut self) -> bool {
+        self.index.clear();
+        true
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.index.shrink_to_fit()
+    }
+}
+
+impl<T: Clone, R: Row<T>> RowIndex<R> for IntervalEntry<T> {
+    fn index_row(&mut self, row: &R, rowid: usize) {
+        self.index.index(row.index(self.start).clone(), row.index(self.end).clone(), rowid);
+    }
+
+    fn undex_row(&mut self, row: &R, rowid: usize) {
+        self.index.undex(row.index(self.start), row.index(self.end), rowid);
+    }
+
+    fn clear(&mut self) -> bool {
+        self.index.clear();
+        true
+    }
+
+    fn rebuild(&mut self) {
+        self.index.rebuild()
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.index.shrink_to_fit()
+    }
+}
+
+/// Statistics about the contents of a `Store`. See `Store::stats`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of rows in the `Store`.
+    pub rows: usize,
+
+    /// The number of columns in each row.
+    pub cols: usize,
+
+    /// Statistics for each of the `Store`'s indices, keyed by the column they index.
+    pub indices: BTreeMap<usize, IndexStats>,
+}
+
+/// Statistics about a single index in a `Store`. See `Store::stats`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexStats {
+    /// The number of (value, rowid) entries in the index.
+    pub entries: usize,
+
+    /// The expected number of rows for a single value, as given by `EqualityIndex::estimate`.
+    pub estimate: usize,
+}
+
+/// Statistics about the values in a single column, gathered by `Store::analyze`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnStats<T> {
+    /// The number of rows that were analyzed.
+    pub rows: usize,
+
+    /// The number of distinct values in the column.
+    pub distinct: usize,
+
+    /// The boundaries of an equi-depth histogram over the column's values. Roughly the same number
+    /// of rows have a value between each pair of adjacent boundaries, and the first and last
+    /// boundaries are the smallest and largest value in the column. Values that occur in many rows
+    /// appear as several boundaries in a row.
+    pub bounds: Vec<T>,
+}
+
+impl<T: Ord + Clone> ColumnStats<T> {
+    /// Compute the statistics for the given values.
+    fn new(mut values: Vec<&T>) -> Option<ColumnStats<T>> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort();
+        let n = values.len();
+        let distinct = 1 + values.windows(2).filter(|w| w[0] != w[1]).count();
+        let buckets = HISTOGRAM_BUCKETS.min(n - 1).max(1);
+        Some(ColumnStats {
+            rows: n,
+            distinct,
+            bounds: (0..buckets + 1).map(|i| values[i * (n - 1) / buckets].clone()).collect(),
+        })
+    }
+
+    /// Give the expected number of rows, out of the given number of rows, whose value in this
+    /// column lies between `min` and `max`.
+    pub fn estimate_range(&self, min: Bound<&T>, max: Bound<&T>, rows: usize) -> usize {
+        let below = match min {
+            Bound::Included(m) => self.bounds.partition_point(|b| b < m),
+            Bound::Excluded(m) => self.bounds.partition_point(|b| b <= m),
+            Bound::Unbounded => 0,
+        };
+        let upto = match max {
+            Bound::Included(m) => self.bounds.partition_point(|b| b <= m),
+            Bound::Excluded(m) => self.bounds.partition_point(|b| b < m),
+            Bound::Unbounded => self.bounds.len(),
+        };
+        if upto == 0 || below == self.bounds.len() || upto < below {
+            // the range lies entirely outside the values we have seen
+            return 0;
+        }
+        // count the buckets partially covered at either end of the range as half a bucket each
+        rows * (upto - below + 1) / (self.bounds.len() + 1)
+    }
+}
+
+/// A description of how `Store::find` evaluates a set of conditions. See `Store::explain`.
+///
+/// Conditions are referred to by their position in the slice of conditions given to `explain`.
+/// Note that `find` checks every candidate row against *all* the conditions, including those that
+/// were used to find the candidates in the first place.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryPlan {
+    /// How the candidate rows are found.
+    pub access: Access,
+
+    /// The expected number of candidate rows.
+    pub estimate: usize,
+
+    /// The conditions that are used to find the candidate rows.
+    pub indexed: Vec<usize>,
+
+    /// The conditions that are only applied as filters to the candidate rows.
+    pub residual: Vec<usize>,
+}
+
+/// How the rows that may match a query are found. See `QueryPlan`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Every row in the `Store` is scanned.
+    Scan,
+    /// The rows are looked up in the index on the given column.
+    Index {
+        /// The indexed column.
+        column: usize,
+        /// Whether the index is a `PartialIndex`.
+        partial: bool,
+        /// The columns of the other indices whose rows are intersected with the rows found in
+        /// this one, if any.
+        intersected: Vec<usize>,
+    },
+    /// The rows are looked up in the `CompositeIndex` over the given columns.
+    Composite {
+        /// The indexed columns.
+        columns: Vec<usize>,
+        /// The number of leading indexed columns that are looked up. If this is less than the
+        /// number of indexed columns, the index is scanned for all keys that start with the
+        /// looked up values.
+        prefix: usize,
+    },
+    /// The rows are yielded directly from the `CoveringIndex` on the given column.
+    Covering {
+        /// The indexed column.
+        column: usize,
+    },
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::Scan => write!(f, "scan")?,
+            Access::Index { column, partial, ref intersected } => {
+                write!(f, "{}index on column {}", if partial { "partial " } else { "" }, column)?;
+                if !intersected.is_empty() {
+                    write!(f, " intersected with columns {:?}", intersected)?;
+                }
+            }
+            Access::Composite { ref columns, prefix } => {
+                write!(f, "composite index on {:?}", columns)?;
+                if prefix < columns.len() {
+                    write!(f, " (prefix of {})", prefix)?;
+                }
+            }
+            Access::Covering { column } => write!(f, "covering index on column {}", column)?,
+        }
+        write!(f, " (~{} rows)", self.estimate)?;
+        if !self.residual.is_empty() {
+            write!(f, ", filter on conditions {:?}", self.residual)?;
+        }
+        Ok(())
+    }
+}
+
+/// Options that control which of the rows that match a query are returned. See
+/// `Store::find_with_options`.
+///
+/// Both the offset and the limit are applied *after* the rows have been filtered by the query's
+/// conditions.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueryOptions {
+    /// The number of matching rows to skip before yielding any rows.
+    pub offset: usize,
+
+    /// The maximum number of rows to yield, if any.
+    pub limit: Option<usize>,
+}
+
+/// A hint that overrides the query planner's choice of index. See `Store::find_with_hint`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hint {
+    /// Look up the rows using the index on the given column, if any of the conditions can use it.
+    UseIndex(usize),
+    /// Never use any index on the given column, including composite indices that include it.
+    IgnoreIndex(usize),
+    /// Scan all the rows without using any index.
+    NoIndex,
+}
+
+/// An aggregate function that only needs to order the values in a column, and so can be computed
+/// over any column. See `Store::aggregate`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aggregate {
+    /// The smallest value.
+    Min,
+    /// The largest value.
+    Max,
+}
+
+/// An aggregate function that needs to add up the values in a column, and so can only be computed
+/// over columns whose values support `+` and `/`. See `Store::aggregate`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arithmetic {
+    /// The sum of all the values.
+    Sum,
+    /// The mean of all the values.
+    Avg,
+}
+
+/// An aggregate function that `Store::aggregate` can compute over a column of `T`s. This is
+/// implemented by `Aggregate` for all `T`s, and by `Arithmetic` for `T`s that can be added up and
+/// divided.
+pub trait Aggregator<T: Clone> {
+    /// Computes this aggregate over the values in the given column of all rows in the given
+    /// `Store` that match all the given `Condition`s. Returns `None` if no rows match.
+    fn aggregate<R: Row<T>>(self,
+                            store: &Store<T, R>,
+                            conds: &[cmp::Condition<T>],
+                            column: usize)
+                            -> Option<T>;
+}
+
+/// `Min` and `Max` walk the `RangeIndex` on the column, if there is one, and stop at the first
+/// matching row.
+impl<T: Ord + Clone> Aggregator<T> for Aggregate {
+    fn aggregate<R: Row<T>>(self,
+                            store: &Store<T, R>,
+                            conds: &[cmp::Condition<T>],
+                            column: usize)
+                            -> Option<T> {
+        let ascending = self == Aggregate::Min;
+        if let Some(mut rows) = store.find_in_index_order(conds, column, ascending) {
+            return rows.next().map(|r| r.index(column).clone());
+        }
+        let vals = store.find(conds).map(|r| r.index(column));
+        if ascending { vals.min() } else { vals.max() }.cloned()
+    }
+}
+
+/// `Sum` and `Avg` always visit every matching row, and add up the values with `+`. For integer
+/// types, they therefore panic if the sum overflows in a debug build, and wrap around in a release
+/// build. The rows are counted in a `usize`, which is only converted to a `T` for the final
+/// division of `Avg`; if the count does not fit in a `T`, `Avg` returns `None`. Note that for
+/// integer types, `Avg` rounds towards zero like integer division does.
+impl<T> Aggregator<T> for Arithmetic
+    where T: Ord + Clone + ops::Add<Output = T> + ops::Div<Output = T> + TryFrom<usize>
+{
+    fn aggregate<R: Row<T>>(self,
+                            store: &Store<T, R>,
+                            conds: &[cmp::Condition<T>],
+                            column: usize)
+                            -> Option<T> {
+        let mut vals = store.find(conds).map(|r| r.index(column).clone());
+        let first = vals.next()?;
+        let (sum, n) = vals.fold((first, 1usize), |(sum, n), v| (sum + v, n + 1));
+        match self {
+            Arithmetic::Sum => Some(sum),
+            Arithmetic::Avg => Some(sum / T::try_from(n).ok()?),
+        }
+    }
+}
+
+/// The reason a `Store::compare_and_update` or `Store::update_if_version` failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CasError {
+    /// There is no row with the given rowid.
+    NotFound,
+    /// The row did not have the expected value (or version).
+    Mismatch,
+    /// The new row has the same value in a uniquely indexed column as another row.
+    Unique(UniqueViolation),
+}
+
+impl fmt::Display for CasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CasError::NotFound => write!(f, "no row with the given rowid exists"),
+            CasError::Mismatch => write!(f, "row does not have the expected value"),
+            CasError::Unique(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for CasError {}
+
+/// The error returned when a row could not be added to a `Store` because it has the same value in
+/// a uniquely indexed column as an existing row. See `Store::index_unique`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UniqueViolation {
+    /// The uniquely indexed column.
+    pub column: usize,
+    /// The rowid of the existing row with the same value in that column.
+    pub rowid: usize,
+}
+
+impl fmt::Display for UniqueViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "row {} already has the same value in unique column {}",
+               self.rowid,
+               self.column)
+    }
+}
+
+impl error::Error for UniqueViolation {}
+
+/// A handle to a row in a `Store`, returned by `Store::insert_handle` and `Store::handle`.
+///
+/// Unlike a plain rowid, a `RowId` also records the generation of the `Store`'s rowids it was
+/// handed out in. The generation changes whenever rowids may be handed out again (see
+/// `Store::truncate` and `Store::compact`), so a `RowId` can never end up referring to a row other
+/// than the one it was handed out for. Using a `RowId` whose row has since been deleted, or that
+/// is from an earlier generation, gives a `StaleRowId` error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RowId {
+    rowid: usize,
+    generation: u64,
+}
+
+impl RowId {
+    /// Returns the plain rowid of the row. This is only meaningful for as long as the `RowId` is
+    /// not stale.
+    pub fn rowid(&self) -> usize {
+        self.rowid
+    }
+}
+
+/// The error returned when a `RowId` no longer refers to a row, because the row has been deleted,
+/// or the `Store`'s rowids have since been reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StaleRowId(pub RowId);
+
+impl fmt::Display for StaleRowId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "row {} (generation {}) no longer exists",
+               self.0.rowid,
+               self.0.generation)
+    }
+}
+
+impl error::Error for StaleRowId {}
+
+/// Returns an iterator that yields all rows matching all the given `Condition`s in any of the
+/// given `Store`s. The rows of each `Store` are yielded in turn, in the order the `Store`s are
+/// given.
+///
+/// This is useful when rows with the same schema are split across several `Store`s (e.g., one per
+/// shard). Each `Store` picks its own index to satisfy the query, just like `Store::find` does.
+pub fn union_find<'c, 's: 'c, T, R>(stores: &'c [&'s Store<T, R>],
+                                    conds: &'c [cmp::Condition<'c, T>])
+                                    -> Box<dyn Iterator<Item = &'s R> + 'c>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    Box::new(stores.iter().flat_map(move |store| store.find(conds)))
 }
 
+// defines `MaybeSync` with the given supertraits, so that it is only documented once.
+macro_rules! maybe_sync {
+    ($($bound:tt)*) => {
+        /// The bound that indices, and the functions given to `FunctionalIndex` and `Predicate`,
+        /// must meet. With the `sync` feature (on by default) this is `Send + Sync`, so that a
+        /// `Store` can be shared between threads. Without it, every type implements `MaybeSync`,
+        /// so that single-threaded users can index values (or write indices and predicates) that
+        /// hold `Rc`s.
+        pub trait MaybeSync: $($bound)* {}
+        impl<T: ?Sized + $($bound)*> MaybeSync for T {}
+    }
+}
+
+#[cfg(feature = "sync")]
+maybe_sync!(Send + Sync);
+#[cfg(not(feature = "sync"))]
+maybe_sync!();
+
 /// Implementors of `Row` can be used to store the individual rows of a `Store`.
 ///
 /// The only requirement of implementors is that they can be indexed by a column number.
@@ -78,18 +699,155 @@ impl<T, R> Store<T, R>
     /// Allocate a new `Store` with the given number of columns. The column count is checked in
     /// `insert` at runtime (bleh).
     pub fn new(cols: usize) -> Store<T, R> {
+        Store::with_storage(cols, Storage::BTree)
+    }
+
+    /// Allocate a new `Store` with the given number of columns that keeps its rows as given by
+    /// `storage`. See `Storage` for the available options.
+    pub fn with_storage(cols: usize, storage: Storage) -> Store<T, R> {
         Store {
-            cols: cols,
+            cols,
             rowid: 0,
-            rows: BTreeMap::new(),
+            rows: RowMap::new(storage),
             indices: HashMap::new(),
+            composites: Vec::new(),
+            partials: Vec::new(),
+            functionals: HashMap::new(),
+            coverings: HashMap::new(),
+            #[cfg(feature = "spatial")]
+            spatials: Vec::new(),
+            intervals: Vec::new(),
+            unique: BTreeSet::new(),
+            column_stats: HashMap::new(),
+            capacity: 0,
+            tombstones: RowMap::default(),
+            generation: 0,
+            versions: Versions::default(),
+            row_versions: HashMap::new(),
+            hooks: Hooks::default(),
+        }
+    }
+
+    /// Allocate a new `Store` with the given number of columns that is expected to hold about
+    /// `rows` rows. Indices added to the `Store` are sized for that many rows up front, so that
+    /// loading the rows does not have to grow them over and over. See also `reserve`.
+    pub fn with_capacity(cols: usize, rows: usize) -> Store<T, R> {
+        let mut store = Store::new(cols);
+        store.capacity = rows;
+        store
+    }
+
+    /// Reserve capacity for at least `additional` more rows in the `Store` and in each of its
+    /// indices. Indices added to the `Store` later are also sized for this many more rows.
+    pub fn reserve(&mut self, additional: usize) {
+        use EqualityIndex;
+        self.capacity = self.capacity.max(self.rows.len() + additional);
+        self.rows.reserve(additional);
+        for idx in self.indices.values_mut() {
+            idx.reserve(additional);
+        }
+        for ci in &mut self.composites {
+            ci.reserve(additional);
+        }
+    }
+
+    /// Returns the number of rows in the `Store`.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns true if the `Store` contains no rows.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Returns the number of columns in each row of the `Store`.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Returns an iterator over all the rows in the `Store`, along with their rowids, in rowid
+    /// order.
+    ///
+    /// Unlike `find(&[])`, this does not go through any of the query machinery.
+    pub fn iter<'a>(&'a self) -> Iter<'a, R> {
+        Iter(self.rows.iter())
+    }
+
+    /// Returns an iterator over all the rows in the `Store`, in rowid order.
+    pub fn values<'a>(&'a self) -> Values<'a, R> {
+        Values(self.rows.values())
+    }
+
+    /// Returns statistics about the rows and indices of the `Store`. Indices that do not keep
+    /// track of how many entries they hold (see `EqualityIndex::entries`) are left out.
+    pub fn stats(&self) -> Stats {
+        use EqualityIndex;
+        Stats {
+            rows: self.rows.len(),
+            cols: self.cols,
+            indices: self.indices
+                .iter()
+                .filter_map(|(&col, idx)| {
+                    idx.entries().map(|entries| {
+                        (col,
+                         IndexStats {
+                             entries,
+                             estimate: idx.estimate(),
+                         })
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Gathers statistics about the values in each column, which the query planner then uses to
+    /// better estimate how many rows each index will yield. In particular, without statistics,
+    /// the planner has to assume that every range comparison selects a fixed fraction of the rows.
+    ///
+    /// The statistics are *not* kept up to date as rows are added, changed, or removed, so this
+    /// should be called again whenever the data has changed significantly. Analyzing requires
+    /// sorting the values of every column, so it is not cheap.
+    pub fn analyze(&mut self) {
+        self.column_stats = (0..self.cols)
+            .filter_map(|col| {
+                let values = self.rows.values().map(|r| r.index(col)).collect();
+                ColumnStats::new(values).map(|s| (col, s))
+            })
+            .collect();
+    }
+
+    /// Returns the statistics gathered for the given column by the last call to `analyze`, if
+    /// any.
+    pub fn column_stats(&self, column: usize) -> Option<&ColumnStats<T>> {
+        self.column_stats.get(&column)
+    }
+
+    /// Filter out the rows deleted with `delete_lazy` from the given rowids found through an
+    /// index, since the indices still hold them until the next `vacuum`.
+    fn live<'s>(&'s self,
+                rowids: Box<dyn Iterator<Item = usize> + 's>)
+                -> Box<dyn Iterator<Item = usize> + 's> {
+        if self.tombstones.is_empty() {
+            return rowids;
         }
+        Box::new(rowids.filter(move |rowid| self.rows.contains_key(rowid)))
+    }
+
+    /// Returns the row with the given rowid, even if it has been deleted with `delete_lazy` but
+    /// not yet vacuumed. Used to look at rows found through an index before they are filtered.
+    fn row_any(&self, rowid: &usize) -> &R {
+        self.rows.get(rowid).unwrap_or_else(|| &self.tombstones[rowid])
     }
 
     /// Decide what index to use in order to match the given conditions most efficiently. Note that
     /// the iterator returned by this method will return a superset of the rows that match the
     /// given conditions. Users will need to match each individual row against `conds` again.
     ///
+    /// If several conditions can use an index, and the others are not much less selective than
+    /// the best one, the rows found through the best index are intersected with those found
+    /// through the others.
+    ///
     /// The lifetime bounds here deserve some explanation. Previously, this was simply `'a` for
     /// everything, but this means that the items returned from the iterator were bound by the
     /// lifetime of the conditions. This is clearly not necessary. It also meant that you couldn't
@@ -97,204 +855,5789 @@ impl<T, R> Store<T, R>
     /// of scope.
     fn using_index<'c, 's: 'c>(&'s self,
                                conds: &'c [cmp::Condition<'c, T>])
-                               -> Box<Iterator<Item = usize> + 's> {
+                               -> Candidates<'s, R> {
+        reject_params(conds);
+        self.index_lookup(conds).unwrap_or_else(|| Candidates::Scan(self.rows.keys()))
+    }
 
-        use EqualityIndex;
-        let best_idx = conds.iter()
-            .enumerate()
-            .filter_map(|(ci, c)| self.indices.get(&c.column).and_then(|idx| Some((ci, idx))))
-            .filter(|&(ci, _)| {
-                // does this index work for the operation in question?
-                match conds[ci].cmp {
-                    cmp::Comparison::Equal(cmp::Value::Const(..)) => true,
-                    _ => false,
+    /// Like `using_index`, but returns `None` if no index can be used, in which case all the rows
+    /// have to be scanned.
+    fn index_lookup<'c, 's: 'c>(&'s self,
+                                conds: &'c [cmp::Condition<'c, T>])
+                                -> Option<Candidates<'s, R>> {
+        let composite = self.best_composite_index(conds);
+        let composite_e = composite.as_ref().map_or(usize::MAX, |&(ce, _, _)| ce);
+        match self.best_index(conds) {
+            Some((e, c, idx)) if e <= composite_e => {
+                // if other indices are nearly as selective, it is cheaper to intersect their rows
+                // with ours than to check each of our rows against their conditions
+                let others = conds.iter()
+                    .filter(|o| !std::ptr::eq(*o, c))
+                    .filter_map(|o| self.index_candidate(o, conds))
+                    .filter(|&(oe, _, _)| worth_intersecting(e, oe))
+                    .map(|(_, c, idx)| self.lookup(c.column, idx, &c.cmp).collect::<HashSet<_>>())
+                    .collect::<Vec<_>>();
+                if others.is_empty() {
+                    return Some(self.lookup_candidates(c.column, idx, &c.cmp));
                 }
-            })
-            .min_by_key(|&(_, idx)| idx.estimate());
+                let rows = self.lookup(c.column, idx, &c.cmp);
+                let rows = rows.filter(move |rowid| others.iter().all(|o| o.contains(rowid)));
+                Some(Candidates::Boxed(Box::new(rows)))
+            }
+            _ => composite.map(|(_, ci, key)| Candidates::Boxed(self.lookup_composite(ci, key))),
+        }
+    }
 
-        best_idx.and_then(|(ci, idx)| match conds[ci].cmp {
-                cmp::Comparison::Equal(cmp::Value::Const(ref v)) => Some(idx.lookup(v)),
-                _ => unreachable!(),
-            })
-            .unwrap_or_else(|| Box::new(self.rows.keys().map(|k| *k)))
+    /// Find a covering index that can be used for the given conditions, and that is expected to
+    /// yield no more rows than any other index, and return the rows it yields. Returns `None` if no
+    /// covering index is applicable. Like `using_index`, the rows must be matched against `conds`
+    /// again.
+    fn using_covering_index<'c, 's: 'c>(&'s self,
+                                        conds: &'c [cmp::Condition<'c, T>])
+                                        -> Option<Box<dyn Iterator<Item = &'s R> + 's>> {
+        let (_, c, ci) = self.best_covering_index(conds)?;
+        let key = match c.cmp {
+            cmp::Comparison::Equal(cmp::Value::Const(ref v)) => v,
+            _ => unreachable!(),
+        };
+        Some(Box::new(ci.lookup(key).map(|(_, row)| row)))
     }
 
-    /// Returns an iterator that yields all rows matching all the given `Condition`s.
-    ///
-    /// This method will automatically determine what index to use to satisfy this query. It
-    /// currently uses a fairly simple heuristic: it picks the index that: a) is over one of
-    /// columns being filtered on; b) supports the operation for that filter; and c) has the lowest
-    /// expected number of rows for a single value. This latter metric is generally the total
-    /// number of rows divided by the number of entries in the index. See `EqualityIndex::estimate`
-    /// for details.
-    pub fn find<'c, 's: 'c>(&'s self,
-                            conds: &'c [cmp::Condition<'c, T>])
-                            -> Box<Iterator<Item = &'s R> + 'c> {
-        let is_a_match = move |r: &&'s _| conds.iter().all(|c| c.matches(*r));
-        Box::new(self.using_index(conds)
-            .map(move |rowi| &self.rows[&rowi])
-            .filter(is_a_match))
+    /// Find the covering index that `using_covering_index` should use for the given conditions.
+    /// Returns the estimated number of rows, along with the condition and the index to use, or
+    /// `None` if no covering index is applicable or another index is expected to be cheaper.
+    fn best_covering_index<'c, 's>(&'s self,
+                                   conds: &'c [cmp::Condition<'c, T>])
+                                   -> Option<(usize, &'c cmp::Condition<'c, T>,
+                                              &'s CoveringIndex<T, R>)> {
+        if !self.tombstones.is_empty() {
+            // the copies of deleted rows are only removed from the index when they are vacuumed
+            return None;
+        }
+        let (e, c, ci) = conds.iter()
+            .filter_map(|c| match c.cmp {
+                cmp::Comparison::Equal(cmp::Value::Const(ref v)) => {
+                    self.coverings.get(&c.column).map(|ci| (ci.estimate_for(v), c, ci))
+                }
+                _ => None,
+            })
+            .min_by_key(|&(e, _, _)| e)?;
+
+        let other = self.best_index(conds).map(|(e, _, _)| e);
+        let composite = self.best_composite_index(conds).map(|(e, _, _)| e);
+        if other.into_iter().chain(composite).any(|other| other < e) {
+            return None;
+        }
+        Some((e, c, ci))
     }
 
-    /// Delete all rows that match the given conditions.
-    pub fn delete(&mut self, conds: &[cmp::Condition<T>]) {
-        self.delete_filter(conds, |_| true);
+    /// Like `using_index`, but for an arbitrary `ConditionExpr`.
+    fn expr_using_index<'s>(&'s self,
+                            expr: &cmp::ConditionExpr<T>)
+                            -> Box<dyn Iterator<Item = usize> + 's> {
+        self.lookup_expr(expr).unwrap_or_else(|| Box::new(self.rows.keys()))
     }
 
-    /// Delete all rows that match the given conditions *and* where the given filter function
-    /// returns true.
+    /// Find the index that is expected to yield the fewest rows for any one of the given
+    /// conditions. Returns the estimated number of rows, along with the condition and index to
+    /// use, or `None` if none of the conditions can use an index.
     ///
-    /// This requires that `R` implements `IntoIterator`, and that the columns are yielded *in
-    /// column order*.
-    pub fn delete_filter<F>(&mut self, conds: &[cmp::Condition<T>], mut f: F)
-        where F: FnMut(&R) -> bool
-    {
-        // find the rows we should delete
-        let rowids = self.using_index(conds)
-            .map(|rowi| (rowi, &self.rows[&rowi]))
-            .filter(move |&(_, row)| conds.iter().all(|c| c.matches(row)))
-            .filter(|&(_, row)| f(row))
-            .map(|(rowid, _)| rowid)
-            .collect::<Vec<_>>();
+    /// Partial indices are only considered if the conditions guarantee that every matching row is
+    /// covered by the index.
+    fn best_index<'c, 's>(&'s self,
+                          conds: &'c [cmp::Condition<'c, T>])
+                          -> Option<(usize, &'c cmp::Condition<'c, T>, &'s Index<T>)> {
+        conds.iter()
+            .filter_map(|c| self.index_candidate(c, conds))
+            .min_by_key(|&(e, _, _)| e)
+    }
 
-        let deleted = rowids.into_iter()
-            .map(|rowid| (rowid, self.rows.remove(&rowid).unwrap()))
+    /// Find the best index for each of the given conditions that can use one. Returns the
+    /// estimated number of rows, the condition, and the index for each, ordered by the estimated
+    /// number of rows. See `best_index`.
+    fn index_candidates<'c, 's>(&'s self,
+                                conds: &'c [cmp::Condition<'c, T>])
+                                -> Vec<(usize, &'c cmp::Condition<'c, T>, &'s Index<T>)> {
+        let mut candidates = conds.iter()
+            .filter_map(|c| self.index_candidate(c, conds))
             .collect::<Vec<_>>();
+        candidates.sort_by_key(|&(e, _, _)| e);
+        candidates
+    }
 
-        for (rowid, row) in deleted.into_iter() {
-            for (col, idx) in self.indices.iter_mut() {
-                idx.undex(row.index(*col), rowid);
-            }
-        }
+    /// Find the best index for the given condition, which is one of the given conditions. Returns
+    /// the estimated number of rows along with the condition and the index, or `None` if the
+    /// condition cannot use an index.
+    fn index_candidate<'c, 's>(&'s self,
+                               c: &'c cmp::Condition<'c, T>,
+                               conds: &[cmp::Condition<T>])
+                               -> Option<(usize, &'c cmp::Condition<'c, T>, &'s Index<T>)> {
+        let partials = self.partials
+            .iter()
+            .filter(move |p| p.column() == c.column && covered_by(p, conds))
+            .map(|p| p.inner());
+        self.indices
+            .get(&c.column)
+            .into_iter()
+            .chain(partials)
+            .filter_map(|idx| self.estimate(c.column, idx, &c.cmp).map(|e| (e, c, idx)))
+            .min_by_key(|&(e, _, _)| e)
     }
 
-    /// Insert a new data row into the `Store`. The row **must** have the same number of columns as
-    /// specified when the `Store` was created. If it does not, the code will panic with an
-    /// assertion failure.
+    /// Find the composite index that is expected to yield the fewest rows for the given
+    /// conditions. Returns the estimated number of rows, along with the index and the key to look
+    /// up in it, or `None` if no composite index can be used.
     ///
-    /// Inserting a row has similar complexity to `BTreeMap::insert`, and *may* need to re-allocate
-    /// the backing memory for the `Store`. The insertion also updates all maintained indices,
-    /// which may also re-allocate.
-    pub fn insert(&mut self, row: R) {
-        debug_assert_eq!(row.columns(), self.cols);
-        let rowid = self.rowid;
-        for (column, idx) in self.indices.iter_mut() {
-            use EqualityIndex;
-            idx.index(row.index(*column).clone(), rowid);
-        }
-        self.rows.insert(self.rowid, row);
-        self.rowid += 1;
+    /// A composite index can be used if there is an equality condition against a constant for
+    /// each of its columns. If the index supports range queries, it can also be used if there is
+    /// such a condition for only a leading prefix of its columns, in which case the returned key
+    /// is that prefix. See `lookup_composite`.
+    fn best_composite_index<'s>(&'s self,
+                                conds: &[cmp::Condition<T>])
+                                -> Option<(usize, &'s CompositeIndex<T>, Vec<T>)> {
+        use EqualityIndex;
+        self.composites
+            .iter()
+            .filter_map(|ci| {
+                let key = ci.columns()
+                    .iter()
+                    .map_while(|&col| {
+                        conds.iter()
+                            .filter(|c| c.column == col)
+                            .filter_map(|c| match c.cmp {
+                                cmp::Comparison::Equal(cmp::Value::Const(ref v)) => {
+                                    Some((**v).clone())
+                                }
+                                _ => None,
+                            })
+                            .next()
+                    })
+                    .collect::<Vec<_>>();
+                if key.len() == ci.columns().len() {
+                    Some((ci.estimate_for(&key), ci, key))
+                } else if !key.is_empty() && ci.as_range().is_some() {
+                    Some((self.estimate_prefix(&ci.columns()[..key.len()]), ci, key))
+                } else {
+                    None
+                }
+            })
+            .min_by_key(|&(e, _, _)| e)
     }
 
-    /// Add an index on the given colum using the given indexer. The indexer *must*, at the very
-    /// least, implement `EqualityIndex`. It *may* also implement other, more sophisticated,
-    /// indexing strategies outlined in `Index`.
+    /// Give the expected number of rows that have a given combination of values in the given
+    /// columns.
     ///
-    /// When an index is added, it is immediately fed all rows in the current dataset. Thus, adding
-    /// an index to a `Store` with many rows can be fairly costly. Keep this in mind!
-    pub fn index<I: Into<Index<T>>>(&mut self, column: usize, indexer: I) {
-        use EqualityIndex;
-        let mut idx = indexer.into();
+    /// If the columns have been analyzed, this assumes that their values are independent of one
+    /// another. Otherwise, matching a prefix of a composite key is much like a two-sided range.
+    fn estimate_prefix(&self, columns: &[usize]) -> usize {
+        let distinct = columns.iter()
+            .map(|col| self.column_stats.get(col).map(|s| s.distinct))
+            .collect::<Option<Vec<_>>>();
+        match distinct {
+            Some(distinct) => {
+                let combinations = distinct.into_iter().fold(1usize, |n, d| n.saturating_mul(d));
+                self.rows.len() / combinations.max(1)
+            }
+            None => self.rows.len() / 200,
+        }
+    }
 
-        // populate the new index
-        for (rowid, row) in self.rows.iter() {
-            idx.index(row.index(column).clone(), *rowid);
+    /// Look up the rows that have the given key in the given composite index, where the key was
+    /// chosen by `best_composite_index`. If the key only covers a prefix of the index's columns,
+    /// all the rows whose key starts with that prefix are found using a range scan.
+    fn lookup_composite<'s>(&'s self,
+                            ci: &'s CompositeIndex<T>,
+                            key: Vec<T>)
+                            -> Box<dyn Iterator<Item = usize> + 's> {
+        use EqualityIndex;
+        if key.len() == ci.columns().len() {
+            return self.live(ci.lookup(&key));
         }
 
-        self.indices.insert(column, idx);
+        // all keys that start with the prefix are adjacent in the index, and come no earlier than
+        // the prefix itself. so, we scan from the prefix and stop as soon as we see a row whose
+        // key does not start with the prefix.
+        let ri = ci.as_range().unwrap();
+        let columns = &ci.columns()[..key.len()];
+        let rowids = ri.between(Bound::Included(&key), Bound::Unbounded);
+        self.live(Box::new(rowids.take_while(move |rowid| {
+            let row = self.row_any(rowid);
+            columns.iter().zip(&key).all(|(&col, v)| row.index(col) == v)
+        })))
     }
-}
 
-impl<'a, T> Row<T> for &'a [T] {
-    fn index(&self, i: usize) -> &T {
-        &self[i]
+    /// Find the index that is expected to yield the fewest rows for the given tuple condition.
+    /// Returns the estimated number of rows, along with the value to look up and the index to look
+    /// it up in, or `None` if the condition cannot use an index.
+    ///
+    /// For now, this only considers single-column indices over each of the tuple's columns.
+    fn best_tuple_index<'c, 's>(&'s self,
+                                cond: &'c cmp::TupleCondition<T>)
+                                -> Option<(usize, &'c T, &'s Index<T>)> {
+        use EqualityIndex;
+        match cond.cmp {
+            cmp::TupleComparison::Equal(ref vs) => {
+                cond.columns
+                    .iter()
+                    .zip(vs)
+                    .filter_map(|(col, v)| match *v {
+                        cmp::Value::Const(ref v) => {
+                            self.indices.get(col).map(|idx| (idx.estimate_for(v), &**v, idx))
+                        }
+                        cmp::Value::Column(..) | cmp::Value::Param(..) => None,
+                    })
+                    .min_by_key(|&(e, _, _)| e)
+            }
+        }
     }
-    fn columns(&self) -> usize {
+
+    /// Give the expected number of rows we will have to look at to evaluate the given expression
+    /// using indices, or `None` if evaluating it requires a full scan.
+    ///
+    /// For an `And`, it is sufficient to look at the rows of its cheapest indexable
+    /// sub-expression, since the remaining sub-expressions can only exclude rows. For an `Or`, we
+    /// need the rows of *all* its sub-expressions, so all of them must be indexable.
+    fn estimate_expr(&self, expr: &cmp::ConditionExpr<T>) -> Option<usize> {
+        match *expr {
+            cmp::ConditionExpr::Leaf(ref c) => {
+                self.best_index(slice::from_ref(c)).map(|(e, _, _)| e)
+            }
+            cmp::ConditionExpr::Tuple(ref c) => self.best_tuple_index(c).map(|(e, _, _)| e),
+            cmp::ConditionExpr::And(ref es) => {
+                es.iter().filter_map(|e| self.estimate_expr(e)).min()
+            }
+            cmp::ConditionExpr::Or(ref es) => es.iter().map(|e| self.estimate_expr(e)).sum(),
+            // an index can only tell us which rows *do* match
+            cmp::ConditionExpr::Not(..) => None,
+        }
+    }
+
+    /// Look up the rows that may match the given expression using indices, or return `None` if
+    /// evaluating it requires a full scan. See `estimate_expr` for how indices are chosen.
+    fn lookup_expr<'s>(&'s self,
+                       expr: &cmp::ConditionExpr<T>)
+                       -> Option<Box<dyn Iterator<Item = usize> + 's>> {
+        match *expr {
+            cmp::ConditionExpr::Leaf(ref c) => {
+                self.best_index(slice::from_ref(c))
+                    .map(|(_, c, idx)| self.lookup(c.column, idx, &c.cmp))
+            }
+            cmp::ConditionExpr::Tuple(ref c) => {
+                use EqualityIndex;
+                self.best_tuple_index(c).map(|(_, v, idx)| self.live(idx.lookup(v)))
+            }
+            cmp::ConditionExpr::And(ref es) => {
+                es.iter()
+                    .filter_map(|e| self.estimate_expr(e).map(|est| (est, e)))
+                    .min_by_key(|&(est, _)| est)
+                    .and_then(|(_, e)| self.lookup_expr(e))
+            }
+            cmp::ConditionExpr::Or(..) if self.estimate_expr(expr).is_none() => None,
+            cmp::ConditionExpr::Or(ref es) => {
+                // the same row may match multiple branches, so we need to de-duplicate
+                let rowids = es.iter()
+                    .flat_map(|e| self.lookup_expr(e).unwrap())
+                    .collect::<BTreeSet<_>>();
+                Some(Box::new(rowids.into_iter()))
+            }
+            cmp::ConditionExpr::Not(..) => None,
+        }
+    }
+
+    /// Give the expected number of rows the given index on the given column will yield when used
+    /// to evaluate the given comparison, or `None` if the index cannot be used for that comparison
+    /// at all.
+    fn estimate(&self,
+                column: usize,
+                idx: &Index<T>,
+                cmp: &cmp::Comparison<T>)
+                -> Option<usize> {
+        use EqualityIndex;
+        match *cmp {
+            cmp::Comparison::Equal(cmp::Value::Const(ref v)) => Some(idx.estimate_for(v)),
+            // an index can't tell us which rows *don't* have a given value without scanning all of
+            // its keys, so we may as well scan the rows directly.
+            cmp::Comparison::NotEqual(..) => None,
+            cmp::Comparison::ContainsTerm(ref t) => {
+                idx.extended()?.lookup_term(t.term()).map(|_| idx.estimate())
+            }
+            cmp::Comparison::IsNull(ref n) => Some(idx.estimate_for(&n.null())),
+            cmp::Comparison::IsNotNull(..) => {
+                match (idx.extended().and_then(|x| x.nulls()), idx.entries(), idx.is_range()) {
+                    (Some(nulls), Some(entries), true) => {
+                        Some(entries.saturating_sub(nulls.len()))
+                    }
+                    // like a one-sided range
+                    (_, _, true) => Some(self.rows.len() / 3),
+                    (_, _, false) => None,
+                }
+            }
+            cmp::Comparison::EqualIgnoreCase(ref a)
+                if idx.extended().is_some_and(|x| x.is_case_insensitive()) => {
+                match *a.value() {
+                    cmp::Value::Const(ref v) => Some(idx.estimate_for(v)),
+                    cmp::Value::Column(..) | cmp::Value::Param(..) => None,
+                }
+            }
+            cmp::Comparison::EqualIgnoreCase(..) => None,
+            cmp::Comparison::StartsWith(ref a) => {
+                match (a.value().is_const(), idx.is_range()) {
+                    // a prefix behaves much like a two-sided range
+                    (true, true) => Some(self.rows.len() / 200),
+                    _ => None,
+                }
+            }
+            _ => {
+                match range_of(cmp) {
+                    Some(..) if !idx.is_range() => None,
+                    Some((min, max)) if self.column_stats.contains_key(&column) => {
+                        Some(self.column_stats[&column].estimate_range(min, max, self.rows.len()))
+                    }
+                    // without statistics, we have no idea how wide the range is, so assume that a
+                    // one-sided range selects a third of the rows, and that a two-sided range
+                    // selects 0.5% of them. these are the same default selectivities PostgreSQL
+                    // uses.
+                    Some((Bound::Unbounded, _)) |
+                    Some((_, Bound::Unbounded)) => Some(self.rows.len() / 3),
+                    Some(..) => Some(self.rows.len() / 200),
+                    None => None,
+                }
+            }
+        }
+    }
+
+    /// Look up the rows that may match the given comparison on the given column using the given
+    /// index. The index *must* be one that `estimate` deemed usable for this comparison.
+    fn lookup<'s>(&'s self,
+                  column: usize,
+                  idx: &'s Index<T>,
+                  cmp: &cmp::Comparison<T>)
+                  -> Box<dyn Iterator<Item = usize> + 's> {
+        self.live(self.lookup_any(column, idx, cmp))
+    }
+
+    /// Like `lookup`, but if the comparison is an equality against a constant, and the index is an
+    /// `Index::Hash` or `Index::BTree`, the rows indexed under it are yielded without boxing.
+    fn lookup_candidates<'s>(&'s self,
+                             column: usize,
+                             idx: &'s Index<T>,
+                             cmp: &cmp::Comparison<T>)
+                             -> Candidates<'s, R> {
+        if let cmp::Comparison::Equal(cmp::Value::Const(ref v)) = *cmp {
+            if let Some(rowids) = idx.lookup_postings(v) {
+                let live = if self.tombstones.is_empty() {
+                    None
+                } else {
+                    Some(&self.rows)
+                };
+                return Candidates::Postings(rowids, live);
+            }
+        }
+        Candidates::Boxed(self.lookup(column, idx, cmp))
+    }
+
+    /// Like `lookup`, but also yields rows deleted with `delete_lazy` that have not yet been
+    /// vacuumed.
+    fn lookup_any<'s>(&'s self,
+                      column: usize,
+                      idx: &'s Index<T>,
+                      cmp: &cmp::Comparison<T>)
+                      -> Box<dyn Iterator<Item = usize> + 's> {
+        use EqualityIndex;
+        let ri = match *cmp {
+            cmp::Comparison::Equal(cmp::Value::Const(ref v)) => return idx.lookup(v),
+            cmp::Comparison::ContainsTerm(ref t) => {
+                return idx.extended().and_then(|x| x.lookup_term(t.term())).unwrap();
+            }
+            cmp::Comparison::IsNull(ref n) => {
+                return match idx.extended().and_then(|x| x.nulls()) {
+                    Some(nulls) => Box::new(nulls.iter().cloned()),
+                    None => idx.lookup(&n.null()),
+                };
+            }
+            cmp::Comparison::EqualIgnoreCase(ref a) => {
+                match *a.value() {
+                    cmp::Value::Const(ref v) => return idx.lookup(v),
+                    cmp::Value::Column(..) | cmp::Value::Param(..) => unreachable!(),
+                }
+            }
+            _ => idx.as_range().unwrap(),
+        };
+
+        if let cmp::Comparison::StartsWith(ref a) = *cmp {
+            // all values with a given prefix are adjacent in the index, and come no earlier than
+            // the prefix itself. so, we scan from the prefix and stop as soon as we see a value
+            // that does not have the prefix.
+            let text = a.as_text;
+            let v = match *a.value() {
+                cmp::Value::Const(ref v) => v,
+                cmp::Value::Column(..) | cmp::Value::Param(..) => unreachable!(),
+            };
+            let prefix = a.text(v).to_owned();
+            return Box::new(ri.between(Bound::Included(v), Bound::Unbounded)
+                .take_while(move |rowid| {
+                    text(self.row_any(rowid).index(column)).starts_with(&*prefix)
+                }));
+        }
+
+        if let cmp::Comparison::IsNotNull(ref n) = *cmp {
+            // everything that sorts before or after the null value
+            let null = n.null();
+            return Box::new(ri.between(Bound::Unbounded, Bound::Excluded(&null))
+                .chain(ri.between(Bound::Excluded(&null), Bound::Unbounded)));
+        }
+
+        let (min, max) = range_of(cmp).unwrap();
+        ri.between(min, max)
+    }
+
+    /// Returns an iterator that yields all rows matching all the given `Condition`s.
+    ///
+    /// This method will automatically determine what index to use to satisfy this query. It
+    /// currently uses a fairly simple heuristic: it picks the index that: a) is over one of
+    /// columns being filtered on; b) supports the operation for that filter; and c) has the lowest
+    /// expected number of rows for the value being looked for. For equality filters, many
+    /// indices know this number exactly; otherwise, it is generally the total number of rows
+    /// divided by the number of entries in the index. See `EqualityIndex::estimate_for` and
+    /// `EqualityIndex::estimate` for details. If the chosen index is a `CoveringIndex`, the rows
+    /// are yielded directly from it; see `index_covering`. Use `explain` to see which index is
+    /// chosen for a given query.
+    ///
+    /// The returned `Find` is a concrete iterator type, so that iterating over rows that are found
+    /// by scanning, or by looking up a single key in a `HashIndex` or `BTreeIndex`, does not go
+    /// through a boxed iterator. Rows found through any other index, or through several indices
+    /// whose rows are intersected, still go through a boxed iterator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any condition compares against a `cmp::Value::Param`. Such conditions must be run
+    /// through `prepare`. The same goes for every other method that takes conditions.
+    pub fn find<'c, 's: 'c>(&'s self, conds: &'c [cmp::Condition<'c, T>]) -> Find<'s, 'c, T, R> {
+        self.find_cow(Cow::Borrowed(conds))
+    }
+
+    /// Like `find`, but the conditions may be owned, so that the returned iterator does not have
+    /// to borrow them.
+    fn find_cow<'c, 's: 'c>(&'s self,
+                            conds: Cow<'c, [cmp::Condition<'c, T>]>)
+                            -> Find<'s, 'c, T, R> {
+        reject_params(&conds);
+        let source = if let Some(rows) = self.using_covering_index(&conds) {
+            Source::Rows(rows)
+        } else if let Some(rowids) = self.index_lookup(&conds) {
+            Source::Rowids(rowids)
+        } else {
+            Source::Scan(self.rows.values())
+        };
+        Find {
+            rows: &self.rows,
+            conds,
+            source,
+        }
+    }
+
+    /// Describes how `find` would evaluate the given conditions: how the candidate rows are found,
+    /// how many of them are expected, and which conditions are only checked against each of them.
+    ///
+    /// This is useful for figuring out why a query is slow, or whether an index is used at all.
+    pub fn explain(&self, conds: &[cmp::Condition<T>]) -> QueryPlan {
+        let position = |c: &cmp::Condition<T>| conds.iter().position(|o| std::ptr::eq(o, c));
+        let (access, estimate, indexed) = if let Some((e, c, ci)) =
+            self.best_covering_index(conds) {
+            (Access::Covering { column: ci.column() }, e, position(c).into_iter().collect())
+        } else {
+            let composite = self.best_composite_index(conds);
+            let composite_e = composite.as_ref().map_or(usize::MAX, |&(ce, _, _)| ce);
+            let candidates = self.index_candidates(conds);
+            match candidates.first() {
+                Some(&(e, c, idx)) if e <= composite_e => {
+                    let partial = self.indices
+                        .get(&c.column)
+                        .map(|i| !std::ptr::eq(i, idx))
+                        .unwrap_or(true);
+                    let others = candidates[1..]
+                        .iter()
+                        .filter(|&&(oe, _, _)| worth_intersecting(e, oe))
+                        .map(|&(_, c, _)| c)
+                        .collect::<Vec<_>>();
+                    (Access::Index {
+                         column: c.column,
+                         partial,
+                         intersected: others.iter().map(|c| c.column).collect(),
+                     },
+                     e,
+                     Some(c).into_iter().chain(others).filter_map(position).collect())
+                }
+                _ => {
+                    match composite {
+                        Some((e, ci, key)) => {
+                            // the first equality condition on each column makes up the key
+                            let indexed = ci.columns()[..key.len()]
+                                .iter()
+                                .filter_map(|&col| {
+                                    conds.iter().position(|c| {
+                                        c.column == col &&
+                                        matches!(c.cmp,
+                                                 cmp::Comparison::Equal(cmp::Value::Const(..)))
+                                    })
+                                })
+                                .collect();
+                            (Access::Composite {
+                                 columns: ci.columns().to_vec(),
+                                 prefix: key.len(),
+                             },
+                             e,
+                             indexed)
+                        }
+                        None => (Access::Scan, self.rows.len(), Vec::new()),
+                    }
+                }
+            }
+        };
+
+        let residual = (0..conds.len()).filter(|i| !indexed.contains(i)).collect();
+        QueryPlan {
+            access,
+            estimate,
+            indexed,
+            residual,
+        }
+    }
+
+    /// Prepares a query with the given conditions, which may compare against
+    /// `cmp::Value::Param` placeholders. The query can then be executed many times with different
+    /// values for the placeholders, without building new conditions each time. See
+    /// `Prepared::execute`.
+    pub fn prepare<'s, 'c>(&'s self,
+                           conds: Vec<cmp::Condition<'c, T>>)
+                           -> Prepared<'s, 'c, T, R> {
+        let params = conds.iter()
+            .flat_map(|c| c.cmp.values())
+            .filter_map(|v| match *v {
+                cmp::Value::Param(i) => Some(i + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        Prepared {
+            store: self,
+            conds,
+            params,
+        }
+    }
+
+    /// Like `find`, but with additional `QueryOptions` controlling which of the matching rows are
+    /// yielded.
+    pub fn find_with_options<'c, 's: 'c>(&'s self,
+                                         conds: &'c [cmp::Condition<'c, T>],
+                                         opts: &QueryOptions)
+                                         -> Box<dyn Iterator<Item = &'s R> + 'c> {
+        let rows = self.find(conds).skip(opts.offset);
+        match opts.limit {
+            Some(limit) => Box::new(rows.take(limit)),
+            None => Box::new(rows),
+        }
+    }
+
+    /// Like `find`, but with a `Hint` that overrides which index is used to find the matching rows.
+    ///
+    /// This is an escape hatch for when the planner's heuristics pick an index that performs worse
+    /// than another index, or worse than not using an index at all. If the hint asks for an index
+    /// that does not exist, or that none of the conditions can use, the planner picks an index as
+    /// usual. The rows that are yielded are the same regardless of the hint.
+    pub fn find_with_hint<'c, 's: 'c>(&'s self,
+                                      conds: &'c [cmp::Condition<'c, T>],
+                                      hint: Hint)
+                                      -> Box<dyn Iterator<Item = &'s R> + 'c> {
+        let is_a_match = move |r: &&'s _| conds.iter().all(|c| c.matches(*r));
+        let rowids: Box<dyn Iterator<Item = usize> + 's> = match hint {
+            Hint::UseIndex(column) => {
+                let forced = self.indices.get(&column).and_then(|idx| {
+                    conds.iter()
+                        .filter(|c| c.column == column)
+                        .filter_map(|c| self.estimate(column, idx, &c.cmp).map(|e| (e, c)))
+                        .min_by_key(|&(e, _)| e)
+                        .map(|(_, c)| self.lookup(column, idx, &c.cmp))
+                });
+                match forced {
+                    Some(rowids) => rowids,
+                    None => return Box::new(self.find(conds)),
+                }
+            }
+            Hint::IgnoreIndex(column) => {
+                // plan as if there were no conditions on the column. this also rules out any
+                // partial index whose predicate is only guaranteed by those conditions.
+                let others = conds.iter()
+                    .filter(|c| c.column != column)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if let Some(rows) = self.using_covering_index(&others) {
+                    return Box::new(rows.filter(is_a_match));
+                }
+                Box::new(self.using_index(&others))
+            }
+            Hint::NoIndex => Box::new(self.rows.keys()),
+        };
+        Box::new(rowids.map(move |rowi| &self.rows[&rowi]).filter(is_a_match))
+    }
+
+    /// Like `find`, but only yields the given columns of each matching row.
+    ///
+    /// The yielded `Projection`s borrow from the rows in the `Store`, so no values are copied.
+    /// Column `i` of a `Projection` is column `cols[i]` of the underlying row.
+    pub fn find_project<'c, 's: 'c>(&'s self,
+                                    conds: &'c [cmp::Condition<'c, T>],
+                                    cols: &'c [usize])
+                                    -> Box<dyn Iterator<Item = Projection<'s, 'c, R>> + 'c> {
+        Box::new(self.find(conds).map(move |row| Projection { row, cols }))
+    }
+
+    /// Like `find`, but only yields the value in the given column of each matching row.
+    ///
+    /// If all the conditions are on that column, only compare against constants, and the column
+    /// has a `RangeIndex` that keeps its values (see `RangeIndex::keys_between`), the query is
+    /// answered from the index alone, without looking up any rows. The values are then yielded in
+    /// ascending order. Otherwise, the rows are found as with `find`.
+    pub fn find_keys<'c, 's: 'c>(&'s self,
+                                 column: usize,
+                                 conds: &'c [cmp::Condition<'c, T>])
+                                 -> Box<dyn Iterator<Item = &'s T> + 'c> {
+        if self.tombstones.is_empty() &&
+           conds.iter().all(|c| c.column == column && c.cmp.is_const()) {
+            if let Some(idx) = self.indices.get(&column) {
+                let (_, (min, max)) = self.narrowest_range(column, idx, conds);
+                let keys = idx.as_range().and_then(|ri| ri.keys_between(min, max));
+                if let Some(keys) = keys {
+                    // the conditions only look at the value itself, so a row that holds just that
+                    // value is enough to check them
+                    return Box::new(keys.map(|(key, _)| key).filter(move |key| {
+                        let row = slice::from_ref(*key);
+                        conds.iter().all(|c| c.cmp.matches(key, &row))
+                    }));
+                }
+            }
+        }
+        Box::new(self.find(conds).map(move |row| row.index(column)))
+    }
+
+    /// Like `find`, but yields the matching rows ordered by the value in the given column, either
+    /// in ascending or descending order.
+    ///
+    /// If there is a `RangeIndex` on the sort column, the rows are found by walking the sort
+    /// column's index in order, restricted to any range the conditions place on the sort column,
+    /// and checking each row against the remaining conditions. The rows are then yielded as they
+    /// are found, so taking only the first few matching rows is cheap. However, if another index
+    /// is expected to yield far fewer rows, all matching rows are instead found through that
+    /// index, collected, and then sorted.
+    pub fn find_sorted<'c, 's: 'c>(&'s self,
+                                   conds: &'c [cmp::Condition<'c, T>],
+                                   column: usize,
+                                   ascending: bool)
+                                   -> Box<dyn Iterator<Item = &'s R> + 'c> {
+        if let Some(rows) = self.find_in_index_order(conds, column, ascending) {
+            return rows;
+        }
+
+        let mut rows = self.find(conds).collect::<Vec<_>>();
+        if ascending {
+            rows.sort_by(|a, b| a.index(column).cmp(b.index(column)));
+        } else {
+            rows.sort_by(|a, b| b.index(column).cmp(a.index(column)));
+        }
+        Box::new(rows.into_iter())
+    }
+
+    /// Computes the given aggregate over the values in the given column of all rows matching all
+    /// the given `Condition`s. Returns `None` if no rows match.
+    ///
+    /// An `Aggregate` (`Min` or `Max`) can be computed over any column, while an `Arithmetic`
+    /// aggregate (`Sum` or `Avg`) needs values that can be added up and divided. See the
+    /// implementations of `Aggregator` for how each is computed.
+    pub fn aggregate<A: Aggregator<T>>(&self,
+                                       conds: &[cmp::Condition<T>],
+                                       column: usize,
+                                       agg: A)
+                                       -> Option<T> {
+        agg.aggregate(self, conds, column)
+    }
+
+    /// Yields the rows matching the given conditions by walking the `RangeIndex` on the given
+    /// column in order. Returns `None` if there is no such index, or if another index is expected
+    /// to yield so many fewer rows that looking them up and sorting them is likely to be cheaper.
+    fn find_in_index_order<'c, 's: 'c>(&'s self,
+                                       conds: &'c [cmp::Condition<'c, T>],
+                                       column: usize,
+                                       ascending: bool)
+                                       -> Option<Box<dyn Iterator<Item = &'s R> + 'c>> {
+        let idx = self.indices.get(&column)?;
+        let ri = idx.as_range()?;
+
+        // only walk the narrowest range that the conditions allow on the sort column
+        let (e, (min, max)) = self.narrowest_range(column, idx, conds);
+
+        let other = self.best_index(conds).map(|(e, _, _)| e);
+        let composite = self.best_composite_index(conds).map(|(e, _, _)| e);
+        let covering = self.best_covering_index(conds).map(|(e, _, _)| e);
+        let mut others = other.into_iter().chain(composite).chain(covering);
+        if others.any(|o| e > o.saturating_mul(SORT_FACTOR)) {
+            return None;
+        }
+
+        let rowids = if ascending {
+            ri.between(min, max)
+        } else {
+            ri.between_rev(min, max)
+        };
+        Some(Box::new(self.live(rowids).map(move |rowid| &self.rows[&rowid])
+            .filter(move |r| conds.iter().all(|c| c.matches(*r)))))
+    }
+
+    /// Find the narrowest range that the given conditions place on the values in the given column,
+    /// which has the given `RangeIndex`. Returns the expected number of rows in that range, along
+    /// with its bounds. If the conditions place no range on the column, the range is unbounded.
+    fn narrowest_range<'c>(&self,
+                           column: usize,
+                           idx: &Index<T>,
+                           conds: &'c [cmp::Condition<'c, T>])
+                           -> (usize, (Bound<&'c T>, Bound<&'c T>)) {
+        conds.iter()
+            .filter(|c| c.column == column)
+            .filter_map(|c| Some((self.estimate(column, idx, &c.cmp)?, range_of(&c.cmp)?)))
+            .min_by_key(|&(e, _)| e)
+            .unwrap_or((self.rows.len(), (Bound::Unbounded, Bound::Unbounded)))
+    }
+
+    /// Returns the `k` rows with the smallest values in the given column, or the `k` rows with the
+    /// largest values if `descending` is true. The rows are yielded in sorted order.
+    ///
+    /// If there is a `RangeIndex` on the column, this walks the index from the appropriate end
+    /// and stops after `k` rows. Otherwise, all rows are scanned, but only the `k` rows that are
+    /// returned are sorted.
+    pub fn top_k<'s>(&'s self,
+                     column: usize,
+                     k: usize,
+                     descending: bool)
+                     -> Box<dyn Iterator<Item = &'s R> + 's> {
+        if let Some(rows) = self.find_in_index_order(&[], column, !descending) {
+            return Box::new(rows.take(k));
+        }
+
+        let order = move |a: &&R, b: &&R| if descending {
+            b.index(column).cmp(a.index(column))
+        } else {
+            a.index(column).cmp(b.index(column))
+        };
+        let mut rows = self.rows.values().collect::<Vec<_>>();
+        if k < rows.len() {
+            if k == 0 {
+                return Box::new(None.into_iter());
+            }
+            rows.select_nth_unstable_by(k - 1, order);
+            rows.truncate(k);
+        }
+        rows.sort_by(order);
+        Box::new(rows.into_iter())
+    }
+
+    /// Returns the value at the given percentile of the given column, where `p` is between 0.0
+    /// and 1.0 (inclusive). For example, `p = 0.5` gives the median. Returns `None` if the `Store`
+    /// is empty.
+    ///
+    /// This uses the nearest-rank method, so the returned value is always one that appears in the
+    /// column. If there is a `RangeIndex` on the column, the index is walked in order until the
+    /// target rank is reached. Otherwise, the values of all rows are collected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `p` is not between 0.0 and 1.0.
+    pub fn percentile(&self, column: usize, p: f64) -> Option<&T> {
+        assert!((0.0..=1.0).contains(&p), "percentile must be between 0.0 and 1.0");
+        if self.rows.is_empty() {
+            return None;
+        }
+        let rank = ((p * self.rows.len() as f64).ceil() as usize).max(1) - 1;
+
+        if let Some(mut rows) = self.find_in_index_order(&[], column, true) {
+            return rows.nth(rank).map(|r| r.index(column));
+        }
+
+        let mut vals = self.rows.values().map(|r| r.index(column)).collect::<Vec<_>>();
+        let (_, v, _) = vals.select_nth_unstable(rank);
+        Some(*v)
+    }
+
+    /// Returns an iterator that yields all rows whose value in the given column lies within the
+    /// given bounds.
+    ///
+    /// If there is a `RangeIndex` on the column, it is used to find the rows, and they are
+    /// yielded in ascending order of their value in that column. Otherwise, all rows are scanned,
+    /// and the matching rows are yielded in rowid order.
+    pub fn find_range<'s>(&'s self,
+                          column: usize,
+                          min: Bound<&T>,
+                          max: Bound<&T>)
+                          -> Box<dyn Iterator<Item = &'s R> + 's> {
+        Box::new(self.rowids_in_range(column, min, max).map(move |rowid| &self.rows[&rowid]))
+    }
+
+    /// Returns an iterator that yields all rows whose value in `column` does *not* appear in
+    /// column `other_column` of any row in `other` (an anti-join). This is useful for finding
+    /// rows that refer to rows in another `Store` that no longer exist.
+    ///
+    /// Every row in this `Store` is visited, but each check against `other` is an equality lookup
+    /// that uses `other`'s index on `other_column` if it has one.
+    pub fn anti_join<'s, R2>(&'s self,
+                             column: usize,
+                             other: &'s Store<T, R2>,
+                             other_column: usize)
+                             -> Box<dyn Iterator<Item = &'s R> + 's>
+        where R2: Row<T>
+    {
+        Box::new(self.rows.values().filter(move |row| {
+            !other.exists(&[cmp::Condition {
+                                column: other_column,
+                                cmp: cmp::Comparison::Equal(cmp::Value::using(row.index(column))),
+                            }])
+        }))
+    }
+
+    /// Returns an iterator that yields all rows whose value in the given column has the given
+    /// computed key according to the column's `FunctionalIndex`, and that match all the given
+    /// `Condition`s. Returns `None` if there is no functional index on the column, or if its keys
+    /// are not of type `K`. See `index_by`.
+    pub fn find_by<'c, 's: 'c, K>(&'s self,
+                                  column: usize,
+                                  key: &'c K,
+                                  conds: &'c [cmp::Condition<'c, T>])
+                                  -> Option<Box<dyn Iterator<Item = &'s R> + 'c>>
+        where K: 'static
+    {
+        let rowids = self.functionals.get(&column)?.lookup_any(key)?;
+        Some(Box::new(self.live(rowids)
+            .map(move |rowid| &self.rows[&rowid])
+            .filter(move |r| conds.iter().all(|c| c.matches(*r)))))
+    }
+
+    /// Returns an iterator that yields all rows whose point, with the x coordinate in column `x`
+    /// and the y coordinate in column `y`, lies within the bounding box given by the `min` and
+    /// `max` corners (inclusive).
+    ///
+    /// If there is a spatial index over the two columns, it is used to find the rows. Otherwise,
+    /// all rows are scanned. See `index_spatial`.
+    #[cfg(feature = "spatial")]
+    pub fn within_bbox<'s>(&'s self,
+                           x: usize,
+                           y: usize,
+                           min: (&T, &T),
+                           max: (&T, &T))
+                           -> Box<dyn Iterator<Item = &'s R> + 's> {
+        if let Some(si) = self.spatials.iter().find(|si| (si.x, si.y) == (x, y)) {
+            let rowids = self.live(si.index.within(min, max));
+            return Box::new(rowids.map(move |rowid| &self.rows[&rowid]));
+        }
+
+        let (min, max) = ((min.0.clone(), min.1.clone()), (max.0.clone(), max.1.clone()));
+        Box::new(self.rows.values().filter(move |row| {
+            let (px, py) = (row.index(x), row.index(y));
+            min.0 <= *px && *px <= max.0 && min.1 <= *py && *py <= max.1
+        }))
+    }
+
+    /// Returns an iterator that yields all rows whose interval, starting at the value in column
+    /// `start` and ending at the value in column `end` (inclusive), overlaps the range from `min`
+    /// to `max` (inclusive).
+    ///
+    /// If there is an interval index over the two columns, it is used to find the rows.
+    /// Otherwise, all rows are scanned. See `index_interval`.
+    pub fn find_overlapping<'s>(&'s self,
+                                start: usize,
+                                end: usize,
+                                min: &T,
+                                max: &T)
+                                -> Box<dyn Iterator<Item = &'s R> + 's> {
+        if let Some(ii) = self.intervals.iter().find(|ii| (ii.start, ii.end) == (start, end)) {
+            let rowids = self.live(ii.index.overlapping(min, max));
+            return Box::new(rowids.map(move |rowid| &self.rows[&rowid]));
+        }
+
+        let (min, max) = (min.clone(), max.clone());
+        Box::new(self.rows
+            .values()
+            .filter(move |row| min <= max && *row.index(start) <= max && *row.index(end) >= min))
+    }
+
+    /// Returns an iterator that yields all rows whose interval, starting at the value in column
+    /// `start` and ending at the value in column `end` (inclusive), contains the given point.
+    ///
+    /// This is equivalent to `find_overlapping` with `point` as both `min` and `max`.
+    pub fn find_containing<'s>(&'s self,
+                               start: usize,
+                               end: usize,
+                               point: &T)
+                               -> Box<dyn Iterator<Item = &'s R> + 's> {
+        self.find_overlapping(start, end, point, point)
+    }
+
+    /// Returns the first row found that matches all the given `Condition`s, if any.
+    ///
+    /// This uses the same index selection as `find`, but stops looking as soon as it finds a
+    /// match. Note that the row returned is not necessarily the one with the lowest rowid.
+    pub fn find_one<'s>(&'s self, conds: &[cmp::Condition<T>]) -> Option<&'s R> {
+        self.using_index(conds)
+            .map(|rowid| &self.rows[&rowid])
+            .find(|r| conds.iter().all(|c| c.matches(*r)))
+    }
+
+    /// Returns true if any row matches all the given `Condition`s.
+    ///
+    /// Like `find_one`, this stops looking as soon as a matching row is found.
+    pub fn exists(&self, conds: &[cmp::Condition<T>]) -> bool {
+        self.using_index(conds).any(|rowid| conds.iter().all(|c| c.matches(&self.rows[&rowid])))
+    }
+
+    /// Like `find`, but also yields the rowid of each matching row. The rowids can be passed to
+    /// `get`.
+    pub fn find_with_ids<'c, 's: 'c>(&'s self,
+                                     conds: &'c [cmp::Condition<'c, T>])
+                                     -> Box<dyn Iterator<Item = (usize, &'s R)> + 'c> {
+        Box::new(self.using_index(conds)
+            .map(move |rowid| (rowid, &self.rows[&rowid]))
+            .filter(move |&(_, r)| conds.iter().all(|c| c.matches(r))))
+    }
+
+    /// Like `find`, but the returned iterator does not borrow the `Store`, so the `Store` can be
+    /// changed while the results are still being read. The iterator yields copies of the rows
+    /// that matched when `find_snapshot` was called, no matter what changes are made afterwards.
+    ///
+    /// The candidate rows are looked up through an index right away. With `Storage::Chunked`, the
+    /// iterator then holds on to a copy of the rows that shares their memory with the `Store`, as
+    /// with `cow_clone`, and changing a row while the iterator is alive copies the chunk holding
+    /// it. With any other storage, the matching rows are copied right away.
+    pub fn find_snapshot<'c>(&mut self,
+                             conds: &'c [cmp::Condition<'c, T>])
+                             -> FindSnapshot<'c, T, R>
+        where R: Clone
+    {
+        let mut rowids = self.using_index(conds).collect::<Vec<_>>();
+        let rows = match self.rows.storage() {
+            Storage::Chunked => self.rows.clone(),
+            _ => {
+                // there are no chunks to share, so copy only the rows that will be yielded
+                rowids.retain(|rowid| conds.iter().all(|c| c.matches(&self.rows[rowid])));
+                let mut rows = RowMap::new(Storage::BTree);
+                for &rowid in &rowids {
+                    rows.insert(rowid, self.rows[&rowid].clone());
+                }
+                rows
+            }
+        };
+        FindSnapshot {
+            rows,
+            conds,
+            rowids: rowids.into_iter(),
+        }
+    }
+
+    /// Returns an iterator that yields all rows matching the given `ConditionExpr`.
+    ///
+    /// Index selection works much like for `find`. For an `And`, the index for the sub-expression
+    /// with the lowest expected number of rows is used. For an `Or`, the rows for *every* branch
+    /// are looked up using indices and then de-duplicated. If any branch of an `Or` cannot use an
+    /// index, the entire `Or` falls back to a full scan.
+    pub fn find_expr<'c, 's: 'c>(&'s self,
+                                 expr: &'c cmp::ConditionExpr<'c, T>)
+                                 -> Box<dyn Iterator<Item = &'s R> + 'c> {
+        Box::new(self.expr_using_index(expr)
+            .map(move |rowi| &self.rows[&rowi])
+            .filter(move |r| expr.matches(*r)))
+    }
+
+    /// Delete all rows that match the given conditions.
+    pub fn delete(&mut self, conds: &[cmp::Condition<T>]) {
+        self.delete_filter(conds, |_| true);
+    }
+
+    /// Delete all rows that match the given conditions *and* where the given filter function
+    /// returns true.
+    ///
+    /// This requires that `R` implements `IntoIterator`, and that the columns are yielded *in
+    /// column order*.
+    pub fn delete_filter<F>(&mut self, conds: &[cmp::Condition<T>], mut f: F)
+        where F: FnMut(&R) -> bool
+    {
+        // find the rows we should delete
+        let rowids = self.using_index(conds)
+            .map(|rowi| (rowi, &self.rows[&rowi]))
+            .filter(move |&(_, row)| conds.iter().all(|c| c.matches(row)))
+            .filter(|&(_, row)| f(row))
+            .map(|(rowid, _)| rowid)
+            .collect::<Vec<_>>();
+
+        for rowid in rowids {
+            self.remove(rowid);
+        }
+    }
+
+    /// Delete all rows whose value in the given column lies within the given bounds.
+    ///
+    /// If there is a `RangeIndex` on the column, it is used to find the rows to delete. Otherwise,
+    /// all rows are scanned.
+    pub fn delete_range(&mut self, column: usize, min: Bound<&T>, max: Bound<&T>) {
+        let rowids = self.rowids_in_range(column, min, max).collect::<Vec<_>>();
+        for rowid in rowids {
+            self.remove(rowid);
+        }
+    }
+
+    /// Like `delete`, but only marks the matching rows as deleted, and leaves them in the indices
+    /// until the next call to `vacuum`. Returns the number of rows deleted.
+    ///
+    /// The rows are gone from the `Store` right away: queries skip them, and their rowids are not
+    /// used again. But since they need not be removed from each index one at a time, deleting
+    /// many rows this way is much faster than with `delete`. The indices are then brought up to
+    /// date with `vacuum`, which removes all the deleted rows from each index in a single pass,
+    /// and which can be called whenever it is convenient, such as when the `Store` is idle.
+    /// Until then, the deleted rows still take up memory, and queries that use an index have to
+    /// skip past them.
+    ///
+    /// Adding an index, and freezing the `Store`, vacuum it first. If the `Store` has any unique
+    /// indices, the rows are deleted right away instead, as with `delete`, so that their values
+    /// can be used again immediately.
+    pub fn delete_lazy(&mut self, conds: &[cmp::Condition<T>]) -> usize {
+        let rowids = self.using_index(conds)
+            .filter(|rowid| conds.iter().all(|c| c.matches(&self.rows[rowid])))
+            .collect::<Vec<_>>();
+
+        let deleted = rowids.len();
+        if !self.unique.is_empty() {
+            for rowid in rowids {
+                self.remove(rowid);
+            }
+            return deleted;
+        }
+        for rowid in rowids {
+            let row = self.rows.remove(&rowid).unwrap();
+            self.versions.supersede(rowid, self.generation, &row, false);
+            self.row_versions.remove(&rowid);
+            self.hooks.deleted(rowid, &row);
+            self.tombstones.insert(rowid, row);
+        }
+        deleted
+    }
+
+    /// Returns the number of rows deleted with `delete_lazy` that have not yet been vacuumed.
+    pub fn tombstones(&self) -> usize {
+        self.tombstones.len()
+    }
+
+    /// Remove all rows deleted with `delete_lazy` from the indices, and release their memory.
+    /// Returns the number of rows vacuumed.
+    pub fn vacuum(&mut self) -> usize {
+        let dead = mem::take(&mut self.tombstones);
+        self.undex_rows(&dead);
+        dead.len()
+    }
+
+    /// Like `vacuum`, but removes at most `max` of the deleted rows (those with the lowest
+    /// rowids) from the indices, so that a large backlog of deleted rows can be worked off in
+    /// batches without stalling other work on the `Store` for too long. Returns the number of
+    /// rows vacuumed.
+    pub fn vacuum_some(&mut self, max: usize) -> usize {
+        let rowids = self.tombstones.keys().take(max).collect::<Vec<_>>();
+        let mut dead = RowMap::default();
+        for rowid in rowids {
+            let row = self.tombstones.remove(&rowid).unwrap();
+            dead.insert(rowid, row);
+        }
+        self.undex_rows(&dead);
+        dead.len()
+    }
+
+    /// Yields the rowids of all rows whose value in the given column lies within the given bounds,
+    /// using the column's `RangeIndex` if it has one.
+    fn rowids_in_range<'s>(&'s self,
+                           column: usize,
+                           min: Bound<&T>,
+                           max: Bound<&T>)
+                           -> Box<dyn Iterator<Item = usize> + 's> {
+        if let Some(ri) = self.indices.get(&column).and_then(|idx| idx.as_range()) {
+            return self.live(ri.between(min, max));
+        }
+
+        let (min, max) = (min.cloned(), max.cloned());
+        Box::new(self.rows
+            .iter()
+            .filter(move |&(_, row)| in_bounds(row.index(column), &min, &max))
+            .map(|(rowid, _)| rowid))
+    }
+
+    /// Remove all rows that match the given conditions, and return an iterator that yields each
+    /// removed row along with its rowid.
+    ///
+    /// Like `Vec::drain`, all matching rows are removed even if the returned iterator is dropped
+    /// before it is fully consumed.
+    pub fn drain<'a>(&'a mut self, conds: &[cmp::Condition<T>]) -> Drain<'a, T, R> {
+        let rowids = self.using_index(conds)
+            .filter(|rowid| conds.iter().all(|c| c.matches(&self.rows[rowid])))
+            .collect::<Vec<_>>();
+        Drain {
+            store: self,
+            rowids: rowids.into_iter(),
+        }
+    }
+
+    /// Remove the row with the given rowid, and remove it from all indices.
+    fn remove(&mut self, rowid: usize) -> R {
+        use EqualityIndex;
+        let row = self.rows.remove(&rowid).unwrap();
+        self.versions.supersede(rowid, self.generation, &row, false);
+        self.row_versions.remove(&rowid);
+        self.hooks.deleted(rowid, &row);
+        for (col, idx) in self.indices.iter_mut() {
+            idx.undex(row.index(*col), rowid);
+        }
+        self.row_indices(|ri| ri.undex_row(&row, rowid));
+        row
+    }
+
+    /// Retain only the rows for which the given function returns true, and delete all others.
+    ///
+    /// This mirrors `Vec::retain`, and is equivalent to calling `delete_filter` with no conditions
+    /// and the inverse of the given function.
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(&R) -> bool
+    {
+        self.delete_filter(&[], |row| !f(row));
+    }
+
+    /// Update all rows that match the given conditions using the given function.
+    ///
+    /// The updated rows keep their rowids, and any indices over columns whose values were changed
+    /// are updated to reflect the new values. The function **must not** change the number of
+    /// columns in a row.
+    ///
+    /// # Panics
+    ///
+    /// Like `insert`, panics if an updated row would have the same value in a uniquely indexed
+    /// column as another row. None of the rows are changed then. See `try_update`.
+    pub fn update<F>(&mut self, conds: &[cmp::Condition<T>], f: F)
+        where F: FnMut(&mut R),
+              R: Clone
+    {
+        self.update_filter(conds, |_| true, f);
+    }
+
+    /// Like `update`, but returns an error instead of panicking if an updated row would have the
+    /// same value in a uniquely indexed column as another row (including another updated row).
+    /// None of the rows are changed then.
+    ///
+    /// To be able to check this before changing any row, the function is applied to a copy of
+    /// each row if the `Store` has any unique indices.
+    pub fn try_update<F>(&mut self,
+                         conds: &[cmp::Condition<T>],
+                         f: F)
+                         -> Result<(), UniqueViolation>
+        where F: FnMut(&mut R),
+              R: Clone
+    {
+        self.try_update_filter(conds, |_| true, f)
+    }
+
+    /// Update all rows that match the given conditions *and* where the given filter function
+    /// returns true, using the given update function. See `update` for details.
+    pub fn update_filter<P, F>(&mut self, conds: &[cmp::Condition<T>], p: P, f: F)
+        where P: FnMut(&R) -> bool,
+              F: FnMut(&mut R),
+              R: Clone
+    {
+        if let Err(e) = self.try_update_filter(conds, p, f) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Like `update_filter`, but returns an error instead of panicking if an updated row would
+    /// have the same value in a uniquely indexed column as another row. See `try_update`.
+    pub fn try_update_filter<P, F>(&mut self,
+                                   conds: &[cmp::Condition<T>],
+                                   mut p: P,
+                                   mut f: F)
+                                   -> Result<(), UniqueViolation>
+        where P: FnMut(&R) -> bool,
+              F: FnMut(&mut R),
+              R: Clone
+    {
+        use EqualityIndex;
+
+        // find the rows we should update
+        let rowids = self.using_index(conds)
+            .filter(|rowid| conds.iter().all(|c| c.matches(&self.rows[rowid])))
+            .filter(|rowid| p(&self.rows[rowid]))
+            .collect::<Vec<_>>();
+
+        // with unique indices, all the updated rows must be checked before any is changed
+        let mut updated = None;
+        if !self.unique.is_empty() {
+            let rows = rowids.iter()
+                .map(|&rowid| {
+                    let mut row = self.rows[&rowid].clone();
+                    f(&mut row);
+                    (rowid, row)
+                })
+                .collect::<Vec<_>>();
+            self.check_updates(&rows)?;
+            updated = Some(rows.into_iter().map(|(_, row)| row));
+        }
+
+        for rowid in rowids {
+            let updated = updated.as_mut().and_then(Iterator::next);
+            let row = self.rows.get_mut(&rowid).unwrap();
+            self.versions.supersede(rowid, self.generation, row, true);
+            *self.row_versions.entry(rowid).or_insert(0) += 1;
+            for ci in self.coverings.values_mut() {
+                ci.undex_row(row, rowid);
+            }
+
+            // remember the old indexed values so we can fix up the indices afterwards
+            let old = self.indices
+                .keys()
+                .map(|&col| (col, row.index(col).clone()))
+                .collect::<Vec<_>>();
+            let old_keys = self.composites.iter().map(|ci| ci.key(&*row)).collect::<Vec<_>>();
+            let old_partial = self.partials
+                .iter()
+                .map(|p| p.key(&*row).cloned())
+                .collect::<Vec<_>>();
+            let old_functional = self.functionals
+                .keys()
+                .map(|&col| (col, row.index(col).clone()))
+                .collect::<Vec<_>>();
+            #[cfg(feature = "spatial")]
+            let old_points = self.spatials
+                .iter()
+                .map(|si| (row.index(si.x).clone(), row.index(si.y).clone()))
+                .collect::<Vec<_>>();
+            let old_intervals = self.intervals
+                .iter()
+                .map(|ii| (row.index(ii.start).clone(), row.index(ii.end).clone()))
+                .collect::<Vec<_>>();
+
+            self.hooks.updating(rowid, row);
+            match updated {
+                Some(updated) => *row = updated,
+                None => f(row),
+            }
+            debug_assert_eq!(row.columns(), self.cols);
+
+            for (col, old) in old {
+                let new = row.index(col);
+                if *new != old {
+                    let idx = self.indices.get_mut(&col).unwrap();
+                    idx.undex(&old, rowid);
+                    idx.index_ref(new, rowid);
+                }
+            }
+            for (ci, old) in self.composites.iter_mut().zip(old_keys) {
+                let new = ci.key(&*row);
+                if new != old {
+                    ci.undex(&old, rowid);
+                    ci.index(new, rowid);
+                }
+            }
+            for (p, old) in self.partials.iter_mut().zip(old_partial) {
+                let new = p.key(&*row);
+                if new != old.as_ref() {
+                    if let Some(old) = old {
+                        p.undex(&old, rowid);
+                    }
+                    if let Some(new) = new {
+                        p.index_ref(new, rowid);
+                    }
+                }
+            }
+            for (col, old) in old_functional {
+                let fi = self.functionals.get_mut(&col).unwrap();
+                fi.update(&old, row.index(col), rowid);
+            }
+            #[cfg(feature = "spatial")]
+            for (si, (x, y)) in self.spatials.iter_mut().zip(old_points) {
+                let (nx, ny) = (row.index(si.x), row.index(si.y));
+                if *nx != x || *ny != y {
+                    si.index.undex(&x, &y, rowid);
+                    si.index.index(nx.clone(), ny.clone(), rowid);
+                }
+            }
+            for (ii, (start, end)) in self.intervals.iter_mut().zip(old_intervals) {
+                let (ns, ne) = (row.index(ii.start), row.index(ii.end));
+                if *ns != start || *ne != end {
+                    ii.index.undex(&start, &end, rowid);
+                    ii.index.index(ns.clone(), ne.clone(), rowid);
+                }
+            }
+            for ci in self.coverings.values_mut() {
+                ci.index_row(row, rowid);
+            }
+            self.hooks.updated(rowid, row);
+        }
+        Ok(())
+    }
+
+    /// Remove all rows from the `Store`, and clear all its indices.
+    ///
+    /// This is much faster than `delete(&[])`, since it does not have to remove the rows from the
+    /// indices one at a time. Rowids of removed rows are not re-used; see `truncate` if you want
+    /// them to be.
+    pub fn clear(&mut self) {
+        if self.versions.snapshots() != 0 {
+            for (rowid, row) in self.rows.iter() {
+                self.versions.supersede(rowid, self.generation, row, false);
+            }
+        }
+        self.hooks.deleted_all(&self.rows);
+        self.clear_indices();
+        self.rows.clear();
+        self.tombstones.clear();
+        self.row_versions.clear();
+    }
+
+    /// Remove all rows, including those deleted with `delete_lazy` but not yet vacuumed, from all
+    /// indices. Indices that cannot be emptied in one go have each row removed from them instead.
+    fn clear_indices(&mut self) {
+        use EqualityIndex;
+        let rows = mem::take(&mut self.rows);
+        let tombstones = mem::take(&mut self.tombstones);
+        let all = || rows.iter().chain(tombstones.iter());
+        for (&column, idx) in self.indices.iter_mut() {
+            if !idx.clear() {
+                for (rowid, row) in all() {
+                    idx.undex(row.index(column), rowid);
+                }
+            }
+        }
+        self.row_indices(|ri| if !ri.clear() {
+            for (rowid, row) in all() {
+                ri.undex_row(row, rowid);
+            }
+        });
+        self.rows = rows;
+        self.tombstones = tombstones;
+    }
+
+    /// Like `clear`, but also resets the rowid counter, so that the next inserted row gets the
+    /// same rowid as the first row that was ever inserted.
+    pub fn truncate(&mut self) {
+        self.clear();
+        self.rowid = 0;
+        self.generation += 1;
+    }
+
+    /// Release memory that the `Store` and its indices hold on to beyond what their current rows
+    /// need, such as spare capacity in postings lists, postings lists left empty by removed rows,
+    /// and (with `Storage::Slab` or `Storage::Chunked`) slots for removed rows past the largest
+    /// rowid in use.
+    ///
+    /// Long-lived `Store`s that see many rows come and go should call this (or `compact`) every
+    /// so often, since the indices otherwise stay sized for the largest number of rows they have
+    /// ever held.
+    pub fn shrink_to_fit(&mut self) {
+        use EqualityIndex;
+        self.rows.shrink_to_fit();
+        for idx in self.indices.values_mut() {
+            idx.shrink_to_fit();
+        }
+        self.row_indices(|ri| ri.shrink_to_fit());
+    }
+
+    /// Like `shrink_to_fit`, but also renumbers the rows so that their rowids are `0..len()`,
+    /// keeping them in the same order, and re-indexes them under their new rowids. The next
+    /// inserted row gets rowid `len()`.
+    ///
+    /// Returns the old rowid of each row, in the order of their new rowids. That is, the row that
+    /// used to have rowid `old[i]` now has rowid `i`. Any rowids held onto from before the call
+    /// must be mapped accordingly. Functions registered with `on_insert` and `on_delete` are not
+    /// called, since no rows are added or removed.
+    ///
+    /// This gets rid of the gaps that removed rows leave in the rowid space, which
+    /// `Storage::Slab` and `Storage::Chunked` keep slots for, and which `FlatIndex`,
+    /// `RoaringBitmap` postings, and other indices that store rowids compactly depend on staying
+    /// small.
+    pub fn compact(&mut self) -> Vec<usize> {
+        if self.versions.snapshots() != 0 {
+            for (rowid, row) in self.rows.iter() {
+                self.versions.supersede(rowid, self.generation, row, false);
+            }
+        }
+        self.clear_indices();
+        self.tombstones.clear();
+        let old = mem::take(&mut self.rows);
+        let mut versions = mem::take(&mut self.row_versions);
+
+        let mut rows = RowMap::new(old.storage());
+        let mut rowids = Vec::with_capacity(old.len());
+        for (rowid, row) in old {
+            if let Some(version) = versions.remove(&rowid) {
+                self.row_versions.insert(rowids.len(), version);
+            }
+            rows.insert(rowids.len(), row);
+            rowids.push(rowid);
+        }
+        self.rowid = rowids.len();
+        self.generation += 1;
+
+        self.index_rows(&rows);
+        self.rows = rows;
+        self.rebuild_indices();
+        self.shrink_to_fit();
+        rowids
+    }
+
+    /// Turn this `Store` into a `FrozenStore`, which cannot be changed, but has its rows and
+    /// indices laid out for fast reads. This is useful for datasets that are built once, and then
+    /// only queried.
+    ///
+    /// The rows are moved into a single contiguous `Storage::Slab`, and every `HashIndex` and
+    /// `BTreeIndex` is replaced by a `FlatIndex` over the same column, which is smaller and faster
+    /// to search. All other indices are kept as they are. Finally, all spare capacity is released
+    /// (see `shrink_to_fit`).
+    ///
+    /// The rows keep their rowids, so the slab still has an (empty) slot for each row that has
+    /// been removed. Call `compact` first if the `Store` has seen many removals and the rowids do
+    /// not matter. If any rowid is too large for a `FlatIndex`, the indices are kept as they are.
+    pub fn freeze(mut self) -> FrozenStore<T, R>
+        where T: 'static + MaybeSync
+    {
+        use EqualityIndex;
+        self.vacuum();
+        let mut rows = RowMap::new(Storage::Slab);
+        rows.reserve(self.rowid);
+        rows.append(&mut self.rows);
+        self.rows = rows;
+
+        if self.rowid <= u32::MAX as usize {
+            for (&column, idx) in self.indices.iter_mut() {
+                if let Index::Hash(..) | Index::BTree(..) = *idx {
+                    let mut flat = idx::FlatIndex::new();
+                    flat.reserve(self.rows.len());
+                    flat.index_all(column_entries(&self.rows, column));
+                    *idx = Index::Range(Box::new(flat));
+                }
+            }
+        }
+        self.rebuild_indices();
+        self.shrink_to_fit();
+        FrozenStore { store: self }
+    }
+
+    /// Replace the row with the given rowid with `new`, but only if it is currently equal to
+    /// `expected`.
+    ///
+    /// This allows read-modify-write cycles to detect that the row changed since it was read, and
+    /// retry. If the row has been deleted, `CasError::NotFound` is returned. If it has been
+    /// changed, `CasError::Mismatch` is returned. If the new row has the same value in a uniquely
+    /// indexed column as another row, `CasError::Unique` is returned. In each case, the `Store` is
+    /// left unchanged.
+    pub fn compare_and_update(&mut self, rowid: usize, expected: &R, new: R) -> Result<(), CasError>
+        where R: PartialEq
+    {
+        match self.rows.get(&rowid) {
+            None => return Err(CasError::NotFound),
+            Some(row) if row != expected => return Err(CasError::Mismatch),
+            Some(_) => {}
+        }
+        self.try_replace(rowid, new).map_err(CasError::Unique)?;
+        Ok(())
+    }
+
+    /// Returns the version of the row with the given rowid, if it exists. A row starts out at
+    /// version 0 when it is inserted, and its version goes up by one every time it is changed.
+    ///
+    /// Together with `update_if_version`, this lets several writers that share a `Store` detect
+    /// that a row they read has since been changed by another writer, without having to compare
+    /// the row itself. Note that versions are not kept for deleted rows, so a deleted row that is
+    /// put back (such as by rolling back a `StoreTransaction`) starts over at version 0.
+    pub fn version(&self, rowid: usize) -> Option<u64> {
+        if !self.rows.contains_key(&rowid) {
+            return None;
+        }
+        Some(self.row_versions.get(&rowid).cloned().unwrap_or(0))
+    }
+
+    /// Update the row with the given rowid using the given function, but only if the row is
+    /// currently at the given version (see `version`). Returns the row's new version.
+    ///
+    /// If the row has been deleted, `CasError::NotFound` is returned. If it has been changed since
+    /// it was at the expected version, `CasError::Mismatch` is returned. If the updated row has the
+    /// same value in a uniquely indexed column as another row, `CasError::Unique` is returned. In
+    /// each case, the `Store` is left unchanged. Like with `update`, the function **must not**
+    /// change the number of columns in the row.
+    pub fn update_if_version<F>(&mut self,
+                                rowid: usize,
+                                expected: u64,
+                                f: F)
+                                -> Result<u64, CasError>
+        where F: FnOnce(&mut R),
+              R: Clone
+    {
+        match self.version(rowid) {
+            None => return Err(CasError::NotFound),
+            Some(version) if version != expected => return Err(CasError::Mismatch),
+            Some(_) => {}
+        }
+        let mut row = self.rows[&rowid].clone();
+        f(&mut row);
+        self.try_replace(rowid, row).map_err(CasError::Unique)?;
+        Ok(expected + 1)
+    }
+
+    /// Insert the given row, or, if a row with the same value in the given key column already
+    /// exists, replace that row instead. The replaced row is returned.
+    ///
+    /// The existing row is found using the index on the key column if there is one, and with a
+    /// full scan otherwise. The replacement keeps the rowid of the row it replaces. If there are
+    /// multiple rows with the same key, only the one with the lowest rowid is replaced.
+    ///
+    /// # Panics
+    ///
+    /// Like `insert`, panics if the row has the same value in a uniquely indexed column as a row
+    /// other than the one it replaces. The `Store` is left unchanged then.
+    pub fn upsert(&mut self, key_column: usize, row: R) -> Option<R> {
+        let existing = {
+            let cond = [cmp::Condition {
+                            column: key_column,
+                            cmp: cmp::Comparison::Equal(cmp::Value::using(row.index(key_column))),
+                        }];
+            self.using_index(&cond).filter(|rowid| cond[0].matches(&self.rows[rowid])).min()
+        };
+
+        match existing {
+            Some(rowid) => Some(self.replace(rowid, row)),
+            None => {
+                self.insert(row);
+                None
+            }
+        }
+    }
+
+    /// Check whether the given row has the same value in a uniquely indexed column as any row
+    /// other than the one with the given rowid.
+    fn unique_violation(&self, row: &R, rowid: Option<usize>) -> Option<UniqueViolation> {
+        self.unique_violation_except(row, |other| Some(other) == rowid)
+    }
+
+    /// Check whether the given row has the same value in a uniquely indexed column as any row
+    /// whose rowid is not skipped by the given function.
+    fn unique_violation_except<F>(&self, row: &R, skip: F) -> Option<UniqueViolation>
+        where F: Fn(usize) -> bool
+    {
+        use EqualityIndex;
+        self.unique
+            .iter()
+            .filter_map(|&column| {
+                let value = row.index(column);
+                // indices such as `TextIndex` and `HashedIndex` may also yield rows that only
+                // resemble the value, so the rows they yield must be checked
+                self.indices[&column]
+                    .lookup(value)
+                    .filter(|&other| !skip(other))
+                    .find(|other| match self.rows.get(other) {
+                        Some(other) => other.index(column) == value,
+                        None => false,
+                    })
+                    .map(|other| {
+                        UniqueViolation {
+                            column,
+                            rowid: other,
+                        }
+                    })
+            })
+            .next()
+    }
+
+    /// Check that the given rows can all replace the rows with the same rowids at once. That is,
+    /// that no new row has the same value in a uniquely indexed column as a row that is not being
+    /// replaced, or as another new row.
+    pub(crate) fn check_updates(&self, rows: &[(usize, R)]) -> Result<(), UniqueViolation> {
+        if self.unique.is_empty() {
+            return Ok(());
+        }
+        let replaced = rows.iter().map(|&(rowid, _)| rowid).collect::<HashSet<_>>();
+        let mut keys = BTreeMap::new();
+        for &(rowid, ref row) in rows {
+            if let Some(e) = self.unique_violation_except(row, |other| replaced.contains(&other)) {
+                return Err(e);
+            }
+            for &column in &self.unique {
+                if let Some(&other) = keys.get(&(column, row.index(column))) {
+                    return Err(UniqueViolation {
+                        column,
+                        rowid: other,
+                    });
+                }
+                keys.insert((column, row.index(column)), rowid);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the row with the given rowid with the given row, and return the old row, or return
+    /// an error if the new row has the same value in a uniquely indexed column as another row.
+    pub(crate) fn try_replace(&mut self, rowid: usize, row: R) -> Result<R, UniqueViolation> {
+        if let Some(e) = self.unique_violation(&row, Some(rowid)) {
+            return Err(e);
+        }
+        Ok(self.replace_unchecked(rowid, row))
+    }
+
+    /// Like `try_replace`, but panics if the new row has the same value in a uniquely indexed
+    /// column as another row.
+    fn replace(&mut self, rowid: usize, row: R) -> R {
+        match self.try_replace(rowid, row) {
+            Ok(old) => old,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Replace the row with the given rowid with the given row, and return the old row. All
+    /// indices are updated accordingly. The new row must already have been checked against the
+    /// unique indices.
+    pub(crate) fn replace_unchecked(&mut self, rowid: usize, row: R) -> R {
+        use EqualityIndex;
+        debug_assert_eq!(row.columns(), self.cols);
+        self.versions.supersede(rowid, self.generation, &self.rows[&rowid], true);
+        *self.row_versions.entry(rowid).or_insert(0) += 1;
+        for (col, idx) in self.indices.iter_mut() {
+            let old = self.rows[&rowid].index(*col);
+            let new = row.index(*col);
+            if old != new {
+                idx.undex(old, rowid);
+                idx.index_ref(new, rowid);
+            }
+        }
+        for ci in &mut self.composites {
+            let old = ci.key(&self.rows[&rowid]);
+            let new = ci.key(&row);
+            if old != new {
+                ci.undex(&old, rowid);
+                ci.index(new, rowid);
+            }
+        }
+        for p in &mut self.partials {
+            let old = p.key(&self.rows[&rowid]);
+            let new = p.key(&row);
+            if old != new {
+                if let Some(old) = old {
+                    p.undex(old, rowid);
+                }
+                if let Some(new) = new {
+                    p.index_ref(new, rowid);
+                }
+            }
+        }
+        for (&col, fi) in self.functionals.iter_mut() {
+            fi.update(self.rows[&rowid].index(col), row.index(col), rowid);
+        }
+        #[cfg(feature = "spatial")]
+        for si in &mut self.spatials {
+            let old = &self.rows[&rowid];
+            let (x, y) = (old.index(si.x), old.index(si.y));
+            let (nx, ny) = (row.index(si.x), row.index(si.y));
+            if x != nx || y != ny {
+                si.index.undex(x, y, rowid);
+                si.index.index(nx.clone(), ny.clone(), rowid);
+            }
+        }
+        for ii in &mut self.intervals {
+            let old = &self.rows[&rowid];
+            let (start, end) = (old.index(ii.start), old.index(ii.end));
+            let (ns, ne) = (row.index(ii.start), row.index(ii.end));
+            if start != ns || end != ne {
+                ii.index.undex(start, end, rowid);
+                ii.index.index(ns.clone(), ne.clone(), rowid);
+            }
+        }
+        for ci in self.coverings.values_mut() {
+            ci.undex_row(&self.rows[&rowid], rowid);
+            ci.index_row(&row, rowid);
+        }
+        self.hooks.updating(rowid, &self.rows[&rowid]);
+        self.hooks.updated(rowid, &row);
+        self.rows.insert(rowid, row).unwrap()
+    }
+
+    /// Insert a new data row into the `Store`. The row **must** have the same number of columns as
+    /// specified when the `Store` was created. If it does not, the code will panic with an
+    /// assertion failure.
+    ///
+    /// Inserting a row has similar complexity to `BTreeMap::insert`, and *may* need to re-allocate
+    /// the backing memory for the `Store`. The insertion also updates all maintained indices,
+    /// which may also re-allocate.
+    ///
+    /// Returns the rowid assigned to the new row, which can later be passed to `get`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the row has the same value in a uniquely indexed column as an existing row. Use
+    /// `try_insert` to handle this case gracefully.
+    pub fn insert(&mut self, row: R) -> usize {
+        match self.try_insert(row) {
+            Ok(rowid) => rowid,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Like `insert`, but returns an error instead of panicking if the row has the same value in a
+    /// uniquely indexed column as an existing row. In that case, the `Store` is left unchanged.
+    pub fn try_insert(&mut self, row: R) -> Result<usize, UniqueViolation> {
+        debug_assert_eq!(row.columns(), self.cols);
+        if let Some(e) = self.unique_violation(&row, None) {
+            return Err(e);
+        }
+        let rowid = self.rowid;
+        self.put(rowid, row);
+        self.rowid += 1;
+        Ok(rowid)
+    }
+
+    /// Add the given row under the given rowid, which must not be in use, and add it to all
+    /// indices.
+    fn put(&mut self, rowid: usize, row: R) {
+        for (column, idx) in self.indices.iter_mut() {
+            use EqualityIndex;
+            idx.index_ref(row.index(*column), rowid);
+        }
+        self.row_indices(|ri| ri.index_row(&row, rowid));
+        self.hooks.inserted(rowid, &row);
+        self.rows.insert(rowid, row);
+    }
+
+    /// Move all rows from `other` into this `Store`. The rows are given new rowids in this `Store`,
+    /// in the order of their rowids in `other`. Any indices on `other` are discarded.
+    ///
+    /// This is more efficient than inserting the rows one at a time, as the rows are added to the
+    /// `Store` in bulk, and then each index is updated with all the new rows in a single pass.
+    pub fn absorb(&mut self, other: Store<T, R>) {
+        debug_assert_eq!(other.cols, self.cols);
+        if !self.unique.is_empty() {
+            // every row must be checked against the rows that came before it
+            self.extend(other.rows.into_iter().map(|(_, row)| row));
+            return;
+        }
+
+        let first = self.rowid;
+        let mut rows = other.rows
+            .into_iter()
+            .enumerate()
+            .map(|(i, (_, row))| (first + i, row))
+            .collect::<RowMap<_>>();
+        self.rowid += rows.len();
+
+        self.index_rows(&rows);
+        self.hooks.inserted_all(&rows);
+        self.rows.append(&mut rows);
+        self.rebuild_indices();
+    }
+
+    /// Add all the rows decoded from the given reader to the `Store`, and return the range of
+    /// rowids assigned to them.
+    ///
+    /// The reader is buffered, and `decode` is called with it over and over to decode the next
+    /// row, until it returns `Ok(None)` at the end of the input. The rows are added in batches of
+    /// `INGEST_BATCH_SIZE`, with each index updated with all the rows in a batch in a single pass
+    /// (as with `absorb`), so that a large file can be loaded without first collecting all of its
+    /// rows. If the `Store` has any unique indices, every row must be checked against the rows
+    /// that came before it, so the rows are then inserted one at a time.
+    ///
+    /// If reading or decoding fails, the rows decoded before the error remain in the `Store`, and
+    /// the error is returned. An error of kind `InvalidData` is also returned if a row does not
+    /// have as many columns as the `Store`, or if it has the same value in a uniquely indexed
+    /// column as another row (in which case the error wraps the `UniqueViolation`).
+    pub fn ingest<Rd, D>(&mut self, reader: Rd, mut decode: D) -> io::Result<ops::Range<usize>>
+        where Rd: io::Read,
+              D: FnMut(&mut io::BufReader<Rd>) -> io::Result<Option<R>>
+    {
+        let first = self.rowid;
+        let mut reader = io::BufReader::new(reader);
+        let mut rows = RowMap::default();
+        let result = loop {
+            let row = match decode(&mut reader) {
+                Ok(Some(row)) => row,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            if row.columns() != self.cols {
+                let msg = format!("row has {} columns, but the store has {}",
+                                  row.columns(),
+                                  self.cols);
+                break Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+            if !self.unique.is_empty() {
+                if let Err(e) = self.try_insert(row) {
+                    break Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                continue;
+            }
+
+            rows.insert(self.rowid, row);
+            self.rowid += 1;
+            if rows.len() == INGEST_BATCH_SIZE {
+                self.index_rows(&rows);
+                self.hooks.inserted_all(&rows);
+                self.rows.append(&mut rows);
+            }
+        };
+        self.index_rows(&rows);
+        self.hooks.inserted_all(&rows);
+        self.rows.append(&mut rows);
+        self.rebuild_indices();
+        result.map(|()| first..self.rowid)
+    }
+
+    /// Write all rows that match the given conditions to the given writer, and return how many
+    /// were written.
+    ///
+    /// The rows are streamed out as they are found, without first being collected. The writer is
+    /// buffered, and `encode` is called with it for each row in turn to write out that row. Every
+    /// `EXPORT_FLUSH_ROWS` rows, and once all rows have been written, the writer is flushed, so
+    /// that a long export makes steady progress to its destination.
+    ///
+    /// If encoding or writing a row fails, the export stops, and the error is returned.
+    pub fn export<W, E>(&self,
+                        conds: &[cmp::Condition<T>],
+                        writer: W,
+                        mut encode: E)
+                        -> io::Result<usize>
+        where W: io::Write,
+              E: FnMut(&R, &mut io::BufWriter<W>) -> io::Result<()>
+    {
+        use std::io::Write;
+        let mut writer = io::BufWriter::new(writer);
+        let mut written = 0;
+        for row in self.find(conds) {
+            encode(row, &mut writer)?;
+            written += 1;
+            if written % EXPORT_FLUSH_ROWS == 0 {
+                writer.flush()?;
+            }
+        }
+        writer.flush()?;
+        Ok(written)
+    }
+
+    /// Returns a wrapper around the `Store` that serializes it along with the contents of its
+    /// `HashIndex`es and `BTreeIndex`es (the rows indexed under each key), so that deserializing
+    /// it restores those indices as they were rather than rebuilding them from the rows. This
+    /// makes the serialized `Store` larger, but deserializing it much faster. Deserializing it as
+    /// a `Trusted` skips checking the restored indices against the rows, and is faster still.
+    #[cfg(feature = "serde")]
+    pub fn with_index_state<'a>(&'a self) -> WithIndexState<'a, T, R> {
+        WithIndexState(self)
+    }
+
+    /// Enter bulk mode, in which rows are inserted and removed through the returned `Bulk` without
+    /// updating the indices for each row. The changes are instead applied to each index in a
+    /// single pass when bulk mode ends, which happens when `Bulk::end_bulk` is called or the
+    /// `Bulk` is dropped. Since the `Bulk` borrows the `Store` mutably, the `Store` cannot be
+    /// queried while its indices are out of date.
+    ///
+    /// If the `Store` has any unique indices, every row must be checked against the rows that
+    /// came before it, so the indices are then updated for each row right away.
+    pub fn begin_bulk<'a>(&'a mut self) -> Bulk<'a, T, R> {
+        Bulk {
+            first: self.rowid,
+            store: self,
+            removed: RowMap::default(),
+        }
+    }
+
+    /// Start a `StoreTransaction`, through which changes to the `Store` can be made and later
+    /// undone all at once. The changes take effect right away, and can be queried through the
+    /// transaction, but are rolled back when the transaction is dropped unless
+    /// `StoreTransaction::commit` is called first.
+    pub fn transaction<'a>(&'a mut self) -> StoreTransaction<'a, T, R> {
+        StoreTransaction::new(self)
+    }
+
+    /// Apply every change recorded in the given log, written by a `LoggedStore`, to this `Store`,
+    /// and return the number of changes applied. Rows are decoded with the given function, which
+    /// must undo the `LoggedStore`'s `encode`.
+    ///
+    /// The `Store` must be identical to the one the `LoggedStore` was created with, so that rows
+    /// are given the same rowids as they were when they were logged. Any partially written record
+    /// at the end of the log, as left behind by a crash, is ignored. An error is returned if the
+    /// log cannot be read, a row cannot be decoded, or a change cannot be applied, in which case
+    /// the changes before it remain applied.
+    pub fn replay<L, D>(&mut self, log: L, decode: D) -> io::Result<usize>
+        where L: io::Read,
+              D: FnMut(&[u8]) -> io::Result<R>
+    {
+        wal::replay(self, log, decode)
+    }
+
+    /// Add the given rows to all indices in a single pass over each index. The rows must not be
+    /// indexed already.
+    fn index_rows(&mut self, rows: &RowMap<R>) {
+        use EqualityIndex;
+        for (column, idx) in self.indices.iter_mut() {
+            idx.reserve(rows.len());
+            idx.index_all(column_entries(rows, *column));
+        }
+        self.row_indices(|ri| for (rowid, row) in rows.iter() {
+            ri.index_row(row, rowid);
+        });
+    }
+
+    /// Remove the given rows from all indices in a single pass over each index.
+    fn undex_rows(&mut self, rows: &RowMap<R>) {
+        use EqualityIndex;
+        for (column, idx) in self.indices.iter_mut() {
+            for (rowid, row) in rows.iter() {
+                idx.undex(row.index(*column), rowid);
+            }
+        }
+        self.row_indices(|ri| for (rowid, row) in rows.iter() {
+            ri.undex_row(row, rowid);
+        });
+    }
+
+    /// Let all indices reorganize themselves after many rows have been added at once. See
+    /// `EqualityIndex::rebuild`.
+    fn rebuild_indices(&mut self) {
+        use EqualityIndex;
+        for idx in self.indices.values_mut() {
+            idx.rebuild();
+        }
+        self.row_indices(|ri| ri.rebuild());
+    }
+
+    /// Call the given function on each index that is maintained from whole rows, which is every
+    /// index other than the single-column ones in `indices`.
+    fn row_indices<F>(&mut self, mut f: F)
+        where F: FnMut(&mut dyn RowIndex<R>)
+    {
+        for ci in &mut self.composites {
+            f(ci);
+        }
+        for p in &mut self.partials {
+            f(p);
+        }
+        for fi in self.functionals.values_mut() {
+            f(fi.as_row_index());
+        }
+        for ci in self.coverings.values_mut() {
+            f(ci);
+        }
+        #[cfg(feature = "spatial")]
+        for si in &mut self.spatials {
+            f(si);
+        }
+        for ii in &mut self.intervals {
+            f(ii);
+        }
+    }
+
+    /// Remove all rows for which the given function returns true, and return them in a new
+    /// `Store` with the same number of columns.
+    ///
+    /// The rows keep their rowids in the new `Store`, and neither `Store` will hand out any of
+    /// those rowids again. The new `Store` keeps its rows in the same kind of `Storage`, but has
+    /// no indices; add them with `index` as needed.
+    pub fn split_off<F>(&mut self, mut f: F) -> Store<T, R>
+        where F: FnMut(&R) -> bool
+    {
+        let rowids = self.rows
+            .iter()
+            .filter(|&(_, row)| f(row))
+            .map(|(rowid, _)| rowid)
+            .collect::<Vec<_>>();
+
+        let mut other = Store::with_storage(self.cols, self.rows.storage());
+        other.rowid = self.rowid;
+        for rowid in rowids {
+            let row = self.remove(rowid);
+            other.rows.insert(rowid, row);
+        }
+        other
+    }
+
+    /// Returns a copy of this `Store` that can be changed independently of it, but shares the
+    /// memory of all the rows with it until either `Store` changes them. This makes it cheap to
+    /// fork a large dataset, for example to try out changes to it and then throw them away.
+    ///
+    /// With `Storage::Chunked`, once either `Store` changes a row, only the chunk holding it (4096
+    /// rows) is copied for that `Store`. With any other storage, the first change to a row copies
+    /// all the rows, so `Store`s that are forked often should use chunked storage.
+    ///
+    /// The copy keeps the rowids of the rows, and hands out the same rowids for new rows as this
+    /// `Store` would. It shares the `HashIndex`es and `BTreeIndex`es on this `Store` in the same
+    /// way, along with the composite, partial, functional, and covering indices built on them, and
+    /// which indices are unique. Each index is copied when either `Store` first changes it, which
+    /// for most changes means every index is copied on the first change. Functions registered with
+    /// `on_insert` and the like are not carried over.
+    ///
+    /// Indices kept as trait objects (see `Index`), including spatial and interval indices, cannot
+    /// be copied, so `None` is returned if the `Store` has any of them.
+    pub fn cow_clone(&self) -> Option<Store<T, R>>
+        where R: Clone
+    {
+        #[cfg(feature = "spatial")]
+        let spatial = !self.spatials.is_empty();
+        #[cfg(not(feature = "spatial"))]
+        let spatial = false;
+        if spatial || !self.intervals.is_empty() {
+            return None;
+        }
+
+        Some(Store {
+            cols: self.cols,
+            rowid: self.rowid,
+            rows: self.rows.clone(),
+            indices: self.indices
+                .iter()
+                .map(|(&column, idx)| idx.try_clone().map(|idx| (column, idx)))
+                .collect::<Option<_>>()?,
+            composites: self.composites
+                .iter()
+                .map(CompositeIndex::try_clone)
+                .collect::<Option<_>>()?,
+            partials: self.partials.iter().map(PartialIndex::try_clone).collect::<Option<_>>()?,
+            functionals: self.functionals
+                .iter()
+                .map(|(&column, fi)| fi.try_clone().map(|fi| (column, fi)))
+                .collect::<Option<_>>()?,
+            coverings: self.coverings.clone(),
+            #[cfg(feature = "spatial")]
+            spatials: Vec::new(),
+            intervals: Vec::new(),
+            unique: self.unique.clone(),
+            column_stats: self.column_stats.clone(),
+            capacity: self.capacity,
+            // rows deleted with `delete_lazy` are still in the copied indices
+            tombstones: self.tombstones.clone(),
+            generation: self.generation,
+            versions: Versions::default(),
+            row_versions: self.row_versions.clone(),
+            hooks: Hooks::default(),
+        })
+    }
+
+    /// Returns the row with the given rowid, if it exists.
+    ///
+    /// This is a single `BTreeMap` lookup, and does not involve any indices.
+    pub fn get(&self, rowid: usize) -> Option<&R> {
+        self.rows.get(&rowid)
+    }
+
+    /// Like `insert`, but returns a `RowId` handle to the new row.
+    pub fn insert_handle(&mut self, row: R) -> RowId {
+        let rowid = self.insert(row);
+        RowId {
+            rowid,
+            generation: self.generation,
+        }
+    }
+
+    /// Returns a `RowId` handle to the row with the given rowid, if it exists.
+    pub fn handle(&self, rowid: usize) -> Option<RowId> {
+        if !self.rows.contains_key(&rowid) {
+            return None;
+        }
+        Some(RowId {
+            rowid,
+            generation: self.generation,
+        })
+    }
+
+    /// Returns the plain rowid of the row the given handle refers to, or an error if the handle
+    /// is stale.
+    fn resolve(&self, id: RowId) -> Result<usize, StaleRowId> {
+        if id.generation != self.generation || !self.rows.contains_key(&id.rowid) {
+            return Err(StaleRowId(id));
+        }
+        Ok(id.rowid)
+    }
+
+    /// Returns the row the given handle refers to.
+    pub fn get_handle(&self, id: RowId) -> Result<&R, StaleRowId> {
+        self.resolve(id).map(|rowid| &self.rows[&rowid])
+    }
+
+    /// Remove the row the given handle refers to, and return it.
+    pub fn remove_handle(&mut self, id: RowId) -> Result<R, StaleRowId> {
+        let rowid = self.resolve(id)?;
+        Ok(self.remove(rowid))
+    }
+
+    /// Update the row the given handle refers to using the given function. Like with `update`,
+    /// the function **must not** change the number of columns in the row.
+    ///
+    /// # Panics
+    ///
+    /// Like `insert`, panics if the updated row has the same value in a uniquely indexed column as
+    /// another row.
+    pub fn update_handle<F>(&mut self, id: RowId, f: F) -> Result<(), StaleRowId>
+        where F: FnOnce(&mut R),
+              R: Clone
+    {
+        let rowid = self.resolve(id)?;
+        let mut row = self.rows[&rowid].clone();
+        f(&mut row);
+        self.replace(rowid, row);
+        Ok(())
+    }
+
+    /// Call the given function with the rowid and contents of every row inserted into the `Store`
+    /// from now on. This makes it possible to keep data structures outside the `Store` in sync
+    /// with it without wrapping every method that changes it.
+    ///
+    /// Every way of adding rows calls the function, including `extend`, `absorb`, bulk mode, and
+    /// putting back rows when a `StoreTransaction` is rolled back. An update (through `update`,
+    /// `upsert`, and so on) is reported as the deletion of the old row (see `on_delete`) followed
+    /// by the insertion of the new one under the same rowid. Rows that are only renumbered by
+    /// `compact` are not reported.
+    ///
+    /// Multiple functions can be registered, and are called in the order they were registered.
+    /// The function is called while the `Store` is being changed, so it must not panic.
+    /// Functions are not carried over to the `Store`s returned by `split_off` and `cow_clone`.
+    pub fn on_insert<F>(&mut self, f: F)
+        where F: FnMut(usize, &R) + 'static + MaybeSync
+    {
+        self.hooks.on_insert(Box::new(f));
+    }
+
+    /// Call the given function with the rowid and contents of every row deleted from the `Store`
+    /// from now on. See `on_insert`.
+    ///
+    /// Every way of removing rows calls the function, including `clear`, `truncate`, `drain`,
+    /// `split_off`, and `delete_lazy` (when the row is deleted, not when it is vacuumed).
+    pub fn on_delete<F>(&mut self, f: F)
+        where F: FnMut(usize, &R) + 'static + MaybeSync
+    {
+        self.hooks.on_delete(Box::new(f));
+    }
+
+    /// Call the given function with the rowid and contents of every row that matches all the
+    /// given conditions when the given event happens to it from now on. This lets code react to
+    /// specific changes, such as a status column becoming `"failed"`, without having to look at
+    /// every change with `on_insert` and `on_delete`.
+    ///
+    /// For `TriggerEvent::Insert` and `TriggerEvent::Delete`, the function is called for inserted
+    /// and deleted rows that match the conditions, in the same places that `on_insert` and
+    /// `on_delete` functions are called, except that updates do not count as either. For
+    /// `TriggerEvent::Update`, the function is called with the new row whenever an update makes a
+    /// row match the conditions where it did not before; rows that matched both before and after
+    /// the update do not fire the trigger again.
+    ///
+    /// The conditions are checked against each changed row on its own, without using any indices,
+    /// so every trigger adds to the cost of every change. Like with `on_insert`, the function must
+    /// not panic, and triggers are not carried over to the `Store`s returned by `split_off` and
+    /// `cow_clone`.
+    pub fn add_trigger<F>(&mut self,
+                          conds: Vec<cmp::Condition<'static, T>>,
+                          event: TriggerEvent,
+                          f: F)
+        where F: FnMut(usize, &R) + 'static + MaybeSync,
+              T: 'static + MaybeSync
+    {
+        let filter = move |row: &R| conds.iter().all(|c| c.matches(row));
+        self.hooks.add_trigger(event, Box::new(filter), Box::new(f));
+    }
+
+    /// Take a `Snapshot` of the `Store` as it is right now. Reading through the snapshot with `at`
+    /// keeps giving the same results no matter how the `Store` changes afterwards.
+    ///
+    /// Taking a snapshot is cheap: no rows are copied up front. Instead, while the snapshot is
+    /// alive, the `Store` keeps a copy of each row that the snapshot can see when that row is
+    /// updated or deleted.
+    pub fn snapshot(&mut self) -> Snapshot
+        where R: Clone
+    {
+        self.versions.snapshot(self.rowid, self.generation, R::clone)
+    }
+
+    /// Returns the number of `Snapshot`s of this `Store` that are still alive.
+    pub fn snapshots(&self) -> usize {
+        self.versions.snapshots()
+    }
+
+    /// Returns a view of the `Store` as it was when the given `Snapshot` was taken.
+    pub fn at<'s>(&'s self, snapshot: &'s Snapshot) -> SnapshotView<'s, T, R> {
+        SnapshotView {
+            store: self,
+            snapshot,
+        }
+    }
+
+    /// Add an index on the given colum using the given indexer. The indexer *must*, at the very
+    /// least, implement `EqualityIndex`. It *may* also implement other, more sophisticated,
+    /// indexing strategies outlined in `Index`.
+    ///
+    /// When an index is added, it is immediately fed all rows in the current dataset. Thus, adding
+    /// an index to a `Store` with many rows can be fairly costly. Keep this in mind!
+    pub fn index<I: Into<Index<T>>>(&mut self, column: usize, indexer: I) {
+        let rows = self.capacity.max(self.rows.len());
+        self.index_with_capacity(column, indexer, rows);
+    }
+
+    /// Like `index`, but sizes the index for the given number of distinct keys, rather than for
+    /// the number of rows the `Store` holds (or was told to expect). This avoids over-allocating
+    /// indices that keep a single entry for all rows with the same value, such as `HashIndex`,
+    /// for columns with few distinct values.
+    pub fn index_with_capacity<I: Into<Index<T>>>(&mut self,
+                                                  column: usize,
+                                                  indexer: I,
+                                                  keys: usize) {
+        use EqualityIndex;
+        self.vacuum();
+        let mut idx = indexer.into();
+
+        // populate the new index
+        idx.reserve(keys);
+        idx.index_all(column_entries(&self.rows, column));
+        idx.rebuild();
+
+        self.indices.insert(column, idx);
+        self.unique.remove(&column);
+    }
+
+    /// Empty the index on the given column, and repopulate it from the current rows in a single
+    /// pass. Returns false if there is no index on the column.
+    ///
+    /// This is useful after large numbers of changes, which may leave an index larger or slower
+    /// than a freshly built one, or if a custom index has otherwise drifted from the rows.
+    pub fn reindex(&mut self, column: usize) -> bool {
+        use EqualityIndex;
+        self.vacuum();
+        let idx = match self.indices.get_mut(&column) {
+            Some(idx) => idx,
+            None => return false,
+        };
+
+        if !idx.clear() {
+            for (rowid, row) in self.rows.iter() {
+                idx.undex(row.index(column), rowid);
+            }
+        }
+        idx.reserve(self.rows.len());
+        idx.index_all(column_entries(&self.rows, column));
+        idx.rebuild();
+        true
+    }
+
+    /// Remove the index on the given column, and return it. Returns `None` if there is no such
+    /// index.
+    ///
+    /// Once removed, the index is no longer maintained as rows change, and queries on the column
+    /// fall back to other indices or to a full scan. If the index was unique, the uniqueness
+    /// requirement is dropped along with it. Composite, partial, and other special indices that
+    /// cover the column are not affected.
+    pub fn remove_index(&mut self, column: usize) -> Option<Index<T>> {
+        self.unique.remove(&column);
+        self.indices.remove(&column)
+    }
+
+    /// Add an index on the given column using the given indexer, and require that no two rows in
+    /// the `Store` have the same value in that column.
+    ///
+    /// Once the index is added, `try_insert` returns an error for rows that would violate the
+    /// uniqueness requirement, while `insert`, `update`, `upsert`, and the like panic. If the
+    /// existing rows already violate the requirement, an error is returned, and the index is not
+    /// added.
+    pub fn index_unique<I: Into<Index<T>>>(&mut self,
+                                           column: usize,
+                                           indexer: I)
+                                           -> Result<(), UniqueViolation> {
+        use EqualityIndex;
+        self.vacuum();
+        let mut idx = indexer.into();
+
+        // populate the new index, checking for duplicates as we go
+        idx.reserve(self.rows.len());
+        for (rowid, row) in self.rows.iter() {
+            let v = row.index(column);
+            let rows = &self.rows;
+            if let Some(existing) = idx.lookup(v).find(|other| rows[other].index(column) == v) {
+                return Err(UniqueViolation {
+                    column,
+                    rowid: existing,
+                });
+            }
+            idx.index_ref(v, rowid);
+        }
+        idx.rebuild();
+
+        self.indices.insert(column, idx);
+        self.unique.insert(column);
+        Ok(())
+    }
+
+    /// Add an index on the given column using the given indexer that only covers rows matching
+    /// the given filter condition. See `PartialIndex`.
+    ///
+    /// The planner only uses a partial index for queries whose conditions guarantee that all
+    /// matching rows are covered, which is the case when the query has a condition on the filter
+    /// column that selects a subset of the values the filter selects. For example, an index with
+    /// the filter `[1] = "active"` is used for queries with that same condition, and one with the
+    /// filter `[1] > 10` is used for queries with the condition `[1] = 20` or `[1] >= 15`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the filter does not compare against constants using one of `Equal`, `Less`,
+    /// `Greater`, `LessEqual`, `GreaterEqual`, or `Between`.
+    pub fn index_partial<I: Into<Index<T>>>(&mut self,
+                                            column: usize,
+                                            filter: &cmp::Condition<T>,
+                                            indexer: I) {
+        self.vacuum();
+        let (min, max) = const_range_of(&filter.cmp)
+            .expect("partial index filter must be a constant equality or range comparison");
+        let mut p = PartialIndex::new(column, filter.column, min.cloned(), max.cloned(), indexer);
+
+        // populate the new index
+        for (rowid, row) in self.rows.iter() {
+            p.index_row(row, rowid);
+        }
+        EqualityIndex::rebuild(&mut p);
+
+        self.partials.push(p);
+    }
+
+    /// Add an index on the given column using the given indexer, where each value is indexed by
+    /// the key computed by `f` rather than by the value itself. See `FunctionalIndex`.
+    ///
+    /// The keys can be of any type `K`, such as the year of a date, and rows can then be looked up
+    /// by their computed key using `find_by`. Any existing functional index on the same column is
+    /// replaced.
+    pub fn index_by<F, K, I>(&mut self, column: usize, f: F, indexer: I)
+        where F: Fn(&T) -> K + MaybeSync + 'static,
+              K: Ord + Clone + MaybeSync + 'static,
+              I: Into<Index<K>>,
+              T: 'static
+    {
+        self.vacuum();
+        let mut fi = FunctionalIndex::new(column, f, indexer);
+
+        // populate the new index
+        for (rowid, row) in self.rows.iter() {
+            fi.index_row(row, rowid);
+        }
+        EqualityIndex::rebuild(&mut fi);
+
+        self.functionals.insert(column, Box::new(fi));
+    }
+
+    /// Add a covering index on the given column, which keeps a copy of every row. See
+    /// `CoveringIndex`.
+    ///
+    /// `find` answers equality conditions on the column directly from the copies in the covering
+    /// index, unless another index is expected to yield fewer rows. Any existing covering index on
+    /// the same column is replaced.
+    pub fn index_covering(&mut self, column: usize)
+        where R: Clone
+    {
+        self.vacuum();
+        let mut ci = CoveringIndex::new(column);
+
+        // populate the new index
+        for (rowid, row) in self.rows.iter() {
+            ci.index_row(row, rowid);
+        }
+
+        self.coverings.insert(column, ci);
+    }
+
+    /// Add a spatial index over the points whose x and y coordinates are held in the given
+    /// columns. The index is used by `within_bbox`. Any existing spatial index over the same
+    /// columns is replaced.
+    #[cfg(feature = "spatial")]
+    pub fn index_spatial<I>(&mut self, x: usize, y: usize, indexer: I)
+        where I: spatial::SpatialIndex<T> + MaybeSync + 'static
+    {
+        self.vacuum();
+        let mut index = Box::new(indexer);
+
+        // populate the new index
+        for (rowid, row) in self.rows.iter() {
+            index.index(row.index(x).clone(), row.index(y).clone(), rowid);
+        }
+
+        self.spatials.retain(|si| (si.x, si.y) != (x, y));
+        self.spatials.push(SpatialEntry { x, y, index });
+    }
+
+    /// Add an interval index over the intervals whose start and end are held in the given
+    /// columns. The index is used by `find_overlapping` and `find_containing`. Any existing
+    /// interval index over the same columns is replaced.
+    pub fn index_interval<I>(&mut self, start: usize, end: usize, indexer: I)
+        where I: interval::IntervalIndex<T> + MaybeSync + 'static
+    {
+        self.vacuum();
+        let mut index = Box::new(indexer);
+
+        // populate the new index
+        for (rowid, row) in self.rows.iter() {
+            index.index(row.index(start).clone(), row.index(end).clone(), rowid);
+        }
+        index.rebuild();
+
+        self.intervals.retain(|ii| (ii.start, ii.end) != (start, end));
+        self.intervals.push(IntervalEntry { start, end, index });
+    }
+
+    /// Add an index over the given columns together using the given indexer. See
+    /// `CompositeIndex`.
+    ///
+    /// A composite index is used for queries that have an equality condition against a constant
+    /// for *every* one of its columns. This is often much more selective than an index over any
+    /// one of those columns. If the indexer supports range queries (like `BTreeIndex`), the index
+    /// can also be used for queries that only have such conditions for a leading prefix of its
+    /// columns. Any existing composite index over the same columns is replaced.
+    pub fn index_multi<I: Into<Index<Vec<T>>>>(&mut self, columns: &[usize], indexer: I) {
+        self.vacuum();
+        let mut ci = CompositeIndex::new(columns, indexer);
+
+        // populate the new index
+        ci.reserve(self.capacity.max(self.rows.len()));
+        for (rowid, row) in self.rows.iter() {
+            ci.index_row(row, rowid);
+        }
+        EqualityIndex::rebuild(&mut ci);
+
+        self.composites.retain(|other| other.columns() != columns);
+        self.composites.push(ci);
+    }
+}
+
+impl<T: Ord + Copy> Store<T, Vec<T>> {
+    /// Add rows given as a single flat slice of values to the `Store`, and return the range of
+    /// rowids assigned to them. The slice holds the values of each row in turn (that is, it is in
+    /// row-major order), so its length must be a multiple of the number of columns.
+    ///
+    /// Since the values are `Copy`, each row is copied out of the slice with a single `memcpy`,
+    /// and each column index is handed the keys in its column straight out of the slice, all at
+    /// once (see `EqualityIndex::index_all`). This makes loading rows of integers or other plain
+    /// values this way much faster than inserting them one at a time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of `values` is not a multiple of the number of columns. Like `insert`,
+    /// also panics if a row has the same value in a uniquely indexed column as another row. Since
+    /// every row must be checked against the rows that came before it, the rows are inserted one
+    /// at a time if the `Store` has any unique indices.
+    pub fn extend_from_slice(&mut self, values: &[T]) -> ops::Range<usize> {
+        use EqualityIndex;
+        let cols = self.cols;
+        assert!(cols > 0 && values.len() % cols == 0,
+                "{} values do not make up whole rows of {} columns",
+                values.len(),
+                cols);
+
+        let first = self.rowid;
+        let rows = values.chunks(cols).map(<[T]>::to_vec);
+        if !self.unique.is_empty() {
+            self.extend(rows);
+            return first..self.rowid;
+        }
+
+        let mut rows = (first..).zip(rows).collect::<RowMap<_>>();
+        self.rowid += rows.len();
+
+        // the column indices are populated straight from the slice, and are set aside while the
+        // other indices are populated from the rows
+        let mut indices = mem::take(&mut self.indices);
+        for (&column, idx) in indices.iter_mut() {
+            let keys = values[column..].iter().step_by(cols).cloned();
+            idx.reserve(rows.len());
+            idx.index_all(keys.zip(first..).collect());
+        }
+        self.index_rows(&rows);
+        self.indices = indices;
+
+        self.hooks.inserted_all(&rows);
+        self.rows.append(&mut rows);
+        self.rebuild_indices();
+        first..self.rowid
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<T, R> Store<T, R>
+    where T: Ord + Clone + Send + Sync,
+          R: Row<T> + Send + Sync
+{
+    /// Add all the given rows to the `Store`, and return the range of rowids assigned to them.
+    ///
+    /// The rows are given rowids in the order they are yielded, just like with `extend`, but each
+    /// index is then populated with all the new rows in parallel with the others, using rayon's
+    /// thread pool. This is much faster than `extend` when loading many rows into a `Store` with
+    /// several indices.
+    ///
+    /// # Panics
+    ///
+    /// Like `insert`, panics if a row has the same value in a uniquely indexed column as another
+    /// row. Since every row must be checked against the rows that came before it, the rows are
+    /// inserted one at a time if the `Store` has any unique indices.
+    pub fn bulk_load<I: IntoIterator<Item = R>>(&mut self, rows: I) -> ops::Range<usize> {
+        use EqualityIndex;
+
+        let first = self.rowid;
+        if !self.unique.is_empty() {
+            self.extend(rows);
+            return first..self.rowid;
+        }
+
+        let mut rows = rows.into_iter()
+            .enumerate()
+            .map(|(i, row)| (first + i, row))
+            .collect::<RowMap<_>>();
+        debug_assert!(rows.values().all(|row| row.columns() == self.cols));
+        self.rowid += rows.len();
+
+        {
+            // the spawned tasks only borrow the new rows
+            let rows = &rows;
+            rayon::scope(|s| {
+                for (&column, idx) in self.indices.iter_mut() {
+                    s.spawn(move |_| {
+                        idx.reserve(rows.len());
+                        idx.index_all(column_entries(rows, column));
+                        idx.rebuild();
+                    });
+                }
+                for ci in &mut self.composites {
+                    s.spawn(move |_| {
+                        for (rowid, row) in rows {
+                            ci.index_row(row, rowid);
+                        }
+                        EqualityIndex::rebuild(ci);
+                    });
+                }
+                for p in &mut self.partials {
+                    s.spawn(move |_| {
+                        for (rowid, row) in rows {
+                            p.index_row(row, rowid);
+                        }
+                        EqualityIndex::rebuild(p);
+                    });
+                }
+                for fi in self.functionals.values_mut() {
+                    s.spawn(move |_| {
+                        for (rowid, row) in rows {
+                            fi.index_row(row, rowid);
+                        }
+                        fi.rebuild();
+                    });
+                }
+                for ci in self.coverings.values_mut() {
+                    s.spawn(move |_| for (rowid, row) in rows {
+                        ci.index_row(row, rowid);
+                    });
+                }
+                #[cfg(feature = "spatial")]
+                for si in &mut self.spatials {
+                    s.spawn(move |_| for (rowid, row) in rows {
+                        si.index.index(row.index(si.x).clone(), row.index(si.y).clone(), rowid);
+                    });
+                }
+                for ii in &mut self.intervals {
+                    s.spawn(move |_| {
+                        for (rowid, row) in rows {
+                            ii.index.index(row.index(ii.start).clone(),
+                                           row.index(ii.end).clone(),
+                                           rowid);
+                        }
+                        ii.index.rebuild();
+                    });
+                }
+            });
+        }
+
+        self.hooks.inserted_all(&rows);
+        self.rows.append(&mut rows);
+        first..self.rowid
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Write all the rows in the `Store`, in rowid order, to the given writer as a Parquet file
+    /// with the given Arrow schema, and return the number of rows written.
+    ///
+    /// The schema must have one field for each column of the `Store`. The rows are written in
+    /// batches of `PARQUET_BATCH_SIZE` rows: for each batch, `column` is called once for every
+    /// column with the column's index and the values in that column of each row in the batch,
+    /// and must return an Arrow array of the field's type with those values, in the same order.
+    pub fn write_parquet<W, F>(&self,
+                               writer: W,
+                               schema: arrow_schema::SchemaRef,
+                               mut column: F)
+                               -> Result<usize, parquet::errors::ParquetError>
+        where W: io::Write + Send,
+              F: FnMut(usize, &[&T]) -> arrow_array::ArrayRef
+    {
+        use parquet::errors::ParquetError;
+
+        let fields = schema.fields().len();
+        if fields != self.cols {
+            let msg = format!("schema has {} fields, but the store has {} columns",
+                              fields,
+                              self.cols);
+            return Err(ParquetError::General(msg));
+        }
+
+        let mut writer = parquet::arrow::ArrowWriter::try_new(writer, schema.clone(), None)?;
+        let mut rows = self.rows.values().peekable();
+        let mut values = Vec::with_capacity(PARQUET_BATCH_SIZE);
+        while rows.peek().is_some() {
+            let batch = rows.by_ref().take(PARQUET_BATCH_SIZE).collect::<Vec<_>>();
+            let columns = (0..self.cols)
+                .map(|c| {
+                    values.clear();
+                    values.extend(batch.iter().map(|row| row.index(c)));
+                    column(c, &values)
+                })
+                .collect();
+            writer.write(&arrow_array::RecordBatch::try_new(schema.clone(), columns)?)?;
+        }
+        writer.close()?;
+        Ok(self.rows.len())
+    }
+}
+
+/// An iterator over the rows that match a query. See `Store::find`.
+///
+/// The candidate rows are either scanned directly, or come from the index the query planner
+/// picked. Each candidate row is then checked against all the query's conditions.
+pub struct Find<'s, 'c, T: Clone + 'c, R: 's> {
+    rows: &'s RowMap<R>,
+    conds: Cow<'c, [cmp::Condition<'c, T>]>,
+    source: Source<'s, R>,
+}
+
+/// An iterator over copies of the rows that matched a query when it was issued. See
+/// `Store::find_snapshot`.
+pub struct FindSnapshot<'c, T: Clone + 'c, R> {
+    rows: RowMap<R>,
+    conds: &'c [cmp::Condition<'c, T>],
+    rowids: vec::IntoIter<usize>,
+}
+
+impl<'c, T, R> Iterator for FindSnapshot<'c, T, R>
+    where T: Ord + Clone,
+          R: Row<T> + Clone
+{
+    type Item = R;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let row = &self.rows[&self.rowids.next()?];
+            if self.conds.iter().all(|c| c.matches(row)) {
+                return Some(row.clone());
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.rowids.size_hint().1)
+    }
+}
+
+/// The rowids of the rows that may match a query. See `Store::using_index`.
+pub(crate) enum Candidates<'s, R: 's> {
+    /// All the rowids, in order.
+    Scan(rows::Keys<'s, R>),
+    /// The rowids indexed under a single key in an `Index::Hash` or `Index::BTree`. If rows have
+    /// been deleted with `delete_lazy`, the rows of the `Store` are given too, so that the deleted
+    /// rows can be skipped.
+    Postings(idx::PostingsIter<'s>, Option<&'s RowMap<R>>),
+    /// The rowids found through any other index.
+    Boxed(Box<dyn Iterator<Item = usize> + 's>),
+}
+
+impl<'s, R: 's> Iterator for Candidates<'s, R> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        match *self {
+            Candidates::Scan(ref mut rowids) => rowids.next(),
+            Candidates::Postings(ref mut rowids, None) => rowids.next(),
+            Candidates::Postings(ref mut rowids, Some(rows)) => {
+                rowids.find(|rowid| rows.contains_key(rowid))
+            }
+            Candidates::Boxed(ref mut rowids) => rowids.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match *self {
+            Candidates::Scan(ref rowids) => rowids.size_hint(),
+            Candidates::Postings(ref rowids, None) => rowids.size_hint(),
+            Candidates::Postings(ref rowids, Some(_)) => (0, rowids.size_hint().1),
+            Candidates::Boxed(ref rowids) => rowids.size_hint(),
+        }
+    }
+}
+
+/// Where a `Find` gets its candidate rows from.
+enum Source<'s, R: 's> {
+    /// All the rows, in rowid order.
+    Scan(rows::Values<'s, R>),
+    /// The rowids found through an index.
+    Rowids(Candidates<'s, R>),
+    /// The rows found through a `CoveringIndex`.
+    Rows(Box<dyn Iterator<Item = &'s R> + 's>),
+}
+
+impl<'s, 'c, T, R> Iterator for Find<'s, 'c, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    type Item = &'s R;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let row = match self.source {
+                Source::Scan(ref mut rows) => rows.next()?,
+                Source::Rowids(ref mut rowids) => &self.rows[&rowids.next()?],
+                Source::Rows(ref mut rows) => rows.next()?,
+            };
+            if self.conds.iter().all(|c| c.matches(row)) {
+                return Some(row);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let upper = match self.source {
+            Source::Scan(ref rows) => rows.size_hint().1,
+            Source::Rowids(ref rowids) => rowids.size_hint().1,
+            Source::Rows(ref rows) => rows.size_hint().1,
+        };
+        (0, upper)
+    }
+}
+
+/// A query whose conditions may compare against `cmp::Value::Param` placeholders, which are given
+/// values each time the query is executed. See `Store::prepare`.
+pub struct Prepared<'s, 'c, T: Clone + 'c, R: 's> {
+    store: &'s Store<T, R>,
+    conds: Vec<cmp::Condition<'c, T>>,
+    params: usize,
+}
+
+impl<'s, 'c, T, R> Prepared<'s, 'c, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Returns the number of parameters that must be given to `execute`.
+    pub fn params(&self) -> usize {
+        self.params
+    }
+
+    /// Returns an iterator that yields all rows matching the prepared conditions, where each
+    /// `Param` placeholder takes on the value at its position in `params`.
+    ///
+    /// The conditions used for the query borrow both the prepared conditions and `params`, so no
+    /// values are cloned. Like `Store::find`, the index to use is picked based on the values
+    /// given.
+    ///
+    /// # Panics
+    ///
+    /// Panics if fewer than `params()` parameters are given.
+    pub fn execute<'p>(&'p self, params: &'p [T]) -> Find<'s, 'p, T, R> {
+        assert!(params.len() >= self.params,
+                "prepared query expects {} parameters, but {} were given",
+                self.params,
+                params.len());
+        let conds = self.conds.iter().map(|c| c.bind(params)).collect::<Vec<_>>();
+        self.store.find_cow(Cow::Owned(conds))
+    }
+}
+
+/// A view of a subset of the columns of a row. See `Store::find_project`.
+#[derive(Debug)]
+pub struct Projection<'r, 'c, R: 'r + ?Sized> {
+    row: &'r R,
+    cols: &'c [usize],
+}
+
+impl<'r, 'c, R: 'r + ?Sized> Clone for Projection<'r, 'c, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'r, 'c, R: 'r + ?Sized> Copy for Projection<'r, 'c, R> {}
+
+impl<'r, 'c, T, R: Row<T> + ?Sized> Row<T> for Projection<'r, 'c, R> {
+    fn index(&self, column: usize) -> &T {
+        self.row.index(self.cols[column])
+    }
+    fn columns(&self) -> usize {
+        self.cols.len()
+    }
+}
+
+impl<'r, 'c, R: 'r + ?Sized> Projection<'r, 'c, R> {
+    /// Returns the full row this is a projection of.
+    pub fn row(&self) -> &'r R {
+        self.row
+    }
+}
+
+/// An iterator over the rows of a `Store` and their rowids. See `Store::iter`.
+pub struct Iter<'a, R: 'a>(rows::Iter<'a, R>);
+
+impl<'a, R: 'a> Iterator for Iter<'a, R> {
+    type Item = (usize, &'a R);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+/// An iterator over the rows of a `Store`. See `Store::values`.
+pub struct Values<'a, R: 'a>(rows::Values<'a, R>);
+
+impl<'a, R: 'a> Iterator for Values<'a, R> {
+    type Item = &'a R;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+/// An owning iterator over the rows of a `Store` and their rowids. See `Store::into_iter`.
+pub struct IntoIter<R>(rows::IntoIter<R>);
+
+impl<R> Iterator for IntoIter<R> {
+    type Item = (usize, R);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<T, R> IntoIterator for Store<T, R> {
+    type Item = (usize, R);
+    type IntoIter = IntoIter<R>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter(self.rows.into_iter())
+    }
+}
+
+impl<'a, T, R> IntoIterator for &'a Store<T, R> {
+    type Item = (usize, &'a R);
+    type IntoIter = Iter<'a, R>;
+    fn into_iter(self) -> Self::IntoIter {
+        Iter(self.rows.iter())
+    }
+}
+
+/// Note that iterating over a mutable reference to a `Store` still only gives shared references
+/// to its rows. This is because modifying the rows directly would leave the `Store`'s indices
+/// stale. Use `Store::update` to modify rows instead.
+impl<'a, T, R> IntoIterator for &'a mut Store<T, R> {
+    type Item = (usize, &'a R);
+    type IntoIter = Iter<'a, R>;
+    fn into_iter(self) -> Self::IntoIter {
+        Iter(self.rows.iter())
+    }
+}
+
+/// An iterator that removes rows from a `Store`, and yields them by value along with their rowids.
+/// See `Store::drain`.
+pub struct Drain<'a, T, R>
+    where T: Ord + Clone + 'a,
+          R: Row<T> + 'a
+{
+    store: &'a mut Store<T, R>,
+    rowids: vec::IntoIter<usize>,
+}
+
+impl<'a, T, R> Iterator for Drain<'a, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    type Item = (usize, R);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rowids.next().map(|rowid| (rowid, self.store.remove(rowid)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.rowids.size_hint()
+    }
+}
+
+impl<'a, T, R> Drop for Drain<'a, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    fn drop(&mut self) {
+        for _ in self.by_ref() {}
+    }
+}
+
+/// A `Store` in bulk mode, which defers index updates until bulk mode ends. See
+/// `Store::begin_bulk`.
+pub struct Bulk<'a, T, R>
+    where T: Ord + Clone + 'a,
+          R: Row<T> + 'a
+{
+    store: &'a mut Store<T, R>,
+    /// The rowid of the first row inserted in bulk mode. None of the rows from here on have been
+    /// indexed yet.
+    first: usize,
+    /// The indexed rows that have been removed in bulk mode, and are yet to be removed from the
+    /// indices.
+    removed: RowMap<R>,
+}
+
+impl<'a, T, R> Bulk<'a, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Returns true if index updates are deferred until bulk mode ends, which is the case unless
+    /// the `Store` has unique indices.
+    fn deferred(&self) -> bool {
+        self.store.unique.is_empty()
+    }
+
+    /// Insert a new data row into the `Store`, and return the rowid assigned to it. See
+    /// `Store::insert`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the row has the same value in a uniquely indexed column as an existing row.
+    pub fn insert(&mut self, row: R) -> usize {
+        if !self.deferred() {
+            return self.store.insert(row);
+        }
+
+        debug_assert_eq!(row.columns(), self.store.cols);
+        let rowid = self.store.rowid;
+        self.store.hooks.inserted(rowid, &row);
+        self.store.rows.insert(rowid, row);
+        self.store.rowid += 1;
+        rowid
+    }
+
+    /// Remove the row with the given rowid from the `Store`. Returns false if there is no such
+    /// row.
+    pub fn remove(&mut self, rowid: usize) -> bool {
+        if !self.store.rows.contains_key(&rowid) {
+            return false;
+        }
+        if !self.deferred() {
+            self.store.remove(rowid);
+            return true;
+        }
+
+        let row = self.store.rows.remove(&rowid).unwrap();
+        self.store.versions.supersede(rowid, self.store.generation, &row, false);
+        self.store.row_versions.remove(&rowid);
+        self.store.hooks.deleted(rowid, &row);
+        if rowid < self.first {
+            self.removed.insert(rowid, row);
+        }
+        true
+    }
+
+    /// Leave bulk mode, and bring all the indices up to date with the changes made in it.
+    ///
+    /// This is equivalent to dropping the `Bulk`.
+    pub fn end_bulk(self) {}
+}
+
+impl<'a, T, R> Drop for Bulk<'a, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    fn drop(&mut self) {
+        if !self.deferred() {
+            return;
+        }
+        let removed = mem::take(&mut self.removed);
+        self.store.undex_rows(&removed);
+        let mut inserted = self.store.rows.split_off(&self.first);
+        self.store.index_rows(&inserted);
+        self.store.rows.append(&mut inserted);
+        self.store.rebuild_indices();
+    }
+}
+
+impl<T, R> Extend<R> for Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    fn extend<I: IntoIterator<Item = R>>(&mut self, rows: I) {
+        for row in rows {
+            self.insert(row);
+        }
+        self.rebuild_indices();
+    }
+}
+
+/// Collecting rows into a `Store` creates a `Store` with as many columns as the first row has. If
+/// there are no rows, the resulting `Store` has no columns, and no rows can be added to it later.
+impl<T, R> FromIterator<R> for Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    fn from_iter<I: IntoIterator<Item = R>>(rows: I) -> Self {
+        let mut rows = rows.into_iter().peekable();
+        let cols = rows.peek().map(|r| r.columns()).unwrap_or(0);
+        let mut store = Store::new(cols);
+        store.extend(rows);
+        store
+    }
+}
+
+/// Collect the value in the given column of every row, along with the row's rowid, for
+/// `EqualityIndex::index_all`.
+fn column_entries<T, R>(rows: &RowMap<R>, column: usize) -> Vec<(T, usize)>
+    where T: Clone,
+          R: Row<T>
+{
+    rows.iter().map(|(rowid, row)| (row.index(column).clone(), rowid)).collect()
+}
+
+/// Panics if any of the given conditions compares against a `cmp::Value::Param`, which only a
+/// prepared query gives a value (see `Store::prepare`). This way a query with such a condition is
+/// rejected up front, rather than when it first gets to compare a row against the parameter.
+fn reject_params<T: Ord + Clone>(conds: &[cmp::Condition<T>]) {
+    assert!(!conds.iter().any(|c| c.cmp.has_params()),
+            "conditions that compare against a parameter must be run through Store::prepare");
+}
+
+/// Returns true if the given conditions guarantee that any row that matches them is covered by the
+/// given partial index.
+fn covered_by<T: Ord + Clone>(p: &PartialIndex<T>, conds: &[cmp::Condition<T>]) -> bool {
+    let (column, min, max) = p.filter();
+    conds.iter()
+        .filter(|c| c.column == column)
+        .filter_map(|c| const_range_of(&c.cmp))
+        .any(|(cmin, cmax)| {
+            let above = match (min, cmin) {
+                (Bound::Unbounded, _) => true,
+                (_, Bound::Unbounded) => false,
+                (Bound::Excluded(min), Bound::Included(cmin)) => cmin > min,
+                (Bound::Included(min), Bound::Included(cmin)) |
+                (Bound::Included(min), Bound::Excluded(cmin)) |
+                (Bound::Excluded(min), Bound::Excluded(cmin)) => cmin >= min,
+            };
+            let below = match (max, cmax) {
+                (Bound::Unbounded, _) => true,
+                (_, Bound::Unbounded) => false,
+                (Bound::Excluded(max), Bound::Included(cmax)) => cmax < max,
+                (Bound::Included(max), Bound::Included(cmax)) |
+                (Bound::Included(max), Bound::Excluded(cmax)) |
+                (Bound::Excluded(max), Bound::Excluded(cmax)) => cmax <= max,
+            };
+            above && below
+        })
+}
+
+/// Like `range_of`, but also expresses equality with a constant as a range.
+fn const_range_of<'a, T: Clone>(cmp: &'a cmp::Comparison<T>)
+                                -> Option<(Bound<&'a T>, Bound<&'a T>)> {
+    match *cmp {
+        cmp::Comparison::Equal(cmp::Value::Const(ref v)) => {
+            Some((Bound::Included(&**v), Bound::Included(&**v)))
+        }
+        _ => range_of(cmp),
+    }
+}
+
+/// Returns true if the given value lies within the given bounds.
+fn in_bounds<T: Ord>(v: &T, min: &Bound<T>, max: &Bound<T>) -> bool {
+    let above = match *min {
+        Bound::Included(ref min) => v >= min,
+        Bound::Excluded(ref min) => v > min,
+        Bound::Unbounded => true,
+    };
+    let below = match *max {
+        Bound::Included(ref max) => v <= max,
+        Bound::Excluded(ref max) => v < max,
+        Bound::Unbounded => true,
+    };
+    above && below
+}
+
+/// Determine the range of values that a `RangeIndex` would have to scan to find all values
+/// satisfying the given comparison, if the comparison can be expressed as such a range.
+fn range_of<'a, T: Clone>(cmp: &'a cmp::Comparison<T>) -> Option<(Bound<&'a T>, Bound<&'a T>)> {
+    use cmp::Comparison::*;
+    use cmp::Value::Const;
+    match *cmp {
+        Less(Const(ref v)) => Some((Bound::Unbounded, Bound::Excluded(&**v))),
+        LessEqual(Const(ref v)) => Some((Bound::Unbounded, Bound::Included(&**v))),
+        Greater(Const(ref v)) => Some((Bound::Excluded(&**v), Bound::Unbounded)),
+        GreaterEqual(Const(ref v)) => Some((Bound::Included(&**v), Bound::Unbounded)),
+        Between(ref min, ref max) => {
+            let bound = |b: &'a Bound<cmp::Value<'a, T>>| match *b {
+                Bound::Included(Const(ref v)) => Some(Bound::Included(&**v)),
+                Bound::Excluded(Const(ref v)) => Some(Bound::Excluded(&**v)),
+                Bound::Unbounded => Some(Bound::Unbounded),
+                _ => None,
+            };
+            match (bound(min), bound(max)) {
+                (Some(min), Some(max)) => Some((min, max)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+impl<T> Row<T> for &[T] {
+    fn index(&self, i: usize) -> &T {
+        &self[i]
+    }
+    fn columns(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> Row<T> for [T] {
+    fn index(&self, i: usize) -> &T {
+        &self[i]
+    }
+    fn columns(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> Row<T> for Vec<T> {
+    fn index(&self, i: usize) -> &T {
+        &self[i]
+    }
+    fn columns(&self) -> usize {
+        self.len()
+    }
+}
+
+use std::sync;
+impl<T> Row<T> for sync::Arc<Vec<T>> {
+    fn index(&self, i: usize) -> &T {
+        &self[i]
+    }
+    fn columns(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> Row<T> for rc::Rc<Vec<T>> {
+    fn index(&self, i: usize) -> &T {
+        &self[i]
+    }
+    fn columns(&self) -> usize {
         self.len()
     }
-}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a1", "a2"]);
+        store.insert(vec!["b1", "b2"]);
+        store.insert(vec!["c1", "c2"]);
+        assert_eq!(store.find(&[]).count(), 3);
+    }
+
+    #[test]
+    fn it_works_w_slab_storage() {
+        let mut store = Store::with_storage(2, Storage::Slab);
+        for i in 0..10 {
+            store.insert(vec![i, i % 3]);
+        }
+        store.index(1, idx::HashIndex::new());
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                   }];
+        assert_eq!(store.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec![0, 3, 6, 9]);
+
+        store.delete(&cmp);
+        assert_eq!(store.len(), 6);
+        assert_eq!(store.get(3), None);
+        assert_eq!(store.iter().map(|(rowid, _)| rowid).collect::<Vec<_>>(),
+                   vec![1, 2, 4, 5, 7, 8]);
+        assert_eq!(store.values().size_hint(), (6, Some(6)));
+
+        let cold = store.split_off(|r| r[0] < 5);
+        assert_eq!(cold.len(), 3);
+        assert_eq!(cold.get(2), Some(&vec![2, 2]));
+        assert_eq!(store.insert(vec![10, 1]), 10);
+        store.absorb(cold);
+        assert_eq!(store.len(), 7);
+        assert_eq!(store.get(13), Some(&vec![4, 1]));
+        assert_eq!(store.stats().indices[&1].entries, 7);
+    }
+
+    #[test]
+    fn it_reports_stats_for_empty_indices() {
+        let mut store = Store::<i32>::new(2);
+        store.index(0, idx::BTreeIndex::new());
+        store.index(1, idx::HashIndex::new());
+        let stats = store.stats();
+        assert_eq!(stats.indices[&0],
+                   IndexStats {
+                       entries: 0,
+                       estimate: 0,
+                   });
+        assert_eq!(stats.indices[&1].estimate, 0);
+    }
+
+    #[test]
+    fn it_works_w_chunked_storage() {
+        let mut store = Store::with_storage(2, Storage::Chunked);
+        for i in 0..10000 {
+            store.insert(vec![i, i % 3]);
+        }
+        store.index(1, idx::BTreeIndex::new());
+        store.delete_range(0, Bound::Unbounded, Bound::Excluded(&5000));
+        assert_eq!(store.len(), 5000);
+        assert_eq!(store.get(4999), None);
+        assert_eq!(store.iter().next(), Some((5000, &vec![5000, 2])));
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1667);
+        assert_eq!(store.insert(vec![10000, 0]), 10000);
+        assert_eq!(store.find(&cmp).count(), 1668);
+    }
+
+    #[test]
+    fn it_works_w_non_vec() {
+        use std::sync;
+        let mut store = Store::new(2);
+        store.insert(sync::Arc::new(vec!["a1", "a2"]));
+        store.insert(sync::Arc::new(vec!["b1", "b2"]));
+        store.insert(sync::Arc::new(vec!["c1", "c2"]));
+        assert_eq!(store.find(&[]).count(), 3);
+    }
+
+    #[test]
+    fn it_works_with_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a1", "a2"]);
+        store.insert(vec!["b1", "b2"]);
+        store.insert(vec!["c1", "c2"]);
+        assert_eq!(store.find(&[]).count(), 3);
+    }
+
+    #[test]
+    fn it_filters() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp)
+                       .count(),
+                   2);
+        assert!(store.find(&cmp).all(|r| r[0] == "a"));
+    }
+
+    #[test]
+    fn it_filters_with_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp)
+                       .count(),
+                   2);
+        assert!(store.find(&cmp).all(|r| r[0] == "a"));
+    }
+
+    #[test]
+    fn it_filters_with_partial_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("x2")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(store.find(&cmp).all(|r| r[0] == "a" && r[1] == "x2"));
+    }
+
+    #[test]
+    fn it_filters_with_late_indices() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        store.index(0, idx::HashIndex::new());
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp)
+                       .count(),
+                   2);
+        assert!(store.find(&cmp).all(|r| r[0] == "a"));
+    }
+
+    #[test]
+    fn it_pages() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        store.insert(vec!["a", "x4"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        let opts = QueryOptions {
+            offset: 1,
+            limit: Some(1),
+        };
+        let rows = store.find_with_options(&cmp, &opts).collect::<Vec<_>>();
+        assert_eq!(rows, vec![&vec!["a", "x2"]]);
+
+        let opts = QueryOptions { offset: 1, ..QueryOptions::default() };
+        assert_eq!(store.find_with_options(&cmp, &opts).count(), 2);
+        let opts = QueryOptions { limit: Some(5), ..QueryOptions::default() };
+        assert_eq!(store.find_with_options(&cmp, &opts).count(), 3);
+    }
+
+    #[test]
+    fn it_aggregates() {
+        let mut store = Store::new(2);
+        store.insert(vec![1, 4]);
+        store.insert(vec![1, 2]);
+        store.insert(vec![2, 9]);
+        store.insert(vec![1, 7]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1)),
+                   }];
+        assert_eq!(store.aggregate(&cmp, 1, Aggregate::Min), Some(2));
+        assert_eq!(store.aggregate(&cmp, 1, Aggregate::Max), Some(7));
+        assert_eq!(store.aggregate(&cmp, 1, Arithmetic::Sum), Some(13));
+        assert_eq!(store.aggregate(&cmp, 1, Arithmetic::Avg), Some(4));
+        assert_eq!(store.aggregate(&[], 1, Aggregate::Max), Some(9));
+
+        // min/max should give the same answers when walking an index
+        store.index(1, idx::BTreeIndex::new());
+        assert_eq!(store.aggregate(&cmp, 1, Aggregate::Min), Some(2));
+        assert_eq!(store.aggregate(&cmp, 1, Aggregate::Max), Some(7));
+        assert_eq!(store.aggregate(&[], 1, Aggregate::Max), Some(9));
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3)),
+                   }];
+        assert_eq!(store.aggregate(&cmp, 1, Aggregate::Min), None);
+        assert_eq!(store.aggregate(&cmp, 1, Arithmetic::Sum), None);
+        assert_eq!(store.aggregate(&cmp, 1, Arithmetic::Avg), None);
+
+        // the rows are not counted in a `T`, so there can be more of them than a `T` can hold
+        let mut store = Store::new(1);
+        for _ in 0..300 {
+            store.insert(vec![0u8]);
+        }
+        assert_eq!(store.aggregate(&[], 0, Arithmetic::Sum), Some(0));
+        assert_eq!(store.aggregate(&[], 0, Arithmetic::Avg), None);
+        store.insert(vec![u8::MAX]);
+        assert_eq!(store.aggregate(&[], 0, Aggregate::Max), Some(u8::MAX));
+
+        // min and max need only `Ord`
+        let mut store = Store::new(1);
+        store.insert(vec!["b".to_owned()]);
+        store.insert(vec!["a".to_owned()]);
+        assert_eq!(store.aggregate(&[], 0, Aggregate::Min), Some("a".to_owned()));
+        assert_eq!(store.aggregate(&[], 0, Aggregate::Max), Some("b".to_owned()));
+    }
+
+    #[test]
+    fn it_finds_top_k() {
+        let mut store = Store::new(1);
+        for &v in &[5, 1, 9, 3, 7] {
+            store.insert(vec![v]);
+        }
+        let top = |store: &Store<_>, k, desc| {
+            store.top_k(0, k, desc).map(|r| r[0]).collect::<Vec<_>>()
+        };
+        assert_eq!(top(&store, 2, false), vec![1, 3]);
+        assert_eq!(top(&store, 3, true), vec![9, 7, 5]);
+        assert_eq!(top(&store, 10, false), vec![1, 3, 5, 7, 9]);
+        assert_eq!(top(&store, 0, true), Vec::<i32>::new());
+
+        store.index(0, idx::BTreeIndex::new());
+        assert_eq!(top(&store, 2, false), vec![1, 3]);
+        assert_eq!(top(&store, 3, true), vec![9, 7, 5]);
+        assert_eq!(top(&store, 10, false), vec![1, 3, 5, 7, 9]);
+        assert_eq!(top(&store, 0, true), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn it_finds_percentiles() {
+        let mut store = Store::new(1);
+        assert_eq!(store.percentile(0, 0.5), None);
+        for &v in &[5, 1, 9, 3, 7, 2, 8, 4, 6, 10] {
+            store.insert(vec![v]);
+        }
+        assert_eq!(store.percentile(0, 0.0), Some(&1));
+        assert_eq!(store.percentile(0, 0.5), Some(&5));
+        assert_eq!(store.percentile(0, 0.9), Some(&9));
+        assert_eq!(store.percentile(0, 0.95), Some(&10));
+        assert_eq!(store.percentile(0, 1.0), Some(&10));
+
+        store.index(0, idx::BTreeIndex::new());
+        assert_eq!(store.percentile(0, 0.0), Some(&1));
+        assert_eq!(store.percentile(0, 0.5), Some(&5));
+        assert_eq!(store.percentile(0, 0.9), Some(&9));
+        assert_eq!(store.percentile(0, 0.95), Some(&10));
+        assert_eq!(store.percentile(0, 1.0), Some(&10));
+    }
+
+    #[test]
+    fn it_finds_ranges() {
+        let mut store = Store::new(2);
+        for &i in &[4, 1, 3, 0, 2] {
+            store.insert(vec![i, i * 10]);
+        }
+        let range = |store: &Store<_>, min, max| {
+            store.find_range(0, min, max).map(|r| r[1]).collect::<Vec<_>>()
+        };
+        assert_eq!(range(&store, Bound::Included(&1), Bound::Excluded(&3)), vec![10, 20]);
+        assert_eq!(range(&store, Bound::Excluded(&2), Bound::Unbounded), vec![40, 30]);
+        assert_eq!(range(&store, Bound::Excluded(&2), Bound::Excluded(&2)), vec![]);
+
+        store.index(0, idx::BTreeIndex::new());
+        assert_eq!(range(&store, Bound::Included(&1), Bound::Excluded(&3)), vec![10, 20]);
+        assert_eq!(range(&store, Bound::Excluded(&2), Bound::Unbounded), vec![30, 40]);
+        assert_eq!(range(&store, Bound::Excluded(&2), Bound::Excluded(&2)), vec![]);
+    }
+
+    #[test]
+    fn it_deletes_ranges() {
+        let mut store = Store::new(2);
+        for i in 0..10 {
+            store.insert(vec![i, i * 10]);
+        }
+        store.delete_range(0, Bound::Included(&2), Bound::Excluded(&5));
+        assert_eq!(store.len(), 7);
+        store.delete_range(0, Bound::Excluded(&7), Bound::Unbounded);
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.values().map(|r| r[0]).collect::<Vec<_>>(), vec![0, 1, 5, 6, 7]);
+
+        store.index(0, idx::BTreeIndex::new());
+        store.index(1, idx::HashIndex::new());
+        store.delete_range(0, Bound::Unbounded, Bound::Included(&5));
+        assert_eq!(store.values().map(|r| r[0]).collect::<Vec<_>>(), vec![6, 7]);
+        assert_eq!(store.stats().indices[&1].entries, 2);
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 0);
+    }
+
+    #[test]
+    fn it_semi_joins() {
+        let mut users = Store::new(2);
+        users.insert(vec![1, 100]);
+        users.insert(vec![2, 200]);
+        users.insert(vec![3, 300]);
+        let mut banned = Store::new(1);
+        banned.insert(vec![2]);
+        banned.insert(vec![3]);
+        banned.insert(vec![4]);
+
+        let found = |users: &Store<_>, banned: &Store<_>| {
+            let cmp = [cmp::Condition {
+                           column: 0,
+                           cmp: cmp::Comparison::InStore(cmp::InStore::new(banned, 0)),
+                       }];
+            users.find(&cmp).map(|r| r[1]).collect::<Vec<_>>()
+        };
+        assert_eq!(found(&users, &banned), vec![200, 300]);
+
+        // membership checks should give the same answer using an index on the other store
+        banned.index(0, idx::HashIndex::new());
+        assert_eq!(found(&users, &banned), vec![200, 300]);
+    }
+
+    #[test]
+    fn it_anti_joins() {
+        let mut orders = Store::new(2);
+        orders.insert(vec![10, 1]);
+        orders.insert(vec![11, 2]);
+        orders.insert(vec![12, 5]);
+        orders.insert(vec![13, 1]);
+        let mut users = Store::new(2);
+        users.insert(vec![1, 100]);
+        users.insert(vec![2, 200]);
+
+        let orphans = |orders: &Store<_>, users: &Store<_>| {
+            orders.anti_join(1, users, 0).map(|r| r[0]).collect::<Vec<_>>()
+        };
+        assert_eq!(orphans(&orders, &users), vec![12]);
+
+        users.index(0, idx::HashIndex::new());
+        assert_eq!(orphans(&orders, &users), vec![12]);
+        users.delete(&[cmp::Condition {
+                           column: 0,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new(1)),
+                       }]);
+        assert_eq!(orphans(&orders, &users), vec![10, 12, 13]);
+    }
+
+    #[test]
+    fn it_finds_across_stores() {
+        let mut a = Store::new(2);
+        a.insert(vec!["a1", "x"]);
+        a.insert(vec!["a2", "y"]);
+        let mut b = Store::new(2);
+        b.insert(vec!["b1", "y"]);
+        b.insert(vec!["b2", "x"]);
+        b.index(1, idx::HashIndex::new());
+        let c = Store::new(2);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("x")),
+                   }];
+        let rows = union_find(&[&a, &b, &c], &cmp).map(|r| r[0]).collect::<Vec<_>>();
+        assert_eq!(rows, vec!["a1", "b2"]);
+        assert_eq!(union_find(&[&c, &b, &a], &[]).count(), 4);
+        assert_eq!(union_find::<&str, Vec<_>>(&[], &cmp).count(), 0);
+    }
+
+    #[test]
+    fn it_absorbs() {
+        let mut a = Store::new(2);
+        a.insert(vec!["a", "x"]);
+        a.index(1, idx::HashIndex::new());
+        let mut b = Store::new(2);
+        b.insert(vec!["b1", "x"]);
+        b.insert(vec!["b2", "y"]);
+        b.insert(vec!["b3", "x"]);
+        b.delete(&[cmp::Condition {
+                      column: 0,
+                      cmp: cmp::Comparison::Equal(cmp::Value::new("b1")),
+                  }]);
+
+        a.absorb(b);
+        assert_eq!(a.len(), 3);
+        assert_eq!(a.get(1), Some(&vec!["b2", "y"]));
+        assert_eq!(a.get(2), Some(&vec!["b3", "x"]));
+        assert_eq!(a.insert(vec!["c", "x"]), 3);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("x")),
+                   }];
+        assert_eq!(a.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec!["a", "b3", "c"]);
+        assert_eq!(a.stats().indices[&1].entries, 4);
+    }
+
+    #[test]
+    fn it_ingests() {
+        use std::io::{self, BufRead};
+        let decode = |r: &mut io::BufReader<&[u8]>| {
+            let mut line = String::new();
+            if r.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            line.trim_end()
+                .split(',')
+                .map(|v| v.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+                .collect::<io::Result<Vec<u32>>>()
+                .map(Some)
+        };
+        let input = (0..INGEST_BATCH_SIZE as u32 + 10)
+            .map(|i| format!("{},{}\n", i, i % 10))
+            .collect::<String>();
+
+        let mut store = Store::new(2);
+        store.insert(vec![0, 0]);
+        store.index(1, idx::HashIndex::new());
+        let rowids = store.ingest(input.as_bytes(), decode).unwrap();
+        assert_eq!(rowids, 1..INGEST_BATCH_SIZE + 11);
+        let last = INGEST_BATCH_SIZE as u32 + 9;
+        assert_eq!(store.get(INGEST_BATCH_SIZE + 10), Some(&vec![last, last % 10]));
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3u32)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), INGEST_BATCH_SIZE / 10 + 1);
+
+        // rows before a decoding error are kept
+        let mut store = Store::new(2);
+        store.index_unique(0, idx::HashIndex::new()).unwrap();
+        let err = store.ingest(&b"1,2\n3,4\nx,5\n6,7\n"[..], decode).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(store.len(), 2);
+        assert!(store.try_insert(vec![3, 0]).is_err());
+
+        // as are rows before a unique violation or a row with the wrong number of columns
+        let err = store.ingest(&b"5,0\n1,0\n7,0\n"[..], decode).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.get_ref().unwrap().is::<UniqueViolation>());
+        assert_eq!(store.len(), 3);
+        let mut store = Store::new(2);
+        let err = store.ingest(&b"1,2\n3\n4,5\n"[..], decode).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn it_rejects_malformed_ingest_input() {
+        use std::io::{self, BufRead};
+        let decode = |r: &mut io::BufReader<&[u8]>| {
+            let mut line = String::new();
+            if r.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            line.trim_end()
+                .split(',')
+                .map(|v| v.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+                .collect::<io::Result<Vec<u32>>>()
+                .map(Some)
+        };
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0u32)),
+                   }];
+
+        // rows with too many or too few columns are never added, with or without unique indices
+        for &unique in &[false, true] {
+            for input in &[&b"1,0
+2,0,0
+3,0
+"[..], &b"1,0
+2
+3,0
+"[..]] {
+                let mut store = Store::new(2);
+                if unique {
+                    store.index_unique(0, idx::HashIndex::new()).unwrap();
+                }
+                store.index(1, idx::HashIndex::new());
+                let err = store.ingest(*input, decode).unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+                assert_eq!(store.len(), 1);
+                assert_eq!(store.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec![1]);
+                assert_eq!(store.insert(vec![4, 0]), 1);
+            }
+        }
+
+        // nor are rows that repeat a unique value from earlier in the same input
+        let mut store = Store::new(2);
+        store.index_unique(0, idx::HashIndex::new()).unwrap();
+        let err = store.ingest(&b"1,0
+2,0
+1,0
+"[..], decode).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.get_ref().unwrap().downcast_ref::<UniqueViolation>(),
+                   Some(&UniqueViolation { column: 0, rowid: 0 }));
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn it_exports() {
+        use std::io::{self, Write};
+
+        /// A writer that counts how many times it is flushed.
+        struct Flushes(Vec<u8>, usize);
+        impl Write for Flushes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                self.1 += 1;
+                Ok(())
+            }
+        }
+
+        let mut store = Store::new(2);
+        store.index(1, idx::HashIndex::new());
+        for i in 0..(2 * EXPORT_FLUSH_ROWS + 10) {
+            store.insert(vec![i, i % 2]);
+        }
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1usize)),
+                   }];
+        let encode = |row: &Vec<usize>, w: &mut io::BufWriter<&mut Flushes>| {
+            writeln!(w, "{}", row[0])
+        };
+
+        let mut out = Flushes(Vec::new(), 0);
+        assert_eq!(store.export(&cmp, &mut out, encode).unwrap(), EXPORT_FLUSH_ROWS + 5);
+        assert_eq!(out.1, 2);
+        let out = String::from_utf8(out.0).unwrap();
+        assert_eq!(out.lines().count(), EXPORT_FLUSH_ROWS + 5);
+        assert!(out.lines().all(|l| l.parse::<usize>().unwrap() % 2 == 1));
+        assert!(out.starts_with("1\n3\n"));
+    }
+
+    #[test]
+    #[cfg(feature = "parquet")]
+    fn it_writes_parquet() {
+        use arrow_array::{Array, ArrayRef, Int64Array, StringArray};
+        use arrow_schema::{DataType, Field, Schema};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        use std::sync::Arc;
+
+        let mut store = Store::new(2);
+        for i in 0..(PARQUET_BATCH_SIZE as i64 + 10) {
+            store.insert(vec![i, i % 3]);
+        }
+        store.delete(&[Condition {
+                           column: 0,
+                           cmp: Comparison::Equal(Value::new(0)),
+                       }]);
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false),
+                                               Field::new("kind", DataType::Utf8, false)]));
+
+        let mut file = Vec::new();
+        let written = store.write_parquet(&mut file, schema.clone(), |c, values| -> ArrayRef {
+                if c == 0 {
+                    Arc::new(values.iter().map(|&&v| v).collect::<Int64Array>())
+                } else {
+                    let kinds = values.iter().map(|v| Some(format!("k{}", v)));
+                    Arc::new(kinds.collect::<StringArray>())
+                }
+            })
+            .unwrap();
+        assert_eq!(written, PARQUET_BATCH_SIZE + 9);
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(file))
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), written);
+        let ids = batches[0].column(0).as_any().downcast_ref::<Int64Array>().unwrap();
+        let kinds = batches[0].column(1).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!((ids.value(0), kinds.value(0)), (1, "k1"));
+        assert_eq!(ids.len(), kinds.len());
+
+        let wrong = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        assert!(store.write_parquet(Vec::new(), wrong, |_, _| unreachable!()).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn it_bulk_loads() {
+        let mut store = Store::new(3);
+        store.insert(vec![0, 0, 0]);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        store.index_multi(&[1, 2], idx::BTreeIndex::new());
+        store.index_interval(1, 2, interval::IntervalTreeIndex::new());
+
+        let rowids = store.bulk_load((1..100).map(|i| vec![i, i % 10, i % 10 + 5]));
+        assert_eq!(rowids, 1..100);
+        assert_eq!(store.len(), 100);
+        assert_eq!(store.insert(vec![100, 0, 0]), 100);
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(42)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Between(Bound::Included(cmp::Value::new(3)),
+                                                     Bound::Included(cmp::Value::new(4))),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 20);
+        assert_eq!(store.stats().indices[&0].entries, 101);
+        assert_eq!(store.stats().indices[&1].entries, 101);
+
+        // unique indices make every row be checked against the ones before it
+        store.index_unique(0, idx::HashIndex::new()).unwrap();
+        assert_eq!(store.bulk_load(vec![vec![101, 1, 1]]), 101..102);
+    }
+
+    #[test]
+    fn it_defers_index_updates_in_bulk() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x"]);
+        store.insert(vec!["b", "y"]);
+        store.index(1, idx::HashIndex::new());
+        store.index_multi(&[0, 1], idx::BTreeIndex::new());
+
+        {
+            let mut bulk = store.begin_bulk();
+            assert_eq!(bulk.insert(vec!["c", "x"]), 2);
+            assert_eq!(bulk.insert(vec!["d", "y"]), 3);
+            assert!(bulk.remove(0));
+            assert!(bulk.remove(3));
+            assert!(!bulk.remove(3));
+            assert_eq!(bulk.insert(vec!["e", "x"]), 4);
+            bulk.end_bulk();
+        }
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.stats().indices[&1].entries, 3);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("x")),
+                   }];
+        assert_eq!(store.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec!["c", "e"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("b")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("y")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+
+        // with a unique index, each row is checked (and indexed) as it is inserted
+        store.index_unique(0, idx::HashIndex::new()).unwrap();
+        store.begin_bulk().insert(vec!["f", "y"]);
+        assert_eq!(store.stats().indices[&0].entries, 4);
+    }
+
+    #[test]
+    fn it_presizes_with_capacity() {
+        let mut store = Store::with_capacity(2, 100);
+        store.index(0, idx::HashIndex::new());
+        store.index_with_capacity(1, idx::HashIndex::new(), 3);
+        store.index_multi(&[0, 1], idx::HashIndex::new());
+        for i in 0..100 {
+            store.insert(vec![i, i % 3]);
+        }
+        store.reserve(50);
+        assert_eq!(store.capacity, 150);
+        for i in 100..150 {
+            store.insert(vec![i, i % 3]);
+        }
+        assert_eq!(store.stats().indices[&0].entries, 150);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 50);
+
+        let mut store = Store::with_storage(1, Storage::Slab);
+        store.reserve(10);
+        store.insert(vec![1]);
+        store.index(0, idx::HashIndex::with_capacity(1));
+        assert_eq!(store.find(&[]).count(), 1);
+    }
+
+    #[test]
+    fn it_compacts() {
+        let mut store = Store::with_storage(2, Storage::Slab);
+        for i in 0..10 {
+            store.insert(vec![i, i % 2]);
+        }
+        store.index(1, idx::HashIndex::new());
+        store.index_multi(&[0, 1], idx::BTreeIndex::new());
+        store.retain(|row| row[0] % 3 == 0);
+        store.shrink_to_fit();
+        assert_eq!(store.len(), 4);
+
+        assert_eq!(store.compact(), vec![0, 3, 6, 9]);
+        assert_eq!(store.iter().map(|(rowid, row)| (rowid, row[0])).collect::<Vec<_>>(),
+                   vec![(0, 0), (1, 3), (2, 6), (3, 9)]);
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1)),
+                   }];
+        assert_eq!(store.find_with_ids(&cmp).map(|(rowid, _)| rowid).collect::<Vec<_>>(),
+                   vec![1, 3]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(6)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                   }];
+        assert_eq!(store.find_with_ids(&cmp).map(|(rowid, _)| rowid).collect::<Vec<_>>(),
+                   vec![2]);
+        assert_eq!(store.insert(vec![10, 0]), 4);
+    }
+
+    #[test]
+    fn it_extends_from_slice() {
+        let mut store = Store::new(2);
+        store.insert(vec![0, 0]);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        store.index_multi(&[0, 1], idx::HashIndex::new());
+
+        assert_eq!(store.extend_from_slice(&[1, 10, 2, 20, 3, 10]), 1..4);
+        assert_eq!(store.len(), 4);
+        assert_eq!(store.get(2), Some(&vec![2, 20]));
+        assert_eq!(store.stats().indices[&0].entries, 4);
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(10)),
+                   }];
+        assert_eq!(store.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(store.find_range(1, Bound::Excluded(&10), Bound::Unbounded).count(), 1);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(10)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+
+        // with a unique index, the rows are inserted one at a time
+        store.index_unique(0, idx::HashIndex::new()).unwrap();
+        assert_eq!(store.extend_from_slice(&[4, 40]), 4..5);
+        assert_eq!(store.stats().indices[&0].entries, 5);
+    }
+
+    #[test]
+    fn it_deletes_lazily() {
+        let mut store = Store::new(2);
+        for i in 0..10 {
+            store.insert(vec![i, i % 2]);
+        }
+        store.index(0, idx::BTreeIndex::new());
+        store.index(1, idx::HashIndex::new());
+        store.index_multi(&[1, 0], idx::BTreeIndex::new());
+        store.index_covering(1);
+
+        let odd = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1)),
+                   }];
+        assert_eq!(store.delete_lazy(&odd), 5);
+        assert_eq!(store.tombstones(), 5);
+        assert_eq!(store.len(), 5);
+        assert!(store.get(3).is_none());
+        assert_eq!(store.stats().indices[&1].entries, 10);
+
+        // the deleted rows are skipped no matter how the rows are found
+        assert_eq!(store.find(&odd).count(), 0);
+        assert_eq!(store.find_range(0, Bound::Included(&2), Bound::Included(&5)).count(), 2);
+        assert_eq!(store.find_keys(0, &[]).count(), 5);
+        assert_eq!(store.top_k(0, 2, true).map(|r| r[0]).collect::<Vec<_>>(), vec![8, 6]);
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1)),
+                   },
+                   cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 0);
+        store.delete_range(0, Bound::Unbounded, Bound::Excluded(&2));
+        assert_eq!(store.len(), 4);
+
+        assert_eq!(store.vacuum_some(2), 2);
+        assert_eq!(store.tombstones(), 3);
+        assert_eq!(store.vacuum(), 3);
+        assert_eq!(store.stats().indices[&1].entries, 4);
+        assert_eq!(store.find(&odd).count(), 0);
+        assert_eq!(store.insert(vec![11, 1]), 10);
+        assert_eq!(store.find(&odd).count(), 1);
+
+        // adding an index vacuums first
+        store.delete_lazy(&odd);
+        store.index(0, idx::HashIndex::new());
+        assert_eq!(store.tombstones(), 0);
+        assert_eq!(store.stats().indices[&1].entries, 4);
+
+        // with a unique index, rows are deleted right away
+        store.index_unique(0, idx::HashIndex::new()).unwrap();
+        store.delete_lazy(&[]);
+        assert_eq!(store.tombstones(), 0);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn it_detects_stale_handles() {
+        let mut store = Store::new(2);
+        store.index(1, idx::HashIndex::new());
+        let a = store.insert_handle(vec![1, 10]);
+        let b = store.insert_handle(vec![2, 20]);
+        assert_eq!(store.handle(a.rowid()), Some(a));
+        assert_eq!(store.get_handle(a), Ok(&vec![1, 10]));
+
+        store.update_handle(b, |row| row[1] = 21).unwrap();
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(21)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+
+        assert_eq!(store.remove_handle(a), Ok(vec![1, 10]));
+        assert_eq!(store.get_handle(a), Err(StaleRowId(a)));
+        assert_eq!(store.remove_handle(a), Err(StaleRowId(a)));
+        assert!(store.handle(a.rowid()).is_none());
+
+        // compaction moves b to rowid 0, so the old handle must not be used for it
+        store.compact();
+        assert_eq!(store.get_handle(b), Err(StaleRowId(b)));
+        assert_eq!(store.update_handle(b, |row| row[1] = 22), Err(StaleRowId(b)));
+        let b = store.handle(0).unwrap();
+        assert_eq!(store.get_handle(b), Ok(&vec![2, 21]));
+
+        store.truncate();
+        let c = store.insert_handle(vec![3, 30]);
+        assert_eq!(c.rowid(), b.rowid());
+        assert_eq!(store.get_handle(b), Err(StaleRowId(b)));
+        assert_eq!(store.get_handle(c), Ok(&vec![3, 30]));
+    }
+
+    #[test]
+    fn it_cow_clones() {
+        let mut store = Store::with_storage(2, Storage::Chunked);
+        store.index(1, idx::HashIndex::new());
+        for i in 0..10000 {
+            store.insert(vec![i, i % 2]);
+        }
+        let mut fork = store.cow_clone().unwrap();
+        assert_eq!(fork.len(), 10000);
+        assert_eq!(fork.stats().indices, store.stats().indices);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                   }];
+        fork.update(&cmp, |row| row[1] = 2);
+        fork.delete(&[cmp::Condition {
+                          column: 0,
+                          cmp: cmp::Comparison::Less(cmp::Value::new(100)),
+                      }]);
+        assert_eq!(fork.insert(vec![10000, 0]), 10000);
+        assert_eq!(fork.find(&cmp).count(), 1);
+        assert_eq!(fork.len(), 9901);
+
+        assert_eq!(store.find(&cmp).count(), 5000);
+        assert_eq!(store.len(), 10000);
+        assert_eq!(store.get(0), Some(&vec![0, 0]));
+        store.delete(&cmp);
+        assert_eq!(store.len(), 5000);
+        assert_eq!(fork.get(101), Some(&vec![101, 1]));
+        assert_eq!(fork.into_iter().filter(|row| row.1[1] == 2).count(), 4950);
+
+        // without chunked storage, the rows are copied all at once, and so are the indices
+        let mut store = Store::new(2);
+        store.index_unique(0, idx::BTreeIndex::new()).unwrap();
+        store.index_by(1, |v: &i32| v / 10, idx::HashIndex::new());
+        store.insert(vec![0, 0]);
+        let mut fork = store.cow_clone().unwrap();
+        assert!(fork.try_insert(vec![0, 1]).is_err());
+        fork.update(&[], |row| row[1] = 10);
+        assert_eq!(store.get(0), Some(&vec![0, 0]));
+        assert_eq!(fork.get(0), Some(&vec![0, 10]));
+        assert_eq!(store.find_by(1, &0, &[]).unwrap().count(), 1);
+        assert_eq!(fork.find_by(1, &0, &[]).unwrap().count(), 0);
+        assert_eq!(fork.find_by(1, &1, &[]).unwrap().count(), 1);
+        assert!(store.try_insert(vec![0, 2]).is_err());
+
+        // indices that are trait objects cannot be copied
+        store.index(1, idx::Index::Equality(Box::new(idx::HashedIndex::new())));
+        assert!(store.cow_clone().is_none());
+    }
+
+    #[test]
+    fn it_finds_snapshots() {
+        let mut store = Store::with_storage(2, Storage::Chunked);
+        store.index(1, idx::HashIndex::new());
+        for i in 0..10 {
+            store.insert(vec![i, i % 2]);
+        }
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                   }];
+
+        let mut evens = store.find_snapshot(&cmp);
+        assert_eq!(evens.next(), Some(vec![0, 0]));
+        store.delete(&cmp);
+        store.insert(vec![10, 0]);
+        store.update(&[], |row| row[1] = 0);
+        assert_eq!(evens.map(|r| r[0]).collect::<Vec<_>>(), vec![2, 4, 6, 8]);
+        assert_eq!(store.find(&cmp).count(), 6);
+
+        let all = store.find_snapshot(&[]);
+        store.clear();
+        assert_eq!(all.count(), 6);
+
+        // without chunked storage, the matching rows are copied right away
+        let mut store = Store::new(2);
+        for i in 0..10 {
+            store.insert(vec![i, i % 2]);
+        }
+        let evens = store.find_snapshot(&cmp);
+        store.update(&[], |row| row[0] += 10);
+        assert_eq!(evens.map(|r| r[0]).collect::<Vec<_>>(), vec![0, 2, 4, 6, 8]);
+    }
+
+    #[test]
+    fn it_tracks_row_versions() {
+        let mut store = Store::new(2);
+        store.index(1, idx::HashIndex::new());
+        let a = store.insert(vec![0, 0]);
+        let b = store.insert(vec![1, 0]);
+        assert_eq!(store.version(a), Some(0));
+
+        assert_eq!(store.update_if_version(a, 0, |row| row[1] = 1), Ok(1));
+        assert_eq!(store.update_if_version(a, 0, |row| row[1] = 2), Err(CasError::Mismatch));
+        assert_eq!(store.get(a), Some(&vec![0, 1]));
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+
+        store.update(&[], |row| row[1] += 1);
+        assert_eq!(store.version(a), Some(2));
+        assert_eq!(store.version(b), Some(1));
+        store.delete(&cmp);
+        assert_eq!(store.version(b), None);
+        assert_eq!(store.update_if_version(b, 1, |_| {}), Err(CasError::NotFound));
+
+        // versions follow their rows when they are renumbered
+        store.compact();
+        assert_eq!(store.version(0), Some(2));
+        assert_eq!(store.update_if_version(0, 2, |row| row[1] = 0), Ok(3));
+    }
+
+    #[test]
+    fn it_calls_hooks() {
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut store = Store::new(2);
+        store.index(1, idx::HashIndex::new());
+        let e = events.clone();
+        store.on_insert(move |rowid, row: &Vec<i32>| e.lock().unwrap().push((true, rowid, row[1])));
+        let e = events.clone();
+        store.on_delete(move |rowid, row: &Vec<i32>| {
+            e.lock().unwrap().push((false, rowid, row[1]))
+        });
+        let take = || mem::take(&mut *events.lock().unwrap());
+
+        store.insert(vec![0, 0]);
+        store.extend(vec![vec![1, 1], vec![2, 0]]);
+        assert_eq!(take(), vec![(true, 0, 0), (true, 1, 1), (true, 2, 0)]);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                   }];
+        store.update(&cmp, |row| row[1] = 2);
+        assert_eq!(take(),
+                   vec![(false, 0, 0), (true, 0, 2), (false, 2, 0), (true, 2, 2)]);
+        store.upsert(0, vec![1, 3]);
+        assert_eq!(take(), vec![(false, 1, 1), (true, 1, 3)]);
+
+        store.delete_lazy(&[cmp::Condition {
+                                column: 0,
+                                cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                            }]);
+        store.vacuum();
+        assert_eq!(take(), vec![(false, 0, 2)]);
+
+        {
+            let mut bulk = store.begin_bulk();
+            bulk.insert(vec![3, 0]);
+            bulk.remove(1);
+        }
+        assert_eq!(take(), vec![(true, 3, 0), (false, 1, 3)]);
+
+        {
+            let mut tx = store.transaction();
+            tx.delete(&[]);
+        }
+        assert_eq!(take(), vec![(false, 2, 2), (false, 3, 0), (true, 3, 0), (true, 2, 2)]);
+
+        store.compact();
+        assert_eq!(take(), vec![]);
+        store.clear();
+        assert_eq!(take(), vec![(false, 0, 2), (false, 1, 0)]);
+    }
+
+    #[test]
+    fn it_fires_triggers() {
+        use std::sync::{Arc, Mutex};
+
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let mut store = Store::new(2);
+        let failed = || {
+            vec![cmp::Condition {
+                     column: 1,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("failed")),
+                 }]
+        };
+        for &event in &[TriggerEvent::Insert, TriggerEvent::Delete, TriggerEvent::Update] {
+            let fired = fired.clone();
+            store.add_trigger(failed(), event, move |rowid, row: &Vec<&str>| {
+                fired.lock().unwrap().push((event, rowid, row[0]))
+            });
+        }
+        let take = || mem::take(&mut *fired.lock().unwrap());
+
+        store.insert(vec!["a", "running"]);
+        store.insert(vec!["b", "failed"]);
+        assert_eq!(take(), vec![(TriggerEvent::Insert, 1, "b")]);
+
+        // only rows that become failed fire the update trigger
+        store.update(&[], |row| row[1] = "failed");
+        assert_eq!(take(), vec![(TriggerEvent::Update, 0, "a")]);
+        store.update(&[], |row| row[1] = "running");
+        assert_eq!(take(), vec![]);
+        store.upsert(0, vec!["b", "failed"]);
+        assert_eq!(take(), vec![(TriggerEvent::Update, 1, "b")]);
+
+        store.delete(&failed());
+        store.delete(&[]);
+        assert_eq!(take(), vec![(TriggerEvent::Delete, 1, "b")]);
+    }
+
+    #[test]
+    fn it_splits_off() {
+        let mut hot = Store::new(2);
+        for i in 0..6 {
+            hot.insert(vec![i, i % 2]);
+        }
+        hot.index(1, idx::HashIndex::new());
+
+        let mut cold = hot.split_off(|r| r[0] < 3);
+        assert_eq!(hot.len(), 3);
+        assert_eq!(cold.len(), 3);
+        assert_eq!(cold.get(2), Some(&vec![2, 0]));
+        assert_eq!(hot.get(2), None);
+        assert_eq!(hot.stats().indices[&1].entries, 3);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                   }];
+        assert_eq!(hot.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec![4]);
+        assert_eq!(cold.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(cold.insert(vec![6, 0]), 6);
+        assert_eq!(hot.insert(vec![6, 0]), 6);
+    }
+
+    #[test]
+    fn it_filters_with_composite_indices() {
+        let mut store = Store::new(3);
+        store.insert(vec!["a", "x", "1"]);
+        store.insert(vec!["a", "y", "2"]);
+        store.index_multi(&[1, 0], idx::HashIndex::new());
+        store.insert(vec!["b", "x", "3"]);
+        store.insert(vec!["a", "x", "4"]);
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("x")),
+                   }];
+        let found = |store: &Store<_>| {
+            let mut rows = store.find(&cmp).map(|r| r[2]).collect::<Vec<_>>();
+            rows.sort();
+            rows
+        };
+        assert_eq!(found(&store), vec!["1", "4"]);
+
+        // the index must be kept up to date through updates and deletes
+        store.update(&cmp[..1], |r| r[1] = "x");
+        assert_eq!(found(&store), vec!["1", "2", "4"]);
+        store.delete(&[cmp::Condition {
+                           column: 2,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new("1")),
+                       }]);
+        assert_eq!(found(&store), vec!["2", "4"]);
+        store.upsert(2, vec!["b", "x", "4"]);
+        assert_eq!(found(&store), vec!["2"]);
+        store.clear();
+        assert_eq!(found(&store), Vec::<&str>::new());
+        store.insert(vec!["a", "x", "5"]);
+        assert_eq!(found(&store), vec!["5"]);
+
+        // a condition on only one of the columns can't use the index
+        assert_eq!(store.find(&cmp[1..]).count(), 1);
+    }
+
+    #[test]
+    fn it_filters_with_composite_prefixes() {
+        let mut store = Store::new(3);
+        for i in 0..1000 {
+            store.insert(vec![i % 10, i % 7, i]);
+        }
+        store.index_multi(&[1, 0], idx::BTreeIndex::new());
+
+        for v in 0..7 {
+            let cmp = [cmp::Condition {
+                           column: 1,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new(v)),
+                       }];
+            let expected = (0..1000).filter(|i| i % 7 == v).collect::<Vec<_>>();
+            assert_eq!(store.using_index(&cmp).count(), expected.len());
+            let mut found = store.find(&cmp).map(|r| r[2]).collect::<Vec<_>>();
+            found.sort();
+            assert_eq!(found, expected);
+        }
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3)),
+                   },
+                   cmp::Condition {
+                       column: 2,
+                       cmp: cmp::Comparison::Less(cmp::Value::new(100)),
+                   }];
+        assert_eq!(store.explain(&cmp).access,
+                   Access::Composite {
+                       columns: vec![1, 0],
+                       prefix: 1,
+                   });
+        assert_eq!(store.find(&cmp).count(), 14);
+
+        // the prefix must be a *leading* prefix
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3)),
+                   }];
+        assert_eq!(store.explain(&cmp).access, Access::Scan);
+        assert_eq!(store.find(&cmp).count(), 100);
+    }
+
+    #[test]
+    fn it_finds_keys() {
+        let mut store = Store::new(2);
+        for i in 0..10 {
+            store.insert(vec![(i * 7) % 10, i]);
+        }
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::GreaterEqual(cmp::Value::new(5)),
+                   },
+                   cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::NotEqual(cmp::Value::new(8)),
+                   }];
+        let keys = |store: &Store<_>, cmp| store.find_keys(0, cmp).cloned().collect::<Vec<_>>();
+        // without an index, the keys come in row order
+        assert_eq!(keys(&store, &cmp), vec![7, 5, 9, 6]);
+
+        // with one, they are read straight from the index, in order
+        store.index(0, idx::BTreeIndex::new());
+        assert_eq!(keys(&store, &cmp), vec![5, 6, 7, 9]);
+        store.index(0, idx::FlatIndex::new());
+        store.insert(vec![5, 10]);
+        assert_eq!(keys(&store, &cmp), vec![5, 5, 6, 7, 9]);
+
+        // but not if the conditions look at other columns
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Less(cmp::Value::new(3)),
+                   }];
+        assert_eq!(keys(&store, &cmp), vec![0, 7, 4]);
+    }
+
+    #[test]
+    fn it_hints_find_sizes() {
+        let mut store = Store::new(2);
+        for i in 0..10 {
+            store.insert(vec![i % 2, i]);
+        }
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1)),
+                   }];
+        assert_eq!(store.find(&cmp).size_hint(), (0, Some(10)));
+        assert_eq!(store.find(&cmp).count(), 5);
+
+        store.index(0, idx::BTreeIndex::new());
+        assert_eq!(store.find(&cmp).size_hint().0, 0);
+        assert_eq!(store.find(&cmp).map(|r| r[1]).collect::<Vec<_>>(), vec![1, 3, 5, 7, 9]);
+    }
+
+    #[test]
+    fn it_executes_prepared_queries() {
+        let mut store = Store::new(2);
+        for i in 0..100 {
+            store.insert(vec![i % 10, i]);
+        }
+        store.index(0, idx::HashIndex::new());
+
+        let prepared = store.prepare(vec![cmp::Condition {
+                                              column: 0,
+                                              cmp: cmp::Comparison::Equal(cmp::Value::param(0)),
+                                          },
+                                          cmp::Condition {
+                                              column: 1,
+                                              cmp: cmp::Comparison::Greater(cmp::Value::param(1)),
+                                          },
+                                          cmp::Condition {
+                                              column: 1,
+                                              cmp: cmp::Comparison::Less(cmp::Value::new(90)),
+                                          }]);
+        assert_eq!(prepared.params(), 2);
+        let found = |params: &[_]| prepared.execute(params).map(|r| r[1]).collect::<Vec<_>>();
+        assert_eq!(found(&[3, 50]), vec![53, 63, 73, 83]);
+        assert_eq!(found(&[7, 0]), vec![7, 17, 27, 37, 47, 57, 67, 77, 87]);
+        assert_eq!(found(&[10, 0]), Vec::<i32>::new());
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_requires_all_parameters() {
+        let store = Store::<i32>::new(1);
+        let prepared = store.prepare(vec![cmp::Condition {
+                                              column: 0,
+                                              cmp: cmp::Comparison::Equal(cmp::Value::param(1)),
+                                          }]);
+        prepared.execute(&[1]).count();
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_rejects_parameters_outside_prepared_queries() {
+        // even though there are no rows to compare against the parameter
+        let store = Store::<i32>::new(1);
+        store.find(&[cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::param(0)),
+                   }]);
+    }
+
+    #[test]
+    fn it_enforces_unique_indices() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "1"]);
+        store.insert(vec!["b", "1"]);
+        assert_eq!(store.index_unique(1, idx::HashIndex::new()),
+                   Err(UniqueViolation { column: 1, rowid: 0 }));
+        assert_eq!(store.index_unique(0, idx::HashIndex::new()), Ok(()));
+
+        assert_eq!(store.try_insert(vec!["a", "2"]),
+                   Err(UniqueViolation { column: 0, rowid: 0 }));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.try_insert(vec!["c", "2"]), Ok(2));
+
+        // replacing a row with itself is fine
+        assert_eq!(store.upsert(0, vec!["c", "3"]), Some(vec!["c", "2"]));
+        assert_eq!(store.get(2), Some(&vec!["c", "3"]));
+
+        // a regular index on the same column lifts the requirement
+        store.index(0, idx::HashIndex::new());
+        assert_eq!(store.try_insert(vec!["a", "4"]), Ok(3));
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_panics_on_unique_update() {
+        let mut store = Store::new(1);
+        store.index_unique(0, idx::BTreeIndex::new()).unwrap();
+        store.insert(vec![1]);
+        store.insert(vec![2]);
+        store.update(&[], |r| r[0] = 3);
+    }
+
+    #[test]
+    fn it_keeps_indices_after_unique_update_panic() {
+        use std::panic;
+
+        let mut store = Store::new(2);
+        store.index_unique(0, idx::HashIndex::new()).unwrap();
+        store.index(1, idx::HashIndex::new());
+        store.insert(vec![1, 10]);
+        store.insert(vec![2, 20]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(2)),
+                   }];
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            store.update(&cmp, |r| {
+                r[0] = 1;
+                r[1] = 99;
+            })
+        }));
+        assert!(result.is_err());
+
+        // the row that would have violated the unique index is left as it was
+        assert_eq!(store.get(1), Some(&vec![2, 20]));
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(20)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+    }
+
+    #[test]
+    fn it_checks_all_unique_updates_before_changing_any() {
+        let mut store = Store::new(2);
+        store.index_unique(0, idx::BTreeIndex::new()).unwrap();
+        store.insert(vec![1, 10]);
+        store.insert(vec![2, 20]);
+        store.insert(vec![3, 30]);
+
+        // the updated rows may take each other's values
+        assert_eq!(store.try_update(&[], |r| r[0] = 4 - r[0]), Ok(()));
+        assert_eq!(store.get(0), Some(&vec![3, 10]));
+        assert_eq!(store.get(2), Some(&vec![1, 30]));
+
+        // but not end up with the same value as each other, nor as a row that is not updated
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Less(cmp::Value::new(30)),
+                   }];
+        assert_eq!(store.try_update(&cmp, |r| r[0] = 5),
+                   Err(UniqueViolation { column: 0, rowid: 0 }));
+        assert_eq!(store.try_update(&cmp, |r| r[0] -= 1),
+                   Err(UniqueViolation { column: 0, rowid: 2 }));
+        assert_eq!(store.iter().map(|(_, r)| r[0]).collect::<Vec<_>>(), vec![3, 2, 1]);
+        assert_eq!(store.find(&[cmp::Condition {
+                                    column: 0,
+                                    cmp: cmp::Comparison::Equal(cmp::Value::new(3)),
+                                }])
+                       .count(),
+                   1);
+
+        assert_eq!(store.compare_and_update(1, &vec![2, 20], vec![1, 20]),
+                   Err(CasError::Unique(UniqueViolation { column: 0, rowid: 2 })));
+        assert_eq!(store.get(1), Some(&vec![2, 20]));
+        let version = store.version(1).unwrap();
+        assert_eq!(store.update_if_version(1, version, |r| r[0] = 3),
+                   Err(CasError::Unique(UniqueViolation { column: 0, rowid: 0 })));
+        assert_eq!(store.get(1), Some(&vec![2, 20]));
+    }
+
+    #[test]
+    fn it_checks_unique_values_not_just_index_hits() {
+        let mut store = Store::new(1);
+        store.index_unique(0, idx::TextIndex::new()).unwrap();
+        store.insert(vec!["hello world"]);
+        assert_eq!(store.try_insert(vec!["hello"]), Ok(1));
+        assert_eq!(store.try_insert(vec!["hello world"]),
+                   Err(UniqueViolation { column: 0, rowid: 0 }));
+    }
+
+    #[test]
+    fn it_allows_unique_hashed_indices() {
+        use std::hash::{BuildHasherDefault, Hasher};
+
+        // every value has the same hash
+        #[derive(Default)]
+        struct Collide;
+        impl Hasher for Collide {
+            fn finish(&self) -> u64 {
+                0
+            }
+            fn write(&mut self, _: &[u8]) {}
+        }
+
+        let mut store = Store::new(1);
+        store.insert(vec!["a"]);
+        store.insert(vec!["b"]);
+        let hashed = idx::HashedIndex::with_hasher(BuildHasherDefault::<Collide>::default());
+        assert_eq!(store.index_unique(0, hashed), Ok(()));
+        assert_eq!(store.try_insert(vec!["c"]), Ok(2));
+        assert_eq!(store.try_insert(vec!["b"]),
+                   Err(UniqueViolation { column: 0, rowid: 1 }));
+        store.update(&[], |r| r[0] = if r[0] == "a" { "d" } else { r[0] });
+        assert_eq!(store.get(0), Some(&vec!["d"]));
+    }
+
+    #[test]
+    fn it_removes_indices() {
+        let mut store = Store::new(2);
+        store.index_unique(0, idx::HashIndex::new()).unwrap();
+        store.insert(vec!["a", "x"]);
+        assert!(store.remove_index(1).is_none());
+
+        let idx = store.remove_index(0).unwrap();
+        assert_eq!(idx.entries(), Some(1));
+        assert!(store.stats().indices.is_empty());
+
+        // the uniqueness requirement goes with the index
+        store.insert(vec!["a", "y"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 2);
+    }
+
+    /// An index that drops the entries it is given while `forget` is set, so that it can be made
+    /// to drift from the rows.
+    struct Forgetful {
+        inner: idx::HashIndex<i32>,
+        forget: sync::Arc<sync::atomic::AtomicBool>,
+    }
+
+    impl EqualityIndex<i32> for Forgetful {
+        fn lookup<'a>(&'a self, key: &i32) -> Box<dyn Iterator<Item = usize> + 'a> {
+            self.inner.lookup(key)
+        }
+        fn index(&mut self, key: i32, row: usize) {
+            if !self.forget.load(sync::atomic::Ordering::SeqCst) {
+                self.inner.index(key, row)
+            }
+        }
+        fn undex(&mut self, key: &i32, row: usize) {
+            self.inner.undex(key, row)
+        }
+        fn clear(&mut self) -> bool {
+            self.inner.clear()
+        }
+        fn entries(&self) -> Option<usize> {
+            self.inner.entries()
+        }
+        fn estimate(&self) -> usize {
+            self.inner.estimate()
+        }
+    }
+
+    #[test]
+    fn it_reindexes() {
+        let forget = sync::Arc::new(sync::atomic::AtomicBool::new(false));
+        let mut store = Store::new(2);
+        store.index(0,
+                    Index::Equality(Box::new(Forgetful {
+                        inner: idx::HashIndex::new(),
+                        forget: forget.clone(),
+                    })));
+        for i in 0..10 {
+            store.insert(vec![i % 2, i]);
+        }
+        assert!(!store.reindex(1));
+
+        // let a row slip past the index so that we can see it being repaired
+        forget.store(true, sync::atomic::Ordering::SeqCst);
+        store.insert(vec![0, 10]);
+        forget.store(false, sync::atomic::Ordering::SeqCst);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 5);
+        assert_eq!(store.stats().indices[&0].entries, 10);
+
+        assert!(store.reindex(0));
+        assert_eq!(store.find(&cmp).count(), 6);
+        assert_eq!(store.stats().indices[&0].entries, 11);
+    }
+
+    #[test]
+    fn it_uses_per_key_estimates() {
+        let mut store = Store::new(2);
+        for i in 0..100 {
+            // column 0 is heavily skewed, column 1 has ten rows per value
+            store.insert(vec![if i == 0 { 1 } else { 0 }, i % 10]);
+        }
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::HashIndex::new());
+        assert_eq!(store.stats().indices[&0].estimate, 50);
+
+        let cmp = |a, b| {
+            [cmp::Condition {
+                 column: 0,
+                 cmp: cmp::Comparison::Equal(cmp::Value::new(a)),
+             },
+             cmp::Condition {
+                 column: 1,
+                 cmp: cmp::Comparison::Equal(cmp::Value::new(b)),
+             }]
+        };
+        // the rare value is looked up in the skewed column, the common one is not
+        let rare = cmp(1, 0);
+        assert_eq!(store.using_index(&rare).count(), 1);
+        let common = cmp(0, 1);
+        assert_eq!(store.using_index(&common).count(), 10);
+        assert_eq!(store.find(&common).count(), 10);
+    }
+
+    #[test]
+    fn it_looks_up_single_keys_without_boxing() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        for i in 0..10 {
+            store.insert(vec![i, i % 3]);
+        }
+        let cmp = |column, cmp| [cmp::Condition { column, cmp }];
+        let eq = |column, v| cmp(column, cmp::Comparison::Equal(cmp::Value::new(v)));
+
+        assert!(matches!(store.using_index(&eq(0, 4)), Candidates::Postings(_, None)));
+        assert!(matches!(store.using_index(&eq(1, 1)), Candidates::Postings(_, None)));
+        assert_eq!(store.using_index(&eq(1, 1)).collect::<Vec<_>>(), vec![1, 4, 7]);
+        assert_eq!(store.using_index(&eq(1, 5)).count(), 0);
+        let lt = cmp(1, cmp::Comparison::Less(cmp::Value::new(1)));
+        assert!(matches!(store.using_index(&lt), Candidates::Boxed(..)));
+
+        // rows deleted with `delete_lazy` are still indexed, but are skipped
+        store.delete_lazy(&eq(0, 4));
+        assert!(matches!(store.using_index(&eq(1, 1)), Candidates::Postings(_, Some(_))));
+        assert_eq!(store.using_index(&eq(1, 1)).collect::<Vec<_>>(), vec![1, 7]);
+        assert_eq!(store.find(&eq(1, 1)).map(|r| r[0]).collect::<Vec<_>>(), vec![1, 7]);
+        assert_eq!(store.find(&eq(0, 4)).count(), 0);
+    }
+
+    #[test]
+    fn it_intersects_indices() {
+        let mut store = Store::new(3);
+        for i in 0..1000 {
+            store.insert(vec![i % 50, i % 40, i]);
+        }
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(7)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(27)),
+                   }];
+        // 20 rows have 7 in column 0, and 25 rows have 27 in column 1, but only 5 rows have both
+        assert_eq!(store.using_index(&cmp).count(), 5);
+        assert_eq!(store.find(&cmp).map(|r| r[2]).collect::<Vec<_>>(),
+                   vec![107, 307, 507, 707, 907]);
+
+        // an index that is much less selective is not worth intersecting with
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(7)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Greater(cmp::Value::new(0)),
+                   }];
+        assert_eq!(store.using_index(&cmp).count(), 20);
+        assert_eq!(store.find(&cmp).count(), 20);
+    }
+
+    #[test]
+    fn it_explains_queries() {
+        let mut store = Store::new(3);
+        for i in 0..1000 {
+            store.insert(vec![i % 50, i % 40, i]);
+        }
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(7)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(27)),
+                   },
+                   cmp::Condition {
+                       column: 2,
+                       cmp: cmp::Comparison::Less(cmp::Value::new(500)),
+                   }];
+
+        let plan = store.explain(&cmp);
+        assert_eq!(plan.access, Access::Scan);
+        assert_eq!(plan.estimate, 1000);
+        assert_eq!(plan.residual, vec![0, 1, 2]);
+        assert_eq!(plan.to_string(), "scan (~1000 rows), filter on conditions [0, 1, 2]");
+
+        store.index(0, idx::HashIndex::new());
+        let plan = store.explain(&cmp);
+        assert_eq!(plan.access,
+                   Access::Index {
+                       column: 0,
+                       partial: false,
+                       intersected: vec![],
+                   });
+        assert_eq!(plan.estimate, 20);
+        assert_eq!(plan.indexed, vec![0]);
+        assert_eq!(plan.residual, vec![1, 2]);
+
+        store.index(1, idx::HashIndex::new());
+        let plan = store.explain(&cmp);
+        assert_eq!(plan.access,
+                   Access::Index {
+                       column: 0,
+                       partial: false,
+                       intersected: vec![1],
+                   });
+        assert_eq!(plan.indexed, vec![0, 1]);
+        assert_eq!(plan.residual, vec![2]);
+        assert_eq!(plan.to_string(),
+                   "index on column 0 intersected with columns [1] (~20 rows), filter on \
+                    conditions [2]");
+
+        store.index_multi(&[1, 0], idx::HashIndex::new());
+        let plan = store.explain(&cmp);
+        assert_eq!(plan.access,
+                   Access::Composite {
+                       columns: vec![1, 0],
+                       prefix: 2,
+                   });
+        assert_eq!(plan.estimate, 5);
+        assert_eq!(plan.indexed, vec![1, 0]);
+        assert_eq!(plan.residual, vec![2]);
+
+        store.index_covering(1);
+        assert_eq!(store.explain(&cmp).access,
+                   Access::Composite {
+                       columns: vec![1, 0],
+                       prefix: 2,
+                   });
+        let plan = store.explain(&cmp[1..]);
+        assert_eq!(plan.access, Access::Covering { column: 1 });
+        assert_eq!(plan.estimate, 25);
+        assert_eq!(plan.indexed, vec![0]);
+        assert_eq!(plan.residual, vec![1]);
+    }
+
+    #[test]
+    fn it_analyzes_columns() {
+        use std::ops::Bound::*;
+        let mut store = Store::new(2);
+        for i in 0..1000 {
+            store.insert(vec![i, i / 100]);
+        }
+        store.index(0, idx::BTreeIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Between(Included(cmp::Value::new(10)),
+                                                     Excluded(cmp::Value::new(990))),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::GreaterEqual(cmp::Value::new(9)),
+                   }];
+        // without statistics, the two-sided range looks much more selective
+        assert_eq!(store.explain(&cmp).indexed, vec![0]);
+        assert!(store.column_stats(0).is_none());
+
+        store.analyze();
+        let stats = store.column_stats(0).unwrap();
+        assert_eq!(stats.rows, 1000);
+        assert_eq!(stats.distinct, 1000);
+        assert_eq!(stats.bounds.len(), 101);
+        assert_eq!(stats.bounds[0], 0);
+        assert_eq!(stats.bounds[100], 999);
+        assert_eq!(stats.estimate_range(Excluded(&999), Unbounded, 1000), 0);
+        assert_eq!(stats.estimate_range(Unbounded, Unbounded, 1000), 1000);
+        assert_eq!(store.column_stats(1).unwrap().distinct, 10);
+
+        let plan = store.explain(&cmp);
+        assert_eq!(plan.indexed, vec![1]);
+        assert!(plan.estimate < 200);
+        assert_eq!(store.find(&cmp).count(), 90);
+    }
+
+    #[test]
+    fn it_finds_with_hints() {
+        let mut store = Store::new(2);
+        for i in 0..100 {
+            store.insert(vec![100 - i, (i * 37) % 100]);
+        }
+        store.index(0, idx::BTreeIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Greater(cmp::Value::new(50)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Less(cmp::Value::new(50)),
+                   }];
+        // range indices yield rows in the order of the indexed column, which tells us which index
+        // was used
+        let find = |hint| store.find_with_hint(&cmp, hint).cloned().collect::<Vec<_>>();
+        let mut expected = store.find(&cmp).cloned().collect::<Vec<_>>();
+        expected.sort_by_key(|r| (r[0], r[1]));
+        assert_eq!(find(Hint::UseIndex(0)), expected);
+        expected.sort_by_key(|r| (r[1], r[0]));
+        assert_eq!(find(Hint::UseIndex(1)), expected);
+        assert_eq!(find(Hint::IgnoreIndex(0)), expected);
+        expected.sort_by_key(|r| (100 - r[0], r[1]));
+        assert_eq!(find(Hint::NoIndex), expected);
+
+        // hints that cannot be followed are ignored
+        assert_eq!(find(Hint::UseIndex(2)), find(Hint::IgnoreIndex(2)));
+        assert_eq!(find(Hint::UseIndex(2)).len(), expected.len());
+    }
+
+    #[test]
+    fn it_filters_with_partial_indices_on_other_columns() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "active"]);
+        store.insert(vec!["a", "deleted"]);
+        store.index_partial(0,
+                            &cmp::Condition {
+                                column: 1,
+                                cmp: cmp::Comparison::Equal(cmp::Value::new("active")),
+                            },
+                            idx::HashIndex::new());
+        store.insert(vec!["b", "active"]);
+        store.insert(vec!["a", "active"]);
+
+        let a = cmp::Condition {
+            column: 0,
+            cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+        };
+        let active = cmp::Condition {
+            column: 1,
+            cmp: cmp::Comparison::Equal(cmp::Value::new("active")),
+        };
+        let partial = Access::Index {
+            column: 0,
+            partial: true,
+            intersected: vec![],
+        };
+        let cmp = [a.clone(), active.clone()];
+        let plan = store.explain(&cmp);
+        assert_eq!(plan.access, partial);
+        assert_eq!(plan.estimate, 2);
+        assert_eq!(store.find(&cmp).count(), 2);
+
+        // without the filter condition, the partial index would miss rows
+        let cmp = [a.clone()];
+        assert_eq!(store.explain(&cmp).access, Access::Scan);
+        assert_eq!(store.find(&cmp).count(), 3);
+
+        // moving rows in and out of the covered set must keep the index up to date
+        store.update(&[], |r| r[1] = if r[1] == "active" { "deleted" } else { "active" });
+        let cmp = [a, active];
+        let plan = store.explain(&cmp);
+        assert_eq!(plan.access, partial);
+        assert_eq!(plan.estimate, 1);
+        assert_eq!(store.find(&cmp).count(), 1);
+        store.delete(&cmp);
+        assert_eq!(store.explain(&cmp).estimate, 0);
+        assert_eq!(store.find(&cmp).count(), 0);
+    }
+
+    #[test]
+    fn it_uses_partial_indices_for_implied_ranges() {
+        let mut store = Store::new(1);
+        for i in 0..10 {
+            store.insert(vec![i]);
+        }
+        store.index_partial(0,
+                            &cmp::Condition {
+                                column: 0,
+                                cmp: cmp::Comparison::Greater(cmp::Value::new(5)),
+                            },
+                            idx::BTreeIndex::new());
+
+        let find = |store: &Store<_>, cmp: cmp::Comparison<i32>| {
+            let cmp = [cmp::Condition { column: 0, cmp }];
+            (store.explain(&cmp).access != Access::Scan, store.find(&cmp).count())
+        };
+        assert_eq!(find(&store, cmp::Comparison::Equal(cmp::Value::new(7))), (true, 1));
+        assert_eq!(find(&store, cmp::Comparison::GreaterEqual(cmp::Value::new(6))), (true, 4));
+        assert_eq!(find(&store, cmp::Comparison::Greater(cmp::Value::new(5))), (true, 4));
+        assert_eq!(find(&store, cmp::Comparison::GreaterEqual(cmp::Value::new(5))), (false, 5));
+        assert_eq!(find(&store, cmp::Comparison::Equal(cmp::Value::new(3))), (false, 1));
+        assert_eq!(find(&store, cmp::Comparison::Less(cmp::Value::new(8))), (false, 8));
+    }
+
+    #[test]
+    fn it_finds_by_functional_keys() {
+        let mut store = Store::new(2);
+        store.insert(vec!["Alice".to_owned(), "1".to_owned()]);
+        store.insert(vec!["ALICE".to_owned(), "2".to_owned()]);
+        store.index_by(0, |v: &String| v.to_lowercase(), idx::HashIndex::new());
+        store.insert(vec!["alice".to_owned(), "3".to_owned()]);
+        store.insert(vec!["Bob".to_owned(), "1".to_owned()]);
+        assert!(store.find_by(1, &"1".to_owned(), &[]).is_none());
+
+        let find = |store: &Store<_>, key: &str, conds: &[cmp::Condition<String>]| {
+            let key = key.to_owned();
+            let mut rows = store.find_by(0, &key, conds)
+                .unwrap()
+                .map(|r| r[1].clone())
+                .collect::<Vec<_>>();
+            rows.sort();
+            rows
+        };
+        assert_eq!(find(&store, "alice", &[]), vec!["1", "2", "3"]);
+        assert_eq!(find(&store, "Alice", &[]), Vec::<String>::new());
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::NotEqual(cmp::Value::new("2".to_owned())),
+                   }];
+        assert_eq!(find(&store, "alice", &cmp), vec!["1", "3"]);
+
+        store.update(&cmp, |r| r[0] = r[0].to_uppercase());
+        assert_eq!(find(&store, "alice", &[]), vec!["1", "2", "3"]);
+        assert_eq!(find(&store, "bob", &[]), vec!["1"]);
+        store.delete(&cmp);
+        assert_eq!(find(&store, "alice", &[]), vec!["2"]);
+        assert_eq!(find(&store, "bob", &[]), Vec::<String>::new());
+
+        // keys can be of another type than the values
+        store.index_by(1, |v: &String| v.len(), idx::BTreeIndex::new());
+        store.insert(vec!["Carol".to_owned(), "10".to_owned()]);
+        assert_eq!(store.find_by(1, &2usize, &[]).unwrap().map(|r| &r[0]).collect::<Vec<_>>(),
+                   vec!["Carol"]);
+        assert_eq!(store.find_by(1, &1usize, &[]).unwrap().count(), 1);
+        assert!(store.find_by(1, &"2".to_owned(), &[]).is_none());
+        store.update(&[], |r| r[1].push('0'));
+        assert_eq!(store.find_by(1, &3usize, &[]).unwrap().count(), 1);
+        assert_eq!(store.find_by(1, &2usize, &[]).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn it_filters_terms_with_text_indices() {
+        let mut store = Store::new(2);
+        store.insert(vec!["1", "The quick brown fox"]);
+        store.insert(vec!["2", "jumps over the lazy dog"]);
+        store.insert(vec!["3", "Quick, quick!"]);
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::ContainsTerm(cmp::Term::new("quick")),
+                   }];
+        let found = |store: &Store<_>| {
+            let mut rows = store.find(&cmp).map(|r| r[0]).collect::<Vec<_>>();
+            rows.sort();
+            rows
+        };
+        assert_eq!(found(&store), vec!["1", "3"]);
+
+        store.index(1, idx::TextIndex::new());
+        assert!(store.best_index(&cmp).is_some());
+        assert_eq!(found(&store), vec!["1", "3"]);
+        store.update(&cmp, |r| r[1] = "slow");
+        assert_eq!(found(&store), Vec::<&str>::new());
+
+        // equality lookups still work, even though the index only knows about terms
+        let eq = [cmp::Condition {
+                      column: 1,
+                      cmp: cmp::Comparison::Equal(cmp::Value::new("the lazy dog")),
+                  }];
+        assert_eq!(store.find(&eq).count(), 0);
+        store.insert(vec!["4", "the lazy dog"]);
+        assert_eq!(store.find(&eq).map(|r| r[0]).collect::<Vec<_>>(), vec!["4"]);
+    }
+
+    #[test]
+    fn it_filters_with_case_insensitive_indices() {
+        let mut store = Store::new(1);
+        store.insert(vec!["Alice".to_owned()]);
+        store.insert(vec!["ALICE".to_owned()]);
+        store.insert(vec!["Bob".to_owned()]);
+
+        let alice = cmp::Affix::new(cmp::Value::new("alice".to_owned()));
+        let folded = [cmp::Condition {
+                          column: 0,
+                          cmp: cmp::Comparison::EqualIgnoreCase(alice),
+                      }];
+        let exact = [cmp::Condition {
+                         column: 0,
+                         cmp: cmp::Comparison::Equal(cmp::Value::new("Alice".to_owned())),
+                     }];
+        assert!(store.best_index(&folded).is_none());
+        assert_eq!(store.find(&folded).count(), 2);
+        assert_eq!(store.find(&exact).count(), 1);
+
+        store.index(0, idx::CaseInsensitive::new(idx::HashIndex::new()));
+        assert!(store.best_index(&folded).is_some());
+        assert!(store.best_index(&exact).is_some());
+        assert_eq!(store.find(&folded).count(), 2);
+        assert_eq!(store.find(&exact).count(), 1);
+    }
+
+    #[test]
+    fn it_filters_nulls() {
+        let mut store = Store::new(2);
+        for i in 0..10 {
+            store.insert(vec![if i % 4 == 0 { Some(i) } else { None }, Some(i)]);
+        }
+
+        let is_null = [cmp::Condition {
+                           column: 0,
+                           cmp: cmp::Comparison::IsNull(cmp::Null::new()),
+                       }];
+        let not_null = [cmp::Condition {
+                            column: 0,
+                            cmp: cmp::Comparison::IsNotNull(cmp::Null::new()),
+                        }];
+        let ids = |store: &Store<Option<i32>>, conds: &[cmp::Condition<_>]| {
+            let mut ids = store.find(conds).map(|r| r[1].unwrap()).collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(&store, &is_null), vec![1, 2, 3, 5, 6, 7, 9]);
+        assert_eq!(ids(&store, &not_null), vec![0, 4, 8]);
+
+        // the same should hold with an index that skips nulls
+        store.index(0, Index::Range(Box::new(idx::SkipNull::new(idx::BTreeIndex::new()))));
+        assert_eq!(store.stats().indices[&0].estimate, 1);
+        assert_eq!(ids(&store, &is_null), vec![1, 2, 3, 5, 6, 7, 9]);
+        assert_eq!(ids(&store, &not_null), vec![0, 4, 8]);
+        store.delete(&is_null);
+        store.insert(vec![None, Some(10)]);
+        assert_eq!(ids(&store, &is_null), vec![10]);
+        assert_eq!(store.find_sorted(&[], 0, true).map(|r| r[1].unwrap()).collect::<Vec<_>>(),
+                   vec![10, 0, 4, 8]);
+
+        // and with a regular index that has to look up the null value
+        store.index(0, idx::BTreeIndex::new());
+        assert_eq!(ids(&store, &is_null), vec![10]);
+        assert_eq!(ids(&store, &not_null), vec![0, 4, 8]);
+    }
+
+    #[test]
+    fn it_finds_overlapping_intervals() {
+        let mut store = Store::new(3);
+        store.insert(vec![9, 12, 0]);
+        store.insert(vec![10, 11, 1]);
+        store.insert(vec![13, 17, 2]);
+        store.insert(vec![8, 18, 3]);
+
+        let containing = |store: &Store<_>, p| {
+            let mut ids = store.find_containing(0, 1, &p).map(|r| r[2]).collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        let overlapping = |store: &Store<_>, min, max| {
+            let mut ids =
+                store.find_overlapping(0, 1, &min, &max).map(|r| r[2]).collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        assert_eq!(containing(&store, 12), vec![0, 3]);
+        assert_eq!(overlapping(&store, 12, 13), vec![0, 2, 3]);
+        assert_eq!(overlapping(&store, 19, 20), vec![]);
+
+        // the same should hold with an index, also as rows change
+        store.index_interval(0, 1, interval::IntervalTreeIndex::new());
+        assert_eq!(containing(&store, 12), vec![0, 3]);
+        assert_eq!(overlapping(&store, 12, 13), vec![0, 2, 3]);
+        store.update(&[cmp::Condition {
+                          column: 2,
+                          cmp: cmp::Comparison::Equal(cmp::Value::new(3)),
+                      }],
+                     |r| r[1] = 9);
+        store.delete(&[cmp::Condition {
+                           column: 2,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                       }]);
+        store.insert(vec![11, 14, 4]);
+        assert_eq!(containing(&store, 12), vec![4]);
+        assert_eq!(overlapping(&store, 9, 13), vec![1, 2, 3, 4]);
+        store.extend(vec![vec![0, 20, 5]]);
+        assert_eq!(containing(&store, 19), vec![5]);
+    }
+
+    #[test]
+    #[cfg(feature = "spatial")]
+    fn it_finds_within_bbox() {
+        let mut store = Store::new(3);
+        for i in 0..30 {
+            store.insert(vec![i, (i * 7) % 30, i % 3]);
+        }
+        let bbox = |store: &Store<_>| {
+            let mut rows = store.within_bbox(0, 1, (&5, &0), (&20, &10))
+                .map(|r| r[0])
+                .collect::<Vec<_>>();
+            rows.sort();
+            rows
+        };
+        let scanned = bbox(&store);
+        assert_eq!(scanned, vec![5, 9, 10, 13, 14, 18]);
+
+        store.index_spatial(0, 1, spatial::RTreeIndex::new());
+        assert_eq!(bbox(&store), scanned);
+
+        store.update(&[cmp::Condition {
+                          column: 0,
+                          cmp: cmp::Comparison::Equal(cmp::Value::new(9)),
+                      }],
+                     |r| r[1] = 25);
+        store.delete(&[cmp::Condition {
+                          column: 0,
+                          cmp: cmp::Comparison::Equal(cmp::Value::new(10)),
+                      }]);
+        store.insert(vec![6, 6, 0]);
+        assert_eq!(bbox(&store), vec![5, 6, 13, 14, 18]);
+        store.shrink_to_fit();
+        assert_eq!(bbox(&store), vec![5, 6, 13, 14, 18]);
+    }
+
+    #[test]
+    fn it_projects() {
+        let mut store = Store::new(3);
+        store.insert(vec!["a", "x1", "y1"]);
+        store.insert(vec!["b", "x2", "y2"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("b")),
+                   }];
+        let cols = [2, 0];
+        let rows = store.find_project(&cmp, &cols).collect::<Vec<_>>();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].columns(), 2);
+        assert_eq!(rows[0].index(0), &"y2");
+        assert_eq!(rows[0].index(1), &"b");
+        assert_eq!(rows[0].row(), &vec!["b", "x2", "y2"]);
+    }
+
+    #[test]
+    fn it_sorts() {
+        let mut store = Store::new(2);
+        store.insert(vec!["b", "x1"]);
+        store.insert(vec!["c", "x2"]);
+        store.insert(vec!["a", "x3"]);
+        store.insert(vec!["d", "y4"]);
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::StartsWith(cmp::Affix::new(cmp::Value::new("x"))),
+                   }];
+        let sorted = |store: &Store<_>, asc| {
+            store.find_sorted(&cmp, 0, asc).map(|r| r[0]).collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(&store, true), vec!["a", "b", "c"]);
+        assert_eq!(sorted(&store, false), vec!["c", "b", "a"]);
+
+        // the same should hold when walking an index
+        store.index(0, idx::BTreeIndex::new());
+        assert_eq!(sorted(&store, true), vec!["a", "b", "c"]);
+        assert_eq!(sorted(&store, false), vec!["c", "b", "a"]);
+
+        // and when another index is used to find the rows
+        store.index(1, idx::BTreeIndex::new());
+        assert_eq!(sorted(&store, true), vec!["a", "b", "c"]);
+        assert_eq!(sorted(&store, false), vec!["c", "b", "a"]);
+    }
+
+    #[test]
+    fn it_sorts_by_walking_indices() {
+        use std::ops::Bound::*;
+        let mut store = Store::new(2);
+        for i in 0..1000 {
+            store.insert(vec![i, i % 10]);
+        }
+        store.index(0, idx::BTreeIndex::new());
+        store.index(1, idx::HashIndex::new());
+
+        // a selective index on another column is cheaper than walking all the rows
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3)),
+                   }];
+        assert!(store.find_in_index_order(&cmp, 0, false).is_none());
+        assert_eq!(store.find_sorted(&cmp, 0, false).take(3).map(|r| r[0]).collect::<Vec<_>>(),
+                   vec![993, 983, 973]);
+
+        // but a range on the sort column narrows the walk
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3)),
+                   },
+                   cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::GreaterEqual(cmp::Value::new(900)),
+                   }];
+        assert!(store.find_in_index_order(&cmp, 0, false).is_some());
+        assert_eq!(store.find_sorted(&cmp, 0, false).take(3).map(|r| r[0]).collect::<Vec<_>>(),
+                   vec![993, 983, 973]);
+        assert_eq!(store.find_sorted(&cmp, 0, true).count(), 10);
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Between(Included(cmp::Value::new(10)),
+                                                     Excluded(cmp::Value::new(20))),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3)),
+                   }];
+        assert!(store.find_in_index_order(&cmp, 0, true).is_some());
+        assert_eq!(store.find_sorted(&cmp, 0, true).map(|r| r[0]).collect::<Vec<_>>(),
+                   vec![13]);
+    }
+
+    #[test]
+    fn it_filters_with_flat_indices() {
+        let mut store = Store::new(2);
+        store.insert(vec![5, 0]);
+        store.index(0, idx::FlatIndex::new());
+        store.extend((0..10).map(|i| vec![(i * 7) % 10, i]));
+        store.insert(vec![5, 10]);
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(5)),
+                   }];
+        assert_eq!(store.find(&cmp).map(|r| r[1]).collect::<Vec<_>>(), vec![0, 5, 10]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::GreaterEqual(cmp::Value::new(8)),
+                   }];
+        assert_eq!(store.find_sorted(&cmp, 0, true).map(|r| r[1]).collect::<Vec<_>>(),
+                   vec![4, 7]);
+        assert_eq!(store.find_sorted(&[], 0, false).take(3).map(|r| r[0]).collect::<Vec<_>>(),
+                   vec![9, 8, 7]);
+    }
+
+    #[test]
+    fn it_finds_with_covering_indices() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        store.index_covering(0);
+        store.insert(vec!["a", "x3"]);
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp).map(|r| r[1]).collect::<Vec<_>>(), vec!["x1", "x3"]);
+
+        // the copies must be kept up to date, even for changes to other columns
+        store.update(&cmp, |r| r[1] = "y");
+        store.delete(&[cmp::Condition {
+                           column: 1,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new("x2")),
+                       }]);
+        store.upsert(1, vec!["a", "y"]);
+        store.insert(vec!["c", "x4"]);
+        assert_eq!(store.find(&cmp).map(|r| r[1]).collect::<Vec<_>>(), vec!["y", "y"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("c")),
+                   }];
+        assert_eq!(store.find(&cmp).map(|r| r[1]).collect::<Vec<_>>(), vec!["x4"]);
+
+        // the remaining conditions still apply, whichever index is used
+        store.index(1, idx::HashIndex::new());
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("y")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 2);
+        store.clear();
+        assert_eq!(store.find(&cmp).count(), 0);
+    }
+
+    #[test]
+    fn it_finds_one() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert!(store.find_one(&cmp).map(|r| r[0] == "a").unwrap_or(false));
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("c")),
+                   }];
+        assert_eq!(store.find_one(&cmp), None);
+    }
+
+    #[test]
+    fn it_checks_existence() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("x1")),
+                   }];
+        assert!(store.exists(&cmp));
+        assert!(store.exists(&cmp[1..]));
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("x2")),
+                   }];
+        assert!(!store.exists(&cmp));
+        assert!(store.exists(&[]));
+        store.clear();
+        assert!(!store.exists(&[]));
+    }
+
+    #[test]
+    fn it_filters_with_ids() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        let a = store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        let found = store.find_with_ids(&cmp).collect::<Vec<_>>();
+        assert_eq!(found, vec![(a, &vec!["a", "x1"])]);
+        assert_eq!(store.get(found[0].0), Some(found[0].1));
+    }
+
+    #[test]
+    fn it_filters_not_equal() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::NotEqual(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(store.find(&cmp).all(|r| r[0] == "b"));
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::NotEqual(cmp::Value::new("x2")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(store.find(&cmp).all(|r| r[0] == "a" && r[1] == "x1"));
+    }
+
+    #[test]
+    fn it_filters_ranges() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        store.insert(vec!["c", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Less(cmp::Value::new("b")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(store.find(&cmp).all(|r| r[0] == "a"));
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::GreaterEqual(cmp::Value::new("b")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 2);
+        assert!(store.find(&cmp).all(|r| r[0] != "a"));
+    }
+
+    #[test]
+    fn it_filters_ranges_with_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::BTreeIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        store.insert(vec!["c", "x3"]);
+        store.insert(vec!["d", "x4"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Greater(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::LessEqual(cmp::Value::new("c")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 2);
+        assert!(store.find(&cmp).all(|r| r[0] == "b" || r[0] == "c"));
 
-impl<T> Row<T> for [T] {
-    fn index(&self, i: usize) -> &T {
-        &self[i]
-    }
-    fn columns(&self) -> usize {
-        self.len()
+        // a hash index can't serve range queries, but they should still work
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Greater(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
     }
-}
 
-impl<T> Row<T> for Vec<T> {
-    fn index(&self, i: usize) -> &T {
-        &self[i]
+    #[test]
+    fn it_filters_between_with_indices() {
+        use std::ops::Bound::*;
+        let mut store = Store::new(2);
+        store.index(0, idx::BTreeIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        store.insert(vec!["c", "x3"]);
+        store.insert(vec!["d", "x4"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Between(Excluded(cmp::Value::new("a")),
+                                                     Included(cmp::Value::new("c"))),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 2);
+        assert!(store.find(&cmp).all(|r| r[0] == "b" || r[0] == "c"));
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Between(Included(cmp::Value::new("c")),
+                                                     Unbounded),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 2);
+
+        // empty and inverted ranges should simply yield nothing
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Between(Excluded(cmp::Value::new("b")),
+                                                     Excluded(cmp::Value::new("b"))),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 0);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Between(Included(cmp::Value::new("c")),
+                                                     Included(cmp::Value::new("a"))),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 0);
     }
-    fn columns(&self) -> usize {
-        self.len()
+
+    #[test]
+    fn it_filters_predicates() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "y2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Predicate(cmp::Predicate::new(|v: &&str| {
+                           v.starts_with('x')
+                       })),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(store.find(&cmp).all(|r| r[0] == "a" && r[1] == "x1"));
     }
-}
 
-use std::sync;
-impl<T> Row<T> for sync::Arc<Vec<T>> {
-    fn index(&self, i: usize) -> &T {
-        &self[i]
+    #[test]
+    fn it_filters_affixes() {
+        let mut store = Store::new(2);
+        store.insert(vec!["ab", "x1"]);
+        store.insert(vec!["abc", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        store.insert(vec!["aa", "x4"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::StartsWith(cmp::Affix::new(cmp::Value::new("ab"))),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 2);
+        assert!(store.find(&cmp).all(|r| r[0].starts_with("ab")));
+
+        store.index(0, idx::BTreeIndex::new());
+        assert_eq!(store.find(&cmp).count(), 2);
+        assert!(store.find(&cmp).all(|r| r[0].starts_with("ab")));
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::EndsWith(cmp::Affix::new(cmp::Value::new("3"))),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(store.find(&cmp).all(|r| r[0] == "b"));
     }
-    fn columns(&self) -> usize {
-        self.len()
+
+    #[test]
+    fn it_filters_exprs() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        store.insert(vec!["c", "x3"]);
+        let ca = cmp::Condition {
+            column: 0,
+            cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+        };
+        let cx = cmp::Condition {
+            column: 1,
+            cmp: cmp::Comparison::Equal(cmp::Value::new("x3")),
+        };
+        let expr = cmp::ConditionExpr::Or(vec![ca.into(), cx.into()]);
+        assert_eq!(store.find_expr(&expr).count(), 2);
+        assert!(store.find_expr(&expr).all(|r| r[0] == "a" || r[0] == "c"));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn it_filters_exprs_with_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        store.insert(vec!["c", "x3"]);
+        let ca = cmp::Condition {
+            column: 0,
+            cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+        };
+        let cx = cmp::Condition {
+            column: 1,
+            cmp: cmp::Comparison::Equal(cmp::Value::new("x1")),
+        };
+        let cc = cmp::Condition {
+            column: 0,
+            cmp: cmp::Comparison::Equal(cmp::Value::new("c")),
+        };
+
+        // row 0 matches both branches, but must only be returned once
+        let expr = cmp::ConditionExpr::Or(vec![ca.into(), cx.into(), cc.into()]);
+        assert_eq!(store.find_expr(&expr).count(), 2);
+        assert!(store.find_expr(&expr).all(|r| r[0] == "a" || r[0] == "c"));
+
+        let cnx = cmp::Condition {
+            column: 1,
+            cmp: cmp::Comparison::NotEqual(cmp::Value::new("x1")),
+        };
+        let expr = cmp::ConditionExpr::And(vec![expr, cnx.into()]);
+        assert_eq!(store.find_expr(&expr).count(), 1);
+        assert!(store.find_expr(&expr).all(|r| r[0] == "c"));
+
+        let expr = cmp::ConditionExpr::Not(Box::new(expr));
+        assert_eq!(store.find_expr(&expr).count(), 2);
+        assert!(store.find_expr(&expr).all(|r| r[0] != "c"));
+
+        let expr = cmp::ConditionExpr::Or(vec![]);
+        assert_eq!(store.find_expr(&expr).count(), 0);
+        let expr = cmp::ConditionExpr::And(vec![]);
+        assert_eq!(store.find_expr(&expr).count(), 3);
+    }
 
     #[test]
-    fn it_works() {
+    fn it_filters_tuples() {
         let mut store = Store::new(2);
-        store.insert(vec!["a1", "a2"]);
-        store.insert(vec!["b1", "b2"]);
-        store.insert(vec!["c1", "c2"]);
-        assert_eq!(store.find(&[]).count(), 3);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x2"]);
+        let expr = cmp::ConditionExpr::Tuple(cmp::TupleCondition {
+            columns: vec![0, 1],
+            cmp: cmp::TupleComparison::Equal(vec![cmp::Value::new("a"), cmp::Value::new("x2")]),
+        });
+        assert_eq!(store.find_expr(&expr).count(), 1);
+        assert!(store.find_expr(&expr).all(|r| r[0] == "a" && r[1] == "x2"));
+
+        store.index(1, idx::HashIndex::new());
+        assert_eq!(store.find_expr(&expr).count(), 1);
+        assert!(store.find_expr(&expr).all(|r| r[0] == "a" && r[1] == "x2"));
     }
 
     #[test]
-    fn it_works_w_non_vec() {
-        use std::sync;
+    fn it_does_not_match_tuples_of_different_lengths() {
         let mut store = Store::new(2);
-        store.insert(sync::Arc::new(vec!["a1", "a2"]));
-        store.insert(sync::Arc::new(vec!["b1", "b2"]));
-        store.insert(sync::Arc::new(vec!["c1", "c2"]));
-        assert_eq!(store.find(&[]).count(), 3);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        let short = cmp::ConditionExpr::Tuple(cmp::TupleCondition {
+            columns: vec![0],
+            cmp: cmp::TupleComparison::Equal(vec![cmp::Value::new("a"), cmp::Value::new("x2")]),
+        });
+        let long = cmp::ConditionExpr::Tuple(cmp::TupleCondition {
+            columns: vec![0, 1],
+            cmp: cmp::TupleComparison::Equal(vec![cmp::Value::new("a")]),
+        });
+        assert_eq!(store.find_expr(&short).count(), 0);
+        assert_eq!(store.find_expr(&long).count(), 0);
+
+        // nor when the rows are looked up in an index on the columns they share
+        store.index(0, idx::HashIndex::new());
+        assert_eq!(store.find_expr(&short).count(), 0);
+        assert_eq!(store.find_expr(&long).count(), 0);
     }
 
     #[test]
-    fn it_works_with_indices() {
+    fn it_drains() {
         let mut store = Store::new(2);
         store.index(0, idx::HashIndex::new());
-        store.insert(vec!["a1", "a2"]);
-        store.insert(vec!["b1", "b2"]);
-        store.insert(vec!["c1", "c2"]);
-        assert_eq!(store.find(&[]).count(), 3);
+        store.insert(vec!["a", "x1"]);
+        let b = store.insert(vec!["b", "x2"]);
+        store.insert(vec!["a", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("b")),
+                   }];
+        let drained = store.drain(&cmp).collect::<Vec<_>>();
+        assert_eq!(drained, vec![(b, vec!["b", "x2"])]);
+        assert_eq!(store.find(&cmp).count(), 0);
+        assert_eq!(store.find(&[]).count(), 2);
+
+        // rows should be removed even if the iterator isn't consumed
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert!(store.drain(&cmp).next().is_some());
+        assert_eq!(store.find(&cmp).count(), 0);
+        assert_eq!(store.find(&[]).count(), 0);
     }
 
     #[test]
-    fn it_filters() {
+    fn it_retains() {
         let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
         store.insert(vec!["a", "x1"]);
         store.insert(vec!["a", "x2"]);
         store.insert(vec!["b", "x3"]);
+        store.retain(|r| r[1] != "x1");
+        assert_eq!(store.find(&[]).count(), 2);
         let cmp = [cmp::Condition {
                        column: 0,
                        cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
                    }];
-        assert_eq!(store.find(&cmp)
-                       .count(),
-                   2);
-        assert!(store.find(&cmp).all(|r| r[0] == "a"));
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(store.find(&cmp).all(|r| r[1] == "x2"));
     }
 
     #[test]
-    fn it_filters_with_indices() {
+    fn it_updates() {
         let mut store = Store::new(2);
-        store.index(0, idx::HashIndex::new());
         store.insert(vec!["a", "x1"]);
         store.insert(vec!["a", "x2"]);
         store.insert(vec!["b", "x3"]);
@@ -302,49 +6645,213 @@ mod tests {
                        column: 0,
                        cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
                    }];
-        assert_eq!(store.find(&cmp)
-                       .count(),
-                   2);
-        assert!(store.find(&cmp).all(|r| r[0] == "a"));
+        store.update(&cmp, |r| r[1] = "y");
+        assert_eq!(store.find(&cmp).count(), 2);
+        assert!(store.find(&cmp).all(|r| r[1] == "y"));
+        assert!(store.find(&[]).any(|r| r[0] == "b" && r[1] == "x3"));
     }
 
     #[test]
-    fn it_filters_with_partial_indices() {
+    fn filtered_update() {
         let mut store = Store::new(2);
         store.index(0, idx::HashIndex::new());
         store.insert(vec!["a", "x1"]);
-        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["a", "y2"]);
         store.insert(vec!["b", "x3"]);
         let cmp = [cmp::Condition {
                        column: 0,
                        cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
-                   },
-                   cmp::Condition {
-                       column: 1,
-                       cmp: cmp::Comparison::Equal(cmp::Value::new("x2")),
                    }];
+        store.update_filter(&cmp, |r| r[1].starts_with('x'), |r| r[0] = "c");
         assert_eq!(store.find(&cmp).count(), 1);
-        assert!(store.find(&cmp).all(|r| r[0] == "a" && r[1] == "x2"));
+        assert!(store.find(&cmp).all(|r| r[1] == "y2"));
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("c")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(store.find(&cmp).all(|r| r[1] == "x1"));
     }
 
     #[test]
-    fn it_filters_with_late_indices() {
+    fn it_compares_and_updates() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        let a = store.insert(vec!["a", "x1"]);
+        assert_eq!(store.compare_and_update(a, &vec!["a", "x2"], vec!["b", "x2"]),
+                   Err(CasError::Mismatch));
+        assert_eq!(store.compare_and_update(a + 1, &vec!["a", "x1"], vec!["b", "x2"]),
+                   Err(CasError::NotFound));
+        assert_eq!(store.compare_and_update(a, &vec!["a", "x1"], vec!["b", "x2"]),
+                   Ok(()));
+        assert_eq!(store.get(a), Some(&vec!["b", "x2"]));
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("b")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+    }
+
+    #[test]
+    fn it_updates_with_indices() {
         let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
         store.insert(vec!["a", "x1"]);
         store.insert(vec!["a", "x2"]);
         store.insert(vec!["b", "x3"]);
-        store.index(0, idx::HashIndex::new());
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("x2")),
+                   }];
+        store.update(&cmp, |r| r[0] = "c");
+
         let cmp = [cmp::Condition {
                        column: 0,
                        cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
                    }];
-        assert_eq!(store.find(&cmp)
-                       .count(),
-                   2);
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(store.find(&cmp).all(|r| r[1] == "x1"));
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("c")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(store.find(&cmp).all(|r| r[1] == "x2"));
+    }
+
+    #[test]
+    fn it_upserts() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::HashIndex::new());
+        assert_eq!(store.upsert(0, vec!["a", "x1"]), None);
+        assert_eq!(store.upsert(0, vec!["b", "x2"]), None);
+        assert_eq!(store.upsert(0, vec!["a", "x3"]), Some(vec!["a", "x1"]));
+        assert_eq!(store.find(&[]).count(), 2);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("x1")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 0);
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("x3")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
         assert!(store.find(&cmp).all(|r| r[0] == "a"));
+
+        // upserting on an unindexed column should also work
+        assert_eq!(store.upsert(1, vec!["c", "x2"]), Some(vec!["b", "x2"]));
+        assert_eq!(store.find(&[]).count(), 2);
+    }
+
+    #[test]
+    fn it_gets() {
+        let mut store = Store::new(2);
+        let a = store.insert(vec!["a", "x1"]);
+        let b = store.insert(vec!["b", "x2"]);
+        assert_eq!(store.get(a), Some(&vec!["a", "x1"]));
+        assert_eq!(store.get(b), Some(&vec!["b", "x2"]));
+        store.delete(&[cmp::Condition {
+                           column: 0,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                       }]);
+        assert_eq!(store.get(a), None);
+        assert_eq!(store.get(b), Some(&vec!["b", "x2"]));
+    }
+
+    #[test]
+    fn it_collects() {
+        let mut store: Store<_> = vec![vec!["a", "x1"], vec!["b", "x2"]].into_iter().collect();
+        assert_eq!(store.find(&[]).count(), 2);
+        store.index(0, idx::HashIndex::new());
+        store.extend(vec![vec!["a", "x3"], vec!["c", "x4"]]);
+        assert_eq!(store.find(&[]).count(), 4);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 2);
+    }
+
+    #[test]
+    fn it_clears() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        let b = store.insert(vec!["b", "x2"]);
+        store.clear();
+        assert_eq!(store.find(&[]).count(), 0);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 0);
+
+        let a = store.insert(vec!["a", "x3"]);
+        assert!(a > b);
+        assert_eq!(store.find(&cmp).count(), 1);
+
+        store.truncate();
+        assert_eq!(store.find(&cmp).count(), 0);
+        assert_eq!(store.insert(vec!["a", "x4"]), 0);
+        assert_eq!(store.find(&cmp).count(), 1);
+    }
+
+    #[test]
+    fn it_iterates() {
+        let mut store = Store::new(2);
+        let a = store.insert(vec!["a", "x1"]);
+        let b = store.insert(vec!["b", "x2"]);
+        assert_eq!(store.iter().collect::<Vec<_>>(),
+                   vec![(a, &vec!["a", "x1"]), (b, &vec!["b", "x2"])]);
+        assert_eq!(store.values().collect::<Vec<_>>(),
+                   vec![&vec!["a", "x1"], &vec!["b", "x2"]]);
+        assert_eq!(store.values().size_hint(), (2, Some(2)));
+    }
+
+    #[test]
+    fn it_iterates_into() {
+        let mut store = Store::new(2);
+        let a = store.insert(vec!["a", "x1"]);
+        let b = store.insert(vec!["b", "x2"]);
+
+        let mut n = 0;
+        for (rowid, row) in &store {
+            assert!(rowid == a && row[0] == "a" || rowid == b && row[0] == "b");
+            n += 1;
+        }
+        assert_eq!(n, 2);
+        assert_eq!((&mut store).into_iter().count(), 2);
+
+        assert_eq!(store.into_iter().collect::<Vec<_>>(),
+                   vec![(a, vec!["a", "x1"]), (b, vec!["b", "x2"])]);
+    }
+
+    #[test]
+    fn it_counts() {
+        let mut store = Store::new(2);
+        assert!(store.is_empty());
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.cols(), 2);
+        assert!(!store.is_empty());
+
+        store.retain(|r| r[1] != "x1");
+        let stats = store.stats();
+        assert_eq!(stats.rows, 2);
+        assert_eq!(stats.cols, 2);
+        assert_eq!(stats.indices.len(), 1);
+        assert_eq!(stats.indices[&0].entries, 2);
+        assert_eq!(stats.indices[&0].estimate, 1);
     }
 
     #[test]
+    #[cfg(feature = "sync")]
     fn is_send_sync() {
         use std::sync;
         use std::thread;
@@ -354,6 +6861,30 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    #[cfg(not(feature = "sync"))]
+    fn it_stores_rc_values() {
+        use std::rc::Rc;
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(Rc::new(vec![Rc::<str>::from("a"), Rc::from("x")]));
+        store.insert(Rc::new(vec![Rc::<str>::from("b"), Rc::from("y")]));
+
+        let x: Rc<str> = Rc::from("x");
+        let cmp = [Condition {
+                       column: 0,
+                       cmp: Comparison::Equal(Value::new(Rc::from("b"))),
+                   },
+                   Condition {
+                       column: 1,
+                       cmp: Comparison::Predicate(cmp::Predicate::new(move |v: &Rc<str>| {
+                           *v != x
+                       })),
+                   }];
+        assert_eq!(store.find(&cmp).map(|r| r[1].clone()).collect::<Vec<_>>(),
+                   vec![Rc::from("y")]);
+    }
+
     #[test]
     fn it_deletes() {
         let mut store = Store::new(2);
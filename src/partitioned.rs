@@ -0,0 +1,285 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::ops;
+use std::ops::Bound;
+
+use cmp;
+use range_of;
+use Index;
+use Row;
+use Store;
+
+/// How a `PartitionedStore` picks the shard for a value in its partition column.
+enum Partitioning<T> {
+    /// By the hash of the value, computed by the given function.
+    Hash(fn(&T) -> u64),
+    /// By the range the value falls in. Shard `i` holds the values from `bounds[i - 1]`
+    /// (inclusive) up to `bounds[i]` (exclusive).
+    Range(Vec<T>),
+}
+
+fn hash<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A `PartitionedStore` splits its rows across a number of `Store`s (shards) by the value in one
+/// designated column, the partition column. Rows are routed to their shard automatically when they
+/// are inserted, and queries with an equality condition on the partition column only have to look
+/// in a single shard. If the rows are partitioned by range, range conditions on the partition
+/// column also only look in the shards that range overlaps.
+///
+/// Each shard is a regular `Store` with rowids of its own, so a row is identified by the index of
+/// its shard along with its rowid in that shard. Since the shards are independent of one another,
+/// they can also be handed out to different threads (see `shards_mut`), or each be put behind a
+/// lock of its own to reduce contention.
+pub struct PartitionedStore<T, R = Vec<T>> {
+    column: usize,
+    partitioning: Partitioning<T>,
+    shards: Vec<Store<T, R>>,
+}
+
+impl<T, R> PartitionedStore<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Allocate a new `PartitionedStore` with the given number of columns, whose rows are split
+    /// across the given number of shards by the hash of their value in the given column.
+    pub fn hashed(cols: usize, column: usize, shards: usize) -> PartitionedStore<T, R>
+        where T: Hash
+    {
+        assert!(shards > 0);
+        PartitionedStore::with_partitioning(cols, column, Partitioning::Hash(hash::<T>), shards)
+    }
+
+    /// Allocate a new `PartitionedStore` with the given number of columns, whose rows are split by
+    /// their value in the given column into one shard for each range between the given (sorted)
+    /// bounds. The first shard holds all values less than the first bound, and the last shard all
+    /// values greater than or equal to the last bound.
+    pub fn ranged(cols: usize, column: usize, bounds: Vec<T>) -> PartitionedStore<T, R> {
+        assert!(bounds.windows(2).all(|w| w[0] < w[1]));
+        let shards = bounds.len() + 1;
+        PartitionedStore::with_partitioning(cols, column, Partitioning::Range(bounds), shards)
+    }
+
+    fn with_partitioning(cols: usize,
+                         column: usize,
+                         partitioning: Partitioning<T>,
+                         shards: usize)
+                         -> PartitionedStore<T, R> {
+        assert!(column < cols);
+        PartitionedStore {
+            column,
+            partitioning,
+            shards: (0..shards).map(|_| Store::new(cols)).collect(),
+        }
+    }
+
+    /// Returns the column the rows are partitioned by.
+    pub fn partition_column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns the index of the shard that holds rows with the given value in the partition
+    /// column.
+    pub fn shard_for(&self, value: &T) -> usize {
+        match self.partitioning {
+            Partitioning::Hash(hash) => (hash(value) % self.shards.len() as u64) as usize,
+            Partitioning::Range(ref bounds) => bounds.partition_point(|b| b <= value),
+        }
+    }
+
+    /// Returns the range of shards that may hold rows matching all the given conditions.
+    fn shards_for(&self, conds: &[cmp::Condition<T>]) -> ops::Range<usize> {
+        let mut shards = 0..self.shards.len();
+        for c in conds.iter().filter(|c| c.column == self.column) {
+            let (min, max) = match (&c.cmp, &self.partitioning) {
+                (&cmp::Comparison::Equal(cmp::Value::Const(ref v)), _) => {
+                    let shard = self.shard_for(v);
+                    (shard, shard)
+                }
+                (_, &Partitioning::Range(_)) => {
+                    match range_of(&c.cmp) {
+                        Some((min, max)) => {
+                            let min = match min {
+                                Bound::Included(v) | Bound::Excluded(v) => self.shard_for(v),
+                                Bound::Unbounded => 0,
+                            };
+                            let max = match max {
+                                Bound::Included(v) | Bound::Excluded(v) => self.shard_for(v),
+                                Bound::Unbounded => self.shards.len() - 1,
+                            };
+                            (min, max)
+                        }
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+            shards.start = shards.start.max(min);
+            shards.end = shards.end.min(max + 1);
+        }
+        if shards.start > shards.end {
+            shards.end = shards.start;
+        }
+        shards
+    }
+
+    /// Returns the number of shards.
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the shards, in order.
+    pub fn shards(&self) -> &[Store<T, R>] {
+        &self.shards
+    }
+
+    /// Returns the shards, in order, so that they can be changed, for example from different
+    /// threads. Rows inserted directly into a shard **must** belong in that shard (see
+    /// `shard_for`), or queries may not find them.
+    pub fn shards_mut(&mut self) -> &mut [Store<T, R>] {
+        &mut self.shards
+    }
+
+    /// Returns the total number of rows in all the shards.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.len()).sum()
+    }
+
+    /// Returns true if none of the shards hold any rows.
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|s| s.is_empty())
+    }
+
+    /// Insert a new data row into the shard it belongs in, and return the index of that shard
+    /// along with the rowid the shard assigned to the row.
+    pub fn insert(&mut self, row: R) -> (usize, usize) {
+        let shard = self.shard_for(row.index(self.column));
+        (shard, self.shards[shard].insert(row))
+    }
+
+    /// Add an index on the given column to every shard. The given function is called once for
+    /// each shard to make the indexer for that shard.
+    pub fn index<F, I>(&mut self, column: usize, mut make: F)
+        where F: FnMut() -> I,
+              I: Into<Index<T>>
+    {
+        for shard in &mut self.shards {
+            shard.index(column, make());
+        }
+    }
+
+    /// Returns an iterator that yields all rows matching all the given `Condition`s, shard by
+    /// shard. Only the shards that may hold matching rows are searched. See `Store::find`.
+    pub fn find<'c, 's: 'c>(&'s self,
+                            conds: &'c [cmp::Condition<'c, T>])
+                            -> Box<dyn Iterator<Item = &'s R> + 'c> {
+        let shards = self.shards_for(conds);
+        Box::new(self.shards[shards].iter().flat_map(move |s| s.find(conds)))
+    }
+
+    /// Delete all rows that match the given conditions, and return how many were deleted.
+    pub fn delete(&mut self, conds: &[cmp::Condition<T>]) -> usize {
+        let shards = self.shards_for(conds);
+        self.shards[shards]
+            .iter_mut()
+            .map(|s| {
+                let before = s.len();
+                s.delete(conds);
+                before - s.len()
+            })
+            .sum()
+    }
+
+    /// Update all rows that match the given conditions using the given function. See
+    /// `Store::update`. The function **must not** change the value in the partition column.
+    pub fn update<F>(&mut self, conds: &[cmp::Condition<T>], mut f: F)
+        where F: FnMut(&mut R),
+              R: Clone
+    {
+        let shards = self.shards_for(conds);
+        for s in &mut self.shards[shards] {
+            s.update(conds, &mut f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp::{Comparison, Condition, Value};
+    use idx::HashIndex;
+
+    #[test]
+    fn hash_partitioned() {
+        let mut store = PartitionedStore::hashed(2, 0, 4);
+        store.index(1, HashIndex::new);
+        for i in 0..100 {
+            let (shard, _) = store.insert(vec![i % 10, i]);
+            assert_eq!(shard, store.shard_for(&(i % 10)));
+        }
+        assert_eq!(store.len(), 100);
+        assert!(store.shards().iter().filter(|s| !s.is_empty()).count() > 1);
+
+        let cmp = [Condition {
+                       column: 0,
+                       cmp: Comparison::Equal(Value::new(3)),
+                   }];
+        assert_eq!(store.shards_for(&cmp).len(), 1);
+        assert_eq!(store.find(&cmp).count(), 10);
+        let cmp = [Condition {
+                       column: 1,
+                       cmp: Comparison::Equal(Value::new(42)),
+                   }];
+        assert_eq!(store.shards_for(&cmp).len(), 4);
+        assert_eq!(store.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec![2]);
+
+        store.update(&cmp, |r| r[1] = 142);
+        assert_eq!(store.find(&cmp).count(), 0);
+        let cmp = [Condition {
+                       column: 0,
+                       cmp: Comparison::Equal(Value::new(2)),
+                   }];
+        assert_eq!(store.delete(&cmp), 10);
+        assert_eq!(store.len(), 90);
+    }
+
+    #[test]
+    fn range_partitioned() {
+        let mut store = PartitionedStore::ranged(2, 0, vec![10, 20, 30]);
+        for i in 0..40 {
+            store.insert(vec![i, i % 2]);
+        }
+        assert_eq!(store.num_shards(), 4);
+        assert!(store.shards().iter().all(|s| s.len() == 10));
+        assert_eq!(store.shard_for(&10), 1);
+        assert_eq!(store.shard_for(&9), 0);
+
+        let cmp = [Condition {
+                       column: 0,
+                       cmp: Comparison::Between(Bound::Included(Value::new(12)),
+                                                Bound::Excluded(Value::new(25))),
+                   }];
+        assert_eq!(store.shards_for(&cmp), 1..3);
+        assert_eq!(store.find(&cmp).count(), 13);
+        let cmp = [Condition {
+                       column: 0,
+                       cmp: Comparison::GreaterEqual(Value::new(25)),
+                   },
+                   Condition {
+                       column: 0,
+                       cmp: Comparison::Less(Value::new(5)),
+                   }];
+        assert_eq!(store.shards_for(&cmp), 2..2);
+        assert_eq!(store.find(&cmp).count(), 0);
+        let cmp = [Condition {
+                       column: 0,
+                       cmp: Comparison::GreaterEqual(Value::new(35)),
+                   }];
+        assert_eq!(store.shards_for(&cmp), 3..4);
+        assert_eq!(store.delete(&cmp), 5);
+        assert_eq!(store.len(), 35);
+    }
+}
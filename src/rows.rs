@@ -0,0 +1,662 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::iter;
+use std::mem;
+use std::ops;
+use std::slice;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::vec;
+
+/// How a `Store` keeps its rows. See `Store::with_storage`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Storage {
+    /// The rows are kept in a `BTreeMap` keyed by rowid. This is the default, and works well no
+    /// matter which rows are inserted and removed. Unlike with the other kinds of storage, every
+    /// row is stored alongside its (`usize`) rowid.
+    ///
+    /// When the rows are shared with another `Store` (see `Store::cow_clone`), the whole map is
+    /// copied once either `Store` changes a row.
+    BTree,
+    /// The rows are kept in a slab, a `Vec<Option<R>>` indexed by rowid. Since rowids are handed
+    /// out in increasing order, this makes looking up a row by its rowid a single array access,
+    /// and scans a walk over contiguous memory.
+    ///
+    /// The slab has a slot for every rowid up to the largest one in use, so it takes up memory in
+    /// proportion to the number of rows ever inserted, rather than the number of rows currently
+    /// in the `Store`. It is best suited to `Store`s where few rows are removed.
+    ///
+    /// Like with `BTree`, rows shared with another `Store` are all copied once either `Store`
+    /// changes one of them.
+    Slab,
+    /// The rows are kept in fixed-size chunks of 4096 slots each, addressed by rowid. Like with
+    /// `Slab`, looking up a row by its rowid is a couple of array accesses, and a scan is a walk
+    /// over each chunk in turn. Unlike `Slab`, a chunk is freed once all of its rows have been
+    /// removed, and scans skip right past such chunks, so rows removed in rowid order (such as
+    /// the oldest rows in a log) do not take up memory forever.
+    ///
+    /// Rows shared with another `Store` (see `Store::cow_clone`) are shared a chunk at a time,
+    /// and a shared chunk is only copied once one of the `Store`s changes a row in it. This makes
+    /// chunked storage the best fit for `Store`s that are copied often.
+    Chunked,
+}
+
+/// The number of rows in each chunk of a `Storage::Chunked` `RowMap`.
+const CHUNK_SIZE: usize = 4096;
+
+/// The rows of a `Store`, keyed by rowid, kept as chosen by its `Storage`. This mirrors the parts
+/// of the `BTreeMap` API that `Store` needs, except that iterators yield rowids by value.
+///
+/// Cloning a `RowMap` does not copy any rows. Instead, the rows are shared with the clone, and are
+/// only copied (a chunk at a time with `Storage::Chunked`, and all at once otherwise) once either
+/// of them changes a row.
+#[derive(Clone)]
+pub(crate) enum RowMap<R> {
+    BTree(Shared<BTreeMap<usize, R>>),
+    Slab(Slab<R>),
+    Chunked(Chunks<R>),
+}
+
+#[derive(Clone)]
+pub(crate) struct Slab<R> {
+    slots: Shared<Vec<Option<R>>>,
+    len: usize,
+}
+
+#[derive(Clone)]
+pub(crate) struct Chunks<R> {
+    /// The chunk holding rowids `i * CHUNK_SIZE` and up is at position `i`, or `None` if none of
+    /// those rows are present.
+    chunks: Vec<Option<Chunk<R>>>,
+    len: usize,
+}
+
+#[derive(Clone)]
+pub(crate) struct Chunk<R> {
+    /// Always holds `CHUNK_SIZE` slots.
+    slots: Shared<Vec<Option<R>>>,
+    len: usize,
+}
+
+/// A value that is shared with the copies of it made by `clone`, until either changes it.
+pub(crate) struct Shared<V> {
+    value: Arc<V>,
+    /// Copies the value. Only set once the value has been shared, which requires `V: Clone`. This
+    /// is behind a `Mutex` so that sharing the value only needs a `&Shared`.
+    clone: Mutex<Option<CloneFn<V>>>,
+}
+
+/// Copies a value. See `Shared`.
+type CloneFn<V> = fn(&V) -> V;
+
+impl<V> Shared<V> {
+    pub(crate) fn new(value: V) -> Shared<V> {
+        Shared {
+            value: Arc::new(value),
+            clone: Mutex::new(None),
+        }
+    }
+
+    /// Returns the value for changing, first copying it if it is shared.
+    pub(crate) fn make_mut(&mut self) -> &mut V {
+        if Arc::get_mut(&mut self.value).is_none() {
+            let clone = *self.clone.get_mut().unwrap();
+            let clone = clone.expect("value is shared, so it can be cloned");
+            self.value = Arc::new(clone(&self.value));
+        }
+        Arc::get_mut(&mut self.value).unwrap()
+    }
+
+    /// Returns the value, copying it if it is shared.
+    fn into_inner(self) -> V {
+        let clone = self.clone.into_inner().unwrap();
+        Arc::try_unwrap(self.value).unwrap_or_else(|value| {
+            clone.expect("value is shared, so it can be cloned")(&value)
+        })
+    }
+}
+
+impl<V> ops::Deref for Shared<V> {
+    type Target = V;
+    fn deref(&self) -> &V {
+        &self.value
+    }
+}
+
+impl<V: Clone> Clone for Shared<V> {
+    fn clone(&self) -> Self {
+        // either copy may now have to copy the value before changing it
+        *self.clone.lock().unwrap() = Some(V::clone);
+        Shared {
+            value: self.value.clone(),
+            clone: Mutex::new(Some(V::clone)),
+        }
+    }
+}
+
+impl<R> Chunk<R> {
+    fn new() -> Chunk<R> {
+        Chunk {
+            slots: Shared::new((0..CHUNK_SIZE).map(|_| None).collect()),
+            len: 0,
+        }
+    }
+}
+
+impl<R> Chunks<R> {
+    fn new() -> Chunks<R> {
+        Chunks {
+            chunks: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn get(&self, rowid: usize) -> Option<&R> {
+        match self.chunks.get(rowid / CHUNK_SIZE) {
+            Some(Some(chunk)) => chunk.slots[rowid % CHUNK_SIZE].as_ref(),
+            _ => None,
+        }
+    }
+
+    fn get_mut(&mut self, rowid: usize) -> Option<&mut R> {
+        match self.chunks.get_mut(rowid / CHUNK_SIZE) {
+            Some(&mut Some(ref mut chunk)) if chunk.slots[rowid % CHUNK_SIZE].is_some() => {
+                chunk.slots.make_mut()[rowid % CHUNK_SIZE].as_mut()
+            }
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, rowid: usize, row: R) -> Option<R> {
+        let c = rowid / CHUNK_SIZE;
+        if c >= self.chunks.len() {
+            self.chunks.resize_with(c + 1, || None);
+        }
+        let chunk = self.chunks[c].get_or_insert_with(Chunk::new);
+        let old = chunk.slots.make_mut()[rowid % CHUNK_SIZE].replace(row);
+        if old.is_none() {
+            chunk.len += 1;
+            self.len += 1;
+        }
+        old
+    }
+
+    fn remove(&mut self, rowid: usize) -> Option<R> {
+        let c = rowid / CHUNK_SIZE;
+        let (old, empty) = match self.chunks.get_mut(c) {
+            Some(&mut Some(ref mut chunk)) if chunk.slots[rowid % CHUNK_SIZE].is_some() => {
+                let old = chunk.slots.make_mut()[rowid % CHUNK_SIZE].take();
+                if old.is_some() {
+                    chunk.len -= 1;
+                }
+                (old, chunk.len == 0)
+            }
+            _ => return None,
+        };
+        if old.is_some() {
+            self.len -= 1;
+        }
+        if empty {
+            self.chunks[c] = None;
+        }
+        old
+    }
+}
+
+/// An iterator over the rowids in a `RowMap`.
+pub(crate) type Keys<'a, R> = iter::Map<Iter<'a, R>, fn((usize, &'a R)) -> usize>;
+
+impl<R> Default for RowMap<R> {
+    fn default() -> Self {
+        RowMap::new(Storage::BTree)
+    }
+}
+
+impl<R> RowMap<R> {
+    pub(crate) fn new(storage: Storage) -> RowMap<R> {
+        match storage {
+            Storage::BTree => RowMap::BTree(Shared::new(BTreeMap::new())),
+            Storage::Slab => {
+                RowMap::Slab(Slab {
+                    slots: Shared::new(Vec::new()),
+                    len: 0,
+                })
+            }
+            Storage::Chunked => RowMap::Chunked(Chunks::new()),
+        }
+    }
+
+    pub(crate) fn storage(&self) -> Storage {
+        match *self {
+            RowMap::BTree(..) => Storage::BTree,
+            RowMap::Slab(..) => Storage::Slab,
+            RowMap::Chunked(..) => Storage::Chunked,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match *self {
+            RowMap::BTree(ref m) => m.len(),
+            RowMap::Slab(ref s) => s.len,
+            RowMap::Chunked(ref c) => c.len,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn get(&self, rowid: &usize) -> Option<&R> {
+        match *self {
+            RowMap::BTree(ref m) => m.get(rowid),
+            RowMap::Slab(ref s) => s.slots.get(*rowid).and_then(|slot| slot.as_ref()),
+            RowMap::Chunked(ref c) => c.get(*rowid),
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, rowid: &usize) -> Option<&mut R> {
+        match *self {
+            RowMap::BTree(ref mut m) if m.contains_key(rowid) => m.make_mut().get_mut(rowid),
+            RowMap::Slab(ref mut s) if s.slots.get(*rowid).is_some_and(Option::is_some) => {
+                s.slots.make_mut()[*rowid].as_mut()
+            }
+            RowMap::BTree(..) |
+            RowMap::Slab(..) => None,
+            RowMap::Chunked(ref mut c) => c.get_mut(*rowid),
+        }
+    }
+
+    pub(crate) fn contains_key(&self, rowid: &usize) -> bool {
+        self.get(rowid).is_some()
+    }
+
+    pub(crate) fn insert(&mut self, rowid: usize, row: R) -> Option<R> {
+        match *self {
+            RowMap::BTree(ref mut m) => m.make_mut().insert(rowid, row),
+            RowMap::Slab(ref mut s) => {
+                let slots = s.slots.make_mut();
+                if rowid >= slots.len() {
+                    slots.resize_with(rowid + 1, || None);
+                }
+                let old = slots[rowid].replace(row);
+                if old.is_none() {
+                    s.len += 1;
+                }
+                old
+            }
+            RowMap::Chunked(ref mut c) => c.insert(rowid, row),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, rowid: &usize) -> Option<R> {
+        match *self {
+            RowMap::BTree(ref mut m) if m.contains_key(rowid) => m.make_mut().remove(rowid),
+            RowMap::Slab(ref mut s) if s.slots.get(*rowid).is_some_and(Option::is_some) => {
+                s.len -= 1;
+                s.slots.make_mut()[*rowid].take()
+            }
+            RowMap::BTree(..) |
+            RowMap::Slab(..) => None,
+            RowMap::Chunked(ref mut c) => c.remove(*rowid),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match *self {
+            // there is no need to copy shared rows only to throw them away
+            RowMap::BTree(ref mut m) => *m = Shared::new(BTreeMap::new()),
+            RowMap::Slab(ref mut s) => {
+                s.slots = Shared::new(Vec::new());
+                s.len = 0;
+            }
+            RowMap::Chunked(ref mut c) => {
+                c.chunks.clear();
+                c.len = 0;
+            }
+        }
+    }
+
+    /// Reserve room for at least `additional` more rows with rowids past the largest one so far,
+    /// if the storage allows it.
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        match *self {
+            RowMap::BTree(..) => {}
+            RowMap::Slab(ref mut s) => s.slots.make_mut().reserve(additional),
+            RowMap::Chunked(ref mut c) => c.chunks.reserve(additional / CHUNK_SIZE + 1),
+        }
+    }
+
+    /// Release the memory held for rowids past the largest one in use, and any spare capacity, if
+    /// the storage allows it.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        match *self {
+            RowMap::BTree(..) => {}
+            RowMap::Slab(ref mut s) => {
+                let slots = s.slots.make_mut();
+                while let Some(&None) = slots.last() {
+                    slots.pop();
+                }
+                slots.shrink_to_fit();
+            }
+            RowMap::Chunked(ref mut c) => {
+                while let Some(&None) = c.chunks.last() {
+                    c.chunks.pop();
+                }
+                c.chunks.shrink_to_fit();
+            }
+        }
+    }
+
+    /// Move all rows from `other` into this map, leaving `other` empty.
+    pub(crate) fn append(&mut self, other: &mut RowMap<R>) {
+        if let (&mut RowMap::BTree(ref mut m), &mut RowMap::BTree(ref mut o)) = (&mut *self,
+                                                                                 &mut *other) {
+            m.make_mut().append(o.make_mut());
+            return;
+        }
+        let other = mem::replace(other, RowMap::new(other.storage()));
+        for (rowid, row) in other {
+            self.insert(rowid, row);
+        }
+    }
+
+    /// Split off all rows with a rowid of at least `rowid`, and return them.
+    pub(crate) fn split_off(&mut self, rowid: &usize) -> RowMap<R> {
+        match *self {
+            RowMap::BTree(ref mut m) => RowMap::BTree(Shared::new(m.make_mut().split_off(rowid))),
+            RowMap::Slab(ref mut s) => {
+                let mut slots = Vec::new();
+                if *rowid < s.slots.len() {
+                    // keep the split-off rows at their rowids
+                    slots.resize_with(*rowid, || None);
+                    slots.extend(s.slots.make_mut().drain(*rowid..));
+                }
+                let len = slots.iter().filter(|slot| slot.is_some()).count();
+                s.len -= len;
+                RowMap::Slab(Slab {
+                    slots: Shared::new(slots),
+                    len,
+                })
+            }
+            RowMap::Chunked(ref mut c) => {
+                let mut other = Chunks::new();
+                let end = c.chunks.len() * CHUNK_SIZE;
+                for rowid in *rowid..end {
+                    if let Some(row) = c.remove(rowid) {
+                        other.insert(rowid, row);
+                    }
+                }
+                RowMap::Chunked(other)
+            }
+        }
+    }
+
+    pub(crate) fn iter<'a>(&'a self) -> Iter<'a, R> {
+        match *self {
+            RowMap::BTree(ref m) => Iter::BTree(m.iter()),
+            RowMap::Slab(ref s) => {
+                Iter::Slab {
+                    slots: s.slots.iter().enumerate(),
+                    left: s.len,
+                }
+            }
+            RowMap::Chunked(ref c) => {
+                Iter::Chunked {
+                    chunks: c.chunks.iter().enumerate(),
+                    slots: None,
+                    left: c.len,
+                }
+            }
+        }
+    }
+
+    pub(crate) fn keys<'a>(&'a self) -> Keys<'a, R> {
+        fn rowid<R>((rowid, _): (usize, &R)) -> usize {
+            rowid
+        }
+        self.iter().map(rowid as fn((usize, &'a R)) -> usize)
+    }
+
+    pub(crate) fn values<'a>(&'a self) -> Values<'a, R> {
+        Values(self.iter())
+    }
+}
+
+/// Collects rows into a `RowMap` with `Storage::BTree`.
+impl<R> iter::FromIterator<(usize, R)> for RowMap<R> {
+    fn from_iter<I: IntoIterator<Item = (usize, R)>>(iter: I) -> Self {
+        RowMap::BTree(Shared::new(iter.into_iter().collect()))
+    }
+}
+
+impl<R> ops::Index<&usize> for RowMap<R> {
+    type Output = R;
+    fn index(&self, rowid: &usize) -> &R {
+        self.get(rowid).expect("no row with the given rowid")
+    }
+}
+
+impl<R> IntoIterator for RowMap<R> {
+    type Item = (usize, R);
+    type IntoIter = IntoIter<R>;
+    fn into_iter(self) -> IntoIter<R> {
+        match self {
+            RowMap::BTree(m) => IntoIter::BTree(m.into_inner().into_iter()),
+            RowMap::Slab(s) => {
+                IntoIter::Slab {
+                    slots: s.slots.into_inner().into_iter().enumerate(),
+                    left: s.len,
+                }
+            }
+            RowMap::Chunked(c) => {
+                IntoIter::Chunked {
+                    chunks: c.chunks.into_iter().enumerate(),
+                    slots: None,
+                    left: c.len,
+                }
+            }
+        }
+    }
+}
+
+impl<'a, R> IntoIterator for &'a RowMap<R> {
+    type Item = (usize, &'a R);
+    type IntoIter = Iter<'a, R>;
+    fn into_iter(self) -> Iter<'a, R> {
+        self.iter()
+    }
+}
+
+pub(crate) enum Iter<'a, R: 'a> {
+    BTree(btree_map::Iter<'a, usize, R>),
+    Slab {
+        slots: iter::Enumerate<slice::Iter<'a, Option<R>>>,
+        left: usize,
+    },
+    Chunked {
+        chunks: iter::Enumerate<slice::Iter<'a, Option<Chunk<R>>>>,
+        /// The rowid of the first slot in the current chunk, and the remaining slots in it.
+        slots: Option<(usize, iter::Enumerate<slice::Iter<'a, Option<R>>>)>,
+        left: usize,
+    },
+}
+
+impl<'a, R: 'a> Iterator for Iter<'a, R> {
+    type Item = (usize, &'a R);
+    fn next(&mut self) -> Option<Self::Item> {
+        match *self {
+            Iter::BTree(ref mut it) => it.next().map(|(&rowid, row)| (rowid, row)),
+            Iter::Slab { ref mut slots, ref mut left } => {
+                let next = slots.filter_map(|(rowid, slot)| slot.as_ref().map(|row| (rowid, row)))
+                    .next();
+                if next.is_some() {
+                    *left -= 1;
+                }
+                next
+            }
+            Iter::Chunked { ref mut chunks, ref mut slots, ref mut left } => {
+                loop {
+                    if let Some((first, ref mut rows)) = *slots {
+                        let next = rows.filter_map(|(i, slot)| slot.as_ref().map(|row| (i, row)))
+                            .next();
+                        if let Some((i, row)) = next {
+                            *left -= 1;
+                            return Some((first + i, row));
+                        }
+                    }
+                    match chunks.next() {
+                        Some((c, Some(chunk))) => {
+                            *slots = Some((c * CHUNK_SIZE, chunk.slots.iter().enumerate()))
+                        }
+                        Some((_, None)) => {}
+                        None => return None,
+                    }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match *self {
+            Iter::BTree(ref it) => it.size_hint(),
+            Iter::Slab { left, .. } |
+            Iter::Chunked { left, .. } => (left, Some(left)),
+        }
+    }
+}
+
+pub(crate) struct Values<'a, R: 'a>(Iter<'a, R>);
+
+impl<'a, R: 'a> Iterator for Values<'a, R> {
+    type Item = &'a R;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(_, row)| row)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+pub(crate) enum IntoIter<R> {
+    BTree(btree_map::IntoIter<usize, R>),
+    Slab {
+        slots: iter::Enumerate<vec::IntoIter<Option<R>>>,
+        left: usize,
+    },
+    Chunked {
+        chunks: iter::Enumerate<vec::IntoIter<Option<Chunk<R>>>>,
+        slots: Option<(usize, iter::Enumerate<vec::IntoIter<Option<R>>>)>,
+        left: usize,
+    },
+}
+
+impl<R> Iterator for IntoIter<R> {
+    type Item = (usize, R);
+    fn next(&mut self) -> Option<Self::Item> {
+        match *self {
+            IntoIter::BTree(ref mut it) => it.next(),
+            IntoIter::Slab { ref mut slots, ref mut left } => {
+                let next = slots.filter_map(|(rowid, slot)| slot.map(|row| (rowid, row))).next();
+                if next.is_some() {
+                    *left -= 1;
+                }
+                next
+            }
+            IntoIter::Chunked { ref mut chunks, ref mut slots, ref mut left } => {
+                loop {
+                    if let Some((first, ref mut rows)) = *slots {
+                        let next = rows.filter_map(|(i, slot)| slot.map(|row| (i, row))).next();
+                        if let Some((i, row)) = next {
+                            *left -= 1;
+                            return Some((first + i, row));
+                        }
+                    }
+                    match chunks.next() {
+                        Some((c, Some(chunk))) => {
+                            let rows = chunk.slots.into_inner().into_iter().enumerate();
+                            *slots = Some((c * CHUNK_SIZE, rows))
+                        }
+                        Some((_, None)) => {}
+                        None => return None,
+                    }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match *self {
+            IntoIter::BTree(ref it) => it.size_hint(),
+            IntoIter::Slab { left, .. } |
+            IntoIter::Chunked { left, .. } => (left, Some(left)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slab() {
+        let mut rows = RowMap::new(Storage::Slab);
+        assert_eq!(rows.insert(0, "a"), None);
+        assert_eq!(rows.insert(2, "c"), None);
+        assert_eq!(rows.insert(2, "C"), Some("c"));
+        assert_eq!(rows.insert(5, "f"), None);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows.get(&1), None);
+        assert_eq!(rows[&2], "C");
+        assert_eq!(rows.remove(&0), Some("a"));
+        assert_eq!(rows.remove(&0), None);
+        assert_eq!(rows.keys().collect::<Vec<_>>(), vec![2, 5]);
+
+        let mut tail = rows.split_off(&3);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(tail.len(), 1);
+        assert_eq!(tail.get(&5), Some(&"f"));
+
+        tail.insert(7, "h");
+        rows.append(&mut tail);
+        assert!(tail.is_empty());
+
+        // a clone shares the rows until either changes them
+        let mut copy = rows.clone();
+        assert_eq!(copy.remove(&5), Some("f"));
+        assert_eq!(rows.get(&5), Some(&"f"));
+        assert_eq!(copy.into_iter().collect::<Vec<_>>(), vec![(2, "C"), (7, "h")]);
+        assert_eq!(rows.into_iter().collect::<Vec<_>>(), vec![(2, "C"), (5, "f"), (7, "h")]);
+    }
+
+    #[test]
+    fn chunked() {
+        let mut rows = RowMap::new(Storage::Chunked);
+        for rowid in 0..3 * CHUNK_SIZE {
+            rows.insert(rowid, rowid);
+        }
+        assert_eq!(rows.len(), 3 * CHUNK_SIZE);
+        assert_eq!(rows.get(&(CHUNK_SIZE + 1)), Some(&(CHUNK_SIZE + 1)));
+
+        // emptying a chunk frees it, and iteration skips right past it
+        for rowid in CHUNK_SIZE..2 * CHUNK_SIZE {
+            assert_eq!(rows.remove(&rowid), Some(rowid));
+        }
+        if let RowMap::Chunked(ref c) = rows {
+            assert!(c.chunks[1].is_none());
+        }
+        assert_eq!(rows.get(&(CHUNK_SIZE + 1)), None);
+        assert_eq!(rows.iter().size_hint(), (2 * CHUNK_SIZE, Some(2 * CHUNK_SIZE)));
+        assert_eq!(rows.keys().nth(CHUNK_SIZE), Some(2 * CHUNK_SIZE));
+        assert_eq!(rows.values().count(), 2 * CHUNK_SIZE);
+
+        let mut tail = rows.split_off(&(CHUNK_SIZE - 1));
+        assert_eq!(rows.len(), CHUNK_SIZE - 1);
+        assert_eq!(tail.keys().take(2).collect::<Vec<_>>(), vec![CHUNK_SIZE - 1, 2 * CHUNK_SIZE]);
+        rows.append(&mut tail);
+        assert!(tail.is_empty());
+        assert_eq!(rows.into_iter().map(|(rowid, _)| rowid).sum::<usize>(),
+                   (0..CHUNK_SIZE).chain(2 * CHUNK_SIZE..3 * CHUNK_SIZE).sum::<usize>());
+    }
+}
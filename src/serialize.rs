@@ -0,0 +1,484 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de;
+use serde::ser::SerializeStruct;
+
+use idx;
+use rows::RowMap;
+use Index;
+use MaybeSync;
+use Row;
+use Storage;
+use Store;
+
+/// The kinds of indices that are recorded when a `Store` is serialized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum IndexKind {
+    /// A `HashIndex` with the default hasher and postings.
+    Hash,
+    /// A `BTreeIndex` with the default postings.
+    BTree,
+}
+
+/// An index to add to a `Store` once its rows have been deserialized. If its `postings` (each key
+/// in the index along with the rows indexed under it) were serialized too, the index is restored
+/// from them rather than rebuilt from the rows.
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexDef<P> {
+    column: usize,
+    kind: IndexKind,
+    unique: bool,
+    postings: Option<P>,
+}
+
+/// The postings of a `HashIndex` or `BTreeIndex`, serialized as a sequence of `(key, rows)` pairs.
+///
+/// Rows deleted with `delete_lazy` stay in the indices until the `Store` is vacuumed, but are not
+/// serialized along with the other rows, so they are left out of the postings too (as are keys
+/// that only they were indexed under).
+struct Postings<'a, T: 'a, R: 'a> {
+    index: &'a Index<T>,
+    tombstones: &'a RowMap<R>,
+}
+
+impl<'a, T, R> Postings<'a, T, R> {
+    /// Serialize the given postings, leaving out the rows deleted with `delete_lazy`.
+    fn serialize_live<'b, S, K, I>(&self, serializer: S, postings: I) -> Result<S::Ok, S::Error>
+        where S: Serializer,
+              K: Serialize + 'b,
+              I: Iterator<Item = (&'b K, &'b Vec<usize>)>
+    {
+        if self.tombstones.is_empty() {
+            return serializer.collect_seq(postings);
+        }
+        // collected first, since some formats need to know how many keys there are up front
+        let tombstones = self.tombstones;
+        let live = postings.filter_map(|(key, rows)| {
+                let rows = rows.iter()
+                    .filter(|rowid| !tombstones.contains_key(rowid))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if rows.is_empty() {
+                    None
+                } else {
+                    Some((key, rows))
+                }
+            })
+            .collect::<Vec<_>>();
+        serializer.collect_seq(live)
+    }
+}
+
+impl<'a, T, R> Serialize for Postings<'a, T, R>
+    where T: Ord + Serialize
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self.index {
+            Index::Hash(ref hi) => self.serialize_live(serializer, hi.postings()),
+            Index::BTree(ref bi) => self.serialize_live(serializer, bi.postings()),
+            Index::Range(..) | Index::Equality(..) => unreachable!(),
+        }
+    }
+}
+
+/// The rows of a `Store`, serialized as a sequence of `(rowid, row)` pairs in rowid order.
+struct Rows<'a, R: 'a>(&'a RowMap<R>);
+
+impl<'a, R: Serialize> Serialize for Rows<'a, R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter())
+    }
+}
+
+/// Serialize the given `Store`, with the postings returned by the given function for each of its
+/// indices.
+fn serialize_store<'a, T, R, S, P, F>(store: &'a Store<T, R>,
+                                      serializer: S,
+                                      postings: F)
+                                      -> Result<S::Ok, S::Error>
+    where R: Serialize,
+          S: Serializer,
+          P: Serialize,
+          F: Fn(&'a Index<T>) -> Option<P>
+{
+    let mut indices = store.indices
+        .iter()
+        .filter_map(|(&column, idx)| {
+            let kind = match *idx {
+                Index::Hash(..) => IndexKind::Hash,
+                Index::BTree(..) => IndexKind::BTree,
+                Index::Range(..) | Index::Equality(..) => return None,
+            };
+            Some(IndexDef {
+                column,
+                kind,
+                unique: store.unique.contains(&column),
+                postings: postings(idx),
+            })
+        })
+        .collect::<Vec<_>>();
+    indices.sort_by_key(|def| def.column);
+
+    let mut s = serializer.serialize_struct("Store", 5)?;
+    s.serialize_field("cols", &store.cols)?;
+    s.serialize_field("rowid", &store.rowid)?;
+    s.serialize_field("storage", &store.rows.storage())?;
+    s.serialize_field("rows", &Rows(&store.rows))?;
+    s.serialize_field("indices", &indices)?;
+    s.end()
+}
+
+/// A `Store` is serialized as its number of columns, the next rowid it would hand out, how it
+/// keeps its rows, its rows along with their rowids, and the indices on it.
+///
+/// Only `HashIndex`es and `BTreeIndex`es with their default hasher and postings are recorded,
+/// along with whether they are unique, and they are rebuilt from the rows when the `Store` is
+/// deserialized, unless their contents were serialized too (see `Store::with_index_state`). All
+/// other indices (including composite, partial, and functional ones, and any custom indices) are
+/// left out, and must be added again after deserializing. Rows deleted with `delete_lazy` are not
+/// serialized.
+impl<T, R> Serialize for Store<T, R>
+    where R: Serialize
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_store(self, serializer, |_| None::<()>)
+    }
+}
+
+/// A `Store` that is serialized along with the contents of its `HashIndex`es and `BTreeIndex`es.
+/// See `Store::with_index_state`.
+pub struct WithIndexState<'a, T: 'a, R: 'a>(pub(crate) &'a Store<T, R>);
+
+impl<'a, T, R> Serialize for WithIndexState<'a, T, R>
+    where T: Ord + Serialize,
+          R: Serialize
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let tombstones = &self.0.tombstones;
+        serialize_store(self.0, serializer, |index| Some(Postings { index, tombstones }))
+    }
+}
+
+/// The serialized form of a `Store`, before its rows are checked and its indices rebuilt.
+#[derive(Deserialize)]
+#[serde(rename = "Store")]
+struct StoreData<T, R> {
+    cols: usize,
+    rowid: usize,
+    storage: Storage,
+    rows: Vec<(usize, R)>,
+    indices: Vec<IndexDef<Entries<T>>>,
+}
+
+/// The deserialized postings of an index.
+type Entries<T> = Vec<(T, Vec<usize>)>;
+
+impl<T, R> StoreData<T, R> {
+    /// Check the deserialized rows and build the `Store` from them. Unless `trusted` is set, the
+    /// serialized contents of each index are also checked against the rows.
+    fn into_store<E>(self, trusted: bool) -> Result<Store<T, R>, E>
+        where T: Ord + Clone + Hash + MaybeSync + 'static,
+              R: Row<T>,
+              E: de::Error
+    {
+        let mut store = Store::with_storage(self.cols, self.storage);
+        store.rows.reserve(self.rows.len());
+        let mut next = 0;
+        for (rowid, row) in self.rows {
+            if rowid < next || rowid >= self.rowid {
+                return Err(E::custom(format_args!("rowid {} is out of order or out of range",
+                                                  rowid)));
+            }
+            if row.columns() != self.cols {
+                return Err(E::invalid_length(row.columns(), &Columns(self.cols)));
+            }
+            store.rows.insert(rowid, row);
+            next = rowid + 1;
+        }
+        store.rowid = self.rowid;
+
+        for def in self.indices {
+            if def.column >= self.cols {
+                return Err(E::custom(format_args!("index on non-existent column {}",
+                                                  def.column)));
+            }
+            if let Some(postings) = def.postings {
+                let (column, unique) = (def.column, def.unique);
+                let mismatch = || {
+                    E::custom(format_args!("index on column {} does not match the rows", column))
+                };
+                if !trusted {
+                    // every row must be indexed exactly once, under the value in its column
+                    let mut seen = HashSet::with_capacity(store.rows.len());
+                    let matches = postings.iter().all(|(key, rows)| {
+                        (!unique || rows.len() <= 1) &&
+                        rows.iter().all(|&rowid| {
+                            seen.insert(rowid) &&
+                            store.rows.get(&rowid).into_iter().any(|r| r.index(column) == key)
+                        })
+                    });
+                    if !matches || seen.len() != store.rows.len() {
+                        return Err(mismatch());
+                    }
+                }
+                // each key must only be given once
+                let idx = match def.kind {
+                    IndexKind::Hash => idx::HashIndex::from_postings(postings).map(Index::Hash),
+                    IndexKind::BTree => idx::BTreeIndex::from_postings(postings).map(Index::BTree),
+                };
+                let idx = idx.ok_or_else(mismatch)?;
+                store.indices.insert(def.column, idx);
+                if def.unique {
+                    store.unique.insert(def.column);
+                }
+                continue;
+            }
+
+            let idx: Index<T> = match def.kind {
+                IndexKind::Hash => idx::HashIndex::new().into(),
+                IndexKind::BTree => idx::BTreeIndex::new().into(),
+            };
+            if def.unique {
+                store.index_unique(def.column, idx).map_err(E::custom)?;
+            } else {
+                store.index(def.column, idx);
+            }
+        }
+        Ok(store)
+    }
+}
+
+/// The number of columns rows were expected to have, for error messages.
+struct Columns(usize);
+
+impl de::Expected for Columns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a row with {} columns", self.0)
+    }
+}
+
+/// A `Store` can be deserialized from the form described for its `Serialize` implementation. Its
+/// `HashIndex`es and `BTreeIndex`es are restored from their serialized contents if it was
+/// serialized through `Store::with_index_state`, and are otherwise rebuilt from the rows.
+///
+/// The serialized contents of an index are checked to hold every row exactly once, under the value
+/// in the row's indexed column (and, for unique indices, to hold no more than one row per key).
+impl<'de, T, R> Deserialize<'de> for Store<T, R>
+    where T: Ord + Clone + Hash + MaybeSync + Deserialize<'de> + 'static,
+          R: Row<T> + Deserialize<'de>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        StoreData::deserialize(deserializer)?.into_store(false)
+    }
+}
+
+/// A `Store` that was deserialized without checking the serialized contents of its indices against
+/// its rows, which makes restoring them about as cheap as reading them in.
+///
+/// The rows themselves are still checked, as is that each key appears only once in an index, but
+/// an index whose contents do not match the rows is restored as it is, and queries through it will
+/// then return the wrong rows. Only use this for data that was serialized through
+/// `Store::with_index_state` and has not been tampered with since.
+pub struct Trusted<T, R = Vec<T>>(pub Store<T, R>);
+
+impl<'de, T, R> Deserialize<'de> for Trusted<T, R>
+    where T: Ord + Clone + Hash + MaybeSync + Deserialize<'de> + 'static,
+          R: Row<T> + Deserialize<'de>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        StoreData::deserialize(deserializer)?.into_store(true).map(Trusted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp::{Comparison, Condition, Value};
+    use bincode;
+
+    #[test]
+    fn serde_roundtrip() {
+        let mut store = Store::with_storage(2, Storage::Slab);
+        store.index_unique(0, idx::HashIndex::new()).unwrap();
+        store.index(1, idx::BTreeIndex::new());
+        store.index_by(1, |v: &i32| v / 10, idx::HashIndex::new());
+        for i in 0..10 {
+            store.insert(vec![i, i * 10]);
+        }
+        store.delete(&[Condition {
+                           column: 0,
+                           cmp: Comparison::Equal(Value::new(3)),
+                       }]);
+
+        let bytes = bincode::serialize(&store).unwrap();
+        let mut de: Store<i32> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(de.len(), 9);
+        assert_eq!(de.iter().collect::<Vec<_>>(), store.iter().collect::<Vec<_>>());
+        assert_eq!(de.stats().indices.len(), 2);
+        assert!(de.try_insert(vec![4, 0]).is_err());
+        assert_eq!(de.insert(vec![10, 100]), 10);
+        let cmp = [Condition {
+                       column: 1,
+                       cmp: Comparison::GreaterEqual(Value::new(80)),
+                   }];
+        assert_eq!(de.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec![8, 9, 10]);
+
+        // rows must have the right number of columns, and be in rowid order
+        let bad = |rows: Vec<(usize, Vec<i32>)>| {
+            let data = (2usize, 2usize, Storage::BTree, rows, Vec::<IndexDef<()>>::new());
+            let bytes = bincode::serialize(&data).unwrap();
+            bincode::deserialize::<Store<i32>>(&bytes).is_err()
+        };
+        assert!(!bad(vec![(0, vec![0, 0]), (1, vec![1, 1])]));
+        assert!(bad(vec![(0, vec![0])]));
+        assert!(bad(vec![(1, vec![0, 0]), (0, vec![1, 1])]));
+        assert!(bad(vec![(2, vec![0, 0])]));
+    }
+    #[test]
+    fn serde_index_state() {
+        let mut store = Store::new(2);
+        store.index_unique(0, idx::BTreeIndex::new()).unwrap();
+        store.index(1, idx::HashIndex::new());
+        for i in 0..10 {
+            store.insert(vec![i, i % 3]);
+        }
+        store.delete(&[Condition {
+                           column: 1,
+                           cmp: Comparison::Equal(Value::new(0)),
+                       }]);
+
+        let plain = bincode::serialize(&store).unwrap();
+        let bytes = bincode::serialize(&store.with_index_state()).unwrap();
+        assert!(bytes.len() > plain.len());
+        let mut de: Store<i32> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(de.stats().indices, store.stats().indices);
+        let cmp = [Condition {
+                       column: 1,
+                       cmp: Comparison::Equal(Value::new(1)),
+                   }];
+        assert_eq!(de.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec![1, 4, 7]);
+        let cmp = [Condition {
+                       column: 0,
+                       cmp: Comparison::Less(Value::new(3)),
+                   }];
+        assert_eq!(de.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(de.try_insert(vec![2, 5]).is_err());
+        de.insert(vec![3, 1]);
+        assert_eq!(de.find(&cmp).count(), 2);
+
+        // index contents that do not match the rows are rejected
+        let restore = |unique: bool, postings: Vec<(i32, Vec<usize>)>| {
+            let def = IndexDef {
+                column: 1,
+                kind: IndexKind::Hash,
+                unique,
+                postings: Some(postings),
+            };
+            let rows = vec![(0usize, vec![0, 0]), (1, vec![1, 0])];
+            let data = (2usize, 2usize, Storage::BTree, rows, vec![def]);
+            bincode::deserialize::<Store<i32>>(&bincode::serialize(&data).unwrap()).is_ok()
+        };
+        assert!(restore(false, vec![(0, vec![0, 1])]));
+        assert!(!restore(false, vec![(0, vec![0])]));
+        assert!(!restore(true, vec![(0, vec![0, 1])]));
+        assert!(!restore(false, vec![(0, vec![0, 2])]));
+        assert!(!restore(false, vec![(0, vec![0, 0])]));
+        assert!(!restore(false, vec![(0, vec![0]), (1, vec![1])]));
+        assert!(!restore(true, vec![(0, vec![0]), (0, vec![1])]));
+        assert!(!restore(false, vec![(0, vec![0]), (0, vec![1])]));
+
+        // trusted index contents are not checked against the rows, but keys must still be unique
+        let mut trusted: Store<i32> = bincode::deserialize::<Trusted<i32>>(&bytes).unwrap().0;
+        assert_eq!(trusted.stats().indices, store.stats().indices);
+        assert!(trusted.try_insert(vec![2, 5]).is_err());
+        let restore = |postings: Vec<(i32, Vec<usize>)>| {
+            let def = IndexDef {
+                column: 1,
+                kind: IndexKind::BTree,
+                unique: true,
+                postings: Some(postings),
+            };
+            let rows = vec![(0usize, vec![0, 0]), (1, vec![1, 0])];
+            let data = (2usize, 2usize, Storage::BTree, rows, vec![def]);
+            bincode::deserialize::<Trusted<i32>>(&bincode::serialize(&data).unwrap()).is_ok()
+        };
+        assert!(restore(vec![(0, vec![0, 1])]));
+        assert!(!restore(vec![(0, vec![0]), (0, vec![1])]));
+    }
+
+    #[test]
+    fn serde_index_state_after_delete_lazy() {
+        let mut store = Store::new(2);
+        store.index(0, idx::BTreeIndex::new());
+        store.index(1, idx::HashIndex::new());
+        for i in 0..10 {
+            store.insert(vec![i, i % 3]);
+        }
+        // leaves the deleted rows in the indices, and the key 2 indexes only deleted rows
+        store.delete_lazy(&[Condition {
+                                column: 1,
+                                cmp: Comparison::Equal(Value::new(2)),
+                            }]);
+        assert_eq!(store.tombstones(), 3);
+
+        let bytes = bincode::serialize(&store.with_index_state()).unwrap();
+        let check = |de: Store<i32>| {
+            assert_eq!(de.len(), 7);
+            assert_eq!(de.tombstones(), 0);
+            let cmp = [Condition {
+                           column: 1,
+                           cmp: Comparison::Equal(Value::new(2)),
+                       }];
+            assert_eq!(de.find(&cmp).count(), 0);
+            let cmp = [Condition {
+                           column: 0,
+                           cmp: Comparison::Less(Value::new(6)),
+                       }];
+            assert_eq!(de.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec![0, 1, 3, 4]);
+        };
+        check(bincode::deserialize::<Store<i32>>(&bytes).unwrap());
+        check(bincode::deserialize::<Trusted<i32>>(&bytes).unwrap().0);
+    }
+
+    #[test]
+    fn serde_rejects_mismatched_index_state() {
+        let rows = vec![(0usize, vec![0, 0]), (1, vec![1, 0]), (2, vec![2, 1])];
+        let restore = |kind: IndexKind, trusted: bool, postings: Vec<(i32, Vec<usize>)>| {
+            let def = IndexDef {
+                column: 1,
+                kind,
+                unique: false,
+                postings: Some(postings),
+            };
+            let data = (2usize, 3usize, Storage::BTree, rows.clone(), vec![def]);
+            let bytes = bincode::serialize(&data).unwrap();
+            let result = if trusted {
+                bincode::deserialize::<Trusted<i32>>(&bytes).map(|t| t.0)
+            } else {
+                bincode::deserialize::<Store<i32>>(&bytes)
+            };
+            result.map(|_| ()).map_err(|e| e.to_string())
+        };
+        let mismatch = Err("index on column 1 does not match the rows".to_owned());
+
+        for &kind in &[IndexKind::Hash, IndexKind::BTree] {
+            assert_eq!(restore(kind, false, vec![(0, vec![0, 1]), (1, vec![2])]), Ok(()));
+
+            // postings of missing rows, or under the wrong key
+            assert_eq!(restore(kind, false, vec![(0, vec![0, 1]), (1, vec![3])]), mismatch);
+            assert_eq!(restore(kind, false, vec![(0, vec![0, 2]), (1, vec![1])]), mismatch);
+            assert_eq!(restore(kind, false, vec![(0, vec![0, 1]), (2, vec![2])]), mismatch);
+            // but a trusted restore takes them as they are
+            assert_eq!(restore(kind, true, vec![(0, vec![0, 2]), (1, vec![1])]), Ok(()));
+
+            // a key given more than once is rejected either way, even if the postings add up
+            for &trusted in &[false, true] {
+                assert_eq!(restore(kind, trusted, vec![(0, vec![0]), (1, vec![2]), (0, vec![1])]),
+                           mismatch);
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use cmp;
+use Row;
+use Store;
+
+/// The point in a `Store`'s history that a `Snapshot` observes.
+struct Mark {
+    /// Row versions written at or before this epoch are visible.
+    epoch: u64,
+    /// Rows with this rowid or greater were inserted after the snapshot was taken.
+    rowid: usize,
+    /// The `Store`'s rowid generation when the snapshot was taken.
+    generation: u64,
+}
+
+impl Mark {
+    /// Returns true if a version of the given row that was written at the given epoch is visible
+    /// to the snapshot, assuming it has not since been superseded.
+    fn sees(&self, rowid: usize, generation: u64, written: u64) -> bool {
+        generation == self.generation && rowid < self.rowid && written <= self.epoch
+    }
+}
+
+/// A consistent view of a `Store` as of the moment it was taken with `Store::snapshot`. Rows
+/// inserted, updated, or deleted afterwards do not affect what is read through the snapshot with
+/// `Store::at`.
+///
+/// A `Snapshot` does not borrow the `Store`, so the `Store` can keep changing while it is held.
+/// For as long as any clone of a `Snapshot` is alive, the `Store` keeps a copy of every row it
+/// removes or replaces that the snapshot can still see, so snapshots should be dropped once they
+/// are no longer needed. A `Snapshot` **must** only be used with the `Store` it was taken of.
+#[derive(Clone)]
+pub struct Snapshot {
+    mark: Arc<Mark>,
+}
+
+/// An old version of a row that is kept around for the snapshots that can still see it.
+struct Version<R> {
+    rowid: usize,
+    generation: u64,
+    written: u64,
+    superseded: u64,
+    row: R,
+}
+
+impl<R> Version<R> {
+    fn seen_by(&self, mark: &Mark) -> bool {
+        mark.sees(self.rowid, self.generation, self.written) && self.superseded > mark.epoch
+    }
+}
+
+/// The old row versions a `Store` keeps for its live snapshots.
+pub(crate) struct Versions<R> {
+    /// The epoch that rows are currently written at. Taking a snapshot starts a new epoch.
+    epoch: u64,
+    snapshots: Vec<Arc<Mark>>,
+    /// The epoch each row was last replaced at, for rows replaced while there were snapshots.
+    /// Rows without an entry were written before any live snapshot was taken.
+    written: HashMap<usize, u64>,
+    history: Vec<Version<R>>,
+    /// Copies a row. Only set once a snapshot has been taken, which requires `R: Clone`.
+    clone: Option<fn(&R) -> R>,
+}
+
+impl<R> Default for Versions<R> {
+    fn default() -> Self {
+        Versions {
+            epoch: 0,
+            snapshots: Vec::new(),
+            written: HashMap::new(),
+            history: Vec::new(),
+            clone: None,
+        }
+    }
+}
+
+impl<R> Versions<R> {
+    /// Take a new snapshot, which sees all rows with rowids less than `rowid`.
+    pub(crate) fn snapshot(&mut self,
+                           rowid: usize,
+                           generation: u64,
+                           clone: fn(&R) -> R)
+                           -> Snapshot {
+        self.gc();
+        let mark = Arc::new(Mark {
+            epoch: self.epoch,
+            rowid,
+            generation,
+        });
+        self.epoch += 1;
+        self.snapshots.push(mark.clone());
+        self.clone = Some(clone);
+        Snapshot { mark }
+    }
+
+    /// Returns the number of live snapshots.
+    pub(crate) fn snapshots(&self) -> usize {
+        self.snapshots.iter().filter(|m| Arc::strong_count(m) > 1).count()
+    }
+
+    /// Forget snapshots that have been dropped, along with the versions only they could see.
+    fn gc(&mut self) {
+        let before = self.snapshots.len();
+        self.snapshots.retain(|m| Arc::strong_count(m) > 1);
+        if self.snapshots.len() == before {
+            return;
+        }
+        if self.snapshots.is_empty() {
+            self.written.clear();
+            self.history.clear();
+            return;
+        }
+        let snapshots = &self.snapshots;
+        self.history.retain(|v| snapshots.iter().any(|m| v.seen_by(m)));
+    }
+
+    /// Record that the given row is about to be removed (or replaced, if `replaced` is true),
+    /// keeping a copy of it if any live snapshot can see it.
+    pub(crate) fn supersede(&mut self, rowid: usize, generation: u64, row: &R, replaced: bool) {
+        if self.snapshots.is_empty() {
+            return;
+        }
+        self.gc();
+        if self.snapshots.is_empty() {
+            return;
+        }
+
+        let written = self.written.get(&rowid).cloned().unwrap_or(0);
+        if self.snapshots.iter().any(|m| m.sees(rowid, generation, written)) {
+            let clone = self.clone.unwrap();
+            self.history.push(Version {
+                rowid,
+                generation,
+                written,
+                superseded: self.epoch,
+                row: clone(row),
+            });
+        }
+        if replaced {
+            self.written.insert(rowid, self.epoch);
+        } else {
+            self.written.remove(&rowid);
+        }
+    }
+
+    /// Record that a row that was removed has been put back, so that live snapshots keep seeing
+    /// the copy that was kept when it was removed rather than the row itself.
+    pub(crate) fn restore(&mut self, rowid: usize) {
+        if !self.snapshots.is_empty() {
+            self.written.insert(rowid, self.epoch);
+        }
+    }
+
+    /// Returns true if the current version of the given row is visible to the given snapshot.
+    fn visible(&self, snapshot: &Snapshot, rowid: usize, generation: u64) -> bool {
+        let written = self.written.get(&rowid).cloned().unwrap_or(0);
+        snapshot.mark.sees(rowid, generation, written)
+    }
+
+    /// Returns all the old row versions that are visible to the given snapshot.
+    fn history<'a>(&'a self, snapshot: &'a Snapshot) -> Box<dyn Iterator<Item = (usize, &'a R)> + 'a> {
+        Box::new(self.history
+            .iter()
+            .filter(move |v| v.seen_by(&snapshot.mark))
+            .map(|v| (v.rowid, &v.row)))
+    }
+}
+
+/// A read-only view of a `Store` as it was when a `Snapshot` was taken. See `Store::at`.
+pub struct SnapshotView<'s, T: 's, R: 's = Vec<T>> {
+    pub(crate) store: &'s Store<T, R>,
+    pub(crate) snapshot: &'s Snapshot,
+}
+
+impl<'s, T, R> SnapshotView<'s, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Returns an iterator over all rows visible to the snapshot, along with their rowids. Rows
+    /// that still exist in their snapshot version come first, in rowid order, followed by the
+    /// others.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (usize, &'s R)> + 's> {
+        let (store, snapshot) = (self.store, self.snapshot);
+        let current = store.iter()
+            .filter(move |&(rowid, _)| store.versions.visible(snapshot, rowid, store.generation));
+        Box::new(current.chain(store.versions.history(snapshot)))
+    }
+
+    /// Returns the number of rows visible to the snapshot.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Returns true if no rows are visible to the snapshot.
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Returns the row with the given rowid as it was when the snapshot was taken, if it existed.
+    pub fn get(&self, rowid: usize) -> Option<&'s R> {
+        let store = self.store;
+        if store.versions.visible(self.snapshot, rowid, store.generation) {
+            if let Some(row) = store.rows.get(&rowid) {
+                return Some(row);
+            }
+        }
+        store.versions
+            .history(self.snapshot)
+            .find(|&(r, _)| r == rowid)
+            .map(|(_, row)| row)
+    }
+
+    /// Returns an iterator that yields all rows visible to the snapshot that match all the given
+    /// `Condition`s.
+    ///
+    /// Rows that have not changed since the snapshot was taken are found using indices as for
+    /// `Store::find`, but old versions of rows that have since been changed or deleted are always
+    /// scanned.
+    pub fn find<'c>(&self, conds: &'c [cmp::Condition<'c, T>]) -> Box<dyn Iterator<Item = &'s R> + 'c>
+        where 's: 'c
+    {
+        let (store, snapshot) = (self.store, self.snapshot);
+        let current = store.find_with_ids(conds)
+            .filter(move |&(rowid, _)| store.versions.visible(snapshot, rowid, store.generation));
+        let old = store.versions
+            .history(snapshot)
+            .filter(move |&(_, row)| conds.iter().all(|c| c.matches(row)));
+        Box::new(current.chain(old).map(|(_, row)| row))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp::{Comparison, Condition, Value};
+    use idx::HashIndex;
+
+    #[test]
+    fn snapshot_reads() {
+        let mut store = Store::new(2);
+        store.index(1, HashIndex::new());
+        for i in 0..10 {
+            store.insert(vec![i, i % 2]);
+        }
+        let cmp = [Condition {
+                       column: 1,
+                       cmp: Comparison::Equal(Value::new(0)),
+                   }];
+
+        let snap = store.snapshot();
+        store.insert(vec![10, 0]);
+        store.delete(&[Condition {
+                           column: 0,
+                           cmp: Comparison::Equal(Value::new(2)),
+                       }]);
+        store.update(&[Condition {
+                           column: 0,
+                           cmp: Comparison::Equal(Value::new(4)),
+                       }],
+                     |row| row[1] = 1);
+        assert_eq!(store.len(), 10);
+        assert_eq!(store.find(&cmp).count(), 4);
+
+        let mut evens = store.at(&snap).find(&cmp).map(|r| r[0]).collect::<Vec<_>>();
+        evens.sort();
+        assert_eq!(evens, vec![0, 2, 4, 6, 8]);
+        assert_eq!(store.at(&snap).len(), 10);
+        assert_eq!(store.at(&snap).get(2), Some(&vec![2, 0]));
+        assert_eq!(store.at(&snap).get(4), Some(&vec![4, 0]));
+        assert!(store.at(&snap).get(10).is_none());
+
+        // a later snapshot sees the changes, and further changes do not disturb either
+        let snap2 = store.snapshot();
+        store.update(&[], |row| row[1] = 2);
+        store.truncate();
+        assert_eq!(store.snapshots(), 2);
+        assert_eq!(store.at(&snap).find(&cmp).count(), 5);
+        assert_eq!(store.at(&snap2).find(&cmp).count(), 4);
+        assert_eq!(store.at(&snap2).get(4), Some(&vec![4, 1]));
+        store.insert(vec![0, 0]);
+        assert_eq!(store.at(&snap2).len(), 10);
+
+        drop(snap);
+        drop(snap2);
+        assert_eq!(store.snapshots(), 0);
+        // the old versions are released the next time a row changes
+        store.delete(&cmp);
+        assert!(store.versions.history.is_empty());
+    }
+}
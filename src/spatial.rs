@@ -0,0 +1,348 @@
+use std::cmp;
+use std::mem;
+
+/// A value that can be used as a coordinate in a `SpatialIndex`.
+///
+/// Spatial indices need to reason about the area of bounding boxes in order to keep them small,
+/// which requires viewing coordinates as numbers. This is implemented for the primitive integer
+/// types, and can be implemented for other (e.g., enum) value types.
+pub trait Coordinate: Ord + Clone {
+    /// Returns this coordinate as an `f64`.
+    fn as_f64(&self) -> f64;
+}
+
+macro_rules! impl_coordinate {
+    ($($t:ty),*) => {
+        $(impl Coordinate for $t {
+            fn as_f64(&self) -> f64 {
+                *self as f64
+            }
+        })*
+    }
+}
+
+impl_coordinate!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// A `SpatialIndex` indexes rows by a point given by a pair of columns, and can efficiently find
+/// all rows whose point lies within a bounding box.
+pub trait SpatialIndex<T> {
+    /// Add the given row index to the index at the given point.
+    fn index(&mut self, x: T, y: T, row: usize);
+
+    /// Remove the given row index at the given point from the index.
+    fn undex(&mut self, x: &T, y: &T, row: usize);
+
+    /// Return an iterator that yields the indices of all rows whose point lies within the given
+    /// bounding box. Both corners of the box are inclusive.
+    fn within<'a>(&'a self, min: (&T, &T), max: (&T, &T)) -> Box<dyn Iterator<Item = usize> + 'a>;
+
+    /// Remove all row indices from the index.
+    fn clear(&mut self);
+
+    /// Give the total number of (point, row index) entries in the index.
+    fn entries(&self) -> usize;
+
+    /// Release any memory the index holds on to beyond what its current entries need. See
+    /// `EqualityIndex::shrink_to_fit`.
+    ///
+    /// The default implementation does nothing.
+    fn shrink_to_fit(&mut self) {}
+}
+
+/// An axis-aligned bounding box.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Rect<T> {
+    min: (T, T),
+    max: (T, T),
+}
+
+impl<T: Coordinate> Rect<T> {
+    fn point(x: &T, y: &T) -> Rect<T> {
+        Rect {
+            min: (x.clone(), y.clone()),
+            max: (x.clone(), y.clone()),
+        }
+    }
+
+    fn contains(&self, x: &T, y: &T) -> bool {
+        self.min.0 <= *x && *x <= self.max.0 && self.min.1 <= *y && *y <= self.max.1
+    }
+
+    fn intersects(&self, min: (&T, &T), max: (&T, &T)) -> bool {
+        self.min.0 <= *max.0 && *min.0 <= self.max.0 && self.min.1 <= *max.1 &&
+        *min.1 <= self.max.1
+    }
+
+    fn expand(&mut self, other: &Rect<T>) {
+        if other.min.0 < self.min.0 {
+            self.min.0 = other.min.0.clone();
+        }
+        if other.min.1 < self.min.1 {
+            self.min.1 = other.min.1.clone();
+        }
+        if other.max.0 > self.max.0 {
+            self.max.0 = other.max.0.clone();
+        }
+        if other.max.1 > self.max.1 {
+            self.max.1 = other.max.1.clone();
+        }
+    }
+
+    fn area(&self) -> f64 {
+        (self.max.0.as_f64() - self.min.0.as_f64()) * (self.max.1.as_f64() - self.min.1.as_f64())
+    }
+
+    /// How much this box would have to grow to also cover the given box.
+    fn enlargement(&self, other: &Rect<T>) -> f64 {
+        let mut grown = self.clone();
+        grown.expand(other);
+        grown.area() - self.area()
+    }
+}
+
+enum Node<T> {
+    Leaf(Vec<(Rect<T>, usize)>),
+    Inner(Vec<(Rect<T>, Node<T>)>),
+}
+
+/// The maximum number of entries in a node of an `RTreeIndex`.
+const MAX_ENTRIES: usize = 8;
+
+impl<T: Coordinate> Node<T> {
+    fn len(&self) -> usize {
+        match *self {
+            Node::Leaf(ref es) => es.len(),
+            Node::Inner(ref cs) => cs.len(),
+        }
+    }
+
+    fn bounds(&self) -> Rect<T> {
+        let mut rects: Box<dyn Iterator<Item = &Rect<T>>> = match *self {
+            Node::Leaf(ref es) => Box::new(es.iter().map(|e| &e.0)),
+            Node::Inner(ref cs) => Box::new(cs.iter().map(|c| &c.0)),
+        };
+        let mut bounds = rects.next().expect("bounds of empty node").clone();
+        for r in rects {
+            bounds.expand(r);
+        }
+        bounds
+    }
+
+    /// Insert the given entry, and return the new sibling of this node if it had to be split.
+    fn insert(&mut self, rect: Rect<T>, row: usize) -> Option<Node<T>> {
+        match *self {
+            Node::Leaf(ref mut es) => {
+                es.push((rect, row));
+                if es.len() > MAX_ENTRIES {
+                    return Some(Node::Leaf(split(es)));
+                }
+            }
+            Node::Inner(ref mut cs) => {
+                let best = cs.iter()
+                    .enumerate()
+                    .min_by(|&(_, a), &(_, b)| {
+                        // prefer the child that grows the least, and then the smallest child
+                        let ka = (a.0.enlargement(&rect), a.0.area());
+                        let kb = (b.0.enlargement(&rect), b.0.area());
+                        ka.partial_cmp(&kb).unwrap_or(cmp::Ordering::Equal)
+                    })
+                    .map(|(i, _)| i)
+                    .expect("inner node with no children");
+
+                cs[best].0.expand(&rect);
+                if let Some(sibling) = cs[best].1.insert(rect, row) {
+                    cs[best].0 = cs[best].1.bounds();
+                    cs.push((sibling.bounds(), sibling));
+                    if cs.len() > MAX_ENTRIES {
+                        return Some(Node::Inner(split(cs)));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Remove the given entry. Returns true if it was found.
+    fn remove(&mut self, x: &T, y: &T, row: usize) -> bool {
+        match *self {
+            Node::Leaf(ref mut es) => {
+                match es.iter().position(|e| e.1 == row && e.0.contains(x, y)) {
+                    Some(i) => {
+                        es.swap_remove(i);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Node::Inner(ref mut cs) => {
+                let found = cs.iter_mut()
+                    .position(|c| c.0.contains(x, y) && c.1.remove(x, y, row));
+                match found {
+                    Some(i) => {
+                        if cs[i].1.len() == 0 {
+                            cs.swap_remove(i);
+                        } else {
+                            cs[i].0 = cs[i].1.bounds();
+                        }
+                        true
+                    }
+                    None => false,
+                }
+            }
+        }
+    }
+
+    fn shrink_to_fit(&mut self) {
+        match *self {
+            Node::Leaf(ref mut es) => es.shrink_to_fit(),
+            Node::Inner(ref mut cs) => {
+                cs.shrink_to_fit();
+                for c in cs {
+                    c.1.shrink_to_fit();
+                }
+            }
+        }
+    }
+
+    fn within(&self, min: (&T, &T), max: (&T, &T), rows: &mut Vec<usize>) {
+        match *self {
+            Node::Leaf(ref es) => {
+                rows.extend(es.iter().filter(|e| e.0.intersects(min, max)).map(|e| e.1));
+            }
+            Node::Inner(ref cs) => {
+                for c in cs.iter().filter(|c| c.0.intersects(min, max)) {
+                    c.1.within(min, max, rows);
+                }
+            }
+        }
+    }
+}
+
+/// Split the given overfull list of entries in two along the axis in which they are most spread
+/// out. The first half is kept in place, and the second half is returned.
+fn split<T: Coordinate, E>(es: &mut Vec<(Rect<T>, E)>) -> Vec<(Rect<T>, E)> {
+    let mut bounds = es[0].0.clone();
+    for e in es.iter() {
+        bounds.expand(&e.0);
+    }
+    let width = bounds.max.0.as_f64() - bounds.min.0.as_f64();
+    let height = bounds.max.1.as_f64() - bounds.min.1.as_f64();
+    if width >= height {
+        es.sort_by(|a, b| a.0.min.0.cmp(&b.0.min.0));
+    } else {
+        es.sort_by(|a, b| a.0.min.1.cmp(&b.0.min.1));
+    }
+    let half = es.len() / 2;
+    es.split_off(half)
+}
+
+/// An implementation of `SpatialIndex` using an R-tree.
+pub struct RTreeIndex<T> {
+    num: usize,
+    root: Node<T>,
+}
+
+impl<T: Coordinate> RTreeIndex<T> {
+    /// Allocate a new `RTreeIndex`.
+    pub fn new() -> RTreeIndex<T> {
+        RTreeIndex {
+            num: 0,
+            root: Node::Leaf(Vec::new()),
+        }
+    }
+}
+
+impl<T: Coordinate> Default for RTreeIndex<T> {
+    fn default() -> Self {
+        RTreeIndex::new()
+    }
+}
+
+impl<T: Coordinate> SpatialIndex<T> for RTreeIndex<T> {
+    fn index(&mut self, x: T, y: T, row: usize) {
+        if let Some(sibling) = self.root.insert(Rect::point(&x, &y), row) {
+            let old = mem::replace(&mut self.root, Node::Leaf(Vec::new()));
+            self.root = Node::Inner(vec![(old.bounds(), old), (sibling.bounds(), sibling)]);
+        }
+        self.num += 1;
+    }
+
+    fn undex(&mut self, x: &T, y: &T, row: usize) {
+        if self.root.remove(x, y, row) {
+            self.num -= 1;
+        }
+
+        // collapse the root if it only has one child left
+        let collapse = match self.root {
+            Node::Inner(ref mut cs) if cs.len() <= 1 => Some(cs.pop()),
+            _ => None,
+        };
+        if let Some(child) = collapse {
+            self.root = child.map(|c| c.1).unwrap_or_else(|| Node::Leaf(Vec::new()));
+        }
+    }
+
+    fn within<'a>(&'a self, min: (&T, &T), max: (&T, &T)) -> Box<dyn Iterator<Item = usize> + 'a> {
+        let mut rows = Vec::new();
+        if min.0 <= max.0 && min.1 <= max.1 {
+            self.root.within(min, max, &mut rows);
+        }
+        Box::new(rows.into_iter())
+    }
+
+    fn clear(&mut self) {
+        self.root = Node::Leaf(Vec::new());
+        self.num = 0;
+    }
+
+    fn entries(&self) -> usize {
+        self.num
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.root.shrink_to_fit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rtree_index() {
+        let mut idx = RTreeIndex::new();
+        let mut row = 0;
+        for x in 0..20 {
+            for y in 0..20 {
+                idx.index(x, y, row);
+                row += 1;
+            }
+        }
+        assert_eq!(idx.entries(), 400);
+
+        let mut found = idx.within((&3, &4), (&5, &5)).collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, vec![64, 65, 84, 85, 104, 105]);
+        assert_eq!(idx.within((&0, &0), (&19, &19)).count(), 400);
+        assert_eq!(idx.within((&20, &0), (&30, &30)).count(), 0);
+        assert_eq!(idx.within((&5, &5), (&4, &4)).count(), 0);
+
+        for x in 0..20 {
+            for y in 0..20 {
+                if (x + y) % 2 == 0 {
+                    idx.undex(&x, &y, (x * 20 + y) as usize);
+                }
+            }
+        }
+        assert_eq!(idx.entries(), 200);
+        let mut found = idx.within((&3, &4), (&5, &5)).collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, vec![64, 85, 104]);
+        idx.shrink_to_fit();
+        assert_eq!(idx.within((&0, &0), (&19, &19)).count(), 200);
+
+        idx.clear();
+        assert_eq!(idx.within((&0, &0), (&19, &19)).count(), 0);
+        assert_eq!(idx.entries(), 0);
+    }
+}
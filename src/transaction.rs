@@ -0,0 +1,312 @@
+use std::ops;
+
+use cmp;
+use Row;
+use Store;
+use UniqueViolation;
+
+/// How to undo a single change made through a `StoreTransaction`.
+enum Undo<R> {
+    /// The row with this rowid was inserted.
+    Inserted(usize),
+    /// This row was removed.
+    Removed(usize, R),
+    /// The row with this rowid used to be this row.
+    Replaced(usize, R),
+}
+
+/// A point in a `StoreTransaction` that it can be rolled back to without undoing the changes
+/// made before it. See `StoreTransaction::savepoint`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Savepoint {
+    /// The number of changes made before the savepoint.
+    undo: usize,
+}
+
+/// A `StoreTransaction` makes changes to a `Store` that can all be undone together. See
+/// `Store::transaction`.
+///
+/// Every change is applied to the `Store` right away, so the rows inserted, updated, and deleted
+/// so far are visible when querying through the transaction (which dereferences to the `Store`).
+/// Alongside, the transaction keeps a log of how to undo each change. Calling `commit` throws
+/// that log away, whereas `rollback` (or dropping the transaction, including while unwinding from
+/// a panic) uses it to restore the `Store` to how it was when the transaction started, with the
+/// same rows under the same rowids. Rowids handed out to rows inserted by a transaction that is
+/// rolled back are not handed out again, so that `RowId`s for those rows stay stale.
+pub struct StoreTransaction<'a, T, R>
+    where T: Ord + Clone + 'a,
+          R: Row<T> + 'a
+{
+    store: &'a mut Store<T, R>,
+    undo: Vec<Undo<R>>,
+}
+
+impl<'a, T, R> StoreTransaction<'a, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    pub(crate) fn new(store: &'a mut Store<T, R>) -> StoreTransaction<'a, T, R> {
+        StoreTransaction {
+            store,
+            undo: Vec::new(),
+        }
+    }
+
+    /// Insert a new data row, and return the rowid assigned to it. See `Store::insert`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the row has the same value in a uniquely indexed column as an existing row. The
+    /// changes made through the transaction so far are then rolled back.
+    pub fn insert(&mut self, row: R) -> usize {
+        match self.try_insert(row) {
+            Ok(rowid) => rowid,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Like `insert`, but returns an error instead of panicking if the row has the same value in
+    /// a uniquely indexed column as an existing row. The transaction can then carry on, or be
+    /// rolled back.
+    pub fn try_insert(&mut self, row: R) -> Result<usize, UniqueViolation> {
+        let rowid = self.store.try_insert(row)?;
+        self.undo.push(Undo::Inserted(rowid));
+        Ok(rowid)
+    }
+
+    /// Delete all rows that match the given conditions, and return how many were deleted.
+    pub fn delete(&mut self, conds: &[cmp::Condition<T>]) -> usize {
+        let rowids = self.store.find_with_ids(conds).map(|(rowid, _)| rowid).collect::<Vec<_>>();
+        for &rowid in &rowids {
+            let row = self.store.remove(rowid);
+            self.undo.push(Undo::Removed(rowid, row));
+        }
+        rowids.len()
+    }
+
+    /// Update all rows that match the given conditions using the given function. See
+    /// `Store::update`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an updated row would have the same value in a uniquely indexed column as another
+    /// row. The changes made through the transaction so far are then rolled back.
+    pub fn update<F>(&mut self, conds: &[cmp::Condition<T>], f: F)
+        where F: FnMut(&mut R),
+              R: Clone
+    {
+        if let Err(e) = self.try_update(conds, f) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Like `update`, but returns an error instead of panicking if an updated row would have the
+    /// same value in a uniquely indexed column as another row (including another updated row).
+    /// None of the rows are updated then, and the transaction can carry on, or be rolled back.
+    pub fn try_update<F>(&mut self,
+                         conds: &[cmp::Condition<T>],
+                         mut f: F)
+                         -> Result<(), UniqueViolation>
+        where F: FnMut(&mut R),
+              R: Clone
+    {
+        let mut rows = self.store
+            .find_with_ids(conds)
+            .map(|(rowid, row)| (rowid, row.clone()))
+            .collect::<Vec<_>>();
+        for &mut (_, ref mut row) in &mut rows {
+            f(row);
+        }
+        self.store.check_updates(&rows)?;
+        for (rowid, row) in rows {
+            let old = self.store.replace_unchecked(rowid, row);
+            self.undo.push(Undo::Replaced(rowid, old));
+        }
+        Ok(())
+    }
+
+    /// Returns a `Savepoint` for the transaction as it is right now. Calling `rollback_to` with it
+    /// later undoes only the changes made after this call, so that, for example, a long import
+    /// can give up on a single group of rows while keeping those that came before it.
+    ///
+    /// Savepoints nest: rolling back to a savepoint also discards every savepoint taken after it.
+    pub fn savepoint(&self) -> Savepoint {
+        Savepoint { undo: self.undo.len() }
+    }
+
+    /// Undo all changes made through the transaction since the given `Savepoint` was taken. The
+    /// savepoint can be rolled back to again afterwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the savepoint was discarded by rolling back to an earlier savepoint, or was not
+    /// taken from this transaction.
+    pub fn rollback_to(&mut self, savepoint: &Savepoint) {
+        assert!(savepoint.undo <= self.undo.len(),
+                "savepoint is no longer part of the transaction");
+        self.undo_to(savepoint.undo);
+    }
+
+    /// Keep all the changes made through the transaction.
+    pub fn commit(mut self) {
+        self.undo.clear();
+    }
+
+    /// Undo all the changes made through the transaction. This is equivalent to dropping it.
+    pub fn rollback(self) {}
+
+    /// Undo the changes made through the transaction after the first `len` changes, most recent
+    /// first.
+    fn undo_to(&mut self, len: usize) {
+        let undo = self.undo.split_off(len);
+        for u in undo.into_iter().rev() {
+            match u {
+                Undo::Inserted(rowid) => {
+                    self.store.remove(rowid);
+                }
+                Undo::Removed(rowid, row) => {
+                    self.store.versions.restore(rowid);
+                    self.store.put(rowid, row);
+                }
+                Undo::Replaced(rowid, row) => {
+                    self.store.replace_unchecked(rowid, row);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T, R> ops::Deref for StoreTransaction<'a, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    type Target = Store<T, R>;
+    fn deref(&self) -> &Store<T, R> {
+        self.store
+    }
+}
+
+impl<'a, T, R> Drop for StoreTransaction<'a, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    fn drop(&mut self) {
+        self.undo_to(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp::{Comparison, Condition, Value};
+    use idx::HashIndex;
+
+    #[test]
+    fn transaction_rollback() {
+        let mut store = Store::new(2);
+        store.index(1, HashIndex::new());
+        for i in 0..10 {
+            store.insert(vec![i, i % 2]);
+        }
+        let cmp = [Condition {
+                       column: 1,
+                       cmp: Comparison::Equal(Value::new(0)),
+                   }];
+
+        {
+            let mut tx = store.transaction();
+            assert_eq!(tx.insert(vec![10, 0]), 10);
+            assert_eq!(tx.delete(&[Condition {
+                                       column: 0,
+                                       cmp: Comparison::Less(Value::new(3)),
+                                   }]),
+                       3);
+            tx.update(&cmp, |row| row[1] = 1);
+            assert_eq!(tx.find(&cmp).count(), 0);
+            assert_eq!(tx.len(), 8);
+        }
+        assert_eq!(store.len(), 10);
+        let mut evens = store.find(&cmp).map(|r| r[0]).collect::<Vec<_>>();
+        evens.sort();
+        assert_eq!(evens, vec![0, 2, 4, 6, 8]);
+        assert_eq!(store.get(1), Some(&vec![1, 1]));
+        assert_eq!(store.insert(vec![10, 0]), 11);
+
+        let mut tx = store.transaction();
+        tx.delete(&cmp);
+        tx.insert(vec![11, 0]);
+        tx.commit();
+        assert_eq!(store.find(&cmp).map(|r| r[0]).collect::<Vec<_>>(), vec![11]);
+        assert_eq!(store.len(), 6);
+    }
+
+    #[test]
+    fn transaction_unique() {
+        let mut store = Store::new(2);
+        store.index_unique(0, HashIndex::new()).unwrap();
+        store.insert(vec![0, 0]);
+
+        let mut tx = store.transaction();
+        tx.insert(vec![1, 0]);
+        assert!(tx.try_insert(vec![0, 1]).is_err());
+        tx.delete(&[Condition {
+                        column: 0,
+                        cmp: Comparison::Equal(Value::new(0)),
+                    }]);
+        tx.insert(vec![0, 2]);
+        assert_eq!(tx.try_update(&[], |row| row[0] = 2),
+                   Err(UniqueViolation { column: 0, rowid: 1 }));
+        tx.update(&[], |row| row[0] = 1 - row[0]);
+        assert_eq!(tx.find(&[]).map(|r| r[0]).collect::<Vec<_>>(), vec![0, 1]);
+        tx.rollback();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(0), Some(&vec![0, 0]));
+        assert!(store.try_insert(vec![1, 1]).is_ok());
+    }
+
+    #[test]
+    fn transaction_savepoints() {
+        let mut store = Store::new(2);
+        store.index(1, HashIndex::new());
+        let mut tx = store.transaction();
+        tx.insert(vec![0, 0]);
+        let sp = tx.savepoint();
+        tx.insert(vec![1, 0]);
+        let sp2 = tx.savepoint();
+        tx.insert(vec![2, 0]);
+        tx.update(&[], |row| row[1] = 1);
+
+        tx.rollback_to(&sp2);
+        assert_eq!(tx.len(), 2);
+        let cmp = [Condition {
+                       column: 1,
+                       cmp: Comparison::Equal(Value::new(0)),
+                   }];
+        assert_eq!(tx.find(&cmp).count(), 2);
+        assert_eq!(tx.insert(vec![3, 0]), 3);
+
+        tx.rollback_to(&sp);
+        assert_eq!(tx.len(), 1);
+        tx.delete(&[]);
+        tx.rollback_to(&sp);
+        assert_eq!(tx.len(), 1);
+        assert_eq!(tx.insert(vec![4, 0]), 4);
+        tx.commit();
+        assert_eq!(store.iter().map(|(_, r)| r[0]).collect::<Vec<_>>(), vec![0, 4]);
+    }
+
+    #[test]
+    fn transaction_rollback_leaves_handles_stale() {
+        let mut store = Store::new(1);
+        store.insert(vec![0]);
+        let h = {
+            let mut tx = store.transaction();
+            let rowid = tx.insert(vec![1]);
+            tx.handle(rowid).unwrap()
+        };
+        assert!(store.get_handle(h).is_err());
+        store.insert(vec![2]);
+        assert!(store.get_handle(h).is_err());
+        assert!(store.handle(0).is_some());
+    }
+}
@@ -0,0 +1,432 @@
+use std::convert::TryFrom;
+use std::io;
+use std::io::{Read, Write};
+use std::ops;
+
+use cmp;
+use Row;
+use Store;
+
+/// The tag of a record that inserts a row under the given rowid.
+const INSERT: u8 = 0;
+/// The tag of a record that removes the row with the given rowid.
+const REMOVE: u8 = 1;
+/// The tag of a record that replaces a number of rows at once.
+const REPLACE: u8 = 2;
+
+/// A `LoggedStore` wraps a `Store`, and writes every change made through it to an append-only
+/// log before making the change. Should the process crash, the `Store` can be reconstructed by
+/// calling `Store::replay` with the log on a `Store` that is identical to the one the
+/// `LoggedStore` was created with (for example, an empty `Store` with the same indices).
+///
+/// Rows are written to the log using the given `encode` function, which appends the encoding of
+/// a row to the given buffer. Each record in the log is a one-byte tag telling what kind of change
+/// it is, followed by a little-endian `u64`: the rowid for inserts and removals, and the number of
+/// rows for updates. An insert then has the length of the encoded row as a little-endian `u32` and
+/// the encoded row itself. An update has the rowid, length, and encoded row of every row it
+/// changed, so that they are all replayed together or not at all.
+///
+/// Every change is handed to the writer with a single `write_all`. If the writer is buffered,
+/// call `flush` to make sure the changes so far have made it to the underlying file. If writing
+/// to the log fails, the change is not made, and the error is returned; the log may then end with
+/// a partially written record, which `Store::replay` ignores, so the log should not be written to
+/// again.
+pub struct LoggedStore<T, R, W, E> {
+    store: Store<T, R>,
+    log: W,
+    encode: E,
+    buf: Vec<u8>,
+}
+
+impl<T, R, W, E> LoggedStore<T, R, W, E>
+    where T: Ord + Clone,
+          R: Row<T>,
+          W: Write,
+          E: FnMut(&R, &mut Vec<u8>)
+{
+    /// Wrap the given `Store`, and log changes to it to the given writer using the given function
+    /// to encode rows.
+    pub fn new(store: Store<T, R>, log: W, encode: E) -> LoggedStore<T, R, W, E> {
+        LoggedStore {
+            store,
+            log,
+            encode,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Returns the wrapped `Store` and the log.
+    pub fn into_inner(self) -> (Store<T, R>, W) {
+        (self.store, self.log)
+    }
+
+    /// Returns a reference to the log.
+    pub fn log(&self) -> &W {
+        &self.log
+    }
+
+    /// Flush the log.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.log.flush()
+    }
+
+    /// Write a record with the given tag, rowid, and row (if any) to the log.
+    fn write(&mut self, tag: u8, rowid: usize, row: Option<&R>) -> io::Result<()> {
+        self.buf.clear();
+        self.buf.push(tag);
+        self.buf.extend_from_slice(&(rowid as u64).to_le_bytes());
+        if let Some(row) = row {
+            self.push_row(row)?;
+        }
+        self.log.write_all(&self.buf)
+    }
+
+    /// Append the length of the given row's encoding, and then the encoding itself, to the record
+    /// being written.
+    fn push_row(&mut self, row: &R) -> io::Result<()> {
+        let start = self.buf.len();
+        self.buf.extend_from_slice(&[0; 4]);
+        (self.encode)(row, &mut self.buf);
+        let len = self.buf.len() - start - 4;
+        if len > u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "encoded row is too large"));
+        }
+        self.buf[start..start + 4].copy_from_slice(&(len as u32).to_le_bytes());
+        Ok(())
+    }
+
+    /// Insert a new data row, and return the rowid assigned to it. See `Store::insert`.
+    ///
+    /// If the row has the same value in a uniquely indexed column as an existing row, nothing is
+    /// logged or inserted, and an error of kind `InvalidInput` that wraps the `UniqueViolation` is
+    /// returned.
+    pub fn insert(&mut self, row: R) -> io::Result<usize> {
+        if let Some(e) = self.store.unique_violation(&row, None) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
+        }
+        let rowid = self.store.rowid;
+        self.write(INSERT, rowid, Some(&row))?;
+        Ok(self.store.insert(row))
+    }
+
+    /// Delete all rows that match the given conditions, and return how many were deleted.
+    ///
+    /// If writing to the log fails, the rows deleted so far stay deleted, and the rest are kept.
+    pub fn delete(&mut self, conds: &[cmp::Condition<T>]) -> io::Result<usize> {
+        let rowids = self.store
+            .find_with_ids(conds)
+            .map(|(rowid, _)| rowid)
+            .collect::<Vec<_>>();
+        for &rowid in &rowids {
+            self.write(REMOVE, rowid, None)?;
+            self.store.remove(rowid);
+        }
+        Ok(rowids.len())
+    }
+
+    /// Update all rows that match the given conditions using the given function, and return how
+    /// many were updated. See `Store::update`.
+    ///
+    /// All the updated rows are written to the log as a single record. If an updated row would have
+    /// the same value in a uniquely indexed column as another row (including another updated
+    /// row), nothing is logged or updated, and an error of kind `InvalidInput` that wraps the
+    /// `UniqueViolation` is returned. If writing to the log fails, none of the rows are updated.
+    pub fn update<F>(&mut self, conds: &[cmp::Condition<T>], mut f: F) -> io::Result<usize>
+        where F: FnMut(&mut R),
+              R: Clone
+    {
+        let mut rows = self.store
+            .find_with_ids(conds)
+            .map(|(rowid, row)| (rowid, row.clone()))
+            .collect::<Vec<_>>();
+        for &mut (_, ref mut row) in &mut rows {
+            f(row);
+        }
+        if let Err(e) = self.store.check_updates(&rows) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
+        }
+        let updated = rows.len();
+        if updated == 0 {
+            return Ok(0);
+        }
+        self.buf.clear();
+        self.buf.push(REPLACE);
+        self.buf.extend_from_slice(&(updated as u64).to_le_bytes());
+        for &(rowid, ref row) in &rows {
+            self.buf.extend_from_slice(&(rowid as u64).to_le_bytes());
+            self.push_row(row)?;
+        }
+        self.log.write_all(&self.buf)?;
+        for (rowid, row) in rows {
+            self.store.replace_unchecked(rowid, row);
+        }
+        Ok(updated)
+    }
+}
+
+impl<T, R, W, E> ops::Deref for LoggedStore<T, R, W, E> {
+    type Target = Store<T, R>;
+    fn deref(&self) -> &Store<T, R> {
+        &self.store
+    }
+}
+
+/// Read exactly `buf.len()` bytes into `buf`. Returns false if the log ended first.
+fn read_full<Rd: Read>(log: &mut Rd, buf: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match log.read(&mut buf[read..]) {
+            Ok(0) => return Ok(false),
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Decode a logged rowid, which may not fit in a `usize` if the log was written on a platform
+/// with larger pointers.
+fn to_rowid(rowid: u64) -> io::Result<usize> {
+    usize::try_from(rowid).map_err(|_| invalid("logged rowid does not fit in a usize"))
+}
+
+/// Read the length of an encoded row, and then the row itself, and decode it. Returns `None` if the
+/// log ended first.
+fn read_row<T, R, Rd, D>(log: &mut Rd,
+                         buf: &mut Vec<u8>,
+                         decode: &mut D,
+                         cols: usize)
+                         -> io::Result<Option<R>>
+    where R: Row<T>,
+          Rd: Read,
+          D: FnMut(&[u8]) -> io::Result<R>
+{
+    let mut len = [0; 4];
+    if !read_full(log, &mut len)? {
+        return Ok(None);
+    }
+    // the length is not trusted, so the buffer only grows as the row's bytes arrive
+    let len = u32::from_le_bytes(len) as usize;
+    buf.clear();
+    if log.by_ref().take(len as u64).read_to_end(buf)? < len {
+        return Ok(None);
+    }
+    let row = decode(buf)?;
+    if row.columns() != cols {
+        return Err(invalid("logged row has the wrong number of columns"));
+    }
+    Ok(Some(row))
+}
+
+/// Apply every record in the given log to the given `Store`. See `Store::replay`.
+pub(crate) fn replay<T, R, Rd, D>(store: &mut Store<T, R>,
+                                  mut log: Rd,
+                                  mut decode: D)
+                                  -> io::Result<usize>
+    where T: Ord + Clone,
+          R: Row<T>,
+          Rd: Read,
+          D: FnMut(&[u8]) -> io::Result<R>
+{
+    let mut records = 0;
+    let mut header = [0; 9];
+    let mut buf = Vec::new();
+    loop {
+        // a record that was only partially written when the process crashed is ignored
+        if !read_full(&mut log, &mut header)? {
+            return Ok(records);
+        }
+        let tag = header[0];
+        let mut value = [0; 8];
+        value.copy_from_slice(&header[1..]);
+        let value = u64::from_le_bytes(value);
+
+        match tag {
+            INSERT => {
+                let row = match read_row(&mut log, &mut buf, &mut decode, store.cols)? {
+                    Some(row) => row,
+                    None => return Ok(records),
+                };
+                let rowid = to_rowid(value)?;
+                let next = rowid.checked_add(1)
+                    .ok_or_else(|| invalid("logged insert of the largest possible rowid"))?;
+                if store.rows.get(&rowid).is_some() {
+                    return Err(invalid("logged insert of a rowid that is in use"));
+                }
+                if let Some(e) = store.unique_violation(&row, None) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                store.put(rowid, row);
+                store.rowid = store.rowid.max(next);
+            }
+            REMOVE => {
+                let rowid = to_rowid(value)?;
+                if store.rows.get(&rowid).is_none() {
+                    return Err(invalid("logged removal of a row that does not exist"));
+                }
+                store.remove(rowid);
+            }
+            REPLACE => {
+                // the number of rows is not trusted either, so they are collected as they arrive
+                let mut rows = Vec::new();
+                for _ in 0..value {
+                    let mut rowid = [0; 8];
+                    if !read_full(&mut log, &mut rowid)? {
+                        return Ok(records);
+                    }
+                    let rowid = to_rowid(u64::from_le_bytes(rowid))?;
+                    let row = match read_row(&mut log, &mut buf, &mut decode, store.cols)? {
+                        Some(row) => row,
+                        None => return Ok(records),
+                    };
+                    if store.rows.get(&rowid).is_none() {
+                        return Err(invalid("logged update of a row that does not exist"));
+                    }
+                    rows.push((rowid, row));
+                }
+                if let Err(e) = store.check_updates(&rows) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                for (rowid, row) in rows {
+                    store.replace_unchecked(rowid, row);
+                }
+            }
+            _ => return Err(invalid("unknown log record")),
+        }
+        records += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp::{Comparison, Condition, Value};
+    use idx::HashIndex;
+
+    fn encode(row: &Vec<i32>, out: &mut Vec<u8>) {
+        for v in row {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Vec<i32>> {
+        Ok(bytes.chunks(4)
+            .map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect())
+    }
+
+    fn new_store() -> Store<i32> {
+        let mut store = Store::new(2);
+        store.index_unique(0, HashIndex::new()).unwrap();
+        store.index(1, HashIndex::new());
+        store
+    }
+
+    #[test]
+    fn log_and_replay() {
+        let mut store = LoggedStore::new(new_store(), Vec::new(), encode);
+        for i in 0..10 {
+            assert_eq!(store.insert(vec![i, i % 2]).unwrap(), i as usize);
+        }
+        let cmp = [Condition {
+                       column: 1,
+                       cmp: Comparison::Equal(Value::new(0)),
+                   }];
+        assert_eq!(store.update(&cmp, |row| row[1] = 2).unwrap(), 5);
+        assert_eq!(store.delete(&[Condition {
+                                      column: 0,
+                                      cmp: Comparison::Less(Value::new(3)),
+                                  }])
+                       .unwrap(),
+                   3);
+        store.flush().unwrap();
+        let (store, log) = store.into_inner();
+
+        let mut replayed = new_store();
+        assert_eq!(replayed.replay(&log[..], decode).unwrap(), 14);
+        assert_eq!(replayed.iter().collect::<Vec<_>>(), store.iter().collect::<Vec<_>>());
+        assert_eq!(replayed.find(&cmp).count(), 0);
+        assert_eq!(replayed.insert(vec![10, 0]), 10);
+
+        // a record that was cut short by a crash is ignored
+        let mut replayed = new_store();
+        assert_eq!(replayed.replay(&log[..log.len() - 1], decode).unwrap(), 13);
+        assert_eq!(replayed.len(), 8);
+
+        // but garbage is not
+        let mut replayed = new_store();
+        let mut garbage = log.clone();
+        garbage[0] = 42;
+        assert_eq!(replayed.replay(&garbage[..], decode).unwrap_err().kind(),
+                   io::ErrorKind::InvalidData);
+
+        // nor is a cut-short record's length trusted
+        let mut replayed = new_store();
+        let mut huge = log[..13].to_vec();
+        huge[9..13].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(replayed.replay(&huge[..], decode).unwrap(), 0);
+
+        // nor are rowids that the store could not hand out
+        let mut replayed = new_store();
+        let mut last = log[..13].to_vec();
+        last[1..9].copy_from_slice(&u64::MAX.to_le_bytes());
+        last.extend_from_slice(&log[13..21]);
+        assert_eq!(replayed.replay(&last[..], decode).unwrap_err().kind(),
+                   io::ErrorKind::InvalidData);
+        assert_eq!(replayed.len(), 0);
+    }
+
+    #[test]
+    fn unique_violations_are_not_logged() {
+        let mut store = LoggedStore::new(new_store(), Vec::new(), encode);
+        store.insert(vec![1, 0]).unwrap();
+        store.insert(vec![2, 1]).unwrap();
+        let logged = store.log().len();
+
+        let err = store.insert(vec![1, 2]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(store.log().len(), logged);
+        assert_eq!(store.len(), 2);
+
+        let cmp = [Condition {
+                       column: 1,
+                       cmp: Comparison::Equal(Value::new(1)),
+                   }];
+        let err = store.update(&cmp, |row| row[0] = 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(store.log().len(), logged);
+        assert_eq!(store.get(1), Some(&vec![2, 1]));
+
+        // the updated rows are checked against each other too
+        let err = store.update(&[], |row| row[0] = 3).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(store.log().len(), logged);
+        assert_eq!(store.get(0), Some(&vec![1, 0]));
+    }
+
+    #[test]
+    fn updates_are_replayed_together() {
+        let mut store = LoggedStore::new(new_store(), Vec::new(), encode);
+        store.insert(vec![1, 0]).unwrap();
+        store.insert(vec![2, 0]).unwrap();
+        // swapping unique values only works if both rows are replaced at once
+        assert_eq!(store.update(&[], |row| row[0] = 3 - row[0]).unwrap(), 2);
+        let (store, log) = store.into_inner();
+        assert_eq!(store.get(0), Some(&vec![2, 0]));
+
+        let mut replayed = new_store();
+        assert_eq!(replayed.replay(&log[..], decode).unwrap(), 3);
+        assert_eq!(replayed.iter().collect::<Vec<_>>(), store.iter().collect::<Vec<_>>());
+
+        // and an update that was cut short is not replayed at all
+        let mut replayed = new_store();
+        assert_eq!(replayed.replay(&log[..log.len() - 1], decode).unwrap(), 2);
+        assert_eq!(replayed.get(0), Some(&vec![1, 0]));
+        assert_eq!(replayed.get(1), Some(&vec![2, 0]));
+    }
+}